@@ -1,8 +1,30 @@
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
 use std::sync::Arc;
 
+use crate::core::backup::{self, BackupJob, BackupOption, Compression};
+use crate::core::catalog::{self, IsoFilter, IsoRow};
+use crate::core::cloudinit::{self, Seed, SeedFile, SeedTemplate};
+use crate::core::config::Config;
 use crate::core::disk_ops::DiskManager;
-use crate::core::flasher::Flasher;
-use crate::core::{AppState, Device, FileSystemType, Iso};
+use crate::core::flasher::{self, Flasher};
+use crate::core::grow;
+use crate::core::history::UrlHistory;
+use crate::core::identify::{self, Identification, Identity};
+use crate::core::inject::{self, Injection};
+use crate::core::inspect::{self, ImageFormat};
+use crate::core::isobrowser::{self, IsoTree, IsoUpdate};
+use crate::core::mirror::{self, MirrorListing, MirrorTree};
+use crate::core::multiboot::{self, MultibootStick};
+use crate::core::persistence::{self, LiveFlavor};
+use crate::core::rpi::{self, PiBoot, PiField, PiPresets, PiSettings};
+use crate::core::source::ImageSource;
+use crate::core::source::device::DeviceSource;
+use crate::core::windows;
+use crate::core::wipe::{self, WipeJob, WipeMode};
+use crate::core::{AppState, Device, DiskError, FileSystemType, FlashJob, Iso};
+use crate::core::{partition, signatures};
+use crate::utils::bytes_to_human;
 
 /// Main application state
 pub struct App {
@@ -15,7 +37,57 @@ pub struct App {
     pub fs_options: Vec<FileSystemType>,
     pub selected_fs_index: usize,
     pub isos: Vec<Iso>,
-    pub selected_iso_index: usize,
+    pub iso_filter: IsoFilter,
+    pub iso_search_active: bool,
+    pub collapsed_groups: HashSet<String>,
+    /// Cursor position within `visible_iso_rows()`
+    pub selected_iso_row: usize,
+    pub url_history: UrlHistory,
+    /// Position in `url_history` while browsing it from the URL input
+    pub history_cursor: Option<usize>,
+    /// Image waiting in the confirm dialog after pre-flight
+    pub flash_job: Option<FlashJob>,
+    /// Device being cloned while the user picks a target
+    pub clone_source: Option<Device>,
+    pub mirror: Option<MirrorTree>,
+    /// Contents of an image being browsed
+    pub iso_tree: Option<IsoTree>,
+    /// What the last identified drive holds, until something writes to a drive
+    pub identified: Option<Identification>,
+    /// Pre-flight results by image URL, for the picker's details pane
+    pub preflights: HashMap<String, flasher::Preflight>,
+    pub backup_compression: Compression,
+    pub backup_skip_unused: bool,
+    pub backup_shrink: bool,
+    pub backup_auto_expand: bool,
+    /// Highlighted checkbox in the backup dialog
+    pub backup_option: BackupOption,
+    pub wipe_mode: WipeMode,
+    pub wipe_passes: u32,
+    /// Read the device back after wiping
+    pub wipe_verify: bool,
+    /// Templates offered in the seed editor, loaded when it opens
+    pub seed_templates: Vec<SeedTemplate>,
+    pub seed_template_index: usize,
+    /// Seed file shown in the editor
+    pub seed_file: SeedFile,
+    /// `<file> -> <partition>:<path>` being typed in the injection editor
+    pub injection_input: String,
+    pub injection_selected: usize,
+    pub injection_error: Option<String>,
+    /// Headless setup for a flashed Raspberry Pi OS image
+    pub pi_settings: PiSettings,
+    /// Index into `PiField::ALL`
+    pub pi_field: usize,
+    pub pi_presets: PiPresets,
+    pub pi_preset_index: usize,
+    /// Result of the last preset save, or why the settings can't be applied
+    pub pi_notice: Option<String>,
+    /// Open multi-boot stick; images picked while it's open are added to it
+    pub multiboot: Option<MultibootStick>,
+    pub multiboot_selected: usize,
+    /// ISO waiting for a second press of the delete key
+    pub multiboot_remove: Option<String>,
     pub should_quit: bool,
     pub tick: u64,
     pub operation_tx: tokio::sync::mpsc::UnboundedSender<AppState>,
@@ -25,84 +97,66 @@ pub struct App {
 impl App {
     pub fn new(disk_manager: Arc<dyn DiskManager>) -> Self {
         let (operation_tx, operation_rx) = tokio::sync::mpsc::unbounded_channel();
+
+        // A broken config file shouldn't keep the app from starting: report
+        // it and carry on with the defaults
+        let mut state = AppState::Idle;
+        let config = Config::load().unwrap_or_else(|e| {
+            state = AppState::Error(format!("{:#}", e));
+            Config::default()
+        });
+        let flasher = Flasher::new(&config.network).unwrap_or_else(|e| {
+            state = AppState::Error(format!("Network config: {:#}", e));
+            Flasher::default()
+        });
+
         Self {
             devices: Vec::new(),
             selected_index: 0,
-            state: AppState::Idle,
+            state,
             input_buffer: String::new(),
             disk_manager,
-            flasher: Arc::new(Flasher::new()),
+            flasher: Arc::new(flasher),
             fs_options: FileSystemType::macos_options(),
             selected_fs_index: 0,
-            isos: vec![
-                Iso {
-                    name: "Debian".to_string(),
-                    version: "13".to_string(),
-                    arch: "amd64".to_string(),
-                    variety: "Netinst".to_string(),
-                    url: "https://cdimage.debian.org/debian-cd/current/amd64/iso-cd/debian-13.2.0-amd64-netinst.iso".to_string(),
-                },
-                Iso {
-                    name: "Debian".to_string(),
-                    version: "13".to_string(),
-                    arch: "arm64".to_string(),
-                    variety: "Netinst".to_string(),
-                    url: "https://cdimage.debian.org/debian-cd/current/arm64/iso-cd/debian-13.2.0-arm64-netinst.iso".to_string(),
-                },
-                Iso {
-                    name: "Ubuntu".to_string(),
-                    version: "24.04.3".to_string(),
-                    arch: "amd64".to_string(),
-                    variety: "Live Server".to_string(),
-                    url: "https://mirror.pilotfiber.com/ubuntu-iso/24.04.3/ubuntu-24.04.3-desktop-amd64.iso".to_string(),
-                },
-                Iso {
-                    name: "Ubuntu".to_string(),
-                    version: "24.04.3".to_string(),
-                    arch: "arm64".to_string(),
-                    variety: "Live Server".to_string(),
-                    url: "https://cdimage.ubuntu.com/releases/24.04.3/release/ubuntu-24.04.3-live-server-arm64.iso".to_string(),
-                },
-                Iso {
-                    name: "Alpine".to_string(),
-                    version: "3.23.2".to_string(),
-                    arch: "x86_64".to_string(),
-                    variety: "Standard".to_string(),
-                    url: "https://dl-cdn.alpinelinux.org/alpine/v3.23/releases/x86_64/alpine-standard-3.23.2-x86_64.iso".to_string(),
-                },
-                Iso {
-                    name: "Alpine".to_string(),
-                    version: "3.23.2".to_string(),
-                    arch: "aarch64".to_string(),
-                    variety: "Standard".to_string(),
-                    url: "https://dl-cdn.alpinelinux.org/alpine/v3.23/releases/aarch64/alpine-standard-3.23.2-aarch64.iso".to_string(),
-                },
-                Iso {
-                    name: "Arch Linux".to_string(),
-                    version: "2025.12.01".to_string(),
-                    arch: "x86_64".to_string(),
-                    variety: "Standard".to_string(),
-                    url: "https://geo.mirror.pkgbuild.com/iso/2025.12.01/archlinux-2025.12.01-x86_64.iso".to_string(),
-                },
-                // Windows 11 - Reserved for future S3 bucket implementation
-                /*
-                Iso {
-                    name: "Windows 11".to_string(),
-                    version: "23H2".to_string(),
-                    arch: "x64".to_string(),
-                    variety: "English Intl".to_string(),
-                    url: "https://www.microsoft.com/software-download/windows11".to_string(),
-                },
-                Iso {
-                    name: "Windows 11".to_string(),
-                    version: "23H2 (ARM)".to_string(),
-                    arch: "arm64".to_string(),
-                    variety: "Insider VHDX".to_string(),
-                    url: "https://www.microsoft.com/en-us/software-download/windowsinsiderpreviewARM64".to_string(),
-                },
-                */
-            ],
-            selected_iso_index: 0,
+            isos: catalog::default_catalog(),
+            iso_filter: IsoFilter {
+                query: String::new(),
+                arch: Some(catalog::host_arch()),
+            },
+            iso_search_active: false,
+            collapsed_groups: HashSet::new(),
+            selected_iso_row: 0,
+            url_history: UrlHistory::load(),
+            history_cursor: None,
+            flash_job: None,
+            clone_source: None,
+            mirror: None,
+            iso_tree: None,
+            identified: None,
+            preflights: HashMap::new(),
+            backup_compression: Compression::Zstd,
+            backup_skip_unused: true,
+            backup_shrink: false,
+            backup_auto_expand: false,
+            backup_option: BackupOption::SkipUnused,
+            wipe_mode: WipeMode::Zero,
+            wipe_passes: 3,
+            wipe_verify: true,
+            seed_templates: Vec::new(),
+            seed_template_index: 0,
+            seed_file: SeedFile::UserData,
+            injection_input: String::new(),
+            injection_selected: 0,
+            injection_error: None,
+            pi_settings: PiSettings::default(),
+            pi_field: 0,
+            pi_presets: PiPresets::default(),
+            pi_preset_index: 0,
+            pi_notice: None,
+            multiboot: None,
+            multiboot_selected: 0,
+            multiboot_remove: None,
             should_quit: false,
             tick: 0,
             operation_tx,
@@ -185,193 +239,2376 @@ impl App {
                 return;
             }
             self.state = AppState::IsoSelection;
-            self.selected_iso_index = 0;
+            self.iso_search_active = false;
+            self.selected_iso_row = 0;
+            self.skip_to_first_iso();
         }
     }
 
+    /// Rows currently shown in the ISO picker, after search, arch filter and folding
+    pub fn visible_iso_rows(&self) -> Vec<IsoRow> {
+        let mut rows = vec![IsoRow::CustomUrl, IsoRow::BrowseMirror];
+        rows.extend(catalog::visible_rows(
+            &self.isos,
+            &self.iso_filter,
+            &self.collapsed_groups,
+        ));
+        rows
+    }
+
     pub fn select_next_iso(&mut self) {
-        if !self.isos.is_empty() {
-            self.selected_iso_index = (self.selected_iso_index + 1) % self.isos.len();
+        let len = self.visible_iso_rows().len();
+        if len > 0 {
+            self.selected_iso_row = (self.selected_iso_row + 1) % len;
         }
     }
 
     pub fn select_previous_iso(&mut self) {
-        if !self.isos.is_empty() {
-            if self.selected_iso_index == 0 {
-                self.selected_iso_index = self.isos.len() - 1;
+        let len = self.visible_iso_rows().len();
+        if len > 0 {
+            if self.selected_iso_row == 0 {
+                self.selected_iso_row = len - 1;
             } else {
-                self.selected_iso_index -= 1;
+                self.selected_iso_row -= 1;
             }
         }
     }
 
+    /// Move the cursor off a group header onto the first entry, if there is one
+    fn skip_to_first_iso(&mut self) {
+        let rows = self.visible_iso_rows();
+        if let Some(pos) = rows
+            .iter()
+            .skip(self.selected_iso_row)
+            .position(|row| matches!(row, IsoRow::Item(_)))
+        {
+            self.selected_iso_row += pos;
+        }
+    }
+
+    /// Keep the cursor in range after the visible rows changed
+    fn clamp_iso_cursor(&mut self) {
+        let len = self.visible_iso_rows().len();
+        if self.selected_iso_row >= len {
+            self.selected_iso_row = len.saturating_sub(1);
+        }
+    }
+
     pub fn selected_iso(&self) -> Option<&Iso> {
-        self.isos.get(self.selected_iso_index)
+        match self.visible_iso_rows().get(self.selected_iso_row) {
+            Some(IsoRow::Item(i)) => self.isos.get(*i),
+            _ => None,
+        }
+    }
+
+    /// Name of the distro group the cursor is in (header or entry)
+    fn selected_iso_group(&self) -> Option<String> {
+        match self.visible_iso_rows().get(self.selected_iso_row)? {
+            IsoRow::Group { name, .. } => Some(name.clone()),
+            IsoRow::Item(i) => self.isos.get(*i).map(|iso| iso.name.clone()),
+            IsoRow::CustomUrl | IsoRow::BrowseMirror => None,
+        }
+    }
+
+    pub fn collapse_selected_group(&mut self) {
+        if let Some(group) = self.selected_iso_group() {
+            self.collapsed_groups.insert(group.clone());
+            // Put the cursor on the header of the group that just folded
+            if let Some(pos) = self
+                .visible_iso_rows()
+                .iter()
+                .position(|row| matches!(row, IsoRow::Group { name, .. } if *name == group))
+            {
+                self.selected_iso_row = pos;
+            }
+        }
+    }
+
+    pub fn expand_selected_group(&mut self) {
+        if let Some(group) = self.selected_iso_group() {
+            self.collapsed_groups.remove(&group);
+        }
+    }
+
+    /// Enter on a group header folds/unfolds it, on an entry starts the flash flow
+    pub fn activate_selected_iso_row(&mut self) {
+        match self.visible_iso_rows().get(self.selected_iso_row) {
+            Some(IsoRow::Group {
+                name, collapsed, ..
+            }) => {
+                if *collapsed {
+                    self.collapsed_groups.remove(name);
+                } else {
+                    self.collapsed_groups.insert(name.clone());
+                }
+                self.clamp_iso_cursor();
+            }
+            Some(IsoRow::Item(_)) => self.flash_selected_iso(),
+            Some(IsoRow::CustomUrl) => self.enter_custom_url(),
+            Some(IsoRow::BrowseMirror) => self.enter_mirror_url(),
+            None => {}
+        }
+    }
+
+    pub fn start_iso_search(&mut self) {
+        self.iso_search_active = true;
+    }
+
+    pub fn finish_iso_search(&mut self) {
+        self.iso_search_active = false;
+    }
+
+    pub fn clear_iso_search(&mut self) {
+        self.iso_filter.query.clear();
+        self.iso_search_active = false;
+        self.clamp_iso_cursor();
+    }
+
+    pub fn push_iso_search(&mut self, c: char) {
+        self.iso_filter.query.push(c);
+        self.selected_iso_row = 0;
+        self.skip_to_first_iso();
+    }
+
+    pub fn pop_iso_search(&mut self) {
+        self.iso_filter.query.pop();
+        self.clamp_iso_cursor();
+    }
+
+    /// Cycle the architecture filter: host arch, all, then the remaining arches
+    pub fn cycle_arch_filter(&mut self) {
+        let options = catalog::arch_options(&self.isos);
+        let current = options
+            .iter()
+            .position(|opt| *opt == self.iso_filter.arch)
+            .unwrap_or(0);
+        self.iso_filter.arch = options[(current + 1) % options.len()].clone();
+        self.selected_iso_row = 0;
+        self.skip_to_first_iso();
     }
 
     pub fn flash_selected_iso(&mut self) {
-        if let Some(device) = self.selected_device().cloned() {
-            self.state = AppState::ConfirmFlash(device.path);
-            self.input_buffer.clear();
+        if let Some(iso) = self.selected_iso().cloned() {
+            self.begin_preflight(iso, false);
         }
     }
 
-    pub fn start_flashing(&mut self) {
-        let device = match self.selected_device().cloned() {
-            Some(d) => d,
-            None => return,
+    pub fn enter_custom_url(&mut self) {
+        self.state = AppState::CustomUrlInput;
+        self.input_buffer.clear();
+        self.history_cursor = None;
+    }
+
+    /// Step back through previously used URLs (Up)
+    pub fn history_previous(&mut self) {
+        if self.url_history.entries.is_empty() {
+            return;
+        }
+        let next = match self.history_cursor {
+            None => 0,
+            Some(i) => (i + 1).min(self.url_history.entries.len() - 1),
         };
+        self.history_cursor = Some(next);
+        self.input_buffer = self.url_history.entries[next].clone();
+    }
 
-        // Verify confirmation
-        if self.input_buffer != device.path {
-            self.state = AppState::Error(format!(
-                "Confirmation mismatch. Expected '{}', got '{}'",
-                device.path, self.input_buffer
-            ));
+    /// Step forward towards an empty input (Down)
+    pub fn history_next(&mut self) {
+        match self.history_cursor {
+            Some(0) | None => {
+                self.history_cursor = None;
+                self.input_buffer.clear();
+            }
+            Some(i) => {
+                self.history_cursor = Some(i - 1);
+                self.input_buffer = self.url_history.entries[i - 1].clone();
+            }
+        }
+    }
+
+    pub fn submit_custom_url(&mut self) {
+        // Invalid input stays in the dialog, which shows the validation error
+        if let Ok(url) = flasher::validate_image_url(&self.input_buffer) {
+            self.begin_preflight(Iso::from_custom_url(url.as_str()), true);
+        }
+    }
+
+    pub fn enter_mirror_url(&mut self) {
+        self.state = AppState::MirrorUrlInput;
+        self.input_buffer.clear();
+    }
+
+    /// Open the browser at the entered base URL and fetch its listing
+    pub fn submit_mirror_url(&mut self) {
+        let mut url = self.input_buffer.trim().to_string();
+        let valid = reqwest::Url::parse(&url)
+            .is_ok_and(|u| matches!(u.scheme(), "http" | "https") && u.host_str().is_some());
+        if !valid {
             return;
         }
+        // Listings are directories; relative links only resolve with the trailing slash
+        if !url.ends_with('/') {
+            url.push('/');
+        }
 
-        let iso = match self.selected_iso().cloned() {
-            Some(i) => i,
-            None => return,
-        };
+        self.mirror = Some(MirrorTree::new(url.clone()));
+        self.state = AppState::MirrorBrowser;
+        self.input_buffer.clear();
+        self.load_mirror_listing(url);
+    }
 
-        self.state = AppState::InProgress(format!("Starting flash of {}...", iso.name));
+    fn load_mirror_listing(&mut self, url: String) {
+        if let Some(tree) = self.mirror.as_mut() {
+            tree.loading = Some(url.clone());
+            tree.error = None;
+        }
 
         let tx = self.operation_tx.clone();
-        let disk_manager = self.disk_manager.clone();
         let flasher = self.flasher.clone();
-        let path = device.path.clone();
-        let url = iso.url.clone();
 
         tokio::spawn(async move {
-            // 1. Unmount device first
-            let _ = tx.send(AppState::InProgress(format!("Unmounting {}...", path)));
-            if let Err(e) = disk_manager.unmount(&path).await {
-                let _ = tx.send(AppState::Error(format!("Failed to unmount: {}", e)));
-                return;
-            }
+            let result = mirror::fetch_listing(flasher.client(), &url)
+                .await
+                .map_err(|e| format!("{:#}", e));
+            let _ = tx.send(AppState::MirrorListingLoaded(Box::new(MirrorListing {
+                url,
+                result,
+            })));
+        });
+    }
 
-            // 2. Prepare for flashing
-            let _ = tx.send(AppState::InProgress(format!("Flashing {}...", iso.name)));
+    pub fn on_mirror_listing(&mut self, listing: MirrorListing) {
+        let Some(tree) = self.mirror.as_mut() else {
+            return;
+        };
+        tree.loading = None;
+        match listing.result {
+            Ok(entries) => tree.insert_listing(&listing.url, entries),
+            Err(e) => tree.error = Some(e),
+        }
+    }
 
-            // On macOS, use raw disk device for performance and correct access
-            #[cfg(target_os = "macos")]
-            let flash_path = path.replace("/dev/disk", "/dev/rdisk");
+    pub fn mirror_select_next(&mut self) {
+        if let Some(tree) = self.mirror.as_mut() {
+            tree.select_next();
+        }
+    }
 
-            #[cfg(not(target_os = "macos"))]
-            let flash_path = path.clone();
+    pub fn mirror_select_previous(&mut self) {
+        if let Some(tree) = self.mirror.as_mut() {
+            tree.select_previous();
+        }
+    }
 
-            // 3. Execute Flash
-            match flasher.flash(url, flash_path.clone(), tx.clone()).await {
-                Ok(()) => {
-                    // 4. Auto-eject on success
-                    let _ = tx.send(AppState::InProgress("Ejecting device...".to_string()));
-                    if let Err(e) = disk_manager.eject(&path).await {
-                        // Warning instead of error? For now, let's just warn but consider it success
-                        let _ = tx.send(AppState::Success(format!(
-                            "Flash complete, but eject failed: {}",
-                            e
-                        )));
-                    } else {
-                        let _ = tx.send(AppState::Success(
-                            "Flash complete! Device ejected safely.".to_string(),
-                        ));
-                    }
-                }
-                Err(e) => {
-                    let _ = tx.send(AppState::Error(format!("{:#}", e)));
-                }
+    /// Enter toggles directories and hands image files to the flash flow
+    pub fn mirror_activate(&mut self) {
+        let Some(tree) = self.mirror.as_mut() else {
+            return;
+        };
+        if tree.loading.is_some() {
+            return;
+        }
+        let Some(node) = tree.selected_node().cloned() else {
+            return;
+        };
+
+        if node.entry.is_dir {
+            if node.expanded {
+                tree.collapse(tree.selected);
+            } else {
+                self.load_mirror_listing(node.entry.url);
             }
-        });
+        } else if node.entry.is_image() {
+            let siblings = tree.siblings(tree.selected);
+            let mut iso = Iso::from_custom_url(&node.entry.url);
+            iso.size_bytes = node.entry.size_bytes;
+            iso.checksum_url =
+                mirror::sibling_checksum(&siblings, &node.entry).map(|c| c.url.clone());
+            self.begin_preflight(iso, true);
+        }
     }
 
-    pub fn enter_confirm_mode(&mut self) {
-        if let Some(device) = self.selected_device() {
-            self.state = AppState::ConfirmDestructive(device.path.clone());
-            self.input_buffer.clear();
+    /// Right unfolds a directory without ever starting a flash
+    pub fn mirror_expand(&mut self) {
+        let expandable = self
+            .mirror
+            .as_ref()
+            .and_then(|tree| tree.selected_node())
+            .is_some_and(|node| node.entry.is_dir && !node.expanded);
+        if expandable {
+            self.mirror_activate();
         }
     }
 
-    pub fn cancel(&mut self) {
-        self.state = AppState::Idle;
-        self.input_buffer.clear();
+    /// Left folds the current directory, or jumps to the parent directory
+    pub fn mirror_collapse(&mut self) {
+        let Some(tree) = self.mirror.as_mut() else {
+            return;
+        };
+        let Some(node) = tree.selected_node() else {
+            return;
+        };
+        if node.entry.is_dir && node.expanded {
+            tree.collapse(tree.selected);
+        } else if node.depth > 0 {
+            let depth = node.depth;
+            if let Some(parent) = tree.nodes[..tree.selected]
+                .iter()
+                .rposition(|n| n.depth < depth)
+            {
+                tree.selected = parent;
+            }
+        }
     }
 
-    pub fn unmount_selected(&mut self) {
-        if let Some(device) = self.selected_device().cloned() {
-            if device.is_protected {
-                self.state = AppState::Error("Cannot unmount protected system drive".to_string());
+    /// Ask which image to look inside, starting from the highlighted entry
+    pub fn enter_iso_contents_input(&mut self) {
+        self.input_buffer = self
+            .selected_iso()
+            .map(|iso| iso.url.clone())
+            .unwrap_or_default();
+        self.state = AppState::IsoContentsInput;
+    }
+
+    /// Open the contents browser on the entered URL or local path
+    pub fn submit_iso_contents(&mut self) {
+        let input = self.input_buffer.trim();
+        let image = if input.starts_with('/') || input.starts_with('~') {
+            let path = crate::utils::expand_home(input);
+            if !path.is_file() {
+                return;
+            }
+            path.to_string_lossy().into_owned()
+        } else {
+            match flasher::validate_image_url(input) {
+                Ok(url) => url.to_string(),
+                Err(_) => return,
+            }
+        };
+        let source = match self.flasher.source_for(&image) {
+            Ok(source) => source,
+            Err(e) => {
+                self.state = AppState::Error(format!("{:#}", e));
                 return;
             }
+        };
 
-            self.state = AppState::InProgress("Unmounting...".to_string());
+        self.iso_tree = Some(IsoTree::new(image));
+        self.state = AppState::IsoContents;
+        self.input_buffer.clear();
 
-            let tx = self.operation_tx.clone();
-            let disk_manager = self.disk_manager.clone();
-            let path = device.path.clone();
+        let tx = self.operation_tx.clone();
+        tokio::spawn(async move {
+            let result = isobrowser::read_contents(source)
+                .await
+                .map_err(|e| format!("{:#}", e));
+            let _ = tx.send(AppState::IsoContentsUpdate(Box::new(IsoUpdate::Loaded(
+                result,
+            ))));
+        });
+    }
 
-            tokio::spawn(async move {
-                match disk_manager.unmount(&path).await {
-                    Ok(()) => {
-                        let _ = tx.send(AppState::Success(format!("Unmounted {}", path)));
-                    }
-                    Err(e) => {
-                        let _ = tx.send(AppState::Error(e.to_string()));
-                    }
+    pub fn on_iso_update(&mut self, update: IsoUpdate) {
+        let Some(tree) = self.iso_tree.as_mut() else {
+            return;
+        };
+        match update {
+            IsoUpdate::Loaded(Ok((kind, entries))) => tree.load(kind, entries),
+            IsoUpdate::Loaded(Err(e)) => {
+                tree.loading = false;
+                tree.error = Some(e);
+            }
+            IsoUpdate::Extracted(result) => {
+                tree.extracting = None;
+                match result {
+                    Ok(dest) => tree.notice = Some(format!("Extracted to {}", dest)),
+                    Err(e) => tree.error = Some(e),
                 }
-            });
+            }
         }
     }
 
-    pub fn format_selected(&mut self) {
-        let device = match self.selected_device().cloned() {
-            Some(d) => d,
-            None => return,
+    pub fn close_iso_contents(&mut self) {
+        self.iso_tree = None;
+        self.enter_iso_selection();
+    }
+
+    pub fn iso_contents_select_next(&mut self) {
+        if let Some(tree) = self.iso_tree.as_mut() {
+            tree.select_next();
+        }
+    }
+
+    pub fn iso_contents_select_previous(&mut self) {
+        if let Some(tree) = self.iso_tree.as_mut() {
+            tree.select_previous();
+        }
+    }
+
+    /// Enter folds directories and offers to extract files
+    pub fn iso_contents_activate(&mut self) {
+        let Some(tree) = self.iso_tree.as_mut() else {
+            return;
+        };
+        let Some(node) = tree.selected_node() else {
+            return;
+        };
+        if !node.entry.is_dir {
+            self.start_iso_extract();
+        } else if node.expanded {
+            tree.collapse(tree.selected);
+        } else {
+            tree.expand(tree.selected);
+        }
+    }
+
+    pub fn iso_contents_expand(&mut self) {
+        if let Some(tree) = self.iso_tree.as_mut() {
+            tree.expand(tree.selected);
+        }
+    }
+
+    /// Left folds the current directory, or jumps to the parent directory
+    pub fn iso_contents_collapse(&mut self) {
+        let Some(tree) = self.iso_tree.as_mut() else {
+            return;
+        };
+        let Some(node) = tree.selected_node() else {
+            return;
         };
+        if node.entry.is_dir && node.expanded {
+            tree.collapse(tree.selected);
+        } else if let Some(parent) = tree.parent(tree.selected) {
+            tree.selected = parent;
+        }
+    }
 
-        if device.is_protected {
-            self.state = AppState::Error("Cannot format protected system drive".to_string());
+    /// Ask where to put the selected file
+    pub fn start_iso_extract(&mut self) {
+        let Some(tree) = self.iso_tree.as_mut() else {
+            return;
+        };
+        if tree.extracting.is_some() {
             return;
         }
+        if let Some(node) = tree.selected_node()
+            && !node.entry.is_dir
+        {
+            tree.extract_input = Some(isobrowser::default_destination(&node.entry));
+            tree.notice = None;
+            tree.error = None;
+        }
+    }
 
-        // Verify confirmation input matches device path
-        if self.input_buffer != device.path {
-            self.state = AppState::Error(format!(
-                "Confirmation mismatch. Expected '{}', got '{}'",
-                device.path, self.input_buffer
-            ));
+    pub fn cancel_iso_extract(&mut self) {
+        if let Some(tree) = self.iso_tree.as_mut() {
+            tree.extract_input = None;
+        }
+    }
+
+    pub fn iso_extract_input_mut(&mut self) -> Option<&mut String> {
+        self.iso_tree.as_mut()?.extract_input.as_mut()
+    }
+
+    /// Copy the selected file to the typed destination; a directory gets
+    /// the file under its own name
+    pub fn extract_selected_iso_file(&mut self) {
+        let Some(tree) = self.iso_tree.as_mut() else {
+            return;
+        };
+        let Some(input) = tree.extract_input.take() else {
+            return;
+        };
+        let Some(entry) = tree.selected_node().map(|node| node.entry.clone()) else {
             return;
+        };
+        let source = match self.flasher.source_for(&tree.image) {
+            Ok(source) => source,
+            Err(e) => {
+                tree.error = Some(format!("{:#}", e));
+                return;
+            }
+        };
+        let mut dest = crate::utils::expand_home(input.trim());
+        if dest.is_dir() {
+            dest.push(entry.name());
         }
+        tree.extracting = Some(entry.path.clone());
 
-        let fs_type = match self.selected_fs() {
-            Some(fs) => fs,
+        let tx = self.operation_tx.clone();
+        tokio::spawn(async move {
+            let result = isobrowser::extract(source, &entry, &dest)
+                .await
+                .map(|_| dest.display().to_string())
+                .map_err(|e| format!("{:#}", e));
+            let _ = tx.send(AppState::IsoContentsUpdate(Box::new(IsoUpdate::Extracted(
+                result,
+            ))));
+        });
+    }
+
+    /// Probe the image (size, checksum) before asking for confirmation
+    fn begin_preflight(&mut self, iso: Iso, is_custom: bool) {
+        let device = match self.selected_device().cloned() {
+            Some(d) => d,
             None => return,
         };
 
-        self.state = AppState::InProgress(format!(
-            "Formatting {} as {}...",
-            device.path,
-            fs_type.display_name()
-        ));
+        self.state = AppState::InProgress(format!("Checking {}...", iso.name));
 
+        // Images for a multi-boot stick become files, so only free space counts
+        let (capacity, target) = match &self.multiboot {
+            Some(stick) => (stick.free_bytes, "the stick".to_string()),
+            None => (device.size_bytes, device.path.clone()),
+        };
+        let is_multiboot = self.multiboot.is_some();
         let tx = self.operation_tx.clone();
-        let disk_manager = self.disk_manager.clone();
-        let path = device.path.clone();
-        let display_name = fs_type.display_name();
+        let flasher = self.flasher.clone();
+        let source = match flasher.source_for(&iso.url) {
+            Ok(source) => source,
+            Err(e) => {
+                self.state = AppState::Error(format!("{:#}", e));
+                return;
+            }
+        };
 
         tokio::spawn(async move {
-            match disk_manager.format(&path, fs_type, "UNTITLED").await {
-                Ok(()) => {
-                    let _ = tx.send(AppState::Success(format!(
-                        "Formatted {} as {}",
-                        path, display_name
+            match flasher.preflight(&iso).await {
+                Ok(preflight) if preflight.total_bytes > capacity => {
+                    let _ = tx.send(AppState::Error(format!(
+                        "Image is {} but {} only holds {}",
+                        bytes_to_human(preflight.total_bytes),
+                        target,
+                        bytes_to_human(capacity)
                     )));
                 }
-                Err(e) => {
+                Ok(preflight) => {
+                    // Not being able to look inside just means it is flashed as is
+                    let inspection = if is_multiboot {
+                        None
+                    } else {
+                        inspect::inspect_source(source.clone(), preflight.total_bytes)
+                            .await
+                            .ok()
+                    };
+                    let windows = match &inspection {
+                        _ if is_multiboot => None,
+                        Some(found) if found.format != ImageFormat::WindowsIso => None,
+                        _ => windows::detect(source, preflight.total_bytes)
+                            .await
+                            .ok()
+                            .flatten(),
+                    };
+                    if let Some(reason) = windows.as_ref().and_then(|m| m.unwritable()) {
+                        let _ = tx.send(AppState::Error(format!(
+                            "This Windows installer can't be written: {}",
+                            reason
+                        )));
+                        return;
+                    }
+                    let grow = iso.grow_to_fill;
+                    let _ = tx.send(AppState::PreflightDone(Box::new(FlashJob {
+                        iso,
+                        preflight,
+                        is_custom,
+                        windows,
+                        seed: None,
+                        injections: Vec::new(),
+                        grow,
+                        inspection,
+                        already_on_device: false,
+                        delta: false,
+                    })));
+                }
+                Err(e) => {
+                    let _ = tx.send(AppState::Error(format!("Pre-flight failed: {:#}", e)));
+                }
+            }
+        });
+    }
+
+    /// Pre-flight finished, ask the user to type the device path. Adding
+    /// to a multi-boot stick erases nothing, so it starts right away.
+    pub fn confirm_flash(&mut self, mut job: FlashJob) {
+        self.preflights
+            .insert(job.iso.url.clone(), job.preflight.clone());
+        if self.multiboot.is_some() {
+            self.add_to_multiboot(job);
+            return;
+        }
+        if let Some(device) = self.selected_device().cloned() {
+            let found = self
+                .identified
+                .as_ref()
+                .filter(|found| found.device_path == device.path);
+            job.already_on_device = found.is_some_and(
+                |found| matches!(&found.identity, Identity::Exact(iso) if iso.url == job.iso.url),
+            );
+            // Another build of the same distro shares most of its blocks, but a
+            // compressed image's length is only known once it's unpacked
+            job.delta = job.windows.is_none()
+                && job.compression() == Compression::None
+                && found.is_some_and(|found| {
+                    matches!(
+                        found.identity,
+                        Identity::Exact(_) | Identity::SameDistro { .. }
+                    )
+                });
+            self.flash_job = Some(job);
+            self.state = AppState::ConfirmFlash(device.path);
+            self.input_buffer.clear();
+        }
+    }
+
+    pub fn start_flashing(&mut self) {
+        let device = match self.selected_device().cloned() {
+            Some(d) => d,
+            None => return,
+        };
+
+        // Verify confirmation
+        if self.input_buffer != device.path {
+            self.state = AppState::Error(format!(
+                "Confirmation mismatch. Expected '{}', got '{}'",
+                device.path, self.input_buffer
+            ));
+            return;
+        }
+
+        let job = match self.flash_job.take() {
+            Some(job) => job,
+            None => return,
+        };
+        let compression = job.compression();
+        let iso = job.iso;
+
+        if job.is_custom {
+            self.url_history.push(&iso.url);
+            // History is a convenience; a read-only config dir must not block the flash
+            let _ = self.url_history.save();
+        }
+
+        self.state = AppState::InProgress(format!("Starting flash of {}...", iso.name));
+
+        let tx = self.operation_tx.clone();
+        let disk_manager = self.disk_manager.clone();
+        let flasher = self.flasher.clone();
+        let path = device.path.clone();
+        let source = match self.flasher.source_for(&iso.url) {
+            Ok(source) => source,
+            Err(e) => {
+                self.state = AppState::Error(format!("{:#}", e));
+                return;
+            }
+        };
+        let expected_sha256 = job.preflight.sha256;
+        let total_bytes = job.preflight.total_bytes;
+        let seed = job.seed;
+        let injections = job.injections;
+        // A seed partition takes the space the last partition would grow into
+        let grow = job.grow && seed.is_none();
+        let delta = job.delta && job.windows.is_none();
+        let windows_media = job.windows;
+        let unpacked = windows_media.is_some();
+
+        tokio::spawn(async move {
+            // 1. Unmount device first
+            let _ = tx.send(AppState::InProgress(format!("Unmounting {}...", path)));
+            if let Err(e) = disk_manager.unmount(&path).await {
+                let _ = tx.send(AppState::Error(format!("Failed to unmount: {}", e)));
+                return;
+            }
+
+            // On macOS, use raw disk device for performance and correct access
+            #[cfg(target_os = "macos")]
+            let flash_path = path.replace("/dev/disk", "/dev/rdisk");
+
+            #[cfg(not(target_os = "macos"))]
+            let flash_path = path.clone();
+
+            // 2. Clear signatures the image won't overwrite, like a backup GPT at the end.
+            // A delta write compares the image's range itself, so only what lies past it goes.
+            let wipe_from = if delta { total_bytes } else { 0 };
+            let wiped = match signatures::wipe_signatures_from(&flash_path, wipe_from) {
+                Ok(wiped) => wiped,
+                Err(e) => {
+                    let _ = tx.send(AppState::Error(format!(
+                        "Failed to clear old signatures: {:#}",
+                        e
+                    )));
+                    return;
+                }
+            };
+
+            let _ = tx.send(AppState::InProgress(format!("Flashing {}...", iso.name)));
+
+            // 3. Execute Flash; Windows installers are unpacked onto a new filesystem
+            // and compressed images are decompressed, so they end past `total_bytes`
+            let mut image_bytes = total_bytes;
+            let result = match windows_media {
+                // Scattered small writes go through the buffered device on macOS
+                Some(media) => windows::write_media(
+                    &flasher,
+                    source,
+                    expected_sha256,
+                    media,
+                    &path,
+                    tx.clone(),
+                )
+                .await
+                .map(|summary| {
+                    let mut detail = format!("\nCopied {} Windows Setup files", summary.files);
+                    if summary.split_parts > 0 {
+                        detail.push_str(&format!(
+                            ", install.wim split into {} parts",
+                            summary.split_parts
+                        ));
+                    }
+                    detail
+                }),
+                None if delta => flasher
+                    .flash_delta(source, expected_sha256, flash_path.clone(), tx.clone())
+                    .await
+                    .map(|stats| format!("\n{}", stats.describe())),
+                None => flasher
+                    .flash(
+                        source,
+                        expected_sha256,
+                        compression,
+                        flash_path.clone(),
+                        tx.clone(),
+                    )
+                    .await
+                    .map(|flashed| {
+                        image_bytes = flashed.image_bytes;
+                        String::new()
+                    }),
+            };
+            match result {
+                Ok(detail) => {
+                    let mut flashed = format!("{}{}", detail, signatures::describe(&wiped));
+                    // 4. Put the backup GPT at the device's end, growing into the space first
+                    if !unpacked {
+                        if grow {
+                            let _ = tx.send(AppState::InProgress(
+                                "Growing the last partition...".to_string(),
+                            ));
+                        }
+                        match repair_table(disk_manager.as_ref(), &path, grow).await {
+                            Ok(notes) => flashed.push_str(&notes),
+                            Err(e) => {
+                                let _ = tx.send(AppState::Error(format!(
+                                    "Flashed, but repairing the partition table failed: {}",
+                                    e
+                                )));
+                                return;
+                            }
+                        }
+                    }
+
+                    // 5. Seed for cloud-init, in a partition of its own after the image
+                    if let Some(seed) = seed {
+                        let _ = tx.send(AppState::InProgress(
+                            "Writing cloud-init seed...".to_string(),
+                        ));
+                        let seeded = add_seed_partition(
+                            disk_manager.as_ref(),
+                            &path,
+                            &flash_path,
+                            image_bytes,
+                            &seed,
+                        )
+                        .await;
+                        match seeded {
+                            Ok(files) => flashed.push_str(&format!(
+                                "\nWrote {} to a {} partition",
+                                files,
+                                cloudinit::LABEL
+                            )),
+                            Err(e) => {
+                                let _ = tx.send(AppState::Error(format!(
+                                    "Flashed, but the cloud-init seed failed: {}",
+                                    e
+                                )));
+                                return;
+                            }
+                        }
+                    }
+
+                    // 6. Files carried by the job
+                    if !injections.is_empty() {
+                        let _ = tx.send(AppState::InProgress(
+                            "Writing files into the image...".to_string(),
+                        ));
+                        if let Err(e) =
+                            inject_files(disk_manager.as_ref(), &path, &injections).await
+                        {
+                            let _ = tx.send(AppState::Error(format!(
+                                "Flashed, but injecting files failed: {}",
+                                e
+                            )));
+                            return;
+                        }
+                        flashed.push_str(&format!(
+                            "\nInjected and verified {} file{}",
+                            injections.len(),
+                            if injections.len() == 1 { "" } else { "s" }
+                        ));
+                    }
+
+                    // 7. Raspberry Pi OS gets its headless setup written to the boot partition
+                    if !unpacked {
+                        let device_path = path.clone();
+                        let boot =
+                            tokio::task::spawn_blocking(move || rpi::detect(&device_path)).await;
+                        if let Ok(Ok(Some(mut boot))) = boot {
+                            boot.flashed = flashed;
+                            let _ = tx.send(AppState::PiSetup(Box::new(boot)));
+                            return;
+                        }
+                    }
+
+                    // 8. Live images can keep changes in the leftover space; ext4 needs Linux
+                    if cfg!(target_os = "linux") && !unpacked {
+                        let device_path = flash_path.clone();
+                        let offer = tokio::task::spawn_blocking(move || {
+                            persistence::detect(&device_path, image_bytes)
+                        })
+                        .await;
+                        if let Ok(Ok(Some(mut offer))) = offer {
+                            offer.device_path = path;
+                            offer.flashed = flashed;
+                            let _ = tx.send(AppState::PersistenceOffer(Box::new(offer)));
+                            return;
+                        }
+                    }
+                    // 9. Auto-eject on success
+                    eject_flashed(disk_manager.as_ref(), &path, &flashed, &tx).await;
+                }
+                Err(e) => {
+                    let _ = tx.send(AppState::Error(format!("{:#}", e)));
+                }
+            }
+        });
+    }
+
+    /// Attach a cloud-init seed to the image waiting for confirmation, or
+    /// edit the one already attached
+    pub fn open_seed_editor(&mut self) {
+        let Some(job) = self.flash_job.as_mut() else {
+            return;
+        };
+        // Windows media gets a filesystem of its own, with no room for a seed
+        if job.windows.is_some() {
+            return;
+        }
+        self.seed_templates = cloudinit::templates();
+        if job.seed.is_none() {
+            self.seed_template_index = 0;
+            job.seed = self.seed_templates.first().map(|t| t.seed.clone());
+        }
+        self.seed_file = SeedFile::UserData;
+        self.state = AppState::SeedEditor;
+    }
+
+    /// Replace the seed with the next template
+    pub fn next_seed_template(&mut self) {
+        if self.seed_templates.is_empty() {
+            return;
+        }
+        self.seed_template_index = (self.seed_template_index + 1) % self.seed_templates.len();
+        if let Some(job) = self.flash_job.as_mut() {
+            job.seed = Some(self.seed_templates[self.seed_template_index].seed.clone());
+        }
+    }
+
+    pub fn next_seed_file(&mut self) {
+        let all = SeedFile::ALL;
+        let current = all.iter().position(|f| *f == self.seed_file).unwrap_or(0);
+        self.seed_file = all[(current + 1) % all.len()];
+    }
+
+    /// The seed file being edited
+    pub fn seed_text_mut(&mut self) -> Option<&mut String> {
+        let file = self.seed_file;
+        self.flash_job
+            .as_mut()
+            .and_then(|job| job.seed.as_mut())
+            .map(|seed| seed.get_mut(file))
+    }
+
+    /// Back to the confirmation, keeping the seed if cloud-init would take it
+    pub fn close_seed_editor(&mut self) {
+        let valid = self
+            .flash_job
+            .as_ref()
+            .and_then(|job| job.seed.as_ref())
+            .is_none_or(|seed| seed.validate().is_ok());
+        if valid && let Some(device) = self.selected_device() {
+            self.state = AppState::ConfirmFlash(device.path.clone());
+        }
+    }
+
+    /// Flash without a seed after all
+    pub fn detach_seed(&mut self) {
+        if let Some(job) = self.flash_job.as_mut() {
+            job.seed = None;
+        }
+        self.close_seed_editor();
+    }
+
+    /// Turn the space after a live image into a persistence partition
+    pub fn accept_persistence(&mut self) {
+        let AppState::PersistenceOffer(offer) = &self.state else {
+            return;
+        };
+        let offer = offer.as_ref().clone();
+        self.state = AppState::InProgress("Adding persistence partition...".to_string());
+
+        let tx = self.operation_tx.clone();
+        let disk_manager = self.disk_manager.clone();
+
+        tokio::spawn(async move {
+            let added = {
+                let offer = offer.clone();
+                tokio::task::spawn_blocking(move || persistence::add_partition(&offer)).await
+            };
+            let number = match added {
+                Ok(Ok(number)) => number,
+                Ok(Err(e)) => {
+                    let _ = tx.send(AppState::Error(format!("{:#}", e)));
+                    return;
+                }
+                Err(e) => {
+                    let _ = tx.send(AppState::Error(format!("Partition task failed: {}", e)));
+                    return;
+                }
+            };
+            if let Err(e) = disk_manager.reread_partitions(&offer.device_path).await {
+                let _ = tx.send(AppState::Error(format!(
+                    "Failed to reload the partition table: {}",
+                    e
+                )));
+                return;
+            }
+
+            let label = offer.flavor.label();
+            let partition_path = disk_manager.partition_path(&offer.device_path, number);
+            let _ = tx.send(AppState::InProgress(format!(
+                "Formatting {} as ext4...",
+                partition_path
+            )));
+            if let Err(e) = disk_manager
+                .format(&partition_path, FileSystemType::Ext4, label)
+                .await
+            {
+                let _ = tx.send(AppState::Error(e.to_string()));
+                return;
+            }
+            if offer.flavor == LiveFlavor::LiveBoot {
+                let result = match disk_manager.mount(&partition_path).await {
+                    Ok(mount_point) => {
+                        persistence::write_conf(offer.flavor, std::path::Path::new(&mount_point))
+                            .map_err(|e| format!("{:#}", e))
+                    }
+                    Err(e) => Err(format!("Failed to mount: {}", e)),
+                };
+                let _ = disk_manager.unmount(&partition_path).await;
+                if let Err(e) = result {
+                    let _ = tx.send(AppState::Error(e));
+                    return;
+                }
+            }
+
+            let flashed = format!(
+                "{}\nAdded a {} persistence partition labelled {}. Boot with `{}` on the kernel command line to use it.",
+                offer.flashed,
+                bytes_to_human(offer.size),
+                label,
+                offer.flavor.boot_option()
+            );
+            eject_flashed(disk_manager.as_ref(), &offer.device_path, &flashed, &tx).await;
+        });
+    }
+
+    /// Leave the space after the image unused and finish the flash
+    pub fn decline_persistence(&mut self) {
+        let AppState::PersistenceOffer(offer) = &self.state else {
+            return;
+        };
+        let offer = offer.as_ref().clone();
+        self.state = AppState::InProgress("Ejecting device...".to_string());
+
+        let tx = self.operation_tx.clone();
+        let disk_manager = self.disk_manager.clone();
+
+        tokio::spawn(async move {
+            eject_flashed(
+                disk_manager.as_ref(),
+                &offer.device_path,
+                &offer.flashed,
+                &tx,
+            )
+            .await;
+        });
+    }
+
+    /// Flip whether the image's last partition grows to fill the device
+    pub fn toggle_grow(&mut self) {
+        if let Some(job) = self.flash_job.as_mut()
+            && job.windows.is_none()
+        {
+            job.grow = !job.grow;
+        }
+    }
+
+    /// Flip whether only the blocks that differ from the drive get written
+    pub fn toggle_delta(&mut self) {
+        if let Some(job) = self.flash_job.as_mut()
+            && job.windows.is_none()
+        {
+            job.delta = !job.delta;
+        }
+    }
+
+    /// List the files going into the image waiting for confirmation
+    pub fn open_injection_editor(&mut self) {
+        if self.flash_job.is_none() {
+            return;
+        }
+        self.injection_input.clear();
+        self.injection_selected = 0;
+        self.injection_error = None;
+        self.state = AppState::InjectionEditor;
+    }
+
+    /// Add the typed `<file> -> <partition>:<path>` to the job
+    pub fn add_injection(&mut self) {
+        let Some(job) = self.flash_job.as_mut() else {
+            return;
+        };
+        match Injection::parse(&self.injection_input) {
+            Ok(injection) => {
+                job.injections.push(injection);
+                self.injection_selected = job.injections.len() - 1;
+                self.injection_input.clear();
+                self.injection_error = None;
+            }
+            Err(e) => self.injection_error = Some(e),
+        }
+    }
+
+    pub fn remove_selected_injection(&mut self) {
+        let Some(job) = self.flash_job.as_mut() else {
+            return;
+        };
+        if self.injection_selected < job.injections.len() {
+            job.injections.remove(self.injection_selected);
+            self.injection_selected = self
+                .injection_selected
+                .min(job.injections.len().saturating_sub(1));
+        }
+    }
+
+    pub fn injection_select_next(&mut self) {
+        let count = self
+            .flash_job
+            .as_ref()
+            .map_or(0, |job| job.injections.len());
+        if count > 0 {
+            self.injection_selected = (self.injection_selected + 1) % count;
+        }
+    }
+
+    pub fn injection_select_previous(&mut self) {
+        let count = self
+            .flash_job
+            .as_ref()
+            .map_or(0, |job| job.injections.len());
+        if count > 0 {
+            self.injection_selected = (self.injection_selected + count - 1) % count;
+        }
+    }
+
+    /// Back to the confirmation
+    pub fn close_injection_editor(&mut self) {
+        if let Some(device) = self.selected_device() {
+            self.state = AppState::ConfirmFlash(device.path.clone());
+        }
+    }
+
+    /// Show the headless setup form for a flashed Raspberry Pi OS image,
+    /// filled from the most recent preset
+    pub fn open_pi_setup(&mut self, boot: PiBoot) {
+        self.pi_presets = PiPresets::load();
+        self.pi_preset_index = 0;
+        self.pi_settings = self
+            .pi_presets
+            .presets
+            .first()
+            .cloned()
+            .unwrap_or_else(|| PiSettings {
+                hostname: "raspberrypi".to_string(),
+                ssh: true,
+                ..Default::default()
+            });
+        self.pi_field = 1;
+        self.pi_notice = None;
+        self.state = AppState::PiSetup(Box::new(boot));
+    }
+
+    pub fn pi_next_field(&mut self) {
+        self.pi_field = (self.pi_field + 1) % PiField::ALL.len();
+    }
+
+    pub fn pi_previous_field(&mut self) {
+        self.pi_field = (self.pi_field + PiField::ALL.len() - 1) % PiField::ALL.len();
+    }
+
+    /// Type into the selected field; space flips the SSH switch
+    pub fn pi_push(&mut self, c: char) {
+        let field = PiField::ALL[self.pi_field];
+        self.pi_notice = None;
+        match self.pi_settings.text_mut(field) {
+            Some(text) => text.push(c),
+            None if c == ' ' => self.pi_settings.ssh = !self.pi_settings.ssh,
+            None => {}
+        }
+    }
+
+    /// Delete from the selected field. Emptying a password field forgets
+    /// the saved hash too.
+    pub fn pi_pop(&mut self) {
+        self.pi_notice = None;
+        let settings = &mut self.pi_settings;
+        match PiField::ALL[self.pi_field] {
+            PiField::Password if settings.password.is_empty() => settings.password_hash.clear(),
+            PiField::WifiPassword if settings.wifi_password.is_empty() => settings.wifi_psk.clear(),
+            field => {
+                if let Some(text) = settings.text_mut(field) {
+                    text.pop();
+                }
+            }
+        }
+    }
+
+    /// Fill the form from the next saved preset
+    pub fn pi_next_preset(&mut self) {
+        let presets = &self.pi_presets.presets;
+        if presets.is_empty() {
+            self.pi_notice = Some("No saved presets yet".to_string());
+            return;
+        }
+        self.pi_preset_index = (self.pi_preset_index + 1) % presets.len();
+        self.pi_settings = presets[self.pi_preset_index].clone();
+        self.pi_notice = None;
+    }
+
+    /// Save the form as a preset under its preset name
+    pub fn save_pi_preset(&mut self) {
+        if self.pi_settings.name.trim().is_empty() {
+            self.pi_notice = Some("Name the preset first".to_string());
+            return;
+        }
+        if let Err(e) = self.pi_settings.validate() {
+            self.pi_notice = Some(e);
+            return;
+        }
+        if let Err(e) = self.pi_settings.seal() {
+            self.pi_notice = Some(format!("{:#}", e));
+            return;
+        }
+        self.pi_presets.push(&self.pi_settings);
+        self.pi_preset_index = 0;
+        self.pi_notice = Some(match self.pi_presets.save() {
+            Ok(()) => format!("Saved preset {}", self.pi_settings.name),
+            Err(e) => format!("Failed to save presets: {}", e),
+        });
+    }
+
+    /// Write the form onto the boot partition, then eject
+    pub fn apply_pi_setup(&mut self) {
+        let AppState::PiSetup(boot) = &self.state else {
+            return;
+        };
+        let boot = boot.as_ref().clone();
+        if let Err(e) = self.pi_settings.validate() {
+            self.pi_notice = Some(e);
+            return;
+        }
+        if let Err(e) = self.pi_settings.seal() {
+            self.pi_notice = Some(format!("{:#}", e));
+            return;
+        }
+        let settings = self.pi_settings.clone();
+        self.state = AppState::InProgress("Writing Raspberry Pi setup...".to_string());
+
+        let tx = self.operation_tx.clone();
+        let disk_manager = self.disk_manager.clone();
+
+        tokio::spawn(async move {
+            // The desktop may have mounted the boot partition after the flash
+            let partition_path =
+                disk_manager.partition_path(&boot.device_path, boot.partition.number);
+            let _ = disk_manager.unmount(&partition_path).await;
+
+            let applied = {
+                let boot = boot.clone();
+                tokio::task::spawn_blocking(move || rpi::apply(&boot, &settings)).await
+            };
+            let written = match applied {
+                Ok(Ok(written)) => written,
+                Ok(Err(e)) => {
+                    let _ = tx.send(AppState::Error(format!(
+                        "Flashed, but the Raspberry Pi setup failed: {:#}",
+                        e
+                    )));
+                    return;
+                }
+                Err(e) => {
+                    let _ = tx.send(AppState::Error(format!("Setup task failed: {}", e)));
+                    return;
+                }
+            };
+
+            let mut flashed = boot.flashed.clone();
+            if !written.is_empty() {
+                flashed.push_str(&format!(
+                    "\nWrote {} to the boot partition",
+                    written.join(", ")
+                ));
+            }
+            eject_flashed(disk_manager.as_ref(), &boot.device_path, &flashed, &tx).await;
+        });
+    }
+
+    /// Leave the image as flashed
+    pub fn skip_pi_setup(&mut self) {
+        let AppState::PiSetup(boot) = &self.state else {
+            return;
+        };
+        let boot = boot.as_ref().clone();
+        self.state = AppState::InProgress("Ejecting device...".to_string());
+
+        let tx = self.operation_tx.clone();
+        let disk_manager = self.disk_manager.clone();
+
+        tokio::spawn(async move {
+            eject_flashed(disk_manager.as_ref(), &boot.device_path, &boot.flashed, &tx).await;
+        });
+    }
+
+    /// Use the selected device as a clone source and pick the target next
+    pub fn enter_clone_target_selection(&mut self) {
+        let Some(device) = self.selected_device().cloned() else {
+            return;
+        };
+        if device.is_protected {
+            self.state = AppState::Error("Cannot clone protected (system) device!".to_string());
+            return;
+        }
+        self.clone_source = Some(device);
+        self.state = AppState::CloneTargetSelection;
+    }
+
+    /// Check the highlighted target and ask the user to type its path
+    pub fn pick_clone_target(&mut self) {
+        let (Some(source), Some(target)) = (self.clone_source.clone(), self.selected_device())
+        else {
+            return;
+        };
+        let error = if target.path == source.path {
+            Some("Pick a different device to clone onto".to_string())
+        } else if target.is_protected {
+            Some("Cannot flash protected (system) device!".to_string())
+        } else if target.size_bytes < source.size_bytes {
+            Some(format!(
+                "{} is {} but {} only holds {}",
+                source.path,
+                bytes_to_human(source.size_bytes),
+                target.path,
+                bytes_to_human(target.size_bytes)
+            ))
+        } else {
+            None
+        };
+        match error {
+            Some(error) => {
+                self.clone_source = None;
+                self.state = AppState::Error(error);
+            }
+            None => {
+                self.state = AppState::ConfirmClone(target.path.clone());
+                self.input_buffer.clear();
+            }
+        }
+    }
+
+    pub fn start_clone(&mut self) {
+        let (Some(source), Some(target)) = (self.clone_source.clone(), self.selected_device())
+        else {
+            return;
+        };
+        let target = target.clone();
+
+        if self.input_buffer != target.path {
+            self.state = AppState::Error(format!(
+                "Confirmation mismatch. Expected '{}', got '{}'",
+                target.path, self.input_buffer
+            ));
+            return;
+        }
+        self.input_buffer.clear();
+        self.clone_source = None;
+        self.state = AppState::InProgress(format!("Starting clone of {}...", source.path));
+
+        let tx = self.operation_tx.clone();
+        let disk_manager = self.disk_manager.clone();
+        let flasher = self.flasher.clone();
+
+        tokio::spawn(async move {
+            // Neither side may change while blocks are copied
+            for path in [&source.path, &target.path] {
+                let _ = tx.send(AppState::InProgress(format!("Unmounting {}...", path)));
+                if let Err(e) = disk_manager.unmount(path).await {
+                    let _ = tx.send(AppState::Error(format!("Failed to unmount: {}", e)));
+                    return;
+                }
+            }
+
+            #[cfg(target_os = "macos")]
+            let (source_path, target_path) = (
+                source.path.replace("/dev/disk", "/dev/rdisk"),
+                target.path.replace("/dev/disk", "/dev/rdisk"),
+            );
+            #[cfg(not(target_os = "macos"))]
+            let (source_path, target_path) = (source.path.clone(), target.path.clone());
+
+            let image = Arc::new(DeviceSource::new(source_path, source.size_bytes));
+            let total_bytes = match image.probe().await {
+                Ok(info) => info.total_bytes,
+                Err(e) => {
+                    let _ = tx.send(AppState::Error(format!("{:#}", e)));
+                    return;
+                }
+            };
+            let _ = tx.send(AppState::InProgress(format!("Cloning {}...", source.path)));
+            let sha256 = match flasher
+                .flash(
+                    image,
+                    None,
+                    Compression::None,
+                    target_path.clone(),
+                    tx.clone(),
+                )
+                .await
+            {
+                Ok(flashed) => flashed.sha256,
+                Err(e) => {
+                    let _ = tx.send(AppState::Error(format!("{:#}", e)));
+                    return;
+                }
+            };
+
+            let progress_tx = tx.clone();
+            let result = tokio::task::spawn_blocking(move || {
+                flasher::verify_device(&target_path, total_bytes, &sha256, &progress_tx)?;
+                let relocated = partition::relocate_gpt_backup(&target_path)?;
+                anyhow::Ok((sha256, relocated))
+            })
+            .await;
+            let (sha256, relocated) = match result {
+                Ok(Ok(done)) => done,
+                Ok(Err(e)) => {
+                    let _ = tx.send(AppState::Error(format!("{:#}", e)));
+                    return;
+                }
+                Err(e) => {
+                    let _ = tx.send(AppState::Error(format!("Clone task failed: {}", e)));
+                    return;
+                }
+            };
+
+            let _ = tx.send(AppState::InProgress("Ejecting device...".to_string()));
+            let eject = match disk_manager.eject(&target.path).await {
+                Ok(()) => "Device ejected safely.".to_string(),
+                Err(e) => format!("Eject failed: {}", e),
+            };
+            let _ = tx.send(AppState::Success(format!(
+                "Cloned {} onto {} and verified it.\nSHA-256: {}{}\n{}",
+                source.path,
+                target.path,
+                sha256,
+                if relocated {
+                    "\nMoved the backup GPT to the end of the larger disk."
+                } else {
+                    ""
+                },
+                eject
+            )));
+        });
+    }
+
+    /// Work out which catalog image the selected drive holds, if any
+    pub fn identify_selected(&mut self) {
+        let Some(device) = self.selected_device().cloned() else {
+            return;
+        };
+        self.state = AppState::InProgress(format!("Reading {}...", device.path));
+
+        let tx = self.operation_tx.clone();
+        let flasher = self.flasher.clone();
+        let catalog = self.isos.clone();
+
+        tokio::spawn(async move {
+            let result = identify_device(&flasher, &catalog, &device, &tx).await;
+            match result {
+                Ok(identity) => {
+                    let _ = tx.send(AppState::Identified(Box::new(Identification {
+                        device_path: device.path,
+                        identity,
+                    })));
+                }
+                Err(e) => {
+                    let _ = tx.send(AppState::Error(format!("{:#}", e)));
+                }
+            }
+        });
+    }
+
+    pub fn on_identified(&mut self, identification: Identification) {
+        self.state = AppState::Success(identification.describe());
+        self.identified = Some(identification);
+    }
+
+    /// Ask where to save an image of the selected device
+    pub fn enter_backup_setup(&mut self) {
+        let Some(device) = self.selected_device() else {
+            return;
+        };
+        if device.is_protected {
+            self.state = AppState::Error("Cannot back up protected (system) device!".to_string());
+            return;
+        }
+        let name = device.path.rsplit('/').next().unwrap_or("disk");
+        let file_name = format!("{}-backup.img{}", name, self.backup_compression.extension());
+        self.input_buffer = std::env::current_dir()
+            .map(|dir| dir.join(&file_name))
+            .unwrap_or_else(|_| PathBuf::from(&file_name))
+            .to_string_lossy()
+            .into_owned();
+        self.state = AppState::BackupSetup;
+    }
+
+    /// Switch compression, keeping the output file's extension in step
+    pub fn cycle_backup_compression(&mut self, forward: bool) {
+        let all = Compression::ALL;
+        let current = all
+            .iter()
+            .position(|c| *c == self.backup_compression)
+            .unwrap_or(0);
+        let next = if forward {
+            all[(current + 1) % all.len()]
+        } else {
+            all[(current + all.len() - 1) % all.len()]
+        };
+        let old_extension = self.backup_compression.extension();
+        if let Some(stem) = self.input_buffer.strip_suffix(old_extension) {
+            self.input_buffer = format!("{}{}", stem, next.extension());
+        }
+        self.backup_compression = next;
+    }
+
+    pub fn move_backup_option(&mut self, down: bool) {
+        let all = BackupOption::ALL;
+        let current = all
+            .iter()
+            .position(|o| *o == self.backup_option)
+            .unwrap_or(0);
+        self.backup_option = if down {
+            all[(current + 1).min(all.len() - 1)]
+        } else {
+            all[current.saturating_sub(1)]
+        };
+    }
+
+    pub fn toggle_backup_option(&mut self) {
+        match self.backup_option {
+            BackupOption::SkipUnused => self.backup_skip_unused = !self.backup_skip_unused,
+            BackupOption::Shrink => self.backup_shrink = !self.backup_shrink,
+            // Expanding only makes sense for a shrunk image
+            BackupOption::AutoExpand => {
+                self.backup_auto_expand = !self.backup_auto_expand;
+                self.backup_shrink |= self.backup_auto_expand;
+            }
+        }
+    }
+
+    pub fn start_backup(&mut self) {
+        let Some(device) = self.selected_device().cloned() else {
+            return;
+        };
+        let output = self.input_buffer.trim();
+        if output.is_empty() {
+            return;
+        }
+
+        let job = BackupJob {
+            device_path: device.path.clone(),
+            device_size: device.size_bytes,
+            output: PathBuf::from(output),
+            compression: self.backup_compression,
+            skip_unused: self.backup_skip_unused,
+            shrink: self.backup_shrink,
+            auto_expand: self.backup_shrink && self.backup_auto_expand,
+        };
+        self.input_buffer.clear();
+        self.state = AppState::InProgress(format!("Unmounting {}...", device.path));
+
+        let tx = self.operation_tx.clone();
+        let disk_manager = self.disk_manager.clone();
+
+        tokio::spawn(async move {
+            // A mounted filesystem keeps changing under the reader
+            if let Err(e) = disk_manager.unmount(&job.device_path).await {
+                let _ = tx.send(AppState::Error(format!("Failed to unmount: {}", e)));
+                return;
+            }
+
+            // Same raw device trick as flashing
+            #[cfg(target_os = "macos")]
+            let job = BackupJob {
+                device_path: job.device_path.replace("/dev/disk", "/dev/rdisk"),
+                ..job
+            };
+
+            let progress_tx = tx.clone();
+            let result = tokio::task::spawn_blocking(move || {
+                backup::backup(&job, &progress_tx).map(|s| (job, s))
+            })
+            .await;
+            match result {
+                Ok(Ok((job, summary))) => {
+                    let shrunk = match &summary.shrink {
+                        Some(shrink) => format!(
+                            "\nShrunk {} from {} to {}{}",
+                            shrink.filesystem.display_name(),
+                            bytes_to_human(shrink.old_size),
+                            bytes_to_human(shrink.new_size),
+                            if shrink.auto_expand {
+                                ", expands on first boot"
+                            } else {
+                                ""
+                            }
+                        ),
+                        None => String::new(),
+                    };
+                    let _ = tx.send(AppState::Success(format!(
+                        "Saved {} to {}\n{} skipped as unused, {} written{}\nImage SHA-256: {}\nFile SHA-256: {}",
+                        bytes_to_human(summary.raw_bytes),
+                        job.output.display(),
+                        bytes_to_human(summary.skipped_bytes),
+                        bytes_to_human(summary.compressed_bytes),
+                        shrunk,
+                        summary.raw_sha256,
+                        summary.compressed_sha256
+                    )));
+                }
+                Ok(Err(e)) => {
+                    let _ = tx.send(AppState::Error(format!("{:#}", e)));
+                }
+                Err(e) => {
+                    let _ = tx.send(AppState::Error(format!("Backup task failed: {}", e)));
+                }
+            }
+        });
+    }
+
+    pub fn enter_wipe_menu(&mut self) {
+        if let Some(device) = self.selected_device() {
+            if device.is_protected {
+                self.state = AppState::Error("Cannot wipe protected system drive".to_string());
+                return;
+            }
+            self.state = AppState::WipeMenu;
+            self.input_buffer.clear();
+        }
+    }
+
+    pub fn select_wipe_mode(&mut self, next: bool) {
+        let all = WipeMode::ALL;
+        let current = all.iter().position(|m| *m == self.wipe_mode).unwrap_or(0);
+        self.wipe_mode = if next {
+            all[(current + 1) % all.len()]
+        } else {
+            all[(current + all.len() - 1) % all.len()]
+        };
+    }
+
+    pub fn adjust_wipe_passes(&mut self, more: bool) {
+        if self.wipe_mode != WipeMode::MultiPass {
+            return;
+        }
+        self.wipe_passes = if more {
+            (self.wipe_passes + 1).min(wipe::MAX_PASSES)
+        } else {
+            self.wipe_passes.saturating_sub(1).max(wipe::MIN_PASSES)
+        };
+    }
+
+    pub fn toggle_wipe_verify(&mut self) {
+        self.wipe_verify = !self.wipe_verify;
+    }
+
+    pub fn enter_wipe_confirm(&mut self) {
+        if let Some(device) = self.selected_device() {
+            self.state = AppState::ConfirmWipe(device.path.clone());
+            self.input_buffer.clear();
+        }
+    }
+
+    pub fn start_wipe(&mut self) {
+        let Some(device) = self.selected_device().cloned() else {
+            return;
+        };
+        if device.is_protected {
+            self.state = AppState::Error("Cannot wipe protected system drive".to_string());
+            return;
+        }
+        if self.input_buffer != device.path {
+            self.state = AppState::Error(format!(
+                "Confirmation mismatch. Expected '{}', got '{}'",
+                device.path, self.input_buffer
+            ));
+            return;
+        }
+        self.input_buffer.clear();
+
+        let job = WipeJob {
+            device_path: device.path.clone(),
+            device_size: device.size_bytes,
+            mode: self.wipe_mode,
+            passes: self.wipe_passes,
+            verify: self.wipe_verify,
+        };
+        self.state = AppState::InProgress(format!("Unmounting {}...", device.path));
+
+        let tx = self.operation_tx.clone();
+        let disk_manager = self.disk_manager.clone();
+
+        tokio::spawn(async move {
+            if let Err(e) = disk_manager.unmount(&job.device_path).await {
+                let _ = tx.send(AppState::Error(format!("Failed to unmount: {}", e)));
+                return;
+            }
+
+            if job.mode.is_discard() {
+                let _ = tx.send(AppState::InProgress(format!(
+                    "Discarding {}...",
+                    job.device_path
+                )));
+                let secure = job.mode == WipeMode::SecureDiscard;
+                if let Err(e) = disk_manager.discard(&job.device_path, secure).await {
+                    let _ = tx.send(AppState::Error(e.to_string()));
+                    return;
+                }
+                if !job.verify {
+                    let _ = tx.send(AppState::Success(format!(
+                        "Discarded every block on {}",
+                        job.device_path
+                    )));
+                    return;
+                }
+            }
+
+            // Same raw device trick as flashing
+            #[cfg(target_os = "macos")]
+            let job = WipeJob {
+                device_path: job.device_path.replace("/dev/disk", "/dev/rdisk"),
+                ..job
+            };
+
+            let progress_tx = tx.clone();
+            let result = tokio::task::spawn_blocking(move || {
+                let bytes = if job.mode.is_discard() {
+                    wipe::verify_discard(&job, &progress_tx)?
+                } else {
+                    wipe::fill(&job, &progress_tx)?
+                };
+                anyhow::Ok((job, bytes))
+            })
+            .await;
+            match result {
+                Ok(Ok((job, bytes))) => {
+                    let passes = match job.mode {
+                        WipeMode::MultiPass => format!(" ({} passes)", job.passes),
+                        _ => String::new(),
+                    };
+                    let verified = if job.verify {
+                        "Read back and verified."
+                    } else {
+                        "Not verified."
+                    };
+                    let _ = tx.send(AppState::Success(format!(
+                        "Wiped {} of {} with {}{}\n{}",
+                        bytes_to_human(bytes),
+                        device.path,
+                        job.mode.display_name(),
+                        passes,
+                        verified
+                    )));
+                }
+                Ok(Err(e)) => {
+                    let _ = tx.send(AppState::Error(format!("{:#}", e)));
+                }
+                Err(e) => {
+                    let _ = tx.send(AppState::Error(format!("Wipe task failed: {}", e)));
+                }
+            }
+        });
+    }
+
+    /// Open the multi-boot stick on the selected device, or offer to make one
+    pub fn open_multiboot(&mut self) {
+        let Some(device) = self.selected_device().cloned() else {
+            return;
+        };
+        if device.is_protected {
+            self.state = AppState::Error("Cannot use protected system drive".to_string());
+            return;
+        }
+        self.state = AppState::InProgress(format!("Looking for ISOs on {}...", device.path));
+
+        let tx = self.operation_tx.clone();
+        let disk_manager = self.disk_manager.clone();
+
+        tokio::spawn(async move {
+            let data_path = disk_manager.partition_path(&device.path, multiboot::DATA_PARTITION);
+            let mount_point = match disk_manager.mount(&data_path).await {
+                Ok(mount_point) => mount_point,
+                Err(DiskError::InsufficientPrivileges) => {
+                    let _ = tx.send(AppState::Error(
+                        DiskError::InsufficientPrivileges.to_string(),
+                    ));
+                    return;
+                }
+                // No data partition, or not one we can mount: not a stick yet
+                Err(_) => {
+                    let _ = tx.send(AppState::ConfirmMultiboot(device.path));
+                    return;
+                }
+            };
+
+            let path = device.path.clone();
+            let result = tokio::task::spawn_blocking(move || {
+                multiboot::scan(&path, std::path::Path::new(&mount_point))
+            })
+            .await;
+            match result {
+                Ok(Ok(stick)) => {
+                    let _ = tx.send(AppState::MultibootReady(Box::new(stick)));
+                }
+                _ => {
+                    let _ = disk_manager.unmount(&data_path).await;
+                    let _ = tx.send(AppState::ConfirmMultiboot(device.path));
+                }
+            }
+        });
+    }
+
+    pub fn on_multiboot_ready(&mut self, stick: MultibootStick) {
+        self.multiboot_selected = self
+            .multiboot_selected
+            .min(stick.isos.len().saturating_sub(1));
+        self.multiboot = Some(stick);
+        self.multiboot_remove = None;
+        self.state = AppState::MultibootMenu;
+    }
+
+    /// Partition the device, install GRUB and set up an empty ISO folder
+    pub fn start_multiboot_prepare(&mut self) {
+        let Some(device) = self.selected_device().cloned() else {
+            return;
+        };
+        if self.input_buffer != device.path {
+            self.state = AppState::Error(format!(
+                "Confirmation mismatch. Expected '{}', got '{}'",
+                device.path, self.input_buffer
+            ));
+            return;
+        }
+        self.input_buffer.clear();
+        self.state = AppState::InProgress(format!("Unmounting {}...", device.path));
+
+        let tx = self.operation_tx.clone();
+        let disk_manager = self.disk_manager.clone();
+
+        tokio::spawn(async move {
+            let path = device.path.clone();
+            if let Err(e) = disk_manager.unmount(&path).await {
+                let _ = tx.send(AppState::Error(format!("Failed to unmount: {}", e)));
+                return;
+            }
+            let wiped = match signatures::wipe_signatures(&path) {
+                Ok(wiped) => wiped,
+                Err(e) => {
+                    let _ = tx.send(AppState::Error(format!(
+                        "Failed to clear old signatures: {:#}",
+                        e
+                    )));
+                    return;
+                }
+            };
+
+            let _ = tx.send(AppState::InProgress(format!("Partitioning {}...", path)));
+            let partitions = match disk_manager.partition(&path, &multiboot::layout()).await {
+                Ok(partitions) => partitions,
+                Err(e) => {
+                    let _ = tx.send(AppState::Error(format!("Failed to partition: {}", e)));
+                    return;
+                }
+            };
+            let esp_path = &partitions[multiboot::ESP_PARTITION as usize - 1];
+            let data_path = &partitions[multiboot::DATA_PARTITION as usize - 1];
+
+            let _ = tx.send(AppState::InProgress("Installing GRUB...".to_string()));
+            let esp_mount = match disk_manager.mount(esp_path).await {
+                Ok(mount_point) => mount_point,
+                Err(e) => {
+                    let _ = tx.send(AppState::Error(format!("Failed to mount: {}", e)));
+                    return;
+                }
+            };
+            let installed = tokio::task::spawn_blocking(move || {
+                multiboot::install_bootloader(std::path::Path::new(&esp_mount))
+            })
+            .await;
+            let _ = disk_manager.unmount(esp_path).await;
+            match installed {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => {
+                    let _ = tx.send(AppState::Error(format!("{:#}", e)));
+                    return;
+                }
+                Err(e) => {
+                    let _ = tx.send(AppState::Error(format!("GRUB install task failed: {}", e)));
+                    return;
+                }
+            }
+
+            let data_mount = match disk_manager.mount(data_path).await {
+                Ok(mount_point) => mount_point,
+                Err(e) => {
+                    let _ = tx.send(AppState::Error(format!("Failed to mount: {}", e)));
+                    return;
+                }
+            };
+            let result = tokio::task::spawn_blocking(move || {
+                let data_mount = std::path::Path::new(&data_mount);
+                multiboot::init(data_mount)?;
+                multiboot::scan(&path, data_mount)
+            })
+            .await;
+            match result {
+                Ok(Ok(mut stick)) => {
+                    stick.notice = Some(format!(
+                        "Prepared {} as a multi-boot stick{}",
+                        device.path,
+                        signatures::describe(&wiped).replace('\n', ". ")
+                    ));
+                    let _ = tx.send(AppState::MultibootReady(Box::new(stick)));
+                }
+                Ok(Err(e)) => {
+                    let _ = tx.send(AppState::Error(format!("{:#}", e)));
+                }
+                Err(e) => {
+                    let _ = tx.send(AppState::Error(format!("Stick setup task failed: {}", e)));
+                }
+            }
+        });
+    }
+
+    pub fn multiboot_select_next(&mut self) {
+        self.multiboot_remove = None;
+        let len = self.multiboot.as_ref().map_or(0, |stick| stick.isos.len());
+        if len > 0 {
+            self.multiboot_selected = (self.multiboot_selected + 1) % len;
+        }
+    }
+
+    pub fn multiboot_select_previous(&mut self) {
+        self.multiboot_remove = None;
+        let len = self.multiboot.as_ref().map_or(0, |stick| stick.isos.len());
+        if len > 0 {
+            self.multiboot_selected = (self.multiboot_selected + len - 1) % len;
+        }
+    }
+
+    pub fn enter_multiboot_file_input(&mut self) {
+        self.state = AppState::MultibootFileInput;
+        self.input_buffer.clear();
+    }
+
+    /// Copy a local ISO onto the stick, checked like any other image
+    pub fn submit_multiboot_file(&mut self) {
+        let path = self.input_buffer.trim().to_string();
+        // Invalid input stays in the dialog, which says what's wrong
+        if !path.starts_with('/') || !std::path::Path::new(&path).is_file() {
+            return;
+        }
+        self.begin_preflight(Iso::from_custom_url(&path), false);
+    }
+
+    /// Copy a pre-flighted image onto the open stick
+    fn add_to_multiboot(&mut self, job: FlashJob) {
+        let Some(stick) = self.multiboot.clone() else {
+            return;
+        };
+        let iso = job.iso;
+        if job.is_custom {
+            self.url_history.push(&iso.url);
+            let _ = self.url_history.save();
+        }
+        let source = match self.flasher.source_for(&iso.url) {
+            Ok(source) => source,
+            Err(e) => {
+                self.state = AppState::Error(format!("{:#}", e));
+                return;
+            }
+        };
+        let name = multiboot::file_name_for(&iso.url);
+        self.state = AppState::InProgress(format!("Copying {} to the stick...", name));
+
+        let tx = self.operation_tx.clone();
+        let flasher = self.flasher.clone();
+        let expected_sha256 = job.preflight.sha256;
+
+        tokio::spawn(async move {
+            let result =
+                multiboot::add_iso(&flasher, source, expected_sha256, &stick, &name, tx.clone())
+                    .await;
+            match result {
+                Ok(mut stick) => {
+                    stick.notice = Some(format!("Added {} to the boot menu", name));
+                    let _ = tx.send(AppState::MultibootReady(Box::new(stick)));
+                }
+                Err(e) => {
+                    let _ = tx.send(AppState::Error(format!("{:#}", e)));
+                }
+            }
+        });
+    }
+
+    /// First press asks, the second deletes the highlighted ISO
+    pub fn remove_selected_multiboot_iso(&mut self) {
+        let Some(stick) = self.multiboot.as_mut() else {
+            return;
+        };
+        let Some(iso) = stick.isos.get(self.multiboot_selected) else {
+            return;
+        };
+        if self.multiboot_remove.as_ref() != Some(&iso.name) {
+            stick.notice = Some(format!("Press d again to delete {}", iso.name));
+            self.multiboot_remove = Some(iso.name.clone());
+            return;
+        }
+
+        let name = iso.name.clone();
+        let stick = stick.clone();
+        self.multiboot_remove = None;
+        self.state = AppState::InProgress(format!("Removing {}...", name));
+        let tx = self.operation_tx.clone();
+
+        tokio::spawn(async move {
+            let result = tokio::task::spawn_blocking(move || {
+                multiboot::remove_iso(&stick, &name).map(|stick| (stick, name))
+            })
+            .await;
+            match result {
+                Ok(Ok((mut stick, name))) => {
+                    stick.notice = Some(format!("Removed {}", name));
+                    let _ = tx.send(AppState::MultibootReady(Box::new(stick)));
+                }
+                Ok(Err(e)) => {
+                    let _ = tx.send(AppState::Error(format!("{:#}", e)));
+                }
+                Err(e) => {
+                    let _ = tx.send(AppState::Error(format!("Remove task failed: {}", e)));
+                }
+            }
+        });
+    }
+
+    /// Done with the stick: unmount and eject it
+    pub fn close_multiboot(&mut self) {
+        let Some(stick) = self.multiboot.take() else {
+            self.cancel();
+            return;
+        };
+        self.multiboot_remove = None;
+        self.state = AppState::InProgress("Ejecting device...".to_string());
+
+        let tx = self.operation_tx.clone();
+        let disk_manager = self.disk_manager.clone();
+
+        tokio::spawn(async move {
+            let data_path =
+                disk_manager.partition_path(&stick.device_path, multiboot::DATA_PARTITION);
+            if let Err(e) = disk_manager.unmount(&data_path).await {
+                let _ = tx.send(AppState::Error(format!("Failed to unmount: {}", e)));
+                return;
+            }
+            let summary = format!(
+                "{} ISOs on the stick, {} free",
+                stick.isos.len(),
+                bytes_to_human(stick.free_bytes)
+            );
+            match disk_manager.eject(&stick.device_path).await {
+                Ok(()) => {
+                    let _ = tx.send(AppState::Success(format!(
+                        "{}\nDevice ejected safely.",
+                        summary
+                    )));
+                }
+                Err(e) => {
+                    let _ = tx.send(AppState::Success(format!(
+                        "{}\nEject failed: {}",
+                        summary, e
+                    )));
+                }
+            }
+        });
+    }
+
+    pub fn enter_confirm_mode(&mut self) {
+        if let Some(device) = self.selected_device() {
+            self.state = AppState::ConfirmDestructive(device.path.clone());
+            self.input_buffer.clear();
+        }
+    }
+
+    /// Raise or lower the download cap; applies to the flash in progress
+    pub fn step_rate_limit(&mut self, faster: bool) {
+        let rate_limit = self.flasher.rate_limit().step(faster);
+        if let AppState::Flashing(progress) = &mut self.state {
+            progress.rate_limit = rate_limit;
+        }
+    }
+
+    pub fn cancel(&mut self) {
+        self.input_buffer.clear();
+        self.flash_job = None;
+        self.clone_source = None;
+        // Backing out of a picker or an error while adding returns to the stick
+        self.state = match self.multiboot {
+            Some(_) => AppState::MultibootMenu,
+            None => AppState::Idle,
+        };
+    }
+
+    pub fn unmount_selected(&mut self) {
+        if let Some(device) = self.selected_device().cloned() {
+            if device.is_protected {
+                self.state = AppState::Error("Cannot unmount protected system drive".to_string());
+                return;
+            }
+
+            self.state = AppState::InProgress("Unmounting...".to_string());
+
+            let tx = self.operation_tx.clone();
+            let disk_manager = self.disk_manager.clone();
+            let path = device.path.clone();
+
+            tokio::spawn(async move {
+                match disk_manager.unmount(&path).await {
+                    Ok(()) => {
+                        let _ = tx.send(AppState::Success(format!("Unmounted {}", path)));
+                    }
+                    Err(e) => {
+                        let _ = tx.send(AppState::Error(e.to_string()));
+                    }
+                }
+            });
+        }
+    }
+
+    pub fn format_selected(&mut self) {
+        let device = match self.selected_device().cloned() {
+            Some(d) => d,
+            None => return,
+        };
+
+        if device.is_protected {
+            self.state = AppState::Error("Cannot format protected system drive".to_string());
+            return;
+        }
+
+        // Verify confirmation input matches device path
+        if self.input_buffer != device.path {
+            self.state = AppState::Error(format!(
+                "Confirmation mismatch. Expected '{}', got '{}'",
+                device.path, self.input_buffer
+            ));
+            return;
+        }
+
+        let fs_type = match self.selected_fs() {
+            Some(fs) => fs,
+            None => return,
+        };
+
+        self.state = AppState::InProgress(format!(
+            "Formatting {} as {}...",
+            device.path,
+            fs_type.display_name()
+        ));
+
+        let tx = self.operation_tx.clone();
+        let disk_manager = self.disk_manager.clone();
+        let path = device.path.clone();
+        let display_name = fs_type.display_name();
+
+        tokio::spawn(async move {
+            // Leftover tables and superblocks outlive mkfs; clear them first
+            if let Err(e) = disk_manager.unmount(&path).await {
+                let _ = tx.send(AppState::Error(format!("Failed to unmount: {}", e)));
+                return;
+            }
+            let wiped = match signatures::wipe_signatures(&path) {
+                Ok(wiped) => wiped,
+                Err(e) => {
+                    let _ = tx.send(AppState::Error(format!(
+                        "Failed to clear old signatures: {:#}",
+                        e
+                    )));
+                    return;
+                }
+            };
+
+            match disk_manager.format(&path, fs_type, "UNTITLED").await {
+                Ok(()) => {
+                    let _ = tx.send(AppState::Success(format!(
+                        "Formatted {} as {}{}",
+                        path,
+                        display_name,
+                        signatures::describe(&wiped)
+                    )));
+                }
+                Err(e) => {
                     let _ = tx.send(AppState::Error(e.to_string()));
                 }
             }
         });
     }
 }
+
+/// Eject a freshly flashed device and report the flash; `detail` goes
+/// under the headline
+async fn eject_flashed(
+    disk_manager: &dyn DiskManager,
+    path: &str,
+    detail: &str,
+    tx: &tokio::sync::mpsc::UnboundedSender<AppState>,
+) {
+    let _ = tx.send(AppState::InProgress("Ejecting device...".to_string()));
+    if let Err(e) = disk_manager.eject(path).await {
+        // Warning instead of error? For now, let's just warn but consider it success
+        let _ = tx.send(AppState::Success(format!(
+            "Flash complete, but eject failed: {}{}",
+            e, detail
+        )));
+    } else {
+        let _ = tx.send(AppState::Success(format!(
+            "Flash complete! Device ejected safely.{}",
+            detail
+        )));
+    }
+}
+
+/// Compare a drive's ISO volume with the catalog: entries of the distro its
+/// label names are sized and checksummed (fetching checksums the catalog
+/// doesn't pin), then the drive's prefix of each size is hashed
+async fn identify_device(
+    flasher: &Flasher,
+    catalog: &[Iso],
+    device: &Device,
+    tx: &tokio::sync::mpsc::UnboundedSender<AppState>,
+) -> anyhow::Result<Identity> {
+    let device_path = device.path.clone();
+    let volume = tokio::task::spawn_blocking(move || identify::read_volume(&device_path)).await??;
+    let Some(volume) = volume else {
+        return Ok(Identity::Unknown { label: None });
+    };
+    let same_distro = identify::same_distro(catalog, &volume.label);
+    let Some(first) = same_distro.first() else {
+        return Ok(Identity::Unknown {
+            label: Some(volume.label),
+        });
+    };
+    let distro = first.name.clone();
+
+    let mut candidates = Vec::new();
+    let mut unchecked = 0;
+    for iso in &same_distro {
+        let _ = tx.send(AppState::InProgress(format!(
+            "Looking up {} {} {}...",
+            iso.name, iso.version, iso.arch
+        )));
+        let known = match (iso.size_bytes, &iso.sha256) {
+            (Some(size), Some(sha256)) => Some((size, sha256.clone())),
+            _ => flasher
+                .preflight(iso)
+                .await
+                .ok()
+                .and_then(|p| p.sha256.map(|sha256| (p.total_bytes, sha256))),
+        };
+        match known {
+            // The image can't be smaller than its own volume, nor bigger than the drive
+            Some((size, sha256)) if size >= volume.size && size <= device.size_bytes => candidates
+                .push(identify::Candidate {
+                    iso: (*iso).clone(),
+                    size,
+                    sha256,
+                }),
+            Some(_) => {}
+            None => unchecked += 1,
+        }
+    }
+
+    let sizes: Vec<u64> = candidates.iter().map(|c| c.size).collect();
+    let device_path = device.path.clone();
+    let progress_tx = tx.clone();
+    let hashes = tokio::task::spawn_blocking(move || {
+        let mut last = 0;
+        identify::hash_prefixes(&device_path, &sizes, |done, total| {
+            let percent = done * 100 / total.max(1);
+            if percent != last {
+                last = percent;
+                let _ = progress_tx.send(AppState::InProgress(format!(
+                    "Hashing {}: {}% of {}",
+                    device_path,
+                    percent,
+                    bytes_to_human(total)
+                )));
+            }
+        })
+    })
+    .await??;
+
+    Ok(match identify::find_match(&candidates, &hashes) {
+        Some(found) => Identity::Exact(found.iso.clone()),
+        None => Identity::SameDistro {
+            distro,
+            label: volume.label,
+            unchecked,
+        },
+    })
+}
+
+/// Move the backup GPT to the end of the device and, with `grow`, extend
+/// the last partition and its filesystem into the free space. Returns
+/// notes for the summary.
+async fn repair_table(
+    disk_manager: &dyn DiskManager,
+    path: &str,
+    grow: bool,
+) -> Result<String, String> {
+    let device_path = path.to_string();
+    let repair = tokio::task::spawn_blocking(move || grow::repair(&device_path, grow))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| format!("{:#}", e))?;
+
+    let mut notes = String::new();
+    if repair.relocated {
+        notes.push_str("\nMoved the backup GPT to the end of the device");
+    }
+    let Some(grown) = repair.grown else {
+        return Ok(notes);
+    };
+    disk_manager
+        .reread_partitions(path)
+        .await
+        .map_err(|e| e.to_string())?;
+    let partition_path = disk_manager.partition_path(path, grown.number);
+    // The desktop may have mounted the partition after the reread
+    let _ = disk_manager.unmount(&partition_path).await;
+    tokio::task::spawn_blocking(move || grow::grow_filesystem(&partition_path, grown.kind))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| format!("{:#}", e))?;
+    notes.push_str(&format!(
+        "\nGrew partition {} from {} to {}",
+        grown.number,
+        bytes_to_human(grown.old_size),
+        bytes_to_human(grown.new_size)
+    ));
+    Ok(notes)
+}
+
+/// Put `seed` into a new FAT partition after the image, writing the table
+/// through `flash_path`. Returns the names of the files written.
+async fn add_seed_partition(
+    disk_manager: &dyn DiskManager,
+    path: &str,
+    flash_path: &str,
+    image_size: u64,
+    seed: &Seed,
+) -> Result<String, String> {
+    let device_path = flash_path.to_string();
+    let number =
+        tokio::task::spawn_blocking(move || cloudinit::add_partition(&device_path, image_size))
+            .await
+            .map_err(|e| e.to_string())?
+            .map_err(|e| format!("{:#}", e))?;
+    disk_manager
+        .reread_partitions(path)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let partition_path = disk_manager.partition_path(path, number);
+    disk_manager
+        .format(&partition_path, FileSystemType::Fat32, cloudinit::LABEL)
+        .await
+        .map_err(|e| e.to_string())?;
+    let mount_point = disk_manager
+        .mount(&partition_path)
+        .await
+        .map_err(|e| format!("Failed to mount: {}", e))?;
+    let written = cloudinit::write_seed(seed, std::path::Path::new(&mount_point));
+    disk_manager
+        .unmount(&partition_path)
+        .await
+        .map_err(|e| format!("Failed to unmount: {}", e))?;
+    written.map_err(|e| format!("{:#}", e))?;
+
+    let names: Vec<&str> = seed.files().iter().map(|f| f.file_name()).collect();
+    Ok(names.join(", "))
+}
+
+/// Write the job's files into the partitions they name, without mounting
+/// them. Every file is read back.
+async fn inject_files(
+    disk_manager: &dyn DiskManager,
+    path: &str,
+    injections: &[Injection],
+) -> Result<(), String> {
+    let device_path = path.to_string();
+    let files = injections.to_vec();
+    let targets = tokio::task::spawn_blocking(move || inject::resolve(&device_path, &files))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| format!("{:#}", e))?;
+
+    for target in targets {
+        let partition_path = disk_manager.partition_path(path, target.partition.number);
+        // The desktop may have mounted the new partitions after the flash
+        let _ = disk_manager.unmount(&partition_path).await;
+        let device_path = path.to_string();
+        tokio::task::spawn_blocking(move || inject::write(&device_path, &target))
+            .await
+            .map_err(|e| e.to_string())?
+            .map_err(|e| format!("{:#}", e))?;
+    }
+    Ok(())
+}
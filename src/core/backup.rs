@@ -0,0 +1,630 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{Receiver, SyncSender, sync_channel};
+use std::thread;
+use std::time::Instant;
+
+use anyhow::{Context, Result, anyhow};
+use flate2::write::GzEncoder;
+use liblzma::stream::{Check, MtStreamBuilder};
+use liblzma::write::XzEncoder;
+use sha2::{Digest, Sha256};
+use tokio::sync::mpsc::UnboundedSender;
+
+use super::AppState;
+use super::shrink::{self, ShrinkSummary};
+use super::{filesystem, partition};
+
+const CHUNK_SIZE: u64 = 1024 * 1024;
+const CHANNEL_BOUND: usize = 4;
+/// Skipped ranges are widened to this so reads stay aligned on any device
+const SKIP_ALIGNMENT: u64 = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+    Xz,
+}
+
+impl Compression {
+    pub const ALL: [Compression; 4] = [
+        Compression::None,
+        Compression::Gzip,
+        Compression::Zstd,
+        Compression::Xz,
+    ];
+
+    pub fn display_name(self) -> &'static str {
+        match self {
+            Compression::None => "None",
+            Compression::Gzip => "gzip",
+            Compression::Zstd => "zstd",
+            Compression::Xz => "xz",
+        }
+    }
+
+    /// Suffix added after `.img`
+    pub fn extension(self) -> &'static str {
+        match self {
+            Compression::None => "",
+            Compression::Gzip => ".gz",
+            Compression::Zstd => ".zst",
+            Compression::Xz => ".xz",
+        }
+    }
+}
+
+/// Checkbox options in the backup dialog
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackupOption {
+    SkipUnused,
+    Shrink,
+    AutoExpand,
+}
+
+impl BackupOption {
+    pub const ALL: [BackupOption; 3] = [
+        BackupOption::SkipUnused,
+        BackupOption::Shrink,
+        BackupOption::AutoExpand,
+    ];
+}
+
+/// A device to read back into an image file
+#[derive(Debug, Clone, PartialEq)]
+pub struct BackupJob {
+    pub device_path: String,
+    /// Size reported by the OS, for devices that can't be seeked to their end
+    pub device_size: u64,
+    pub output: PathBuf,
+    pub compression: Compression,
+    /// Only read what the partition table and filesystems use; the rest of
+    /// the image is written as zeros
+    pub skip_unused: bool,
+    /// Shrink the last partition to its used size before compressing
+    pub shrink: bool,
+    /// Install a first-boot hook that grows the shrunk filesystem back
+    pub auto_expand: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackupStage {
+    Reading,
+    Shrinking,
+    /// Compressing the shrunk image into the output file
+    Compressing,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BackupProgress {
+    pub stage: BackupStage,
+    /// Position on the device, skipped space included
+    pub bytes_read: u64,
+    pub total_bytes: u64,
+    pub skipped_bytes: u64,
+    pub compressed_bytes: u64,
+    pub speed_mbps: f64,
+    pub percent: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BackupSummary {
+    pub raw_bytes: u64,
+    pub raw_sha256: String,
+    pub compressed_bytes: u64,
+    pub compressed_sha256: String,
+    pub skipped_bytes: u64,
+    pub shrink: Option<ShrinkSummary>,
+}
+
+/// Stream a device into a (compressed) image file. Blocking: run it on a
+/// blocking thread. A partly written image is removed on failure.
+pub fn backup(job: &BackupJob, progress_tx: &UnboundedSender<AppState>) -> Result<BackupSummary> {
+    let output = create_output(&job.output)?;
+    if !job.shrink {
+        let result = write_backup(job, output, BackupStage::Reading, progress_tx);
+        if result.is_err() {
+            let _ = fs::remove_file(&job.output);
+        }
+        return result;
+    }
+
+    // Shrinking needs the raw image on disk; a compressed one goes through
+    // a temporary file next to the output first
+    let raw_path = if job.compression == Compression::None {
+        job.output.clone()
+    } else {
+        let mut name = job.output.clone().into_os_string();
+        name.push(".part");
+        PathBuf::from(name)
+    };
+    let result = (|| {
+        let raw_output = if raw_path == job.output {
+            output.try_clone()?
+        } else {
+            create_output(&raw_path)?
+        };
+        let read = write_backup(
+            &BackupJob {
+                compression: Compression::None,
+                ..job.clone()
+            },
+            raw_output,
+            BackupStage::Reading,
+            progress_tx,
+        )?;
+        let _ = progress_tx.send(AppState::BackingUp(BackupProgress {
+            stage: BackupStage::Shrinking,
+            bytes_read: read.raw_bytes,
+            total_bytes: read.raw_bytes,
+            skipped_bytes: read.skipped_bytes,
+            compressed_bytes: 0,
+            speed_mbps: 0.0,
+            percent: 100.0,
+        }));
+        let shrunk = shrink::shrink_image(&raw_path, job.auto_expand)?;
+
+        let mut summary = if raw_path == job.output {
+            let mut hasher = Sha256::new();
+            io::copy(&mut File::open(&raw_path)?, &mut hasher)?;
+            let sha256 = format!("{:x}", hasher.finalize());
+            BackupSummary {
+                raw_bytes: shrunk.new_size,
+                raw_sha256: sha256.clone(),
+                compressed_bytes: shrunk.new_size,
+                compressed_sha256: sha256,
+                skipped_bytes: 0,
+                shrink: None,
+            }
+        } else {
+            let compressed = write_backup(
+                &BackupJob {
+                    device_path: raw_path.to_string_lossy().into_owned(),
+                    device_size: shrunk.new_size,
+                    skip_unused: false,
+                    ..job.clone()
+                },
+                output,
+                BackupStage::Compressing,
+                progress_tx,
+            );
+            let _ = fs::remove_file(&raw_path);
+            compressed?
+        };
+        summary.skipped_bytes = read.skipped_bytes;
+        summary.shrink = Some(shrunk);
+        Ok(summary)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&raw_path);
+        let _ = fs::remove_file(&job.output);
+    }
+    result
+}
+
+fn create_output(path: &Path) -> Result<File> {
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .with_context(|| format!("Cannot create {}", path.display()))
+}
+
+fn write_backup(
+    job: &BackupJob,
+    output: File,
+    stage: BackupStage,
+    progress_tx: &UnboundedSender<AppState>,
+) -> Result<BackupSummary> {
+    let mut device = File::open(&job.device_path)
+        .with_context(|| format!("Failed to open device {}", job.device_path))?;
+    let total_size = match device.seek(SeekFrom::End(0)) {
+        Ok(size) if size > 0 => size,
+        _ => job.device_size,
+    };
+    let used = if job.skip_unused {
+        used_space(&mut device, total_size)?
+    } else {
+        None
+    };
+
+    // Compression runs on its own thread so it overlaps with device reads
+    let (data_tx, data_rx): (SyncSender<Vec<u8>>, Receiver<Vec<u8>>) = sync_channel(CHANNEL_BOUND);
+    let compressed_bytes = Arc::new(AtomicU64::new(0));
+    let compression = job.compression;
+    let counter = compressed_bytes.clone();
+    let writer_handle = thread::spawn(move || -> Result<String> {
+        let mut encoder = Encoder::new(compression, HashingWriter::new(output, counter))?;
+        for chunk in data_rx {
+            encoder
+                .write_all(&chunk)
+                .context("Failed to write image file")?;
+        }
+        let mut writer = encoder.finish().context("Failed to finish image file")?;
+        writer.flush()?;
+        writer
+            .inner
+            .sync_all()
+            .context("Failed to sync image file")?;
+        Ok(format!("{:x}", writer.hasher.finalize()))
+    });
+
+    let start_time = Instant::now();
+    let mut last_update_time = Instant::now();
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; CHUNK_SIZE as usize];
+    let mut position = 0u64;
+    let mut skipped_bytes = 0u64;
+
+    while position < total_size {
+        let (in_use, span_end) = match &used {
+            Some(used) => span_at(used, position, total_size),
+            None => (true, total_size),
+        };
+        let len = (span_end.min(position + CHUNK_SIZE) - position) as usize;
+        let chunk = if in_use {
+            device.seek(SeekFrom::Start(position))?;
+            let read = read_full(&mut device, &mut buf[..len])
+                .with_context(|| format!("Failed to read device at byte {}", position))?;
+            if read == 0 {
+                // The device is smaller than reported
+                break;
+            }
+            buf[..read].to_vec()
+        } else {
+            skipped_bytes += len as u64;
+            vec![0u8; len]
+        };
+        hasher.update(&chunk);
+        position += chunk.len() as u64;
+
+        if data_tx.send(chunk).is_err() {
+            // Writer thread died; its error says why
+            drop(data_tx);
+            return match writer_handle.join() {
+                Ok(result) => Err(result
+                    .err()
+                    .unwrap_or_else(|| anyhow!("Writer thread stopped early"))),
+                Err(e) => Err(anyhow!("Writer thread panicked: {:?}", e)),
+            };
+        }
+
+        let now = Instant::now();
+        if now.duration_since(last_update_time).as_millis() > 100 {
+            let elapsed_secs = start_time.elapsed().as_secs_f64();
+            let progress = BackupProgress {
+                stage,
+                bytes_read: position,
+                total_bytes: total_size,
+                skipped_bytes,
+                compressed_bytes: compressed_bytes.load(Ordering::Relaxed),
+                speed_mbps: (position as f64 / 1_000_000.0) / elapsed_secs,
+                percent: (position as f64 / total_size as f64) * 100.0,
+            };
+            let _ = progress_tx.send(AppState::BackingUp(progress));
+            last_update_time = now;
+        }
+    }
+
+    drop(data_tx);
+    let compressed_sha256 = match writer_handle.join() {
+        Ok(result) => result?,
+        Err(e) => return Err(anyhow!("Writer thread panicked: {:?}", e)),
+    };
+
+    Ok(BackupSummary {
+        raw_bytes: position,
+        raw_sha256: format!("{:x}", hasher.finalize()),
+        compressed_bytes: compressed_bytes.load(Ordering::Relaxed),
+        compressed_sha256,
+        skipped_bytes,
+        shrink: None,
+    })
+}
+
+/// Parts of a disk worth reading: partition tables, boot loader gaps and
+/// what each filesystem has allocated. Partitions with a filesystem we
+/// can't read are kept whole. `None` when the layout is unknown.
+pub fn used_space<R: Read + Seek>(
+    reader: &mut R,
+    disk_size: u64,
+) -> Result<Option<Vec<Range<u64>>>> {
+    let Some(table) = partition::read_partition_table(reader, disk_size)? else {
+        // A filesystem straight on the device, without a partition table
+        return Ok(filesystem::used_ranges(reader, 0)
+            .ok()
+            .flatten()
+            .map(|used| align_ranges(used, disk_size)));
+    };
+
+    let mut ranges = table.reserved.clone();
+    // Boot loaders often sit between the table and the first partition
+    let first_partition = table
+        .partitions
+        .iter()
+        .map(|p| p.start)
+        .min()
+        .unwrap_or(disk_size);
+    ranges.push(0..first_partition);
+    for partition in &table.partitions {
+        match filesystem::used_ranges(reader, partition.start) {
+            Ok(Some(used)) => ranges.extend(
+                used.into_iter()
+                    .map(|r| r.start.max(partition.start)..r.end.min(partition.end())),
+            ),
+            _ => ranges.push(partition.start..partition.end()),
+        }
+    }
+    Ok(Some(align_ranges(ranges, disk_size)))
+}
+
+fn align_ranges(ranges: Vec<Range<u64>>, disk_size: u64) -> Vec<Range<u64>> {
+    filesystem::merge_ranges(
+        ranges
+            .into_iter()
+            .map(|r| {
+                r.start / SKIP_ALIGNMENT * SKIP_ALIGNMENT
+                    ..r.end
+                        .div_ceil(SKIP_ALIGNMENT)
+                        .saturating_mul(SKIP_ALIGNMENT)
+                        .min(disk_size)
+            })
+            .collect(),
+    )
+}
+
+/// Whether `position` is in a used range, and where that stretch ends
+fn span_at(used: &[Range<u64>], position: u64, total: u64) -> (bool, u64) {
+    match used.iter().find(|r| r.end > position) {
+        Some(r) if r.start <= position => (true, r.end.min(total)),
+        Some(r) => (false, r.start.min(total)),
+        None => (false, total),
+    }
+}
+
+/// Fill `buf` unless the device ends first; returns the bytes read
+fn read_full(reader: &mut impl Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+/// Hashes and counts what reaches the image file
+struct HashingWriter<W> {
+    inner: W,
+    hasher: Sha256,
+    written: Arc<AtomicU64>,
+}
+
+impl<W: Write> HashingWriter<W> {
+    fn new(inner: W, written: Arc<AtomicU64>) -> Self {
+        Self {
+            inner,
+            hasher: Sha256::new(),
+            written,
+        }
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hasher.update(&buf[..n]);
+        self.written.fetch_add(n as u64, Ordering::Relaxed);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+enum Encoder<W: Write> {
+    Plain(W),
+    Gzip(GzEncoder<W>),
+    Zstd(zstd::Encoder<'static, W>),
+    Xz(XzEncoder<W>),
+}
+
+impl<W: Write> Encoder<W> {
+    fn new(compression: Compression, inner: W) -> Result<Self> {
+        let threads = thread::available_parallelism().map_or(1, |n| n.get() as u32);
+        Ok(match compression {
+            Compression::None => Encoder::Plain(inner),
+            Compression::Gzip => {
+                Encoder::Gzip(GzEncoder::new(inner, flate2::Compression::default()))
+            }
+            Compression::Zstd => {
+                let mut encoder = zstd::Encoder::new(inner, 3)?;
+                encoder.include_checksum(true)?;
+                encoder.multithread(threads)?;
+                Encoder::Zstd(encoder)
+            }
+            Compression::Xz => {
+                let stream = MtStreamBuilder::new()
+                    .threads(threads)
+                    .preset(6)
+                    .check(Check::Crc64)
+                    .encoder()?;
+                Encoder::Xz(XzEncoder::new_stream(inner, stream))
+            }
+        })
+    }
+
+    fn finish(self) -> io::Result<W> {
+        match self {
+            Encoder::Plain(w) => Ok(w),
+            Encoder::Gzip(e) => e.finish(),
+            Encoder::Zstd(e) => e.finish(),
+            Encoder::Xz(e) => e.finish(),
+        }
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Encoder::Plain(w) => w.write(buf),
+            Encoder::Gzip(e) => e.write(buf),
+            Encoder::Zstd(e) => e.write(buf),
+            Encoder::Xz(e) => e.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Encoder::Plain(w) => w.flush(),
+            Encoder::Gzip(e) => e.flush(),
+            Encoder::Zstd(e) => e.flush(),
+            Encoder::Xz(e) => e.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 8 MiB MBR disk with a 4 MiB FAT16 partition at 1 MiB using two clusters
+    fn sample_disk() -> Vec<u8> {
+        let mut disk = vec![0u8; 8 * 1024 * 1024];
+        let part = 1024 * 1024;
+        disk[446 + 4] = 0x06;
+        disk[446 + 8..446 + 12].copy_from_slice(&2048u32.to_le_bytes());
+        disk[446 + 12..446 + 16].copy_from_slice(&8192u32.to_le_bytes());
+        disk[510..512].copy_from_slice(&[0x55, 0xaa]);
+
+        let boot = &mut disk[part..part + 512];
+        boot[11..13].copy_from_slice(&512u16.to_le_bytes());
+        boot[13] = 1;
+        boot[14..16].copy_from_slice(&1u16.to_le_bytes());
+        boot[16] = 2;
+        boot[17..19].copy_from_slice(&512u16.to_le_bytes());
+        boot[19..21].copy_from_slice(&8192u16.to_le_bytes());
+        boot[21] = 0xf8;
+        boot[22..24].copy_from_slice(&32u16.to_le_bytes());
+        boot[510..512].copy_from_slice(&[0x55, 0xaa]);
+        disk[part + 512..part + 520].copy_from_slice(&[0xf8, 0xff, 0xff, 0xff, 3, 0, 0xff, 0xff]);
+
+        // File data in clusters 2-3, and stale data in free cluster 100
+        let data_start = part + (1 + 64 + 32) * 512;
+        disk[data_start..data_start + 1024].fill(0x5a);
+        disk[data_start + 98 * 512..data_start + 99 * 512].fill(0x77);
+        disk
+    }
+
+    #[test]
+    fn test_backup_skips_unused_space() {
+        let disk = sample_disk();
+        let dir = std::env::temp_dir().join(format!("pervie-backup-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let device = dir.join("disk.img");
+        fs::write(&device, &disk).unwrap();
+
+        let mut expected = disk.clone();
+        let stale = 1024 * 1024 + (1 + 64 + 32 + 98) * 512;
+        expected[stale..stale + 512].fill(0);
+
+        let (tx, _rx) = tokio::sync::mpsc::unbounded_channel();
+        for compression in Compression::ALL {
+            let output = dir.join(format!("backup.img{}", compression.extension()));
+            let job = BackupJob {
+                device_path: device.to_string_lossy().into_owned(),
+                device_size: 0,
+                output: output.clone(),
+                compression,
+                skip_unused: true,
+                shrink: false,
+                auto_expand: false,
+            };
+            let summary = backup(&job, &tx).unwrap();
+            assert_eq!(summary.raw_bytes, disk.len() as u64);
+            assert_eq!(
+                summary.raw_sha256,
+                format!("{:x}", Sha256::digest(&expected))
+            );
+            assert!(summary.skipped_bytes > 6 * 1024 * 1024);
+
+            let written = fs::read(&output).unwrap();
+            assert_eq!(summary.compressed_bytes, written.len() as u64);
+            assert_eq!(
+                summary.compressed_sha256,
+                format!("{:x}", Sha256::digest(&written))
+            );
+            let mut image = Vec::new();
+            match compression {
+                Compression::None => image = written,
+                Compression::Gzip => {
+                    flate2::read::GzDecoder::new(&written[..])
+                        .read_to_end(&mut image)
+                        .unwrap();
+                }
+                Compression::Zstd => image = zstd::decode_all(&written[..]).unwrap(),
+                Compression::Xz => {
+                    liblzma::read::XzDecoder::new(&written[..])
+                        .read_to_end(&mut image)
+                        .unwrap();
+                }
+            }
+            assert!(image == expected, "{:?} image differs", compression);
+
+            // Never overwrite an existing file
+            assert!(backup(&job, &tx).is_err());
+            assert!(output.exists());
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_backup_with_shrink() {
+        let disk = sample_disk();
+        let dir = std::env::temp_dir().join(format!("pervie-shrink-backup-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let device = dir.join("disk.img");
+        fs::write(&device, &disk).unwrap();
+        let output = dir.join("backup.img.gz");
+
+        let (tx, _rx) = tokio::sync::mpsc::unbounded_channel();
+        let job = BackupJob {
+            device_path: device.to_string_lossy().into_owned(),
+            device_size: 0,
+            output: output.clone(),
+            compression: Compression::Gzip,
+            skip_unused: true,
+            shrink: true,
+            auto_expand: false,
+        };
+        let summary = backup(&job, &tx).unwrap();
+        // The partition ends after the smallest FAT16 cluster count
+        let new_size = 1024 * 1024 + (97 + 4085) * 512;
+        let shrink = summary.shrink.unwrap();
+        assert_eq!(
+            (shrink.old_size, shrink.new_size),
+            (disk.len() as u64, new_size)
+        );
+        assert_eq!(summary.raw_bytes, new_size);
+
+        let mut image = Vec::new();
+        flate2::read::GzDecoder::new(&fs::read(&output).unwrap()[..])
+            .read_to_end(&mut image)
+            .unwrap();
+        assert_eq!(image.len() as u64, new_size);
+        assert_eq!(summary.raw_sha256, format!("{:x}", Sha256::digest(&image)));
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
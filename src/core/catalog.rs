@@ -0,0 +1,312 @@
+use std::collections::HashSet;
+
+use super::Iso;
+
+/// Built-in list of ISO images offered in the picker
+pub fn default_catalog() -> Vec<Iso> {
+    vec![
+        Iso {
+            name: "Debian".to_string(),
+            version: "13".to_string(),
+            arch: "amd64".to_string(),
+            variety: "Netinst".to_string(),
+            url: "https://cdimage.debian.org/debian-cd/current/amd64/iso-cd/debian-13.2.0-amd64-netinst.iso".to_string(),
+            size_bytes: None,
+            sha256: None,
+            checksum_url: None,
+            grow_to_fill: false,
+            release_date: Some("2025-11-15".to_string()),
+            description: "Minimal network installer. Fetches packages during install.".to_string(),
+        },
+        Iso {
+            name: "Debian".to_string(),
+            version: "13".to_string(),
+            arch: "arm64".to_string(),
+            variety: "Netinst".to_string(),
+            url: "https://cdimage.debian.org/debian-cd/current/arm64/iso-cd/debian-13.2.0-arm64-netinst.iso".to_string(),
+            size_bytes: None,
+            sha256: None,
+            checksum_url: None,
+            grow_to_fill: false,
+            release_date: Some("2025-11-15".to_string()),
+            description: "Minimal network installer. Fetches packages during install.".to_string(),
+        },
+        Iso {
+            name: "Ubuntu".to_string(),
+            version: "24.04.3".to_string(),
+            arch: "amd64".to_string(),
+            variety: "Live Server".to_string(),
+            url: "https://mirror.pilotfiber.com/ubuntu-iso/24.04.3/ubuntu-24.04.3-desktop-amd64.iso".to_string(),
+            size_bytes: None,
+            sha256: None,
+            checksum_url: None,
+            grow_to_fill: false,
+            release_date: Some("2025-08-07".to_string()),
+            description: "LTS release. Supported until 2029.".to_string(),
+        },
+        Iso {
+            name: "Ubuntu".to_string(),
+            version: "24.04.3".to_string(),
+            arch: "arm64".to_string(),
+            variety: "Live Server".to_string(),
+            url: "https://cdimage.ubuntu.com/releases/24.04.3/release/ubuntu-24.04.3-live-server-arm64.iso".to_string(),
+            size_bytes: None,
+            sha256: None,
+            checksum_url: None,
+            grow_to_fill: false,
+            release_date: Some("2025-08-07".to_string()),
+            description: "LTS release. Supported until 2029.".to_string(),
+        },
+        Iso {
+            name: "Alpine".to_string(),
+            version: "3.23.2".to_string(),
+            arch: "x86_64".to_string(),
+            variety: "Standard".to_string(),
+            url: "https://dl-cdn.alpinelinux.org/alpine/v3.23/releases/x86_64/alpine-standard-3.23.2-x86_64.iso".to_string(),
+            size_bytes: None,
+            sha256: None,
+            checksum_url: None,
+            grow_to_fill: false,
+            release_date: None,
+            description: "Small musl/busybox based distribution. Runs from RAM.".to_string(),
+        },
+        Iso {
+            name: "Alpine".to_string(),
+            version: "3.23.2".to_string(),
+            arch: "aarch64".to_string(),
+            variety: "Standard".to_string(),
+            url: "https://dl-cdn.alpinelinux.org/alpine/v3.23/releases/aarch64/alpine-standard-3.23.2-aarch64.iso".to_string(),
+            size_bytes: None,
+            sha256: None,
+            checksum_url: None,
+            grow_to_fill: false,
+            release_date: None,
+            description: "Small musl/busybox based distribution. Runs from RAM.".to_string(),
+        },
+        Iso {
+            name: "Arch Linux".to_string(),
+            version: "2025.12.01".to_string(),
+            arch: "x86_64".to_string(),
+            variety: "Standard".to_string(),
+            url: "https://geo.mirror.pkgbuild.com/iso/2025.12.01/archlinux-2025.12.01-x86_64.iso".to_string(),
+            size_bytes: None,
+            sha256: None,
+            checksum_url: None,
+            grow_to_fill: false,
+            release_date: Some("2025-12-01".to_string()),
+            description: "Monthly rolling release snapshot.".to_string(),
+        },
+        // Windows 11 - Reserved until the images are uploaded to our S3 bucket
+        // (point `url` at s3://bucket/key once they are). They are detected at
+        // pre-flight and unpacked by core::windows rather than dd'd.
+        /*
+        Iso {
+            name: "Windows 11".to_string(),
+            version: "23H2".to_string(),
+            arch: "x64".to_string(),
+            variety: "English Intl".to_string(),
+            url: "https://www.microsoft.com/software-download/windows11".to_string(),
+            size_bytes: None,
+            sha256: None,
+            checksum_url: None,
+            grow_to_fill: false,
+            release_date: None,
+            description: String::new(),
+        },
+        Iso {
+            name: "Windows 11".to_string(),
+            version: "23H2 (ARM)".to_string(),
+            arch: "arm64".to_string(),
+            variety: "Insider VHDX".to_string(),
+            url: "https://www.microsoft.com/en-us/software-download/windowsinsiderpreviewARM64".to_string(),
+            size_bytes: None,
+            sha256: None,
+            checksum_url: None,
+            grow_to_fill: false,
+            release_date: None,
+            description: String::new(),
+        },
+        */
+    ]
+}
+
+/// Map the different spellings distros use onto one name per architecture
+/// (e.g. amd64/x64 -> x86_64, arm64 -> aarch64)
+pub fn normalize_arch(arch: &str) -> String {
+    match arch.to_lowercase().as_str() {
+        "amd64" | "x64" | "x86_64" => "x86_64".to_string(),
+        "arm64" | "aarch64" => "aarch64".to_string(),
+        "i386" | "i686" | "x86" => "i686".to_string(),
+        other => other.to_string(),
+    }
+}
+
+/// Architecture of the machine Pervie is running on
+pub fn host_arch() -> String {
+    normalize_arch(std::env::consts::ARCH)
+}
+
+/// Search and architecture filter applied to the ISO picker
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct IsoFilter {
+    pub query: String,
+    /// Normalized architecture, `None` shows every architecture
+    pub arch: Option<String>,
+}
+
+impl IsoFilter {
+    pub fn matches(&self, iso: &Iso) -> bool {
+        if let Some(arch) = &self.arch
+            && normalize_arch(&iso.arch) != *arch
+        {
+            return false;
+        }
+
+        let query = self.query.trim().to_lowercase();
+        if query.is_empty() {
+            return true;
+        }
+
+        let haystack = format!(
+            "{} {} {} {} {}",
+            iso.name, iso.version, iso.arch, iso.variety, iso.description
+        )
+        .to_lowercase();
+        query.split_whitespace().all(|term| haystack.contains(term))
+    }
+}
+
+/// One visible line of the grouped ISO picker
+#[derive(Debug, Clone, PartialEq)]
+pub enum IsoRow {
+    Group {
+        name: String,
+        count: usize,
+        collapsed: bool,
+    },
+    /// Index into the catalog
+    Item(usize),
+    /// "Custom URL…" entry that opens the URL input
+    CustomUrl,
+    /// "Browse mirror…" entry that opens the directory listing browser
+    BrowseMirror,
+}
+
+/// Group the filtered catalog by distro, in catalog order.
+/// Collapsed groups hide their entries unless a search query is active.
+pub fn visible_rows(isos: &[Iso], filter: &IsoFilter, collapsed: &HashSet<String>) -> Vec<IsoRow> {
+    let mut groups: Vec<(&str, Vec<usize>)> = Vec::new();
+    for (i, iso) in isos.iter().enumerate() {
+        if !filter.matches(iso) {
+            continue;
+        }
+        match groups.iter_mut().find(|(name, _)| *name == iso.name) {
+            Some((_, items)) => items.push(i),
+            None => groups.push((&iso.name, vec![i])),
+        }
+    }
+
+    let searching = !filter.query.trim().is_empty();
+    let mut rows = Vec::new();
+    for (name, items) in groups {
+        let is_collapsed = collapsed.contains(name) && !searching;
+        rows.push(IsoRow::Group {
+            name: name.to_string(),
+            count: items.len(),
+            collapsed: is_collapsed,
+        });
+        if !is_collapsed {
+            rows.extend(items.into_iter().map(IsoRow::Item));
+        }
+    }
+    rows
+}
+
+/// Architecture filter choices: host first, then all, then every other catalog arch
+pub fn arch_options(isos: &[Iso]) -> Vec<Option<String>> {
+    let host = host_arch();
+    let mut options = vec![Some(host.clone()), None];
+    for iso in isos {
+        let arch = normalize_arch(&iso.arch);
+        if arch != host && !options.contains(&Some(arch.clone())) {
+            options.push(Some(arch));
+        }
+    }
+    options
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn iso(name: &str, arch: &str, variety: &str) -> Iso {
+        Iso {
+            name: name.to_string(),
+            version: "1".to_string(),
+            arch: arch.to_string(),
+            variety: variety.to_string(),
+            url: String::new(),
+            size_bytes: None,
+            sha256: None,
+            checksum_url: None,
+            grow_to_fill: false,
+            release_date: None,
+            description: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_visible_rows_filters_and_groups() {
+        let isos = vec![
+            iso("Debian", "amd64", "Netinst"),
+            iso("Debian", "arm64", "Netinst"),
+            iso("Alpine", "x86_64", "Standard"),
+        ];
+        let filter = IsoFilter {
+            query: String::new(),
+            arch: Some("x86_64".to_string()),
+        };
+
+        let rows = visible_rows(&isos, &filter, &HashSet::new());
+        assert_eq!(
+            rows,
+            vec![
+                IsoRow::Group {
+                    name: "Debian".to_string(),
+                    count: 1,
+                    collapsed: false
+                },
+                IsoRow::Item(0),
+                IsoRow::Group {
+                    name: "Alpine".to_string(),
+                    count: 1,
+                    collapsed: false
+                },
+                IsoRow::Item(2),
+            ]
+        );
+
+        let collapsed = HashSet::from(["Debian".to_string()]);
+        let rows = visible_rows(&isos, &filter, &collapsed);
+        assert_eq!(rows.len(), 3);
+
+        // A search query expands collapsed groups
+        let filter = IsoFilter {
+            query: "net".to_string(),
+            arch: None,
+        };
+        let rows = visible_rows(&isos, &filter, &collapsed);
+        assert_eq!(
+            rows,
+            vec![
+                IsoRow::Group {
+                    name: "Debian".to_string(),
+                    count: 2,
+                    collapsed: false
+                },
+                IsoRow::Item(0),
+                IsoRow::Item(1),
+            ]
+        );
+    }
+}
@@ -0,0 +1,241 @@
+use std::fs::{self, File};
+use std::io::{Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, bail};
+
+use super::FileSystemType;
+use super::partition;
+
+/// Filesystem label cloud-init's NoCloud datasource looks for
+pub const LABEL: &str = "CIDATA";
+/// Big enough for mkfs.vfat to make FAT32, which every installer reads
+const PARTITION_SIZE: u64 = 64 * 1024 * 1024;
+const TEMPLATE_DIR: &str = "cloud-init";
+
+const AUTOINSTALL_USER_DATA: &str = "#cloud-config
+autoinstall:
+  version: 1
+  identity:
+    hostname: ubuntu-server
+    username: ubuntu
+    # The password is \"ubuntu\"; make a new hash with `openssl passwd -6`
+    password: \"$6$pervieseed$vmIEcu4UaJQLK86ysSsQijQpsX6e.JWSrmPKWq40t8MNcFHW70ORarY3pfPU4oUxPrPljl/b4SBvExqrH4BS1/\"
+  ssh:
+    install-server: true
+    allow-pw: false
+    authorized-keys: []
+  storage:
+    layout:
+      name: lvm
+";
+
+const FIRST_BOOT_USER_DATA: &str = "#cloud-config
+hostname: pervie
+users:
+  - name: admin
+    sudo: ALL=(ALL) NOPASSWD:ALL
+    shell: /bin/bash
+    ssh_authorized_keys: []
+package_update: true
+";
+
+const META_DATA: &str = "instance-id: pervie-seed\n";
+
+/// The files of a NoCloud seed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SeedFile {
+    UserData,
+    MetaData,
+    NetworkConfig,
+}
+
+impl SeedFile {
+    pub const ALL: [SeedFile; 3] = [
+        SeedFile::UserData,
+        SeedFile::MetaData,
+        SeedFile::NetworkConfig,
+    ];
+
+    pub fn file_name(&self) -> &'static str {
+        match self {
+            SeedFile::UserData => "user-data",
+            SeedFile::MetaData => "meta-data",
+            SeedFile::NetworkConfig => "network-config",
+        }
+    }
+}
+
+/// `user-data`, `meta-data` and optionally `network-config`, written onto
+/// the stick after the image
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Seed {
+    pub user_data: String,
+    pub meta_data: String,
+    /// Left out of the seed when empty
+    pub network_config: String,
+}
+
+impl Seed {
+    pub fn get(&self, file: SeedFile) -> &str {
+        match file {
+            SeedFile::UserData => &self.user_data,
+            SeedFile::MetaData => &self.meta_data,
+            SeedFile::NetworkConfig => &self.network_config,
+        }
+    }
+
+    pub fn get_mut(&mut self, file: SeedFile) -> &mut String {
+        match file {
+            SeedFile::UserData => &mut self.user_data,
+            SeedFile::MetaData => &mut self.meta_data,
+            SeedFile::NetworkConfig => &mut self.network_config,
+        }
+    }
+
+    /// Files that go into the seed partition
+    pub fn files(&self) -> Vec<SeedFile> {
+        SeedFile::ALL
+            .into_iter()
+            .filter(|f| *f != SeedFile::NetworkConfig || !self.network_config.trim().is_empty())
+            .collect()
+    }
+
+    /// Why cloud-init would ignore this seed, if it would
+    pub fn validate(&self) -> Result<(), String> {
+        let first_line = self.user_data.lines().next().unwrap_or("").trim_end();
+        if first_line != "#cloud-config" && !first_line.starts_with("#!") {
+            return Err("user-data must start with #cloud-config or a #! script line".to_string());
+        }
+        if !self
+            .meta_data
+            .lines()
+            .any(|line| line.trim_start().starts_with("instance-id:"))
+        {
+            return Err("meta-data needs an instance-id".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// A named starting point for a seed
+#[derive(Debug, Clone, PartialEq)]
+pub struct SeedTemplate {
+    pub name: String,
+    pub seed: Seed,
+}
+
+/// Built-in templates, then the user's own from
+/// `<config dir>/cloud-init/<name>/{user-data,meta-data,network-config}`
+pub fn templates() -> Vec<SeedTemplate> {
+    let mut templates = vec![
+        SeedTemplate {
+            name: "Ubuntu Server autoinstall".to_string(),
+            seed: Seed {
+                user_data: AUTOINSTALL_USER_DATA.to_string(),
+                meta_data: META_DATA.to_string(),
+                network_config: String::new(),
+            },
+        },
+        SeedTemplate {
+            name: "cloud-init first boot".to_string(),
+            seed: Seed {
+                user_data: FIRST_BOOT_USER_DATA.to_string(),
+                meta_data: META_DATA.to_string(),
+                network_config: String::new(),
+            },
+        },
+    ];
+    if let Some(dir) = crate::utils::config_dir() {
+        templates.extend(load_templates(&dir.join(TEMPLATE_DIR)));
+    }
+    templates
+}
+
+/// Every subdirectory holding a `user-data`, sorted by name
+fn load_templates(dir: &Path) -> Vec<SeedTemplate> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut dirs: Vec<PathBuf> = entries
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|path| path.join(SeedFile::UserData.file_name()).is_file())
+        .collect();
+    dirs.sort();
+    dirs.into_iter()
+        .map(|path| {
+            let mut seed = Seed::default();
+            for file in SeedFile::ALL {
+                *seed.get_mut(file) =
+                    fs::read_to_string(path.join(file.file_name())).unwrap_or_default();
+            }
+            SeedTemplate {
+                name: path
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or_default(),
+                seed,
+            }
+        })
+        .collect()
+}
+
+/// Add the seed partition in the free space after a flashed image of
+/// `image_size` bytes. Returns its partition number.
+pub fn add_partition(device_path: &str, image_size: u64) -> Result<u32> {
+    let mut device = File::open(device_path)
+        .with_context(|| format!("Failed to open device {}", device_path))?;
+    let disk_size = device.seek(SeekFrom::End(0))?;
+    let free = partition::free_space_after(&mut device, disk_size, image_size)?;
+    if free.end - free.start < PARTITION_SIZE {
+        bail!("No room for the {} partition after the image", LABEL);
+    }
+    drop(device);
+    partition::append_partition(
+        device_path,
+        free.start,
+        Some(PARTITION_SIZE),
+        FileSystemType::Fat32,
+        LABEL,
+    )
+}
+
+/// Write the seed files into the mounted seed partition
+pub fn write_seed(seed: &Seed, mount_point: &Path) -> Result<()> {
+    for file in seed.files() {
+        let path = mount_point.join(file.file_name());
+        fs::write(&path, seed.get(file))
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seed() {
+        let templates = templates();
+        let seed = &templates[0].seed;
+        assert_eq!(seed.validate(), Ok(()));
+        assert_eq!(seed.files(), vec![SeedFile::UserData, SeedFile::MetaData]);
+
+        let mut seed = seed.clone();
+        seed.network_config = "version: 2\n".to_string();
+        seed.meta_data.clear();
+        assert!(seed.validate().is_err());
+        assert_eq!(seed.files().len(), 3);
+
+        let dir = std::env::temp_dir().join(format!("pervie-seed-{}", std::process::id()));
+        fs::create_dir_all(dir.join("lab")).unwrap();
+        fs::create_dir_all(dir.join("empty")).unwrap();
+        fs::write(dir.join("lab").join("user-data"), "#cloud-config\n").unwrap();
+        let loaded = load_templates(&dir);
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(loaded[0].name, "lab");
+        assert_eq!(loaded[0].seed.user_data, "#cloud-config\n");
+        assert_eq!(loaded[0].seed.meta_data, "");
+    }
+}
@@ -0,0 +1,111 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+
+const CONFIG_FILE: &str = "config.toml";
+
+/// Settings from `config.toml` in the config directory
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub network: NetworkConfig,
+}
+
+/// `[network]`: how Pervie talks to image servers
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NetworkConfig {
+    /// Proxy for every request, e.g. `http://proxy.corp:3128`. Without it the
+    /// usual `HTTP_PROXY`/`HTTPS_PROXY`/`NO_PROXY` variables apply.
+    pub proxy: Option<String>,
+    /// Hosts or domains that bypass `proxy`
+    pub no_proxy: Vec<String>,
+    /// PEM files with extra root certificates (internal CAs)
+    pub ca_certificates: Vec<PathBuf>,
+    /// Use logins from `$NETRC` or `~/.netrc` for matching hosts
+    pub netrc: bool,
+    pub user_agent: Option<String>,
+    pub connect_timeout_secs: Option<u64>,
+    /// Longest wait for more data on an open connection
+    pub read_timeout_secs: Option<u64>,
+    /// Download cap in bytes per second; can be changed from the TUI while flashing
+    pub rate_limit: Option<u64>,
+    /// Credentials and headers per host. A key starting with `.` also
+    /// matches every subdomain.
+    pub hosts: BTreeMap<String, HostConfig>,
+}
+
+/// `[network.hosts."artifacts.corp"]`
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HostConfig {
+    /// Sent as `Authorization: Bearer <token>`
+    pub token: Option<String>,
+    /// Environment variable holding the bearer token, to keep it out of the file
+    pub token_env: Option<String>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub headers: BTreeMap<String, String>,
+}
+
+impl Config {
+    /// Load the config file. A missing file gives the defaults; a malformed
+    /// one is an error so typos don't silently drop settings.
+    pub fn load() -> Result<Self> {
+        let Some(path) = Self::path() else {
+            return Ok(Self::default());
+        };
+        match fs::read_to_string(&path) {
+            Ok(contents) => Self::parse(&contents)
+                .with_context(|| format!("Invalid config file {}", path.display())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        }
+    }
+
+    pub fn parse(contents: &str) -> Result<Self> {
+        Ok(toml::from_str(contents)?)
+    }
+
+    fn path() -> Option<PathBuf> {
+        crate::utils::config_dir().map(|dir| dir.join(CONFIG_FILE))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_network_config() {
+        let config = Config::parse(
+            r#"
+            [network]
+            proxy = "http://proxy.corp:3128"
+            no_proxy = ["localhost", ".corp"]
+            ca_certificates = ["/etc/pervie/corp-ca.pem"]
+            connect_timeout_secs = 10
+
+            [network.hosts."artifacts.corp"]
+            token_env = "ARTIFACTS_TOKEN"
+            headers = { "X-Team" = "infra" }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.network.proxy.as_deref(),
+            Some("http://proxy.corp:3128")
+        );
+        assert_eq!(config.network.no_proxy, vec!["localhost", ".corp"]);
+        assert_eq!(config.network.connect_timeout_secs, Some(10));
+        let host = &config.network.hosts["artifacts.corp"];
+        assert_eq!(host.token_env.as_deref(), Some("ARTIFACTS_TOKEN"));
+        assert_eq!(host.headers["X-Team"], "infra");
+
+        assert_eq!(Config::parse("").unwrap(), Config::default());
+        assert!(Config::parse("[network]\nproxi = \"typo\"").is_err());
+    }
+}
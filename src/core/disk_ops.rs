@@ -2,6 +2,17 @@ use async_trait::async_trait;
 
 use super::{Device, DiskError, FileSystemType};
 
+/// One partition of a new layout for `DiskManager::partition`
+#[derive(Debug, Clone, PartialEq)]
+pub struct PartitionSpec {
+    pub fs_type: FileSystemType,
+    pub label: String,
+    /// `None` takes the rest of the disk
+    pub size: Option<u64>,
+    /// Typed as an EFI system partition rather than basic data
+    pub efi_system: bool,
+}
+
 /// Trait for platform-specific disk operations
 #[async_trait]
 pub trait DiskManager: Send + Sync {
@@ -19,6 +30,27 @@ pub trait DiskManager: Send + Sync {
         label: &str,
     ) -> Result<(), DiskError>;
 
+    /// Writes a new GPT with these partitions and formats each of them.
+    /// Returns the partition device paths.
+    async fn partition(
+        &self,
+        path: &str,
+        partitions: &[PartitionSpec],
+    ) -> Result<Vec<String>, DiskError>;
+
+    /// Has the OS pick up a partition table changed behind its back
+    async fn reread_partitions(&self, path: &str) -> Result<(), DiskError>;
+
+    /// Mounts a partition, or finds where it already is. Returns the mount point.
+    async fn mount(&self, path: &str) -> Result<String, DiskError>;
+
+    /// Device path of partition `number` on the disk at `path`
+    fn partition_path(&self, path: &str, number: u32) -> String;
+
+    /// Tells the device every block is unused (TRIM). `secure` asks it to
+    /// also erase the underlying flash.
+    async fn discard(&self, path: &str, secure: bool) -> Result<(), DiskError>;
+
     /// Ejects the device (safely remove)
     async fn eject(&self, path: &str) -> Result<(), DiskError>;
 
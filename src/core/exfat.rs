@@ -0,0 +1,700 @@
+//! Writing files into an exFAT volume in place, without mounting it
+
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::time::SystemTime;
+
+use anyhow::{Context, Result, anyhow, bail};
+
+use super::filesystem::{self, ExFatLayout};
+use super::partition::{le32, le64, read_at};
+
+const ENTRY_SIZE: usize = 32;
+const ENTRY_BITMAP: u8 = 0x81;
+const ENTRY_UPCASE: u8 = 0x82;
+const ENTRY_FILE: u8 = 0x85;
+const ENTRY_STREAM: u8 = 0xc0;
+const ENTRY_NAME: u8 = 0xc1;
+/// Type codes without this bit are free slots
+const ENTRY_IN_USE: u8 = 0x80;
+const ATTR_DIRECTORY: u16 = 0x10;
+const ATTR_ARCHIVE: u16 = 0x20;
+const ALLOCATION_POSSIBLE: u8 = 0x01;
+const NO_FAT_CHAIN: u8 = 0x02;
+const FAT_END: u32 = 0xffff_ffff;
+const NAME_CHARS_PER_ENTRY: usize = 15;
+const MAX_NAME_CHARS: usize = 255;
+const VOLUME_DIRTY: u16 = 0x02;
+/// Timestamp offsets are valid and zero: times are stored in UTC
+const UTC: u8 = 0x80;
+
+/// Where a directory's entries are
+#[derive(Debug, Clone)]
+struct Dir {
+    first_cluster: u32,
+    /// Consecutive clusters, not recorded in the FAT
+    contiguous: bool,
+    len: u64,
+    /// Its entry set in the parent; the root has none
+    entry: Option<EntrySet>,
+}
+
+/// A file or directory's entries: file, stream extension and name entries
+#[derive(Debug, Clone)]
+struct EntrySet {
+    /// Volume offset of each entry; a set can span clusters
+    offsets: Vec<u64>,
+    entries: Vec<[u8; ENTRY_SIZE]>,
+}
+
+impl EntrySet {
+    fn stream(&self) -> &[u8; ENTRY_SIZE] {
+        &self.entries[1]
+    }
+
+    fn is_dir(&self) -> bool {
+        u16::from_le_bytes([self.entries[0][4], self.entries[0][5]]) & ATTR_DIRECTORY != 0
+    }
+
+    fn first_cluster(&self) -> u32 {
+        le32(&self.stream()[20..])
+    }
+
+    fn len(&self) -> u64 {
+        le64(&self.stream()[24..])
+    }
+
+    fn contiguous(&self) -> bool {
+        self.stream()[1] & NO_FAT_CHAIN != 0
+    }
+
+    fn name(&self) -> Vec<u16> {
+        let len = self.stream()[3] as usize;
+        self.entries[2..]
+            .iter()
+            .flat_map(|entry| entry[2..].chunks_exact(2))
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .take(len)
+            .collect()
+    }
+
+    fn as_dir(&self) -> Dir {
+        Dir {
+            first_cluster: self.first_cluster(),
+            contiguous: self.contiguous(),
+            len: self.len(),
+            entry: Some(self.clone()),
+        }
+    }
+
+    /// Point the stream extension at new clusters and fix the checksum
+    fn set_data(&mut self, first_cluster: u32, len: u64, contiguous: bool) {
+        let stream = &mut self.entries[1];
+        stream[1] = ALLOCATION_POSSIBLE | if contiguous { NO_FAT_CHAIN } else { 0 };
+        stream[8..16].copy_from_slice(&len.to_le_bytes());
+        stream[20..24].copy_from_slice(&first_cluster.to_le_bytes());
+        stream[24..32].copy_from_slice(&len.to_le_bytes());
+        let checksum = set_checksum(&self.entries);
+        self.entries[0][2..4].copy_from_slice(&checksum.to_le_bytes());
+    }
+}
+
+/// An exFAT volume opened for writing files into
+pub struct ExFat<F> {
+    io: F,
+    layout: ExFatLayout,
+    boot: Vec<u8>,
+    bitmap: Vec<u8>,
+    bitmap_cluster: u32,
+    /// Upper-case mapping of every UTF-16 code unit, from the volume's table
+    upcase: Vec<u16>,
+}
+
+impl<F: Read + Write + Seek> ExFat<F> {
+    /// Read the boot sector, allocation bitmap and up-case table
+    pub fn open(mut io: F) -> Result<Self> {
+        let boot = read_at(&mut io, 0, 512)?;
+        if &boot[3..11] != b"EXFAT   " {
+            bail!("Not an exFAT volume");
+        }
+        let layout = ExFatLayout::parse(&boot, 0).ok_or_else(|| anyhow!("Bad exFAT geometry"))?;
+        if boot[110] != 1 {
+            bail!("exFAT volumes with {} FATs aren't supported", boot[110]);
+        }
+
+        let root = layout.root_entries(&mut io)?;
+        let table = |kind: u8| {
+            root.iter()
+                .find(|entry| entry[0] == kind)
+                .map(|entry| (le32(&entry[20..]), le64(&entry[24..])))
+                .ok_or_else(|| anyhow!("exFAT root directory has no entry {:#x}", kind))
+        };
+        let (bitmap_cluster, bitmap_len) = table(ENTRY_BITMAP)?;
+        let (upcase_cluster, upcase_len) = table(ENTRY_UPCASE)?;
+
+        let mut volume = Self {
+            io,
+            layout,
+            boot,
+            bitmap: Vec::new(),
+            bitmap_cluster,
+            upcase: Vec::new(),
+        };
+        volume.bitmap = volume.read_chain(bitmap_cluster, bitmap_len)?;
+        let upcase = volume.read_chain(upcase_cluster, upcase_len)?;
+        volume.upcase = expand_upcase(&upcase);
+        Ok(volume)
+    }
+
+    /// Write `len` bytes from `source` to `path`, replacing any file there
+    /// and creating missing directories
+    pub fn write_file(&mut self, path: &str, source: &mut impl Read, len: u64) -> Result<()> {
+        let (parents, name) = split_path(path)?;
+        self.set_dirty(true)?;
+
+        let mut dir = self.root();
+        for part in parents {
+            dir = match self.find(&dir, part)? {
+                Some(found) if found.is_dir() => found.as_dir(),
+                Some(_) => bail!("{} is a file, not a directory", part),
+                None => self.create_dir(&dir, part)?,
+            };
+        }
+        if let Some(existing) = self.find(&dir, name)? {
+            if existing.is_dir() {
+                bail!("{} is a directory", path);
+            }
+            self.delete(existing)?;
+        }
+
+        let (clusters, contiguous) = self.allocate(len.div_ceil(self.layout.cluster_size))?;
+        let mut buf = vec![0u8; self.layout.cluster_size as usize];
+        let mut left = len;
+        for cluster in &clusters {
+            let chunk = left.min(buf.len() as u64) as usize;
+            source
+                .read_exact(&mut buf[..chunk])
+                .context("Failed to read the file to write")?;
+            self.write_at(self.layout.cluster_at(*cluster), &buf[..chunk])?;
+            left -= chunk as u64;
+        }
+        let first = clusters.first().copied().unwrap_or(0);
+        let entries = new_entry_set(name, ATTR_ARCHIVE, first, len, contiguous, &self.upcase)?;
+        self.insert(&mut dir, entries)?;
+
+        self.write_bitmap()?;
+        self.set_dirty(false)?;
+        self.io.flush()?;
+        Ok(())
+    }
+
+    /// Copy the file at `path` into `out`
+    pub fn read_file(&mut self, path: &str, out: &mut impl Write) -> Result<u64> {
+        let (parents, name) = split_path(path)?;
+        let mut dir = self.root();
+        for part in parents {
+            dir = match self.find(&dir, part)? {
+                Some(found) if found.is_dir() => found.as_dir(),
+                _ => bail!("No directory {} in {}", part, path),
+            };
+        }
+        let file = match self.find(&dir, name)? {
+            Some(found) if !found.is_dir() => found,
+            _ => bail!("{} not found", path),
+        };
+
+        let mut left = file.len();
+        for cluster in self.clusters(file.first_cluster(), left, file.contiguous())? {
+            let chunk = left.min(self.layout.cluster_size) as usize;
+            let data = read_at(&mut self.io, self.layout.cluster_at(cluster), chunk)?;
+            out.write_all(&data)?;
+            left -= chunk as u64;
+        }
+        Ok(file.len())
+    }
+
+    fn root(&self) -> Dir {
+        Dir {
+            first_cluster: self.layout.root_cluster,
+            contiguous: false,
+            len: 0,
+            entry: None,
+        }
+    }
+
+    /// Clusters holding `len` bytes from `first`. The root directory's
+    /// length isn't recorded anywhere, so for it (`len` 0) the FAT decides.
+    fn clusters(&mut self, first: u32, len: u64, contiguous: bool) -> Result<Vec<u32>> {
+        let count = len.div_ceil(self.layout.cluster_size) as usize;
+        if contiguous {
+            return Ok((first..).take(count).collect());
+        }
+        let chain = filesystem::exfat_chain(
+            &mut self.io,
+            self.layout.fat_start,
+            first,
+            self.layout.cluster_count,
+        )?;
+        Ok(match len {
+            0 => chain,
+            _ => chain.into_iter().take(count).collect(),
+        })
+    }
+
+    fn dir_clusters(&mut self, dir: &Dir) -> Result<Vec<u32>> {
+        match dir.entry {
+            None => self.clusters(dir.first_cluster, 0, false),
+            Some(_) => self.clusters(dir.first_cluster, dir.len, dir.contiguous),
+        }
+    }
+
+    fn read_chain(&mut self, first: u32, len: u64) -> Result<Vec<u8>> {
+        let mut data = Vec::with_capacity(len as usize);
+        for cluster in self.clusters(first, len, false)? {
+            let at = self.layout.cluster_at(cluster);
+            data.extend(read_at(
+                &mut self.io,
+                at,
+                self.layout.cluster_size as usize,
+            )?);
+        }
+        data.truncate(len as usize);
+        Ok(data)
+    }
+
+    /// Every entry slot of a directory, with its volume offset
+    fn slots(&mut self, dir: &Dir) -> Result<Vec<(u64, [u8; ENTRY_SIZE])>> {
+        let mut slots = Vec::new();
+        for cluster in self.dir_clusters(dir)? {
+            let at = self.layout.cluster_at(cluster);
+            let data = read_at(&mut self.io, at, self.layout.cluster_size as usize)?;
+            for (i, entry) in data.chunks_exact(ENTRY_SIZE).enumerate() {
+                let mut slot = [0u8; ENTRY_SIZE];
+                slot.copy_from_slice(entry);
+                slots.push((at + (i * ENTRY_SIZE) as u64, slot));
+            }
+        }
+        Ok(slots)
+    }
+
+    /// The entry set named `name` in `dir`, compared the way exFAT does:
+    /// through the volume's up-case table
+    fn find(&mut self, dir: &Dir, name: &str) -> Result<Option<EntrySet>> {
+        let wanted: Vec<u16> = name.encode_utf16().map(|c| self.upcase(c)).collect();
+        let slots = self.slots(dir)?;
+        let mut i = 0;
+        while i < slots.len() {
+            let kind = slots[i].1[0];
+            if kind == 0 {
+                break;
+            }
+            let secondary = slots[i].1[1] as usize;
+            if kind != ENTRY_FILE || secondary < 2 || i + secondary >= slots.len() {
+                i += 1;
+                continue;
+            }
+            let set = EntrySet {
+                offsets: slots[i..=i + secondary].iter().map(|s| s.0).collect(),
+                entries: slots[i..=i + secondary].iter().map(|s| s.1).collect(),
+            };
+            if set.stream()[0] == ENTRY_STREAM
+                && set
+                    .name()
+                    .iter()
+                    .map(|c| self.upcase(*c))
+                    .eq(wanted.iter().copied())
+            {
+                return Ok(Some(set));
+            }
+            i += secondary + 1;
+        }
+        Ok(None)
+    }
+
+    fn upcase(&self, c: u16) -> u16 {
+        self.upcase[c as usize]
+    }
+
+    fn create_dir(&mut self, parent: &Dir, name: &str) -> Result<Dir> {
+        let (clusters, _) = self.allocate(1)?;
+        let cluster = clusters[0];
+        let size = self.layout.cluster_size;
+        self.write_at(self.layout.cluster_at(cluster), &vec![0u8; size as usize])?;
+        let entries = new_entry_set(name, ATTR_DIRECTORY, cluster, size, true, &self.upcase)?;
+        let mut parent = parent.clone();
+        let set = self.insert(&mut parent, entries)?;
+        Ok(set.as_dir())
+    }
+
+    /// Free a file's clusters and its directory entries
+    fn delete(&mut self, set: EntrySet) -> Result<()> {
+        let clusters = self.clusters(set.first_cluster(), set.len(), set.contiguous())?;
+        for cluster in clusters {
+            self.set_used(cluster, false);
+            if !set.contiguous() {
+                self.write_fat(cluster, 0)?;
+            }
+        }
+        for (offset, entry) in set.offsets.iter().zip(&set.entries) {
+            self.write_at(*offset, &[entry[0] & !ENTRY_IN_USE])?;
+        }
+        Ok(())
+    }
+
+    /// Put a new entry set into `dir`, growing the directory if it's full
+    fn insert(&mut self, dir: &mut Dir, entries: Vec<[u8; ENTRY_SIZE]>) -> Result<EntrySet> {
+        loop {
+            let slots = self.slots(dir)?;
+            let mut run = 0;
+            for (i, (_, slot)) in slots.iter().enumerate() {
+                run = if slot[0] & ENTRY_IN_USE == 0 {
+                    run + 1
+                } else {
+                    0
+                };
+                if run == entries.len() {
+                    let start = i + 1 - run;
+                    let set = EntrySet {
+                        offsets: slots[start..=i].iter().map(|s| s.0).collect(),
+                        entries,
+                    };
+                    for (offset, entry) in set.offsets.iter().zip(&set.entries) {
+                        self.write_at(*offset, entry)?;
+                    }
+                    return Ok(set);
+                }
+            }
+            self.grow_dir(dir)?;
+        }
+    }
+
+    /// Add a zeroed cluster to a directory
+    fn grow_dir(&mut self, dir: &mut Dir) -> Result<()> {
+        let clusters = self.dir_clusters(dir)?;
+        let last = *clusters
+            .last()
+            .ok_or_else(|| anyhow!("Directory has no clusters"))?;
+        let next = last + 1;
+        let extends = dir.contiguous
+            && u64::from(next) < self.layout.cluster_count + 2
+            && !self.is_used(next);
+        let added = if extends {
+            self.set_used(next, true);
+            next
+        } else {
+            let (allocated, _) = self.allocate(1)?;
+            if dir.contiguous {
+                // Now fragmented: the FAT has to describe the whole chain
+                let mut chain = clusters;
+                chain.push(allocated[0]);
+                self.write_chain(&chain)?;
+            } else {
+                self.write_chain(&[last, allocated[0]])?;
+            }
+            dir.contiguous = false;
+            allocated[0]
+        };
+        let size = self.layout.cluster_size;
+        self.write_at(self.layout.cluster_at(added), &vec![0u8; size as usize])?;
+
+        // The root's length lives in the FAT alone; others record theirs
+        if let Some(set) = dir.entry.as_mut() {
+            dir.len += size;
+            set.set_data(dir.first_cluster, dir.len, dir.contiguous);
+            for (offset, entry) in set.offsets.iter().zip(&set.entries) {
+                self.write_at(*offset, entry)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Mark `count` free clusters used, consecutive ones if there's a long
+    /// enough run. Returns them and whether they are consecutive.
+    fn allocate(&mut self, count: u64) -> Result<(Vec<u32>, bool)> {
+        if count == 0 {
+            return Ok((Vec::new(), true));
+        }
+        let last = self.layout.cluster_count as u32 + 1;
+        let mut run_start = 2;
+        let mut run = 0;
+        let mut scattered = Vec::new();
+        for cluster in 2..=last {
+            if self.is_used(cluster) {
+                run = 0;
+                continue;
+            }
+            if run == 0 {
+                run_start = cluster;
+            }
+            run += 1;
+            if (scattered.len() as u64) < count {
+                scattered.push(cluster);
+            }
+            if run == count {
+                let clusters: Vec<u32> = (run_start..=cluster).collect();
+                clusters.iter().for_each(|c| self.set_used(*c, true));
+                return Ok((clusters, true));
+            }
+        }
+        if (scattered.len() as u64) < count {
+            bail!("Not enough free space on the exFAT volume");
+        }
+        scattered.iter().for_each(|c| self.set_used(*c, true));
+        self.write_chain(&scattered)?;
+        Ok((scattered, false))
+    }
+
+    fn is_used(&self, cluster: u32) -> bool {
+        let index = (cluster - 2) as usize;
+        self.bitmap[index / 8] & (1 << (index % 8)) != 0
+    }
+
+    fn set_used(&mut self, cluster: u32, used: bool) {
+        let index = (cluster - 2) as usize;
+        if used {
+            self.bitmap[index / 8] |= 1 << (index % 8);
+        } else {
+            self.bitmap[index / 8] &= !(1 << (index % 8));
+        }
+    }
+
+    /// Link `clusters` in the FAT, ending the chain after the last one
+    fn write_chain(&mut self, clusters: &[u32]) -> Result<()> {
+        for (i, cluster) in clusters.iter().enumerate() {
+            let next = clusters.get(i + 1).copied().unwrap_or(FAT_END);
+            self.write_fat(*cluster, next)?;
+        }
+        Ok(())
+    }
+
+    fn write_fat(&mut self, cluster: u32, next: u32) -> Result<()> {
+        let at = self.layout.fat_start + u64::from(cluster) * 4;
+        self.write_at(at, &next.to_le_bytes())
+    }
+
+    /// Store the bitmap and the boot sector's in-use percentage
+    fn write_bitmap(&mut self) -> Result<()> {
+        let bitmap = self.bitmap.clone();
+        let size = self.layout.cluster_size as usize;
+        let clusters = self.clusters(self.bitmap_cluster, bitmap.len() as u64, false)?;
+        for (cluster, chunk) in clusters.iter().zip(bitmap.chunks(size)) {
+            self.write_at(self.layout.cluster_at(*cluster), chunk)?;
+        }
+
+        // 0xff means the volume doesn't keep track; the boot checksum skips it
+        if self.boot[112] != 0xff {
+            let used: u64 = bitmap.iter().map(|b| u64::from(b.count_ones())).sum();
+            let percent = (used * 100 / self.layout.cluster_count.max(1)) as u8;
+            self.boot[112] = percent;
+            self.write_at(112, &[percent])?;
+        }
+        Ok(())
+    }
+
+    /// Flag the volume as being written, so an interrupted write gets checked
+    fn set_dirty(&mut self, dirty: bool) -> Result<()> {
+        let mut flags = u16::from_le_bytes([self.boot[106], self.boot[107]]);
+        if dirty {
+            flags |= VOLUME_DIRTY;
+        } else {
+            flags &= !VOLUME_DIRTY;
+        }
+        self.boot[106..108].copy_from_slice(&flags.to_le_bytes());
+        self.write_at(106, &flags.to_le_bytes())?;
+        self.io.flush()?;
+        Ok(())
+    }
+
+    fn write_at(&mut self, offset: u64, data: &[u8]) -> Result<()> {
+        self.io.seek(SeekFrom::Start(offset))?;
+        self.io.write_all(data)?;
+        Ok(())
+    }
+}
+
+/// Directory names and the file name of an absolute path
+fn split_path(path: &str) -> Result<(Vec<&str>, &str)> {
+    let mut parts: Vec<&str> = path.split('/').filter(|p| !p.is_empty()).collect();
+    let name = parts
+        .pop()
+        .ok_or_else(|| anyhow!("No file name in {}", path))?;
+    Ok((parts, name))
+}
+
+/// File, stream extension and name entries for a new file or directory
+fn new_entry_set(
+    name: &str,
+    attributes: u16,
+    first_cluster: u32,
+    len: u64,
+    contiguous: bool,
+    upcase: &[u16],
+) -> Result<Vec<[u8; ENTRY_SIZE]>> {
+    let name_chars: Vec<u16> = name.encode_utf16().collect();
+    if name_chars.len() > MAX_NAME_CHARS
+        || name.chars().any(|c| c < ' ' || "\"*/:<>?\\|".contains(c))
+    {
+        bail!("{} isn't a valid exFAT file name", name);
+    }
+    let name_entries = name_chars.len().div_ceil(NAME_CHARS_PER_ENTRY);
+
+    let now = timestamp(SystemTime::now()).to_le_bytes();
+    let mut file = [0u8; ENTRY_SIZE];
+    file[0] = ENTRY_FILE;
+    file[1] = (1 + name_entries) as u8;
+    file[4..6].copy_from_slice(&attributes.to_le_bytes());
+    for at in [8, 12, 16] {
+        file[at..at + 4].copy_from_slice(&now);
+    }
+    file[22..25].copy_from_slice(&[UTC; 3]);
+
+    let mut stream = [0u8; ENTRY_SIZE];
+    stream[0] = ENTRY_STREAM;
+    stream[3] = name_chars.len() as u8;
+    stream[4..6].copy_from_slice(&name_hash(&name_chars, upcase).to_le_bytes());
+
+    let mut entries = vec![file, stream];
+    for chunk in name_chars.chunks(NAME_CHARS_PER_ENTRY) {
+        let mut entry = [0u8; ENTRY_SIZE];
+        entry[0] = ENTRY_NAME;
+        for (i, c) in chunk.iter().enumerate() {
+            entry[2 + i * 2..4 + i * 2].copy_from_slice(&c.to_le_bytes());
+        }
+        entries.push(entry);
+    }
+
+    let mut set = EntrySet {
+        offsets: Vec::new(),
+        entries,
+    };
+    set.set_data(first_cluster, len, contiguous);
+    Ok(set.entries)
+}
+
+/// Checksum over a whole entry set, skipping the checksum field itself
+fn set_checksum(entries: &[[u8; ENTRY_SIZE]]) -> u16 {
+    let mut checksum = 0u16;
+    for (i, byte) in entries.iter().flatten().enumerate() {
+        if i == 2 || i == 3 {
+            continue;
+        }
+        checksum = checksum.rotate_right(1).wrapping_add(u16::from(*byte));
+    }
+    checksum
+}
+
+/// Hash of the up-cased name, for quick lookups by other implementations
+fn name_hash(name: &[u16], upcase: &[u16]) -> u16 {
+    let mut hash = 0u16;
+    for c in name {
+        for byte in upcase[*c as usize].to_le_bytes() {
+            hash = hash.rotate_right(1).wrapping_add(u16::from(byte));
+        }
+    }
+    hash
+}
+
+/// The up-case table maps code units in order; 0xffff followed by a count
+/// stands for that many code units that map to themselves
+fn expand_upcase(table: &[u8]) -> Vec<u16> {
+    let mut upcase: Vec<u16> = (0..=u16::MAX).collect();
+    let mut values = table
+        .chunks_exact(2)
+        .map(|c| u16::from_le_bytes([c[0], c[1]]));
+    let mut next = 0usize;
+    while let Some(value) = values.next() {
+        if next >= upcase.len() {
+            break;
+        }
+        if value == 0xffff {
+            next += values.next().unwrap_or(0) as usize;
+        } else {
+            upcase[next] = value;
+            next += 1;
+        }
+    }
+    upcase
+}
+
+/// exFAT timestamp in UTC: date in the high half, time (2 s units) in the low
+fn timestamp(time: SystemTime) -> u32 {
+    let (year, month, day, hour, minute, second) = crate::utils::utc_datetime(time);
+    if year < 1980 {
+        return 0x0021_0000;
+    }
+    (((year - 1980) as u32) << 25)
+        | ((month as u32) << 21)
+        | ((day as u32) << 16)
+        | ((hour as u32) << 11)
+        | ((minute as u32) << 5)
+        | ((second as u32) / 2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::filesystem::FsKind;
+    use std::io::Cursor;
+
+    fn fixture() -> Cursor<Vec<u8>> {
+        let mut image = Vec::new();
+        flate2::read::GzDecoder::new(&include_bytes!("../../tests/fixtures/fs/exfat.img.gz")[..])
+            .read_to_end(&mut image)
+            .unwrap();
+        Cursor::new(image)
+    }
+
+    fn read(volume: &mut ExFat<&mut Cursor<Vec<u8>>>, path: &str) -> Vec<u8> {
+        let mut data = Vec::new();
+        volume.read_file(path, &mut data).unwrap();
+        data
+    }
+
+    #[test]
+    fn test_write_and_read_back() {
+        let config: Vec<u8> = (0..10_000u32).map(|i| i as u8).collect();
+        let mut image = fixture();
+        let mut volume = ExFat::open(&mut image).unwrap();
+        volume
+            .write_file("/boot/grub/grub.cfg", &mut &config[..], config.len() as u64)
+            .unwrap();
+        volume
+            .write_file("/readme.txt", &mut &b"old"[..], 3)
+            .unwrap();
+        volume
+            .write_file("/readme.txt", &mut &b"new"[..], 3)
+            .unwrap();
+        // Enough entries to outgrow the directory's first cluster
+        for i in 0..60 {
+            let name = format!("/keys/key-{:02}.pub", i);
+            volume
+                .write_file(&name, &mut name.as_bytes(), name.len() as u64)
+                .unwrap();
+        }
+        volume.write_file("/empty", &mut &b""[..], 0).unwrap();
+        assert!(
+            volume
+                .write_file("/readme.txt/x", &mut &b""[..], 0)
+                .is_err()
+        );
+
+        drop(volume);
+        assert_eq!(
+            filesystem::detect(&mut image, 0).unwrap(),
+            Some(FsKind::ExFat)
+        );
+        assert_eq!(
+            filesystem::label(&mut image, 0).unwrap().as_deref(),
+            Some("DATA")
+        );
+        let mut volume = ExFat::open(&mut image).unwrap();
+        assert_eq!(read(&mut volume, "/boot/grub/grub.cfg"), config);
+        assert_eq!(read(&mut volume, "/BOOT/Grub/GRUB.CFG"), config);
+        assert_eq!(read(&mut volume, "/readme.txt"), b"new");
+        for i in 0..60 {
+            let name = format!("/keys/key-{:02}.pub", i);
+            assert_eq!(read(&mut volume, &name), name.as_bytes());
+        }
+        assert!(read(&mut volume, "/empty").is_empty());
+        assert!(volume.read_file("/missing", &mut Vec::new()).is_err());
+    }
+}
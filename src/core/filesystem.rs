@@ -0,0 +1,848 @@
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+
+use anyhow::Result;
+
+use super::partition::{le32, le64, read_at};
+
+const EXT_MAGIC: u16 = 0xef53;
+const EXT_INCOMPAT_META_BG: u32 = 0x10;
+const EXT_INCOMPAT_64BIT: u32 = 0x80;
+const EXT_RO_COMPAT_SPARSE_SUPER: u32 = 0x1;
+const EXT_COMPAT_SPARSE_SUPER2: u32 = 0x200;
+const EXT_BG_BLOCK_UNINIT: u16 = 0x2;
+/// Cluster chains longer than this are assumed to be corrupt loops
+const MAX_CHAIN: usize = 1 << 24;
+/// More block groups than this (a 128 TiB filesystem) means a corrupt superblock
+const MAX_EXT_GROUPS: u64 = 1 << 20;
+
+/// Filesystems Pervie can find inside a partition
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsKind {
+    Fat12,
+    Fat16,
+    Fat32,
+    ExFat,
+    Ntfs,
+    Ext,
+}
+
+impl FsKind {
+    pub fn display_name(self) -> &'static str {
+        match self {
+            FsKind::Fat12 => "FAT12",
+            FsKind::Fat16 => "FAT16",
+            FsKind::Fat32 => "FAT32",
+            FsKind::ExFat => "exFAT",
+            FsKind::Ntfs => "NTFS",
+            FsKind::Ext => "ext2/3/4",
+        }
+    }
+}
+
+/// Identify the filesystem starting at `offset`
+pub fn detect<R: Read + Seek>(reader: &mut R, offset: u64) -> Result<Option<FsKind>> {
+    let head = read_at(reader, offset, 2048)?;
+    Ok(detect_in(&head))
+}
+
+/// Bytes the filesystem at `offset` spans, from its superblock or boot sector
+pub fn filesystem_size<R: Read + Seek>(reader: &mut R, offset: u64) -> Result<Option<u64>> {
+    let head = read_at(reader, offset, 2048)?;
+    let size = match detect_in(&head) {
+        Some(FsKind::Fat12 | FsKind::Fat16 | FsKind::Fat32) => {
+            FatLayout::parse(&head).map(|fat| fat.total_sectors * fat.bytes_per_sector)
+        }
+        Some(FsKind::ExFat) => (9..=12)
+            .contains(&head[108])
+            .then(|| le64(&head[72..]) << head[108]),
+        // The backup boot sector sits just past the counted sectors
+        Some(FsKind::Ntfs) => {
+            let bytes_per_sector = u64::from(u16::from_le_bytes([head[11], head[12]]));
+            Some((le64(&head[40..]) + 1) * bytes_per_sector)
+        }
+        Some(FsKind::Ext) => {
+            let sb = &head[1024..];
+            let mut blocks = u64::from(le32(&sb[4..]));
+            if le32(&sb[96..]) & EXT_INCOMPAT_64BIT != 0 {
+                blocks |= u64::from(le32(&sb[336..])) << 32;
+            }
+            let log_block_size = le32(&sb[24..]);
+            (log_block_size <= 6).then(|| blocks * (1024 << log_block_size))
+        }
+        None => None,
+    };
+    Ok(size)
+}
+
+/// Volume label of the filesystem at `offset`. NTFS keeps its label in an
+/// MFT record and isn't read.
+pub fn label<R: Read + Seek>(reader: &mut R, offset: u64) -> Result<Option<String>> {
+    let head = read_at(reader, offset, 2048)?;
+    let label = match detect_in(&head) {
+        Some(FsKind::Fat12 | FsKind::Fat16) => Some(fat_label(&head[43..54])),
+        Some(FsKind::Fat32) => Some(fat_label(&head[71..82])),
+        Some(FsKind::ExFat) => match ExFatLayout::parse(&head, offset) {
+            Some(layout) => layout
+                .root_entries(reader)?
+                .into_iter()
+                .find(|entry| entry[0] == 0x83)
+                .map(|entry| {
+                    let len = usize::from(entry[1].min(11));
+                    let units: Vec<u16> = entry[2..2 + len * 2]
+                        .chunks_exact(2)
+                        .map(|c| u16::from_le_bytes([c[0], c[1]]))
+                        .collect();
+                    String::from_utf16_lossy(&units)
+                }),
+            None => None,
+        },
+        Some(FsKind::Ext) => {
+            let name = &head[1024 + 120..1024 + 136];
+            let len = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+            Some(String::from_utf8_lossy(&name[..len]).into_owned())
+        }
+        Some(FsKind::Ntfs) | None => None,
+    };
+    Ok(label.filter(|label| !label.is_empty()))
+}
+
+/// FAT pads labels with spaces and writes `NO NAME` when there is none
+fn fat_label(raw: &[u8]) -> String {
+    let label = String::from_utf8_lossy(raw)
+        .trim_end_matches([' ', '\0'])
+        .to_string();
+    if label == "NO NAME" {
+        String::new()
+    } else {
+        label
+    }
+}
+
+/// Cut a FAT filesystem short after its last used cluster, without moving
+/// any data. Returns the new size in bytes, or `None` if it isn't FAT.
+pub fn shrink_fat<F: Read + Write + Seek>(file: &mut F, offset: u64) -> Result<Option<u64>> {
+    let boot = read_at(file, offset, 512)?;
+    let Some(fat) = FatLayout::parse(&boot) else {
+        return Ok(None);
+    };
+    let last_used = fat_usage(file, offset, &fat)?
+        .iter()
+        .rposition(|used| *used)
+        .map_or(0, |i| i as u64 + 1);
+    // The cluster count decides the FAT type, so it must stay in the same band
+    let min_clusters = match fat.kind {
+        FsKind::Fat12 => 1,
+        FsKind::Fat16 => 4085,
+        _ => 65525,
+    };
+    let clusters = last_used.max(min_clusters).min(fat.clusters);
+    let total_sectors = fat.first_data_sector + clusters * fat.sectors_per_cluster;
+
+    let mut patched = boot.clone();
+    if u16::from_le_bytes([boot[19], boot[20]]) != 0 && total_sectors <= u64::from(u16::MAX) {
+        patched[19..21].copy_from_slice(&(total_sectors as u16).to_le_bytes());
+    } else {
+        patched[19..21].copy_from_slice(&[0, 0]);
+        patched[32..36].copy_from_slice(&(total_sectors as u32).to_le_bytes());
+    }
+    write_at(file, offset, &patched)?;
+
+    if fat.kind == FsKind::Fat32 {
+        // FAT32 keeps a backup boot sector, and an FSInfo free count that is now wrong
+        let backup_sector = u64::from(u16::from_le_bytes([boot[50], boot[51]]));
+        if backup_sector != 0 && backup_sector < fat.reserved_sectors {
+            write_at(
+                file,
+                offset + backup_sector * fat.bytes_per_sector,
+                &patched,
+            )?;
+        }
+        let fs_info = u64::from(u16::from_le_bytes([boot[48], boot[49]]));
+        if fs_info != 0 && fs_info < fat.reserved_sectors {
+            write_at(
+                file,
+                offset + fs_info * fat.bytes_per_sector + 488,
+                &u32::MAX.to_le_bytes(),
+            )?;
+        }
+    }
+    Ok(Some(total_sectors * fat.bytes_per_sector))
+}
+
+fn write_at<F: Write + Seek>(file: &mut F, offset: u64, data: &[u8]) -> Result<()> {
+    file.seek(SeekFrom::Start(offset))?;
+    file.write_all(data)?;
+    Ok(())
+}
+
+fn detect_in(head: &[u8]) -> Option<FsKind> {
+    match &head[3..11] {
+        b"NTFS    " => return Some(FsKind::Ntfs),
+        b"EXFAT   " => return Some(FsKind::ExFat),
+        _ => {}
+    }
+    if u16::from_le_bytes([head[1080], head[1081]]) == EXT_MAGIC {
+        return Some(FsKind::Ext);
+    }
+    FatLayout::parse(head).map(|fat| fat.kind)
+}
+
+/// Byte ranges, relative to the start of the disk, that the filesystem at
+/// `offset` has in use (metadata included). `None` when the filesystem isn't
+/// one we can read, so the caller keeps the whole partition.
+pub fn used_ranges<R: Read + Seek>(reader: &mut R, offset: u64) -> Result<Option<Vec<Range<u64>>>> {
+    let head = read_at(reader, offset, 2048)?;
+    let ranges = match detect_in(&head) {
+        Some(FsKind::Fat12 | FsKind::Fat16 | FsKind::Fat32) => match FatLayout::parse(&head) {
+            Some(fat) => fat_used(reader, offset, &fat)?,
+            None => return Ok(None),
+        },
+        Some(FsKind::ExFat) => exfat_used(reader, offset, &head)?,
+        Some(FsKind::Ntfs) => ntfs_used(reader, offset, &head)?,
+        Some(FsKind::Ext) => ext_used(reader, offset, &head[1024..])?,
+        None => None,
+    };
+    Ok(ranges.map(merge_ranges))
+}
+
+/// Sort ranges and join the ones that touch or overlap
+pub fn merge_ranges(mut ranges: Vec<Range<u64>>) -> Vec<Range<u64>> {
+    ranges.retain(|r| r.start < r.end);
+    ranges.sort_by_key(|r| r.start);
+    let mut merged: Vec<Range<u64>> = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+            _ => merged.push(range),
+        }
+    }
+    merged
+}
+
+/// Collects runs of used allocation units into byte ranges
+struct RunBuilder {
+    base: u64,
+    unit: u64,
+    ranges: Vec<Range<u64>>,
+    run: Option<Range<u64>>,
+}
+
+impl RunBuilder {
+    fn new(base: u64, unit: u64) -> Self {
+        Self {
+            base,
+            unit,
+            ranges: Vec::new(),
+            run: None,
+        }
+    }
+
+    fn mark(&mut self, index: u64) {
+        match &mut self.run {
+            Some(run) if run.end == index => run.end += 1,
+            _ => {
+                self.flush();
+                self.run = Some(index..index + 1);
+            }
+        }
+    }
+
+    fn flush(&mut self) {
+        if let Some(run) = self.run.take() {
+            self.ranges
+                .push(self.base + run.start * self.unit..self.base + run.end * self.unit);
+        }
+    }
+
+    fn finish(mut self) -> Vec<Range<u64>> {
+        self.flush();
+        self.ranges
+    }
+}
+
+struct FatLayout {
+    kind: FsKind,
+    bytes_per_sector: u64,
+    sectors_per_cluster: u64,
+    reserved_sectors: u64,
+    fat_sectors: u64,
+    first_data_sector: u64,
+    total_sectors: u64,
+    clusters: u64,
+}
+
+impl FatLayout {
+    fn parse(boot: &[u8]) -> Option<Self> {
+        let bytes_per_sector = u64::from(u16::from_le_bytes([boot[11], boot[12]]));
+        let sectors_per_cluster = u64::from(boot[13]);
+        let reserved_sectors = u64::from(u16::from_le_bytes([boot[14], boot[15]]));
+        let fats = u64::from(boot[16]);
+        let root_entries = u64::from(u16::from_le_bytes([boot[17], boot[18]]));
+        let total_sectors = match u16::from_le_bytes([boot[19], boot[20]]) {
+            0 => u64::from(le32(&boot[32..])),
+            n => u64::from(n),
+        };
+        let fat_sectors = match u16::from_le_bytes([boot[22], boot[23]]) {
+            0 => u64::from(le32(&boot[36..])),
+            n => u64::from(n),
+        };
+        if boot[510..512] != [0x55, 0xaa]
+            || !matches!(bytes_per_sector, 512 | 1024 | 2048 | 4096)
+            || !sectors_per_cluster.is_power_of_two()
+            || reserved_sectors == 0
+            || !(1..=4).contains(&fats)
+            || fat_sectors == 0
+            || boot[21] < 0xf0
+        {
+            return None;
+        }
+
+        let root_dir_sectors = (root_entries * 32).div_ceil(bytes_per_sector);
+        let first_data_sector = reserved_sectors + fats * fat_sectors + root_dir_sectors;
+        let clusters = total_sectors.checked_sub(first_data_sector)? / sectors_per_cluster;
+        let kind = match clusters {
+            0..4085 => FsKind::Fat12,
+            4085..65525 => FsKind::Fat16,
+            _ => FsKind::Fat32,
+        };
+        Some(Self {
+            kind,
+            bytes_per_sector,
+            sectors_per_cluster,
+            reserved_sectors,
+            fat_sectors,
+            first_data_sector,
+            total_sectors,
+            clusters,
+        })
+    }
+}
+
+fn fat_used<R: Read + Seek>(
+    reader: &mut R,
+    offset: u64,
+    fat: &FatLayout,
+) -> Result<Option<Vec<Range<u64>>>> {
+    let cluster_size = fat.sectors_per_cluster * fat.bytes_per_sector;
+    let data_start = offset + fat.first_data_sector * fat.bytes_per_sector;
+    let mut runs = RunBuilder::new(data_start, cluster_size);
+    for (cluster, used) in fat_usage(reader, offset, fat)?.into_iter().enumerate() {
+        if used {
+            runs.mark(cluster as u64);
+        }
+    }
+    let mut ranges = runs.finish();
+    // Boot sector, FATs and the FAT12/16 root directory
+    ranges.push(offset..data_start);
+    Ok(Some(ranges))
+}
+
+/// Whether each data cluster, counting from cluster 2, is allocated
+fn fat_usage<R: Read + Seek>(reader: &mut R, offset: u64, fat: &FatLayout) -> Result<Vec<bool>> {
+    let fat_bytes = read_at(
+        reader,
+        offset + fat.reserved_sectors * fat.bytes_per_sector,
+        (fat.fat_sectors * fat.bytes_per_sector) as usize,
+    )?;
+    let entry = |n: u64| -> u32 {
+        let n = n as usize;
+        match fat.kind {
+            FsKind::Fat12 => {
+                let at = n + n / 2;
+                let byte = |i: usize| fat_bytes.get(i).copied().unwrap_or(0);
+                let pair = u16::from_le_bytes([byte(at), byte(at + 1)]);
+                u32::from(if n.is_multiple_of(2) {
+                    pair & 0xfff
+                } else {
+                    pair >> 4
+                })
+            }
+            FsKind::Fat16 => {
+                u32::from(u16::from_le_bytes([fat_bytes[n * 2], fat_bytes[n * 2 + 1]]))
+            }
+            _ => le32(&fat_bytes[n * 4..]) & 0x0fff_ffff,
+        }
+    };
+    let entry_bits = match fat.kind {
+        FsKind::Fat12 => 12,
+        FsKind::Fat16 => 16,
+        _ => 32,
+    };
+    // Never trust the cluster count beyond what the FAT can describe
+    let clusters = fat
+        .clusters
+        .min((fat_bytes.len() as u64 * 8 / entry_bits).saturating_sub(2));
+    Ok((0..clusters)
+        .map(|cluster| entry(cluster + 2) != 0)
+        .collect())
+}
+
+/// Where an exFAT volume keeps its FAT, clusters and root directory
+pub struct ExFatLayout {
+    pub fat_start: u64,
+    pub heap_start: u64,
+    pub cluster_size: u64,
+    pub cluster_count: u64,
+    pub root_cluster: u32,
+}
+
+impl ExFatLayout {
+    pub fn parse(boot: &[u8], offset: u64) -> Option<Self> {
+        let (sector_shift, cluster_shift) = (boot[108], boot[109]);
+        if !(9..=12).contains(&sector_shift) || cluster_shift > 25 - sector_shift {
+            return None;
+        }
+        let sector_size = 1u64 << sector_shift;
+        Some(Self {
+            fat_start: offset + u64::from(le32(&boot[80..])) * sector_size,
+            heap_start: offset + u64::from(le32(&boot[88..])) * sector_size,
+            cluster_size: sector_size << cluster_shift,
+            cluster_count: u64::from(le32(&boot[92..])),
+            root_cluster: le32(&boot[96..]),
+        })
+    }
+
+    pub fn cluster_at(&self, cluster: u32) -> u64 {
+        self.heap_start + u64::from(cluster.saturating_sub(2)) * self.cluster_size
+    }
+
+    /// The 32-byte entries of the root directory, up to the end marker
+    pub fn root_entries<R: Read + Seek>(&self, reader: &mut R) -> Result<Vec<Vec<u8>>> {
+        let mut entries = Vec::new();
+        let chain = exfat_chain(
+            reader,
+            self.fat_start,
+            self.root_cluster,
+            self.cluster_count,
+        )?;
+        for cluster in chain {
+            let dir = read_at(reader, self.cluster_at(cluster), self.cluster_size as usize)?;
+            for entry in dir.chunks_exact(32) {
+                if entry[0] == 0x00 {
+                    return Ok(entries);
+                }
+                entries.push(entry.to_vec());
+            }
+        }
+        Ok(entries)
+    }
+}
+
+fn exfat_used<R: Read + Seek>(
+    reader: &mut R,
+    offset: u64,
+    boot: &[u8],
+) -> Result<Option<Vec<Range<u64>>>> {
+    let Some(layout) = ExFatLayout::parse(boot, offset) else {
+        return Ok(None);
+    };
+    let ExFatLayout {
+        fat_start,
+        heap_start,
+        cluster_size,
+        cluster_count,
+        ..
+    } = layout;
+
+    // The allocation bitmap is found through its entry in the root directory
+    let bitmap = layout
+        .root_entries(reader)?
+        .into_iter()
+        .find(|entry| entry[0] == 0x81)
+        .map(|entry| (le32(&entry[20..]), le64(&entry[24..])));
+    let Some((bitmap_cluster, bitmap_len)) = bitmap else {
+        return Ok(None);
+    };
+
+    let mut bits = Vec::with_capacity(bitmap_len as usize);
+    for cluster in exfat_chain(reader, fat_start, bitmap_cluster, cluster_count)? {
+        if bits.len() as u64 >= bitmap_len {
+            break;
+        }
+        bits.extend(read_at(
+            reader,
+            layout.cluster_at(cluster),
+            cluster_size as usize,
+        )?);
+    }
+    bits.truncate(bitmap_len as usize);
+
+    let mut runs = RunBuilder::new(heap_start, cluster_size);
+    for cluster in 0..cluster_count.min(bits.len() as u64 * 8) {
+        if bits[(cluster / 8) as usize] & (1 << (cluster % 8)) != 0 {
+            runs.mark(cluster);
+        }
+    }
+    let mut ranges = runs.finish();
+    // Boot region and FAT
+    ranges.push(offset..heap_start);
+    Ok(Some(ranges))
+}
+
+/// Clusters of an exFAT chain. A zero FAT entry means the chain is
+/// contiguous and the FAT wasn't used, so it runs to the end of the heap.
+pub fn exfat_chain<R: Read + Seek>(
+    reader: &mut R,
+    fat_start: u64,
+    first: u32,
+    cluster_count: u64,
+) -> Result<Vec<u32>> {
+    let last_cluster = cluster_count + 1;
+    let mut chain = Vec::new();
+    let mut cluster = first;
+    while (2..=last_cluster).contains(&u64::from(cluster)) && chain.len() < MAX_CHAIN {
+        chain.push(cluster);
+        let next = le32(&read_at(reader, fat_start + u64::from(cluster) * 4, 4)?);
+        cluster = match next {
+            0 => cluster + 1,
+            n => n,
+        };
+    }
+    Ok(chain)
+}
+
+fn ntfs_used<R: Read + Seek>(
+    reader: &mut R,
+    offset: u64,
+    boot: &[u8],
+) -> Result<Option<Vec<Range<u64>>>> {
+    let bytes_per_sector = u64::from(u16::from_le_bytes([boot[11], boot[12]]));
+    let cluster_size = match boot[13] {
+        n @ 1..=0x80 => u64::from(n) * bytes_per_sector,
+        n => 1u64.checked_shl(256 - u32::from(n)).unwrap_or(0),
+    };
+    let total_sectors = le64(&boot[40..]);
+    let mft_cluster = le64(&boot[48..]);
+    let record_size = match boot[64] as i8 {
+        n @ 1.. => n as u64 * cluster_size,
+        n => 1u64.checked_shl(n.unsigned_abs().into()).unwrap_or(0),
+    };
+    if !matches!(bytes_per_sector, 512 | 1024 | 2048 | 4096)
+        || cluster_size == 0
+        || !(512..=65536).contains(&record_size)
+    {
+        return Ok(None);
+    }
+
+    // $Bitmap is MFT record 6; its $DATA has one bit per cluster
+    let mut record = read_at(
+        reader,
+        offset + mft_cluster * cluster_size + 6 * record_size,
+        record_size as usize,
+    )?;
+    if &record[..4] != b"FILE" || !apply_fixups(&mut record, bytes_per_sector as usize) {
+        return Ok(None);
+    }
+    let Some((runs, data_len)) = ntfs_data_runs(&record) else {
+        return Ok(None);
+    };
+
+    let mut bits = Vec::with_capacity(data_len as usize);
+    for (lcn, clusters) in runs {
+        if bits.len() as u64 >= data_len {
+            break;
+        }
+        let len = (clusters * cluster_size) as usize;
+        match lcn {
+            Some(lcn) => bits.extend(read_at(reader, offset + lcn * cluster_size, len)?),
+            None => bits.resize(bits.len() + len, 0),
+        }
+    }
+    bits.truncate(data_len as usize);
+
+    let volume_clusters = total_sectors * bytes_per_sector / cluster_size;
+    let mut used = RunBuilder::new(offset, cluster_size);
+    for cluster in 0..volume_clusters.min(bits.len() as u64 * 8) {
+        if bits[(cluster / 8) as usize] & (1 << (cluster % 8)) != 0 {
+            used.mark(cluster);
+        }
+    }
+    let mut ranges = used.finish();
+    // Boot sector, and its backup just past the last cluster
+    let backup = offset + total_sectors * bytes_per_sector;
+    ranges.push(offset..offset + bytes_per_sector);
+    ranges.push(backup..backup + bytes_per_sector);
+    Ok(Some(ranges))
+}
+
+/// Starting cluster (`None` for a sparse run) and length in clusters
+type DataRun = (Option<u64>, u64);
+
+/// Undo the update sequence that protects the last bytes of each sector
+fn apply_fixups(record: &mut [u8], sector_size: usize) -> bool {
+    let usa_offset = usize::from(u16::from_le_bytes([record[4], record[5]]));
+    let usa_count = usize::from(u16::from_le_bytes([record[6], record[7]]));
+    if usa_count == 0 || usa_offset + usa_count * 2 > record.len() {
+        return false;
+    }
+    for i in 1..usa_count {
+        let end = i * sector_size;
+        if end > record.len() {
+            return false;
+        }
+        let fix = usa_offset + i * 2;
+        record[end - 2] = record[fix];
+        record[end - 1] = record[fix + 1];
+    }
+    true
+}
+
+/// Data runs and size of a record's
+/// unnamed non-resident $DATA attribute
+fn ntfs_data_runs(record: &[u8]) -> Option<(Vec<DataRun>, u64)> {
+    let mut at = usize::from(u16::from_le_bytes([record[20], record[21]]));
+    while at + 16 <= record.len() {
+        let kind = le32(&record[at..]);
+        let len = le32(&record[at + 4..]) as usize;
+        if kind == 0xffff_ffff || len == 0 || at + len > record.len() {
+            return None;
+        }
+        let attr = &record[at..at + len];
+        // Unnamed, non-resident $DATA
+        if kind == 0x80 && attr[8] == 1 && attr[9] == 0 {
+            let runs_offset = usize::from(u16::from_le_bytes([attr[32], attr[33]]));
+            let data_len = le64(&attr[48..]);
+            return Some((decode_runs(attr.get(runs_offset..)?)?, data_len));
+        }
+        at += len;
+    }
+    None
+}
+
+fn decode_runs(mut data: &[u8]) -> Option<Vec<DataRun>> {
+    let mut runs = Vec::new();
+    let mut lcn: i64 = 0;
+    while let Some(&header) = data.first() {
+        if header == 0 {
+            break;
+        }
+        let (len_size, off_size) = (usize::from(header & 0xf), usize::from(header >> 4));
+        if len_size == 0 || len_size > 8 || off_size > 8 || data.len() < 1 + len_size + off_size {
+            return None;
+        }
+        let mut length = [0u8; 8];
+        length[..len_size].copy_from_slice(&data[1..1 + len_size]);
+        let length = u64::from_le_bytes(length);
+        let start = if off_size == 0 {
+            None
+        } else {
+            // Signed, relative to the previous run
+            let bytes = &data[1 + len_size..1 + len_size + off_size];
+            let fill = if bytes[off_size - 1] & 0x80 != 0 {
+                0xff
+            } else {
+                0
+            };
+            let mut delta = [fill; 8];
+            delta[..off_size].copy_from_slice(bytes);
+            lcn += i64::from_le_bytes(delta);
+            Some(u64::try_from(lcn).ok()?)
+        };
+        runs.push((start, length));
+        data = &data[1 + len_size + off_size..];
+    }
+    Some(runs)
+}
+
+fn ext_used<R: Read + Seek>(
+    reader: &mut R,
+    offset: u64,
+    sb: &[u8],
+) -> Result<Option<Vec<Range<u64>>>> {
+    let u16_at = |at: usize| u64::from(u16::from_le_bytes([sb[at], sb[at + 1]]));
+    let u32_at = |at: usize| u64::from(le32(&sb[at..]));
+
+    let compat = le32(&sb[92..]);
+    let incompat = le32(&sb[96..]);
+    let ro_compat = le32(&sb[100..]);
+    let is_64bit = incompat & EXT_INCOMPAT_64BIT != 0;
+    let blocks = u32_at(4) | if is_64bit { u32_at(336) << 32 } else { 0 };
+    let first_data_block = u32_at(20);
+    let log_block_size = u32_at(24);
+    let blocks_per_group = u32_at(32);
+    let inodes_per_group = u32_at(40);
+    let inode_size = if u32_at(76) == 0 { 128 } else { u16_at(88) };
+    let reserved_gdt_blocks = u16_at(206);
+    let desc_size = if is_64bit { u16_at(254).max(32) } else { 32 };
+    // meta_bg scatters the descriptors; not worth the trouble for a backup
+    if incompat & EXT_INCOMPAT_META_BG != 0 || log_block_size > 6 || blocks_per_group == 0 {
+        return Ok(None);
+    }
+    let block_size = 1024u64 << log_block_size;
+    let groups = blocks
+        .saturating_sub(first_data_block)
+        .div_ceil(blocks_per_group);
+    if groups > MAX_EXT_GROUPS {
+        return Ok(None);
+    }
+    let gdt_blocks = (groups * desc_size).div_ceil(block_size);
+    let inode_table_blocks = (inodes_per_group * inode_size).div_ceil(block_size);
+
+    let sparse_super2_groups = [u32_at(0x24c), u32_at(0x250)];
+    let has_backup = |group: u64| -> bool {
+        if group == 0 {
+            return true;
+        }
+        if compat & EXT_COMPAT_SPARSE_SUPER2 != 0 {
+            return sparse_super2_groups.contains(&group);
+        }
+        if ro_compat & EXT_RO_COMPAT_SPARSE_SUPER == 0 || group == 1 {
+            return true;
+        }
+        [3, 5, 7].iter().any(|base| {
+            let mut power = *base;
+            while power < group {
+                power *= base;
+            }
+            power == group
+        })
+    };
+
+    let descriptors = read_at(
+        reader,
+        offset + (first_data_block + 1) * block_size,
+        (groups * desc_size) as usize,
+    )?;
+    let mut used = RunBuilder::new(offset, block_size);
+    let mut ranges = Vec::new();
+    // Boot block ahead of the superblock on 1 KiB-block filesystems
+    ranges.push(offset..offset + (first_data_block + 1) * block_size);
+
+    for (group, desc) in descriptors.chunks_exact(desc_size as usize).enumerate() {
+        let group = group as u64;
+        let field = |lo: usize, hi: usize| {
+            let high = if desc_size >= 64 {
+                u64::from(le32(&desc[hi..])) << 32
+            } else {
+                0
+            };
+            u64::from(le32(&desc[lo..])) | high
+        };
+        let block_bitmap = field(0x0, 0x20);
+        let inode_bitmap = field(0x4, 0x24);
+        let inode_table = field(0x8, 0x28);
+        let flags = u16::from_le_bytes([desc[0x12], desc[0x13]]);
+
+        for (start, len) in [
+            (block_bitmap, 1),
+            (inode_bitmap, 1),
+            (inode_table, inode_table_blocks),
+        ] {
+            ranges.push(offset + start * block_size..offset + (start + len) * block_size);
+        }
+
+        let group_start = first_data_block + group * blocks_per_group;
+        let group_blocks = blocks_per_group.min(blocks.saturating_sub(group_start));
+        if flags & EXT_BG_BLOCK_UNINIT != 0 {
+            // No bitmap yet: only the superblock backup and descriptors are in use
+            if has_backup(group) {
+                let len = 1 + gdt_blocks + reserved_gdt_blocks;
+                ranges.push(
+                    offset + group_start * block_size..offset + (group_start + len) * block_size,
+                );
+            }
+            continue;
+        }
+        let bitmap = read_at(
+            reader,
+            offset + block_bitmap * block_size,
+            block_size as usize,
+        )?;
+        for bit in 0..group_blocks.min(block_size * 8) {
+            if bitmap[(bit / 8) as usize] & (1 << (bit % 8)) != 0 {
+                used.mark(group_start + bit);
+            }
+        }
+    }
+
+    ranges.extend(used.finish());
+    Ok(Some(ranges))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_fat16_used_clusters() {
+        // 512-byte sectors, 4 sectors per cluster, 1 reserved sector,
+        // 2 FATs of 40 sectors, 512 root entries (32 sectors), 40960 sectors
+        let mut disk = vec![0u8; 40960 * 512];
+        disk[11..13].copy_from_slice(&512u16.to_le_bytes());
+        disk[13] = 4;
+        disk[14..16].copy_from_slice(&1u16.to_le_bytes());
+        disk[16] = 2;
+        disk[17..19].copy_from_slice(&512u16.to_le_bytes());
+        disk[19..21].copy_from_slice(&40960u16.to_le_bytes());
+        disk[21] = 0xf8;
+        disk[22..24].copy_from_slice(&40u16.to_le_bytes());
+        disk[510..512].copy_from_slice(&[0x55, 0xaa]);
+        // Clusters 2-3 form a file, cluster 6 another
+        for (cluster, value) in [
+            (0, 0xfff8u16),
+            (1, 0xffff),
+            (2, 3),
+            (3, 0xffff),
+            (6, 0xffff),
+        ] {
+            disk[512 + cluster * 2..512 + cluster * 2 + 2].copy_from_slice(&value.to_le_bytes());
+        }
+
+        let mut reader = Cursor::new(&disk);
+        assert_eq!(detect_in(&disk), Some(FsKind::Fat16));
+        assert_eq!(label(&mut reader, 0).unwrap(), None);
+        disk[43..54].copy_from_slice(b"PERVIE     ");
+        let mut reader = Cursor::new(&disk);
+        assert_eq!(label(&mut reader, 0).unwrap().as_deref(), Some("PERVIE"));
+        let data_start = (1 + 2 * 40 + 32) * 512;
+        let cluster = 4 * 512;
+        assert_eq!(
+            used_ranges(&mut reader, 0).unwrap().unwrap(),
+            vec![
+                0..data_start + 2 * cluster,
+                data_start + 4 * cluster..data_start + 5 * cluster
+            ]
+        );
+    }
+
+    #[test]
+    fn test_ext4_used_blocks() {
+        use std::io::Read;
+        // mkfs.ext4 -b 1024 of a 4 MiB image holding one 100 KiB file
+        let mut image = Vec::new();
+        flate2::read::GzDecoder::new(&include_bytes!("../../tests/fixtures/fs/ext4.img.gz")[..])
+            .read_to_end(&mut image)
+            .unwrap();
+
+        let mut reader = Cursor::new(&image);
+        assert_eq!(detect_in(&image), Some(FsKind::Ext));
+        let used = used_ranges(&mut reader, 0).unwrap().unwrap();
+        let used_bytes: u64 = used.iter().map(|r| r.end - r.start).sum();
+        assert!(used_bytes < image.len() as u64 / 2);
+        // The file's contents are all 0xa5; every such block must be kept
+        for (i, block) in image.chunks(1024).enumerate() {
+            let at = i as u64 * 1024;
+            if block.iter().all(|b| *b == 0xa5) {
+                assert!(used.iter().any(|r| r.contains(&at)), "block {} dropped", i);
+            }
+        }
+    }
+
+    #[test]
+    fn test_decode_runs() {
+        // 0x18 clusters at 0x5634, then 0x10 clusters 0x100 back, then sparse
+        let runs = decode_runs(&[
+            0x21, 0x18, 0x34, 0x56, 0x22, 0x10, 0x00, 0x00, 0xff, 0x01, 0x08, 0x00,
+        ]);
+        assert_eq!(
+            runs,
+            Some(vec![
+                (Some(0x5634), 0x18),
+                (Some(0x5534), 0x10),
+                (None, 0x08)
+            ])
+        );
+    }
+}
@@ -1,17 +1,35 @@
 use std::fs::OpenOptions;
-use std::io::Write;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::mpsc::{Receiver, SyncSender, sync_channel};
 use std::thread;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 use anyhow::{Context, Result, anyhow};
 use futures_util::StreamExt;
-use reqwest::Client;
+use sha2::{Digest, Sha256};
 use tokio::sync::mpsc::UnboundedSender;
 
-use crate::core::AppState;
+use crate::core::backup::Compression;
+use crate::core::config::NetworkConfig;
+use crate::core::net::HttpClient;
+use crate::core::source::{self, ImageSource, PieceAlgorithm, PieceHashes};
+use crate::core::{AppState, Iso};
 
 const CHANNEL_BOUND: usize = 4; // Buffer up to 16MB in memory
+const MAX_RESUME_ATTEMPTS: u64 = 5;
+const MAX_PIECE_RETRIES: u32 = 3;
+/// Caps offered by the +/- keys while flashing, in bytes per second
+const RATE_LIMIT_STEPS: [u64; 7] = [
+    1_000_000,
+    2_000_000,
+    5_000_000,
+    10_000_000,
+    20_000_000,
+    50_000_000,
+    100_000_000,
+];
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct FlashProgress {
@@ -19,41 +37,458 @@ pub struct FlashProgress {
     pub total_bytes: u64,
     pub speed_mbps: f64,
     pub percent: f64,
+    /// Source-specific detail, e.g. torrent peers and pieces
+    pub source_status: Option<String>,
+    /// Download cap in bytes per second
+    pub rate_limit: Option<u64>,
+    /// Read over the network, so the download cap applies
+    pub remote: bool,
+    /// Reading the device back after writing
+    pub verifying: bool,
+    /// File being written, when the image is unpacked rather than copied
+    pub current_file: Option<String>,
 }
 
+/// Result of probing an image before asking for confirmation
+#[derive(Debug, Clone, PartialEq)]
+pub struct Preflight {
+    pub total_bytes: u64,
+    pub sha256: Option<String>,
+    /// Where the checksum came from: "catalog" or the checksum file URL
+    pub checksum_source: Option<String>,
+    /// The source publishes piece hashes that are checked while streaming
+    pub verifies_pieces: bool,
+}
+
+/// Download cap in bytes per second. Shared between the UI and a running
+/// flash, so changes apply to the download in progress.
+#[derive(Debug, Clone, Default)]
+pub struct RateLimit(Arc<AtomicU64>);
+
+impl RateLimit {
+    pub fn get(&self) -> Option<u64> {
+        Some(self.0.load(Ordering::Relaxed)).filter(|rate| *rate > 0)
+    }
+
+    pub fn set(&self, rate: Option<u64>) {
+        self.0.store(rate.unwrap_or(0), Ordering::Relaxed);
+    }
+
+    /// Step to the next preset cap, going from the highest to unlimited and back
+    pub fn step(&self, faster: bool) -> Option<u64> {
+        let next = next_rate_limit(self.get(), faster);
+        self.set(next);
+        next
+    }
+}
+
+fn next_rate_limit(current: Option<u64>, faster: bool) -> Option<u64> {
+    match (current, faster) {
+        (None, true) => None,
+        (None, false) => RATE_LIMIT_STEPS.last().copied(),
+        (Some(rate), true) => RATE_LIMIT_STEPS.iter().copied().find(|step| *step > rate),
+        (Some(rate), false) => RATE_LIMIT_STEPS
+            .iter()
+            .copied()
+            .rev()
+            .find(|step| *step < rate)
+            .or(Some(rate.min(RATE_LIMIT_STEPS[0]))),
+    }
+}
+
+/// Token bucket holding up to one second's worth of bytes
+struct TokenBucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new() -> Self {
+        Self {
+            tokens: 0.0,
+            last_refill: Instant::now(),
+        }
+    }
+
+    /// Account for `bytes` just downloaded, sleeping until the cap allows
+    /// more. Sleeps in short steps so a new cap applies right away.
+    async fn consume(&mut self, bytes: usize, limit: &RateLimit) {
+        self.tokens -= bytes as f64;
+        loop {
+            let now = Instant::now();
+            let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+            self.last_refill = now;
+            let Some(rate) = limit.get() else {
+                self.tokens = 0.0;
+                return;
+            };
+            let rate = rate as f64;
+            self.tokens = (self.tokens + elapsed * rate).min(rate);
+            if self.tokens >= 0.0 {
+                return;
+            }
+            let wait = (-self.tokens / rate).min(0.1);
+            tokio::time::sleep(Duration::from_secs_f64(wait)).await;
+        }
+    }
+}
+
+/// Receives image data on the writer thread
+pub trait ImageSink: Send + 'static {
+    /// Take the next chunk of the image
+    fn write(&mut self, chunk: &[u8]) -> Result<()>;
+
+    /// All data is in: flush and sync
+    fn finish(&mut self) -> Result<()>;
+}
+
+/// Writes the image to a device byte for byte
+struct DeviceSink {
+    file: std::fs::File,
+    buffer: Vec<u8>,
+    /// Differential mode: compare each block with the device first
+    delta: Option<DeltaBlocks>,
+}
+
+/// Read-back state for a differential write
+struct DeltaBlocks {
+    existing: Vec<u8>,
+    offset: u64,
+    stats: Arc<DeltaStats>,
+}
+
+/// How much of a differential write actually reached the device
+#[derive(Debug, Default)]
+pub struct DeltaStats {
+    pub written: AtomicU64,
+    pub unchanged: AtomicU64,
+}
+
+impl DeltaStats {
+    /// One line for the completion message
+    pub fn describe(&self) -> String {
+        let written = self.written.load(Ordering::Relaxed);
+        let unchanged = self.unchanged.load(Ordering::Relaxed);
+        format!(
+            "Delta write: {} changed and written, {} already matched",
+            crate::utils::bytes_to_human(written),
+            crate::utils::bytes_to_human(unchanged)
+        )
+    }
+}
+
+// Manual buffering to ensure ALL writes are aligned (e.g. 1MB blocks).
+// BufWriter is risky because if input chunk > capacity, it might bypass buffer.
+const WRITE_BUFFER_SIZE: usize = 1024 * 1024;
+
+impl DeviceSink {
+    fn new(file: std::fs::File) -> Self {
+        Self {
+            file,
+            buffer: Vec::with_capacity(WRITE_BUFFER_SIZE),
+            delta: None,
+        }
+    }
+
+    /// Only write blocks that differ from what the device already holds
+    fn delta(file: std::fs::File, stats: Arc<DeltaStats>) -> Self {
+        Self {
+            delta: Some(DeltaBlocks {
+                existing: vec![0; WRITE_BUFFER_SIZE],
+                offset: 0,
+                stats,
+            }),
+            ..Self::new(file)
+        }
+    }
+
+    /// Write the first `len` buffered bytes, skipping them in delta mode
+    /// when the device already matches
+    fn write_block(&mut self, len: usize) -> Result<()> {
+        let block = &self.buffer[..len];
+        if let Some(delta) = self.delta.as_mut() {
+            let existing = &mut delta.existing[..len];
+            // Compare with what the device holds, not what an earlier flash left cached
+            drop_page_cache(&self.file, delta.offset, len as u64);
+            // A short or failed read just means the block gets written
+            let matches = self.file.seek(SeekFrom::Start(delta.offset)).is_ok()
+                && self.file.read_exact(existing).is_ok()
+                && existing == block;
+            if matches {
+                delta
+                    .stats
+                    .unchanged
+                    .fetch_add(len as u64, Ordering::Relaxed);
+            } else {
+                self.file
+                    .seek(SeekFrom::Start(delta.offset))
+                    .context("Failed to seek on device")?;
+                self.file
+                    .write_all(block)
+                    .context("Failed to write to device (changed block)")?;
+                delta.stats.written.fetch_add(len as u64, Ordering::Relaxed);
+            }
+            delta.offset += len as u64;
+            return Ok(());
+        }
+        self.file
+            .write_all(block)
+            .context("Failed to write to device (aligned block)")
+    }
+}
+
+impl ImageSink for DeviceSink {
+    fn write(&mut self, chunk: &[u8]) -> Result<()> {
+        self.buffer.extend_from_slice(chunk);
+
+        // Write aligned blocks
+        while self.buffer.len() >= WRITE_BUFFER_SIZE {
+            self.write_block(WRITE_BUFFER_SIZE)?;
+
+            // Remove Written part efficiently
+            self.buffer.drain(..WRITE_BUFFER_SIZE);
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        // Flush remaining bytes (unaligned, but it's the end of file)
+        if !self.buffer.is_empty() {
+            self.write_block(self.buffer.len())?;
+            self.buffer.clear();
+        }
+
+        // Sync disk
+        if let Err(e) = self.file.sync_all() {
+            // Ignore "inappropriate ioctl for device" (ENOTTY/25) on macOS/BSD raw devices
+            #[cfg(any(target_os = "macos", target_os = "freebsd"))]
+            if let Some(code) = e.raw_os_error() {
+                if code == 25 {
+                    return Ok(());
+                }
+            }
+            return Err(anyhow::Error::new(e).context("Failed to sync device"));
+        }
+
+        Ok(())
+    }
+}
+
+/// Counts the bytes that reach the sink behind it
+struct CountingSink {
+    sink: Box<dyn ImageSink>,
+    written: Arc<AtomicU64>,
+}
+
+impl Write for CountingSink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.sink
+            .write(buf)
+            .map_err(|e| io::Error::other(format!("{:#}", e)))?;
+        self.written.fetch_add(buf.len() as u64, Ordering::Relaxed);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl ImageSink for CountingSink {
+    fn write(&mut self, chunk: &[u8]) -> Result<()> {
+        self.sink.write(chunk)?;
+        self.written
+            .fetch_add(chunk.len() as u64, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.sink.finish()
+    }
+}
+
+/// Decompresses a compressed image on its way into another sink
+enum DecompressSink {
+    Gzip(flate2::write::MultiGzDecoder<CountingSink>),
+    Zstd(zstd::stream::write::Decoder<'static, CountingSink>),
+    Xz(liblzma::write::XzDecoder<CountingSink>),
+}
+
+impl DecompressSink {
+    /// Put a decoder for `compression` in front of `inner`, if it needs one
+    fn wrap(compression: Compression, inner: CountingSink) -> Result<Box<dyn ImageSink>> {
+        Ok(match compression {
+            Compression::None => Box::new(inner),
+            Compression::Gzip => Box::new(Self::Gzip(flate2::write::MultiGzDecoder::new(inner))),
+            Compression::Zstd => Box::new(Self::Zstd(zstd::stream::write::Decoder::new(inner)?)),
+            Compression::Xz => Box::new(Self::Xz(liblzma::write::XzDecoder::new_multi_decoder(
+                inner,
+            ))),
+        })
+    }
+}
+
+impl ImageSink for DecompressSink {
+    fn write(&mut self, chunk: &[u8]) -> Result<()> {
+        let result = match self {
+            Self::Gzip(decoder) => decoder.write_all(chunk),
+            Self::Zstd(decoder) => decoder.write_all(chunk),
+            Self::Xz(decoder) => decoder.write_all(chunk),
+        };
+        result.context("Failed to decompress the image")
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        let inner = match self {
+            Self::Gzip(decoder) => {
+                decoder
+                    .try_finish()
+                    .context("Failed to decompress the image")?;
+                decoder.get_mut()
+            }
+            Self::Zstd(decoder) => {
+                decoder.flush().context("Failed to decompress the image")?;
+                decoder.get_mut()
+            }
+            Self::Xz(decoder) => {
+                decoder
+                    .try_finish()
+                    .context("Failed to decompress the image")?;
+                decoder.get_mut()
+            }
+        };
+        inner.sink.finish()
+    }
+}
+
+/// What a flash left on the device
+#[derive(Debug, Clone, PartialEq)]
+pub struct Flashed {
+    /// SHA-256 of the image as downloaded, compressed or not
+    pub sha256: String,
+    /// Bytes written to the device; more than the download for compressed images
+    pub image_bytes: u64,
+}
+
+#[derive(Default)]
 pub struct Flasher {
-    client: Client,
+    client: HttpClient,
+    rate_limit: RateLimit,
 }
 
 impl Flasher {
-    pub fn new() -> Self {
-        Self {
-            client: Client::new(),
+    /// Build the flasher's HTTP client from the `[network]` config
+    pub fn new(network: &NetworkConfig) -> Result<Self> {
+        let rate_limit = RateLimit::default();
+        rate_limit.set(network.rate_limit);
+        Ok(Self {
+            client: HttpClient::new(network)?,
+            rate_limit,
+        })
+    }
+
+    /// Download cap applied to every flash, adjustable while one runs
+    pub fn rate_limit(&self) -> &RateLimit {
+        &self.rate_limit
+    }
+
+    /// Client shared by every network operation (listings, checksums, images)
+    pub fn client(&self) -> &HttpClient {
+        &self.client
+    }
+
+    /// Resolve the source behind an image URL (http(s), s3, ...)
+    pub fn source_for(&self, url: &str) -> Result<Arc<dyn ImageSource>> {
+        source::from_url(url, &self.client)
+    }
+
+    /// Probe the image for its size and look for a published SHA-256 when
+    /// the catalog does not pin one.
+    pub async fn preflight(&self, iso: &Iso) -> Result<Preflight> {
+        let url = iso.url.as_str();
+        let info = self.source_for(url)?.probe().await?;
+        let preflight = |sha256: Option<String>, checksum_source: Option<&str>| Preflight {
+            total_bytes: info.total_bytes,
+            sha256,
+            checksum_source: checksum_source.map(str::to_string),
+            verifies_pieces: info.pieces.is_some(),
+        };
+
+        if let Some(sha256) = &iso.sha256 {
+            return Ok(preflight(Some(sha256.to_lowercase()), Some("catalog")));
+        }
+        if let Some(sha256) = &info.sha256 {
+            return Ok(preflight(Some(sha256.clone()), Some("published by source")));
+        }
+        // Only plain image URLs have checksum files next to them
+        if !url.starts_with("http://") && !url.starts_with("https://")
+            || source::metalink::is_metalink_url(url)
+            || source::torrent::is_torrent_url(url)
+        {
+            return Ok(preflight(None, None));
+        }
+
+        match self
+            .discover_checksum(url, iso.checksum_url.as_deref())
+            .await
+        {
+            Some((hash, checksum_source)) => Ok(preflight(Some(hash), Some(&checksum_source))),
+            None => Ok(preflight(None, None)),
         }
     }
 
+    /// Try the hinted checksum file, then the usual places distros publish
+    /// checksums: `<image>.sha256` and a SHA256SUMS listing in the same directory.
+    async fn discover_checksum(&self, url: &str, hint: Option<&str>) -> Option<(String, String)> {
+        let parsed = reqwest::Url::parse(url).ok()?;
+        let file_name = parsed.path_segments()?.next_back()?.to_string();
+        if file_name.is_empty() {
+            return None;
+        }
+
+        let mut candidates: Vec<String> = hint.map(str::to_string).into_iter().collect();
+        candidates.push(format!("{}.sha256", url));
+        candidates.push(format!("{}.sha256sum", url));
+        for listing in ["SHA256SUMS", "sha256sums.txt", "SHA256SUMS.txt"] {
+            if let Ok(sibling) = parsed.join(listing) {
+                candidates.push(sibling.to_string());
+            }
+        }
+
+        for candidate in candidates {
+            let Ok(resp) = self.client.get(&candidate).send().await else {
+                continue;
+            };
+            if !resp.status().is_success() {
+                continue;
+            }
+            // Checksum files are tiny; anything large is an error page or an unrelated file
+            if resp.content_length().is_some_and(|len| len > 1024 * 1024) {
+                continue;
+            }
+            let Ok(body) = resp.text().await else {
+                continue;
+            };
+            if let Some(hash) = parse_checksum_file(&body, &file_name) {
+                return Some((hash, candidate));
+            }
+        }
+
+        None
+    }
+
+    /// Stream `source` onto the device, decompressing it on the way when
+    /// `compression` says it is compressed.
     pub async fn flash(
         &self,
-        url: String,
+        source: Arc<dyn ImageSource>,
+        expected_sha256: Option<String>,
+        compression: Compression,
         device_path: String,
         progress_tx: UnboundedSender<AppState>,
-    ) -> Result<()> {
-        // 1. Pre-flight check
-        let head_resp = self.client.head(&url).send().await?;
-        if !head_resp.status().is_success() {
-            return Err(anyhow!("Failed to access URL: {}", head_resp.status()));
-        }
-
-        let total_size = head_resp
-            .headers()
-            .get(reqwest::header::CONTENT_LENGTH)
-            .and_then(|v| v.to_str().ok())
-            .and_then(|v| v.parse::<u64>().ok())
-            .ok_or_else(|| anyhow!("Could not retrieve content length from URL"))?;
-
-        // 2. Open device
+    ) -> Result<Flashed> {
         #[cfg(unix)]
-        let mut file = OpenOptions::new()
+        let file = OpenOptions::new()
             .write(true)
             .read(false)
             .open(&device_path)
@@ -61,88 +496,178 @@ impl Flasher {
 
         // TODO: Windows implementation
 
-        // 3. Setup Producer-Consumer channels
+        let written = Arc::new(AtomicU64::new(0));
+        let device = CountingSink {
+            sink: Box::new(DeviceSink::new(file)),
+            written: written.clone(),
+        };
+        let sink = DecompressSink::wrap(compression, device)?;
+        let sha256 = self
+            .stream_to(source, expected_sha256, sink, |_| None, progress_tx)
+            .await?;
+        Ok(Flashed {
+            sha256,
+            image_bytes: written.load(Ordering::Relaxed),
+        })
+    }
+
+    /// Flash `source` but only write the blocks that differ from the device's
+    /// current contents, for reflashing a newer build of the same image
+    pub async fn flash_delta(
+        &self,
+        source: Arc<dyn ImageSource>,
+        expected_sha256: Option<String>,
+        device_path: String,
+        progress_tx: UnboundedSender<AppState>,
+    ) -> Result<Arc<DeltaStats>> {
+        let file = OpenOptions::new()
+            .write(true)
+            .read(true)
+            .open(&device_path)
+            .context(format!("Failed to open device {}", device_path))?;
+        let stats = Arc::new(DeltaStats::default());
+
+        self.stream_to(
+            source,
+            expected_sha256,
+            Box::new(DeviceSink::delta(file, stats.clone())),
+            |_| None,
+            progress_tx,
+        )
+        .await?;
+        Ok(stats)
+    }
+
+    /// Stream `source` into `sink`, with resuming, piece checks and the rate
+    /// limit. `current_file` names what is being written at an image offset.
+    /// Returns the SHA-256 of the image.
+    pub async fn stream_to(
+        &self,
+        source: Arc<dyn ImageSource>,
+        expected_sha256: Option<String>,
+        mut sink: Box<dyn ImageSink>,
+        current_file: impl Fn(u64) -> Option<String> + Send + Sync,
+        progress_tx: UnboundedSender<AppState>,
+    ) -> Result<String> {
+        // 1. Pre-flight check
+        let info = source.probe().await?;
+        let total_size = info.total_bytes;
+        let mut verifier = info
+            .pieces
+            .and_then(|pieces| PieceVerifier::new(pieces, total_size));
+
+        // 2. Setup Producer-Consumer channels
         // We use a sync channel for backpressure handling
         let (data_tx, data_rx): (SyncSender<Vec<u8>>, Receiver<Vec<u8>>) =
             sync_channel(CHANNEL_BOUND);
 
-        // 4. Spawn Consumer (Writer Thread)
+        // 3. Spawn Consumer (Writer Thread)
         // We use a dedicated thread for blocking IO to avoid blocking the async runtime
-        // We use a dedicated thread for blocking IO to avoid blocking the async runtime
-
         let writer_handle = thread::spawn(move || -> Result<()> {
-            let mut _written = 0u64;
-            // Manual buffering to ensure ALL writes are aligned (e.g. 1MB blocks).
-            // BufWriter is risky because if input chunk > capacity, it might bypass buffer.
-            const WRITE_BUFFER_SIZE: usize = 1 * 1024 * 1024;
-            let mut buffer: Vec<u8> = Vec::with_capacity(WRITE_BUFFER_SIZE);
-
             for chunk in data_rx {
-                buffer.extend_from_slice(&chunk);
+                sink.write(&chunk)?;
+            }
+            sink.finish()
+        });
 
-                // Write aligned blocks
-                while buffer.len() >= WRITE_BUFFER_SIZE {
-                    // Extract exact buffer size
-                    // We avoid drain(..) for performance on large buffers, but for 1MB it's acceptable.
-                    // Or better: write just the slice and shift using rotation?
-                    // Actually, simple way:
-                    file.write_all(&buffer[..WRITE_BUFFER_SIZE])
-                        .context("Failed to write to device (aligned block)")?;
+        // 4. Producer (Downloader)
+        let mut stream = source.open(0).await?;
 
-                    // Remove Written part efficiently
-                    buffer.drain(..WRITE_BUFFER_SIZE);
+        let start_time = Instant::now();
+        let mut bytes_processed = 0u64;
+        let mut hasher = Sha256::new();
+        let mut last_update_time = Instant::now();
+        let mut resume_attempts = 0;
+        let mut piece_failures = 0;
+        let mut bucket = TokenBucket::new();
 
-                    _written += WRITE_BUFFER_SIZE as u64;
+        loop {
+            let chunk = match stream.next().await {
+                Some(Ok(chunk)) => chunk,
+                Some(Err(_)) | None if bytes_processed < total_size => {
+                    // Connection dropped or ended early: continue from where we are
+                    // with a ranged request, on the next mirror if there is one
+                    if resume_attempts >= MAX_RESUME_ATTEMPTS {
+                        return Err(anyhow!(
+                            "Download of {} failed at {} of {} bytes",
+                            source.describe(),
+                            bytes_processed,
+                            total_size
+                        ));
+                    }
+                    resume_attempts += 1;
+                    source.mark_failed();
+                    tokio::time::sleep(Duration::from_secs(resume_attempts)).await;
+                    match source.open(bytes_processed).await {
+                        Ok(resumed) => stream = resumed,
+                        Err(e) if resume_attempts >= MAX_RESUME_ATTEMPTS => {
+                            return Err(e.context("Could not resume download"));
+                        }
+                        Err(_) => {}
+                    }
+                    continue;
                 }
+                Some(Err(e)) => return Err(e.context("Error downloading chunk")),
+                None => break,
+            };
+            resume_attempts = 0;
+            if source.is_remote() {
+                bucket.consume(chunk.len(), &self.rate_limit).await;
             }
+            bytes_processed += chunk.len() as u64;
 
-            // Flush remaining bytes (unaligned, but it's the end of file)
-            if !buffer.is_empty() {
-                file.write_all(&buffer)
-                    .context("Failed to write to device (final block)")?;
-                _written += buffer.len() as u64;
-            }
-
-            // Sync disk
-            if let Err(e) = file.sync_all() {
-                // Ignore "inappropriate ioctl for device" (ENOTTY/25) on macOS/BSD raw devices
-                #[cfg(any(target_os = "macos", target_os = "freebsd"))]
-                if let Some(code) = e.raw_os_error() {
-                    if code == 25 {
-                        return Ok(());
-                    }
+            // With piece hashes, only verified pieces reach the device
+            let (verified, corrupt_piece) = match verifier.as_mut() {
+                Some(verifier) => {
+                    let result = verifier.push(chunk);
+                    (verifier.take_verified(), result.err())
                 }
-                return Err(anyhow::Error::new(e).context("Failed to sync device"));
+                None => (vec![chunk], None),
+            };
+            if !verified.is_empty() {
+                piece_failures = 0;
             }
 
-            Ok(())
-        });
+            for chunk in verified {
+                hasher.update(&chunk);
 
-        // 5. Producer (Downloader)
-        let mut stream = self.client.get(&url).send().await?.bytes_stream();
+                // Send to writer (blocking if full)
+                if data_tx.send(chunk).is_err() {
+                    // Writer thread died, probably due to IO error.
+                    // Drop tx to ensure we stop producing.
+                    drop(data_tx);
 
-        let start_time = Instant::now();
-        let mut bytes_processed = 0u64;
-        let mut last_update_time = Instant::now();
-
-        while let Some(item) = stream.next().await {
-            let chunk = item.context("Error downloading chunk")?;
-            let chunk_len = chunk.len();
-
-            // Send to writer (blocking if full)
-            if let Err(_) = data_tx.send(chunk.to_vec()) {
-                // Writer thread died, probably due to IO error.
-                // Drop tx to ensure we stop producing.
-                drop(data_tx);
-
-                // Join writer to get the actual error
-                match writer_handle.join() {
-                    Ok(result) => return result.context("Writer thread failed"),
-                    Err(e) => return Err(anyhow!("Writer thread panicked: {:?}", e)),
+                    // Join writer to get the actual error
+                    match writer_handle.join() {
+                        Ok(result) => {
+                            return Err(result
+                                .err()
+                                .unwrap_or_else(|| anyhow!("Writer thread stopped early"))
+                                .context("Writer thread failed"));
+                        }
+                        Err(e) => return Err(anyhow!("Writer thread panicked: {:?}", e)),
+                    }
                 }
             }
 
-            bytes_processed += chunk_len as u64;
+            if let (Some(piece), Some(verifier)) = (corrupt_piece, verifier.as_mut()) {
+                // Corrupt piece: refetch it, from another mirror if possible
+                piece_failures += 1;
+                if piece_failures > MAX_PIECE_RETRIES {
+                    return Err(anyhow!(
+                        "Piece {} of {} failed verification",
+                        piece,
+                        source.describe()
+                    ));
+                }
+                source.mark_failed();
+                bytes_processed = verifier.restart_piece();
+                stream = source
+                    .open(bytes_processed)
+                    .await
+                    .context("Could not refetch corrupt piece")?;
+                continue;
+            }
 
             // Update Progress
             let now = Instant::now();
@@ -156,6 +681,11 @@ impl Flasher {
                     total_bytes: total_size,
                     speed_mbps,
                     percent,
+                    source_status: source.status(),
+                    rate_limit: self.rate_limit.get(),
+                    remote: source.is_remote(),
+                    verifying: false,
+                    current_file: current_file(bytes_processed),
                 };
 
                 // Ignore send errors (e.g. if app closed)
@@ -173,6 +703,453 @@ impl Flasher {
             Err(e) => return Err(anyhow!("Writer thread panicked: {:?}", e)),
         }
 
+        // 5. Verify integrity against the published checksum
+        let actual = format!("{:x}", hasher.finalize());
+        if let Some(expected) = expected_sha256
+            && !actual.eq_ignore_ascii_case(&expected)
+        {
+            return Err(anyhow!(
+                "Checksum mismatch: expected {}, got {}",
+                expected,
+                actual
+            ));
+        }
+
+        Ok(actual)
+    }
+}
+
+/// Evict a range of the device from the page cache (`len` 0 for all of it),
+/// so reads come from the device. Raw devices on macOS aren't cached.
+pub fn drop_page_cache(device: &std::fs::File, offset: u64, len: u64) {
+    #[cfg(target_os = "linux")]
+    unsafe {
+        use std::os::fd::AsRawFd;
+        libc::posix_fadvise(
+            device.as_raw_fd(),
+            offset as libc::off_t,
+            len as libc::off_t,
+            libc::POSIX_FADV_DONTNEED,
+        );
+    }
+    #[cfg(not(target_os = "linux"))]
+    let _ = (device, offset, len);
+}
+
+/// Read the first `len` bytes of a device back and compare their SHA-256.
+/// Blocking: run it on a blocking thread.
+pub fn verify_device(
+    device_path: &str,
+    len: u64,
+    expected_sha256: &str,
+    progress_tx: &UnboundedSender<AppState>,
+) -> Result<()> {
+    let mut device = std::fs::File::open(device_path)
+        .with_context(|| format!("Failed to open device {}", device_path))?;
+    // Make sure the data comes from the device, not from the page cache
+    drop_page_cache(&device, 0, 0);
+
+    let start_time = Instant::now();
+    let mut last_update_time = Instant::now();
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 1024 * 1024];
+    let mut verified = 0u64;
+    while verified < len {
+        let want = (len - verified).min(buf.len() as u64) as usize;
+        let read = device
+            .read(&mut buf[..want])
+            .with_context(|| format!("Failed to read back {} at byte {}", device_path, verified))?;
+        if read == 0 {
+            return Err(anyhow!(
+                "{} ended after {} of {} bytes",
+                device_path,
+                verified,
+                len
+            ));
+        }
+        hasher.update(&buf[..read]);
+        verified += read as u64;
+
+        let now = Instant::now();
+        if now.duration_since(last_update_time).as_millis() > 100 {
+            let elapsed_secs = start_time.elapsed().as_secs_f64();
+            let _ = progress_tx.send(AppState::Flashing(FlashProgress {
+                bytes_written: verified,
+                total_bytes: len,
+                speed_mbps: (verified as f64 / 1_000_000.0) / elapsed_secs,
+                percent: (verified as f64 / len as f64) * 100.0,
+                source_status: None,
+                rate_limit: None,
+                remote: false,
+                verifying: true,
+                current_file: None,
+            }));
+            last_update_time = now;
+        }
+    }
+
+    let actual = format!("{:x}", hasher.finalize());
+    if !actual.eq_ignore_ascii_case(expected_sha256) {
+        return Err(anyhow!(
+            "Verification failed: wrote {}, read back {}",
+            expected_sha256,
+            actual
+        ));
+    }
+    Ok(())
+}
+
+/// Holds back each piece of the image until its hash checks out, so a bad
+/// mirror is caught within one piece instead of at the end of the download
+struct PieceVerifier {
+    pieces: PieceHashes,
+    total_bytes: u64,
+    index: usize,
+    filled: u64,
+    hasher: PieceHasher,
+    /// Data of the piece being downloaded
+    pending: Vec<Vec<u8>>,
+    verified: Vec<Vec<u8>>,
+}
+
+enum PieceHasher {
+    Sha1(sha1::Sha1),
+    Sha256(Sha256),
+}
+
+impl PieceHasher {
+    fn new(algorithm: PieceAlgorithm) -> Self {
+        match algorithm {
+            PieceAlgorithm::Sha1 => Self::Sha1(sha1::Sha1::new()),
+            PieceAlgorithm::Sha256 => Self::Sha256(Sha256::new()),
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            Self::Sha1(h) => h.update(data),
+            Self::Sha256(h) => h.update(data),
+        }
+    }
+
+    fn finalize_hex(self) -> String {
+        match self {
+            Self::Sha1(h) => format!("{:x}", h.finalize()),
+            Self::Sha256(h) => format!("{:x}", h.finalize()),
+        }
+    }
+}
+
+impl PieceVerifier {
+    /// `None` if the piece list doesn't cover the image exactly
+    fn new(pieces: PieceHashes, total_bytes: u64) -> Option<Self> {
+        if pieces.length == 0 || pieces.hashes.len() as u64 != total_bytes.div_ceil(pieces.length) {
+            return None;
+        }
+        Some(Self {
+            hasher: PieceHasher::new(pieces.algorithm),
+            pieces,
+            total_bytes,
+            index: 0,
+            filled: 0,
+            pending: Vec::new(),
+            verified: Vec::new(),
+        })
+    }
+
+    fn piece_start(&self) -> u64 {
+        self.index as u64 * self.pieces.length
+    }
+
+    fn piece_len(&self) -> u64 {
+        self.pieces
+            .length
+            .min(self.total_bytes - self.piece_start())
+    }
+
+    /// Feed downloaded data. Fails with the index of a piece whose hash
+    /// didn't match; pieces completed before it are still in `take_verified`.
+    fn push(&mut self, mut chunk: Vec<u8>) -> Result<(), usize> {
+        while !chunk.is_empty() && self.index < self.pieces.hashes.len() {
+            let wanted = (self.piece_len() - self.filled) as usize;
+            let rest = if chunk.len() > wanted {
+                chunk.split_off(wanted)
+            } else {
+                Vec::new()
+            };
+            self.hasher.update(&chunk);
+            self.filled += chunk.len() as u64;
+            self.pending.push(chunk);
+            chunk = rest;
+
+            if self.filled == self.piece_len() {
+                let hasher =
+                    std::mem::replace(&mut self.hasher, PieceHasher::new(self.pieces.algorithm));
+                if !hasher
+                    .finalize_hex()
+                    .eq_ignore_ascii_case(&self.pieces.hashes[self.index])
+                {
+                    return Err(self.index);
+                }
+                self.verified.append(&mut self.pending);
+                self.index += 1;
+                self.filled = 0;
+            }
+        }
         Ok(())
     }
+
+    /// Data of the pieces verified so far, ready to write
+    fn take_verified(&mut self) -> Vec<Vec<u8>> {
+        std::mem::take(&mut self.verified)
+    }
+
+    /// Drop the current piece's data; returns the offset to download it from
+    fn restart_piece(&mut self) -> u64 {
+        self.hasher = PieceHasher::new(self.pieces.algorithm);
+        self.pending.clear();
+        self.filled = 0;
+        self.piece_start()
+    }
+}
+
+/// Check that a user-entered image URL is something the flasher can fetch
+pub fn validate_image_url(input: &str) -> Result<reqwest::Url, String> {
+    let input = input.trim();
+    if input.is_empty() {
+        return Err("Enter an image URL".to_string());
+    }
+    if input.chars().any(char::is_whitespace) {
+        return Err("URL must not contain spaces".to_string());
+    }
+
+    let url = reqwest::Url::parse(input).map_err(|e| format!("Invalid URL: {}", e))?;
+    if url.scheme() == "magnet" {
+        source::torrent::Magnet::parse(input).map_err(|e| e.to_string())?;
+        return Ok(url);
+    }
+    if !matches!(url.scheme(), "http" | "https" | "s3" | "oci") {
+        return Err(format!("Unsupported scheme '{}'", url.scheme()));
+    }
+    if url.host_str().is_none_or(str::is_empty) {
+        return Err("URL has no host".to_string());
+    }
+    if url
+        .path_segments()
+        .and_then(|mut s| s.next_back())
+        .is_none_or(str::is_empty)
+    {
+        return Err("URL must point to a file, not a directory".to_string());
+    }
+
+    Ok(url)
+}
+
+/// Find the SHA-256 for `file_name` in a checksum file. Handles GNU
+/// (`<hash>  name`, `<hash> *name`), BSD (`SHA256 (name) = <hash>`) and
+/// bare single-hash files.
+pub fn parse_checksum_file(contents: &str, file_name: &str) -> Option<String> {
+    let is_sha256 = |s: &str| s.len() == 64 && s.chars().all(|c| c.is_ascii_hexdigit());
+
+    let lines: Vec<&str> = contents
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty())
+        .collect();
+
+    for line in &lines {
+        if let Some(rest) = line.strip_prefix("SHA256 (") {
+            if let Some((name, hash)) = rest.split_once(") = ")
+                && name.trim_start_matches("./") == file_name
+                && is_sha256(hash.trim())
+            {
+                return Some(hash.trim().to_lowercase());
+            }
+            continue;
+        }
+
+        let mut parts = line.splitn(2, char::is_whitespace);
+        let (Some(hash), Some(name)) = (parts.next(), parts.next()) else {
+            continue;
+        };
+        let name = name.trim().trim_start_matches('*').trim_start_matches("./");
+        if is_sha256(hash) && name == file_name {
+            return Some(hash.to_lowercase());
+        }
+    }
+
+    // A `.sha256` file sometimes holds just the hash
+    match lines.as_slice() {
+        [only] if is_sha256(only) => Some(only.to_lowercase()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delta_sink() {
+        let path = std::env::temp_dir().join(format!("pervie-delta-{}", std::process::id()));
+        let mut old = vec![7u8; 3 * WRITE_BUFFER_SIZE];
+        old.extend_from_slice(b"old tail");
+        std::fs::write(&path, &old).unwrap();
+
+        // Only the second block and the short tail change
+        let mut new = old.clone();
+        new[WRITE_BUFFER_SIZE + 10] = 8;
+        let tail = new.len() - 8;
+        new[tail..].copy_from_slice(b"new tail");
+
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&path)
+            .unwrap();
+        let stats = Arc::new(DeltaStats::default());
+        let mut sink = DeviceSink::delta(file, stats.clone());
+        for chunk in new.chunks(300_000) {
+            sink.write(chunk).unwrap();
+        }
+        sink.finish().unwrap();
+        let written = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(written, new);
+        assert_eq!(
+            stats.written.load(Ordering::Relaxed),
+            WRITE_BUFFER_SIZE as u64 + 8
+        );
+        assert_eq!(
+            stats.unchanged.load(Ordering::Relaxed),
+            2 * WRITE_BUFFER_SIZE as u64
+        );
+    }
+
+    #[test]
+    fn test_decompress_sink() {
+        let image: Vec<u8> = (0..3 * WRITE_BUFFER_SIZE as u32 + 17)
+            .map(|i| (i / 1000) as u8)
+            .collect();
+        for compression in Compression::ALL {
+            let compressed = match compression {
+                Compression::None => image.clone(),
+                Compression::Gzip => {
+                    let mut encoder =
+                        flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+                    encoder.write_all(&image).unwrap();
+                    encoder.finish().unwrap()
+                }
+                Compression::Zstd => zstd::encode_all(&image[..], 3).unwrap(),
+                Compression::Xz => {
+                    let mut encoder = liblzma::write::XzEncoder::new(Vec::new(), 1);
+                    encoder.write_all(&image).unwrap();
+                    encoder.finish().unwrap()
+                }
+            };
+
+            let path = std::env::temp_dir().join(format!(
+                "pervie-decompress-{}-{}",
+                compression.display_name(),
+                std::process::id()
+            ));
+            let file = std::fs::File::create(&path).unwrap();
+            let written = Arc::new(AtomicU64::new(0));
+            let device = CountingSink {
+                sink: Box::new(DeviceSink::new(file)),
+                written: written.clone(),
+            };
+            let mut sink = DecompressSink::wrap(compression, device).unwrap();
+            for chunk in compressed.chunks(100_000) {
+                sink.write(chunk).unwrap();
+            }
+            sink.finish().unwrap();
+            let flashed = std::fs::read(&path).unwrap();
+            std::fs::remove_file(&path).unwrap();
+
+            assert_eq!(flashed, image, "{}", compression.display_name());
+            assert_eq!(written.load(Ordering::Relaxed), image.len() as u64);
+        }
+    }
+
+    #[test]
+    fn test_piece_verifier() {
+        let data: Vec<u8> = (0..10u8).collect();
+        let hashes = data
+            .chunks(4)
+            .map(|piece| format!("{:x}", Sha256::digest(piece)))
+            .collect();
+        let pieces = PieceHashes {
+            algorithm: PieceAlgorithm::Sha256,
+            length: 4,
+            hashes,
+        };
+
+        // Chunks that straddle piece boundaries, ending on the short last piece
+        let mut verifier = PieceVerifier::new(pieces.clone(), 10).unwrap();
+        assert_eq!(verifier.push(data[..3].to_vec()), Ok(()));
+        assert!(verifier.take_verified().is_empty());
+        assert_eq!(verifier.push(data[3..9].to_vec()), Ok(()));
+        assert_eq!(verifier.take_verified().concat(), data[..8]);
+        assert_eq!(verifier.push(data[9..].to_vec()), Ok(()));
+        assert_eq!(verifier.take_verified().concat(), data[8..]);
+
+        // A bad second piece keeps the good first one
+        let mut verifier = PieceVerifier::new(pieces.clone(), 10).unwrap();
+        assert_eq!(verifier.push(vec![0, 1, 2, 3, 4, 5, 0xff, 7]), Err(1));
+        assert_eq!(verifier.take_verified().concat(), data[..4]);
+        assert_eq!(verifier.restart_piece(), 4);
+        assert_eq!(verifier.push(data[4..].to_vec()), Ok(()));
+        assert_eq!(verifier.take_verified().concat(), data[4..]);
+
+        assert!(PieceVerifier::new(pieces, 20).is_none());
+    }
+
+    #[test]
+    fn test_next_rate_limit() {
+        assert_eq!(next_rate_limit(None, true), None);
+        assert_eq!(next_rate_limit(None, false), Some(100_000_000));
+        assert_eq!(next_rate_limit(Some(100_000_000), true), None);
+        assert_eq!(next_rate_limit(Some(3_000_000), true), Some(5_000_000));
+        assert_eq!(next_rate_limit(Some(3_000_000), false), Some(2_000_000));
+        assert_eq!(next_rate_limit(Some(1_000_000), false), Some(1_000_000));
+        assert_eq!(next_rate_limit(Some(500_000), false), Some(500_000));
+    }
+
+    #[test]
+    fn test_parse_checksum_file() {
+        let hash = "a".repeat(64);
+        let other = "b".repeat(64);
+
+        let gnu = format!("{other}  other.iso\n{hash}  debian.iso\n");
+        assert_eq!(parse_checksum_file(&gnu, "debian.iso"), Some(hash.clone()));
+
+        let binary = format!("{hash} *debian.iso\n");
+        assert_eq!(
+            parse_checksum_file(&binary, "debian.iso"),
+            Some(hash.clone())
+        );
+
+        let bsd = format!("SHA256 (debian.iso) = {hash}\n");
+        assert_eq!(parse_checksum_file(&bsd, "debian.iso"), Some(hash.clone()));
+
+        assert_eq!(
+            parse_checksum_file(&format!("{hash}\n"), "debian.iso"),
+            Some(hash)
+        );
+        assert_eq!(parse_checksum_file(&gnu, "missing.iso"), None);
+    }
+
+    #[test]
+    fn test_validate_image_url() {
+        assert!(validate_image_url("https://example.com/nightly.img.xz").is_ok());
+        assert!(validate_image_url("oci://ghcr.io/acme/edge:1.4").is_ok());
+        assert!(validate_image_url("magnet:?xt=urn:btih:CIQGBKOUY6QVCFZU3CQDP7MFHUE4ELTQ").is_ok());
+        assert!(validate_image_url("magnet:?dn=no-hash").is_err());
+        assert!(validate_image_url("").is_err());
+        assert!(validate_image_url("ftp://example.com/a.iso").is_err());
+        assert!(validate_image_url("https://example.com/dir/").is_err());
+        assert!(validate_image_url("https://example.com/a b.iso").is_err());
+    }
 }
@@ -0,0 +1,114 @@
+use std::fs::OpenOptions;
+use std::io::{Seek, SeekFrom};
+use std::process::{Command, Stdio};
+
+use anyhow::{Context, Result, bail};
+
+use super::filesystem::{self, FsKind};
+use super::partition;
+use super::shrink::run_e2fsprogs;
+
+/// Less room than this after the last partition isn't worth a resize
+const MIN_GROWTH: u64 = 1024 * 1024;
+
+/// A partition extended to the end of the device
+#[derive(Debug, Clone, PartialEq)]
+pub struct Grown {
+    pub number: u32,
+    pub old_size: u64,
+    pub new_size: u64,
+    /// The filesystem that still has to be grown into the new space
+    pub kind: FsKind,
+}
+
+/// What was fixed up in the partition table of a freshly written image
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Repair {
+    /// The backup GPT moved from where the image ended to the device's end
+    pub relocated: bool,
+    pub grown: Option<Grown>,
+}
+
+/// Move a GPT's backup header to the end of the device and, with `grow`,
+/// extend the last partition into the rest of the device when it holds
+/// ext2/3/4 or FAT. The filesystem itself is left for `grow_filesystem`.
+pub fn repair(device_path: &str, grow: bool) -> Result<Repair> {
+    let relocated = partition::relocate_gpt_backup(device_path)?;
+    if !grow {
+        return Ok(Repair {
+            relocated,
+            grown: None,
+        });
+    }
+
+    let mut device = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(device_path)
+        .with_context(|| format!("Failed to open device {}", device_path))?;
+    let disk_size = device.seek(SeekFrom::End(0))?;
+    let Some(table) = partition::read_partition_table(&mut device, disk_size)? else {
+        bail!("The written image has no partition table");
+    };
+    let Some(last) = table.partitions.iter().max_by_key(|p| p.end()).cloned() else {
+        bail!("The written image has no partitions");
+    };
+    let kind = match filesystem::detect(&mut device, last.start)? {
+        Some(kind @ (FsKind::Ext | FsKind::Fat12 | FsKind::Fat16 | FsKind::Fat32)) => kind,
+        Some(kind) => bail!(
+            "Can't grow {}; only ext2/3/4 and FAT are supported",
+            kind.display_name()
+        ),
+        None => bail!("No filesystem found in partition {}", last.number),
+    };
+    let room = disk_size.saturating_sub(last.end() + table.gpt_backup_len().unwrap_or(0));
+    if room < MIN_GROWTH {
+        return Ok(Repair {
+            relocated,
+            grown: None,
+        });
+    }
+
+    let grown = partition::grow_last_partition(&mut device, disk_size)?;
+    device.sync_all().context("Failed to sync device")?;
+    Ok(Repair {
+        relocated,
+        grown: grown.map(|partition| Grown {
+            number: partition.number,
+            old_size: last.size,
+            new_size: partition.size,
+            kind,
+        }),
+    })
+}
+
+/// Grow the filesystem on a partition to fill it: ext with `resize2fs`
+/// after a forced check, FAT with `fatresize`
+pub fn grow_filesystem(partition_path: &str, kind: FsKind) -> Result<()> {
+    match kind {
+        FsKind::Ext => {
+            // resize2fs insists on a fresh check; exit code 1 means errors were fixed
+            let status = run_e2fsprogs(Command::new("e2fsck").args(["-f", "-y", partition_path]))?;
+            if status.code().is_none_or(|code| code > 1) {
+                bail!("e2fsck found errors it couldn't fix");
+            }
+            let status = run_e2fsprogs(Command::new("resize2fs").arg(partition_path))?;
+            if !status.success() {
+                bail!("resize2fs failed to grow the filesystem");
+            }
+        }
+        FsKind::Fat12 | FsKind::Fat16 | FsKind::Fat32 => {
+            let status = Command::new("fatresize")
+                .args(["-f", "-s", "max", partition_path])
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .status()
+                .context("Failed to run fatresize; is it installed?")?;
+            if !status.success() {
+                bail!("fatresize failed to grow the filesystem");
+            }
+        }
+        kind => bail!("Can't grow {}", kind.display_name()),
+    }
+    Ok(())
+}
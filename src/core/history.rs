@@ -0,0 +1,45 @@
+use std::fs;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+const HISTORY_FILE: &str = "url_history.json";
+const MAX_ENTRIES: usize = 20;
+
+/// Recently flashed custom image URLs, most recent first
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UrlHistory {
+    pub entries: Vec<String>,
+}
+
+impl UrlHistory {
+    /// Load history from the config directory. Missing or unreadable files yield an empty history.
+    pub fn load() -> Self {
+        Self::path()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) -> std::io::Result<()> {
+        let Some(path) = Self::path() else {
+            return Ok(());
+        };
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let contents = serde_json::to_string_pretty(self).map_err(std::io::Error::other)?;
+        fs::write(path, contents)
+    }
+
+    /// Move `url` to the front, dropping duplicates and the oldest entries
+    pub fn push(&mut self, url: &str) {
+        self.entries.retain(|entry| entry != url);
+        self.entries.insert(0, url.to_string());
+        self.entries.truncate(MAX_ENTRIES);
+    }
+
+    fn path() -> Option<PathBuf> {
+        crate::utils::config_dir().map(|dir| dir.join(HISTORY_FILE))
+    }
+}
@@ -0,0 +1,234 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+
+use anyhow::{Context, Result, bail};
+use sha2::{Digest, Sha256};
+
+use super::Iso;
+use super::partition::{le32, read_at};
+
+const ISO_SECTOR: u64 = 2048;
+/// The primary volume descriptor sits at sector 16
+const PRIMARY_DESCRIPTOR: u64 = 16 * ISO_SECTOR;
+
+/// The ISO 9660 volume at the start of a drive
+#[derive(Debug, Clone, PartialEq)]
+pub struct Volume {
+    pub label: String,
+    /// Size the volume descriptor records, which a hybrid image may exceed
+    pub size: u64,
+}
+
+/// A catalog image whose size and checksum are known, to compare a drive with
+#[derive(Debug, Clone, PartialEq)]
+pub struct Candidate {
+    pub iso: Iso,
+    pub size: u64,
+    pub sha256: String,
+}
+
+/// What a drive holds, compared with the catalog
+#[derive(Debug, Clone, PartialEq)]
+pub enum Identity {
+    /// Byte for byte the image of this catalog entry
+    Exact(Iso),
+    /// The label names a catalog distro, but no entry's checksum matches
+    SameDistro {
+        distro: String,
+        label: String,
+        /// Entries that couldn't be compared, for lack of a checksum
+        unchecked: usize,
+    },
+    Unknown {
+        label: Option<String>,
+    },
+}
+
+/// Result of identifying a drive, for the app to remember
+#[derive(Debug, Clone, PartialEq)]
+pub struct Identification {
+    pub device_path: String,
+    pub identity: Identity,
+}
+
+impl Identification {
+    pub fn describe(&self) -> String {
+        match &self.identity {
+            Identity::Exact(iso) => format!(
+                "{} holds exactly {} {} {} ({}); flashing it again can be skipped",
+                self.device_path, iso.name, iso.version, iso.variety, iso.arch
+            ),
+            Identity::SameDistro {
+                distro,
+                label,
+                unchecked,
+            } => {
+                let mut text = format!(
+                    "{} holds {} (\"{}\"), but not the catalog's current image",
+                    self.device_path, distro, label
+                );
+                if *unchecked > 0 {
+                    text.push_str(&format!(
+                        "; {} entr{} had no checksum to compare with",
+                        unchecked,
+                        if *unchecked == 1 { "y" } else { "ies" }
+                    ));
+                }
+                text
+            }
+            Identity::Unknown { label: Some(label) } => format!(
+                "{} holds an ISO labelled \"{}\" that isn't in the catalog",
+                self.device_path, label
+            ),
+            Identity::Unknown { label: None } => {
+                format!("{} doesn't start with an ISO image", self.device_path)
+            }
+        }
+    }
+}
+
+/// Read the primary volume descriptor at the start of a drive
+pub fn read_volume(device_path: &str) -> Result<Option<Volume>> {
+    let mut device =
+        File::open(device_path).with_context(|| format!("Failed to open {}", device_path))?;
+    let desc = read_at(&mut device, PRIMARY_DESCRIPTOR, ISO_SECTOR as usize)?;
+    if desc[0] != 1 || &desc[1..6] != b"CD001" {
+        return Ok(None);
+    }
+    let label = String::from_utf8_lossy(&desc[40..72]).trim().to_string();
+    let block_size = u64::from(u16::from_le_bytes([desc[128], desc[129]]));
+    Ok(Some(Volume {
+        label,
+        size: u64::from(le32(&desc[80..])) * block_size,
+    }))
+}
+
+/// Catalog entries of the distro a volume label names. Labels such as
+/// "Debian 13.2.0 amd64 n" or "ARCH_202512" start with the distro, so a
+/// whole word of the label must match the first word of the entry's name.
+pub fn same_distro<'a>(catalog: &'a [Iso], label: &str) -> Vec<&'a Iso> {
+    let words: Vec<String> = label
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .map(str::to_lowercase)
+        .collect();
+    catalog
+        .iter()
+        .filter(|iso| {
+            iso.name
+                .split_whitespace()
+                .next()
+                .is_some_and(|distro| words.contains(&distro.to_lowercase()))
+        })
+        .collect()
+}
+
+/// SHA-256 of the first `size` bytes for each of `sizes`, in one pass over
+/// the device. Blocking: run it on a blocking thread.
+pub fn hash_prefixes(
+    device_path: &str,
+    sizes: &[u64],
+    mut progress: impl FnMut(u64, u64),
+) -> Result<HashMap<u64, String>> {
+    let mut sizes = sizes.to_vec();
+    sizes.sort_unstable();
+    sizes.dedup();
+    let Some(&total) = sizes.last() else {
+        return Ok(HashMap::new());
+    };
+
+    let mut device =
+        File::open(device_path).with_context(|| format!("Failed to open {}", device_path))?;
+    let mut hasher = Sha256::new();
+    let mut hashes = HashMap::new();
+    let mut buf = vec![0u8; 1024 * 1024];
+    let mut done = 0u64;
+    for size in sizes {
+        while done < size {
+            let want = (size - done).min(buf.len() as u64) as usize;
+            let read = device
+                .read(&mut buf[..want])
+                .with_context(|| format!("Failed to read {} at byte {}", device_path, done))?;
+            if read == 0 {
+                bail!("{} ended after {} bytes", device_path, done);
+            }
+            hasher.update(&buf[..read]);
+            done += read as u64;
+            progress(done, total);
+        }
+        hashes.insert(size, format!("{:x}", hasher.clone().finalize()));
+    }
+    Ok(hashes)
+}
+
+/// The candidate whose checksum matches the drive's prefix of its size
+pub fn find_match<'a>(
+    candidates: &'a [Candidate],
+    hashes: &HashMap<u64, String>,
+) -> Option<&'a Candidate> {
+    candidates.iter().find(|c| {
+        hashes
+            .get(&c.size)
+            .is_some_and(|hash| hash.eq_ignore_ascii_case(&c.sha256))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn iso(name: &str, version: &str) -> Iso {
+        let mut iso = Iso::from_custom_url("https://example.com/image.iso");
+        iso.name = name.to_string();
+        iso.version = version.to_string();
+        iso
+    }
+
+    #[test]
+    fn test_identify() {
+        let catalog = vec![
+            iso("Debian", "13"),
+            iso("Arch Linux", "2025.12.01"),
+            iso("Alpine", "3.23"),
+        ];
+        let names = |label| -> Vec<String> {
+            same_distro(&catalog, label)
+                .iter()
+                .map(|iso| iso.name.clone())
+                .collect()
+        };
+        assert_eq!(names("Debian 13.2.0 amd64 n"), ["Debian"]);
+        assert_eq!(names("ARCH_202512"), ["Arch Linux"]);
+        // "aarch64" is not Arch Linux
+        assert_eq!(names("alpine-std 3.23.2 aarch64"), ["Alpine"]);
+        assert!(names("CDROM").is_empty());
+
+        let path = std::env::temp_dir().join(format!("pervie-identify-{}", std::process::id()));
+        let mut data = vec![0u8; 3 * 1024 * 1024];
+        data[..5].copy_from_slice(b"image");
+        std::fs::write(&path, &data).unwrap();
+        let mut calls = 0;
+        let hashes = hash_prefixes(&path.to_string_lossy(), &[5, 3 * 1024 * 1024, 5], |_, _| {
+            calls += 1
+        });
+        std::fs::remove_file(&path).unwrap();
+        let hashes = hashes.unwrap();
+        assert!(calls >= 3);
+
+        let candidate = |version: &str, size: u64, sha256: &str| Candidate {
+            iso: iso("Debian", version),
+            size,
+            sha256: sha256.to_string(),
+        };
+        let candidates = [
+            candidate("12", 5, &"0".repeat(64)),
+            candidate(
+                "13",
+                5,
+                "6105D6CC76AF400325E94D588CE511BE5BFDBB73B437DC51ECA43917D7A43E3D",
+            ),
+        ];
+        assert_eq!(hashes.len(), 2);
+        assert_eq!(find_match(&candidates, &hashes).unwrap().iso.version, "13");
+    }
+}
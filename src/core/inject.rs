@@ -0,0 +1,491 @@
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Component, Path, PathBuf};
+use std::process::Command;
+
+use anyhow::{Context, Result, bail};
+use fatfs::{FileSystem, FsOptions, ReadWriteSeek};
+use sha2::{Digest, Sha256};
+
+use super::exfat::ExFat;
+use super::filesystem::{self, FsKind};
+use super::flasher::drop_page_cache;
+use super::partition::{self, Partition, PartitionIo};
+
+/// ext superblock flag for a journal that still needs replaying
+const EXT_INCOMPAT_RECOVER: u32 = 0x4;
+
+/// Which partition of the written image a file goes into
+#[derive(Debug, Clone, PartialEq)]
+pub enum PartitionSelector {
+    Number(u32),
+    /// Filesystem label or GPT partition name, ignoring case
+    Label(String),
+}
+
+impl PartitionSelector {
+    pub fn parse(text: &str) -> Self {
+        match text.parse() {
+            Ok(number) => PartitionSelector::Number(number),
+            Err(_) => PartitionSelector::Label(text.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for PartitionSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PartitionSelector::Number(number) => write!(f, "{}", number),
+            PartitionSelector::Label(label) => f.write_str(label),
+        }
+    }
+}
+
+/// A local file to drop into the image after it is written
+#[derive(Debug, Clone, PartialEq)]
+pub struct Injection {
+    pub source: PathBuf,
+    pub partition: PartitionSelector,
+    /// Absolute path inside the partition's filesystem
+    pub dest: String,
+}
+
+impl Injection {
+    /// Parse `<local file> -> <partition>:<path>`, e.g. `ks.cfg -> 1:/ks.cfg`
+    /// or `~/.ssh/id.pub -> BOOT:/keys/id.pub`
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let Some((source, target)) = text.split_once("->") else {
+            return Err("Expected <file> -> <partition>:<path>".to_string());
+        };
+        let source = crate::utils::expand_home(source.trim());
+        if !source.is_file() {
+            return Err(format!("{} is not a file", source.display()));
+        }
+        let Some((partition, dest)) = target.trim().split_once(':') else {
+            return Err("Expected <partition>:<path> after ->".to_string());
+        };
+        let (partition, dest) = (partition.trim(), dest.trim());
+        if partition.is_empty() {
+            return Err("Name the partition by number or label".to_string());
+        }
+        let dest_path = Path::new(dest);
+        if !dest_path.is_absolute()
+            || dest_path.file_name().is_none()
+            || dest_path
+                .components()
+                .any(|c| matches!(c, Component::ParentDir | Component::CurDir))
+        {
+            return Err("The path in the partition must be absolute, e.g. /ks.cfg".to_string());
+        }
+        Ok(Self {
+            source,
+            partition: PartitionSelector::parse(partition),
+            dest: dest.to_string(),
+        })
+    }
+}
+
+impl fmt::Display for Injection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} -> {}:{}",
+            self.source.display(),
+            self.partition,
+            self.dest
+        )
+    }
+}
+
+/// A partition of the written image and the files going into it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Target {
+    pub partition: Partition,
+    pub kind: Option<FsKind>,
+    pub files: Vec<Injection>,
+}
+
+/// Find the partition each injection names, grouping files by partition
+pub fn resolve(device_path: &str, injections: &[Injection]) -> Result<Vec<Target>> {
+    let mut device =
+        File::open(device_path).with_context(|| format!("Failed to open {}", device_path))?;
+    let disk_size = device.seek(SeekFrom::End(0))?;
+    let Some(table) = partition::read_partition_table(&mut device, disk_size)? else {
+        bail!("The written image has no partition table");
+    };
+
+    let mut found = Vec::new();
+    for partition in table.partitions {
+        let kind = filesystem::detect(&mut device, partition.start)?;
+        let label = filesystem::label(&mut device, partition.start)?;
+        found.push((partition, kind, label));
+    }
+
+    let mut targets: Vec<Target> = Vec::new();
+    for injection in injections {
+        let named = |partition: &Partition, label: &Option<String>| match &injection.partition {
+            PartitionSelector::Number(number) => partition.number == *number,
+            PartitionSelector::Label(wanted) => {
+                label
+                    .as_deref()
+                    .is_some_and(|label| label.eq_ignore_ascii_case(wanted))
+                    || partition.name.eq_ignore_ascii_case(wanted)
+            }
+        };
+        let Some((partition, kind, _)) = found.iter().find(|(p, _, label)| named(p, label)) else {
+            bail!("No partition {} in the written image", injection.partition);
+        };
+        match targets
+            .iter_mut()
+            .find(|t| t.partition.number == partition.number)
+        {
+            Some(target) => target.files.push(injection.clone()),
+            None => targets.push(Target {
+                partition: partition.clone(),
+                kind: *kind,
+                files: vec![injection.clone()],
+            }),
+        }
+    }
+
+    // Checked up front so no partition is written when another can't be
+    if let Some(target) = targets.iter().find(|t| !t.is_writable()) {
+        bail!(
+            "Unsupported filesystem on partition {} ({}): files can only be written into FAT, exFAT and ext2/3/4 partitions",
+            target.partition.number,
+            target
+                .kind
+                .map_or("unknown filesystem", |kind| kind.display_name())
+        );
+    }
+    Ok(targets)
+}
+
+impl Target {
+    /// Whether the partition's filesystem can be written without mounting
+    pub fn is_writable(&self) -> bool {
+        matches!(
+            self.kind,
+            Some(FsKind::Fat12 | FsKind::Fat16 | FsKind::Fat32 | FsKind::ExFat | FsKind::Ext)
+        )
+    }
+}
+
+/// Write files into a partition without mounting it, then read them back
+/// from a fresh look at the filesystem
+pub fn write(device_path: &str, target: &Target) -> Result<()> {
+    match target.kind {
+        Some(FsKind::ExFat) => write_exfat(device_path, target),
+        Some(FsKind::Ext) => write_ext(device_path, target),
+        _ => write_fat(device_path, target),
+    }
+}
+
+fn write_fat(device_path: &str, target: &Target) -> Result<()> {
+    let open = || -> Result<(FileSystem<PartitionIo>, File)> {
+        let (io, device) = open_partition(device_path, &target.partition)?;
+        let fs = FileSystem::new(io, FsOptions::new())
+            .with_context(|| format!("Partition {} isn't readable FAT", target.partition.number))?;
+        Ok((fs, device))
+    };
+
+    let (fs, device) = open()?;
+    for file in &target.files {
+        fat_write(&fs, file)?;
+    }
+    fs.unmount().context("Failed to flush the partition")?;
+    flush(&device, device_path, &target.partition)?;
+
+    let (fs, _) = open()?;
+    for file in &target.files {
+        let written = fs
+            .root_dir()
+            .open_file(file.dest.trim_start_matches('/'))
+            .with_context(|| format!("{} is missing after writing", file.dest))?;
+        verify(file, sha256(written)?)?;
+    }
+    Ok(())
+}
+
+fn write_exfat(device_path: &str, target: &Target) -> Result<()> {
+    let open = || -> Result<(ExFat<PartitionIo>, File)> {
+        let (io, device) = open_partition(device_path, &target.partition)?;
+        let volume = ExFat::open(io).with_context(|| {
+            format!("Partition {} isn't readable exFAT", target.partition.number)
+        })?;
+        Ok((volume, device))
+    };
+
+    let (mut volume, device) = open()?;
+    for file in &target.files {
+        let mut source = File::open(&file.source)
+            .with_context(|| format!("Failed to open {}", file.source.display()))?;
+        let len = source.metadata()?.len();
+        volume
+            .write_file(&file.dest, &mut source, len)
+            .with_context(|| format!("Failed to write {}", file.dest))?;
+    }
+    drop(volume);
+    flush(&device, device_path, &target.partition)?;
+
+    let (mut volume, _) = open()?;
+    for file in &target.files {
+        let mut hasher = Sha256::new();
+        volume
+            .read_file(&file.dest, &mut hasher)
+            .with_context(|| format!("{} is missing after writing", file.dest))?;
+        verify(file, hasher.finalize().into())?;
+    }
+    Ok(())
+}
+
+/// ext has no in-process writer here, so requests go through debugfs, which
+/// edits the filesystem image directly rather than through the kernel
+fn write_ext(device_path: &str, target: &Target) -> Result<()> {
+    let start = target.partition.start;
+    let superblock = partition::read_at(&mut File::open(device_path)?, start + 1024, 256)?;
+    if partition::le32(&superblock[0x60..]) & EXT_INCOMPAT_RECOVER != 0 {
+        bail!(
+            "Partition {} has a journal that needs replaying; run e2fsck on it first",
+            target.partition.number
+        );
+    }
+
+    // e2fsprogs opens a filesystem at an offset into a disk with this suffix
+    let fs = format!("{}?offset={}", device_path, start);
+    let debugfs = |args: &[&str]| -> Result<Vec<u8>> {
+        let output = Command::new("debugfs")
+            .args(args)
+            .arg(&fs)
+            .output()
+            .context("Failed to run debugfs; is e2fsprogs installed?")?;
+        Ok(output.stdout)
+    };
+    // debugfs splits requests on spaces unless they're quoted, and has no
+    // way to escape a quote
+    let quote = |path: &str| -> Result<String> {
+        if path.contains(['"', '\n']) {
+            bail!("{} can't be written into an ext partition", path);
+        }
+        Ok(format!("\"{}\"", path))
+    };
+
+    for file in &target.files {
+        let source = quote(&file.source.to_string_lossy())?;
+        let dest = quote(&file.dest)?;
+        let mut parent = String::new();
+        let parts: Vec<&str> = file.dest.split('/').filter(|p| !p.is_empty()).collect();
+        for part in &parts[..parts.len().saturating_sub(1)] {
+            parent = format!("{}/{}", parent, part);
+            let existing = debugfs(&["-R", &format!("stat {}", quote(&parent)?)])?;
+            // mkdir over an existing directory leaves an orphaned inode behind
+            if !String::from_utf8_lossy(&existing).contains("Type: directory") {
+                debugfs(&["-w", "-R", &format!("mkdir {}", quote(&parent)?)])?;
+            }
+        }
+        // write refuses to replace a file, so remove any old one first
+        debugfs(&["-w", "-R", &format!("rm {}", dest)])?;
+        debugfs(&["-w", "-R", &format!("write {} {}", source, dest)])?;
+    }
+    let device = File::open(device_path)?;
+    flush(&device, device_path, &target.partition)?;
+
+    // debugfs exits cleanly even when a request fails, so check the result
+    for file in &target.files {
+        let dest = quote(&file.dest)?;
+        let stat = debugfs(&["-R", &format!("stat {}", dest)])?;
+        if !String::from_utf8_lossy(&stat).contains("Type: regular") {
+            bail!("{} is missing after writing", file.dest);
+        }
+        let written = debugfs(&["-R", &format!("cat {}", dest)])?;
+        verify(file, sha256(&written[..])?)?;
+    }
+    Ok(())
+}
+
+/// Open the partition for reading and writing, with a handle on the whole
+/// device for flushing it afterwards
+fn open_partition(device_path: &str, partition: &Partition) -> Result<(PartitionIo, File)> {
+    let device = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(device_path)
+        .with_context(|| format!("Failed to open {}", device_path))?;
+    let handle = device.try_clone()?;
+    Ok((PartitionIo::new(device, partition), handle))
+}
+
+fn flush(device: &File, device_path: &str, partition: &Partition) -> Result<()> {
+    device
+        .sync_all()
+        .with_context(|| format!("Failed to sync {}", device_path))?;
+    // Read back what reached the device, not what the page cache kept
+    drop_page_cache(device, partition.start, partition.size);
+    Ok(())
+}
+
+fn fat_write<T: ReadWriteSeek>(fs: &FileSystem<T>, file: &Injection) -> Result<()> {
+    let path = file.dest.trim_start_matches('/');
+    let mut dir = fs.root_dir();
+    let mut parts: Vec<&str> = path.split('/').filter(|p| !p.is_empty()).collect();
+    let name = parts.pop().unwrap_or_default();
+    for part in parts {
+        dir = match dir.open_dir(part) {
+            Ok(existing) => existing,
+            Err(_) => dir
+                .create_dir(part)
+                .with_context(|| format!("Failed to create {} for {}", part, file.dest))?,
+        };
+    }
+
+    let mut source = File::open(&file.source)
+        .with_context(|| format!("Failed to open {}", file.source.display()))?;
+    let mut dest = dir
+        .create_file(name)
+        .with_context(|| format!("Failed to create {}", file.dest))?;
+    dest.truncate()?;
+    io::copy(&mut source, &mut dest).with_context(|| format!("Failed to write {}", file.dest))?;
+    Ok(())
+}
+
+fn verify(file: &Injection, written: [u8; 32]) -> Result<()> {
+    let source = File::open(&file.source)
+        .with_context(|| format!("Failed to open {}", file.source.display()))?;
+    if sha256(source)? != written {
+        bail!("{} doesn't match {}", file.dest, file.source.display());
+    }
+    Ok(())
+}
+
+fn sha256(mut reader: impl Read) -> Result<[u8; 32]> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut reader, &mut hasher)?;
+    Ok(hasher.finalize().into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::io::{Cursor, Write};
+
+    #[test]
+    fn test_parse_injection() {
+        let source = std::env::temp_dir().join(format!("pervie-inject-{}", std::process::id()));
+        fs::write(&source, "license").unwrap();
+        let spec = format!("{} -> BOOT:/keys/id.pub", source.display());
+        let parsed = Injection::parse(&spec);
+        let relative = Injection::parse(&format!("{} -> 2:ks.cfg", source.display()));
+        let escape = Injection::parse(&format!("{} -> 2:/../ks.cfg", source.display()));
+        let numbered = Injection::parse(&format!("{} -> 2:/ks.cfg", source.display()));
+        fs::remove_file(&source).unwrap();
+
+        let parsed = parsed.unwrap();
+        assert_eq!(
+            parsed.partition,
+            PartitionSelector::Label("BOOT".to_string())
+        );
+        assert_eq!(parsed.dest, "/keys/id.pub");
+        assert_eq!(parsed.to_string(), spec);
+        assert_eq!(numbered.unwrap().partition, PartitionSelector::Number(2));
+        assert!(relative.is_err());
+        assert!(escape.is_err());
+        assert!(Injection::parse("/nonexistent -> 1:/a").is_err());
+    }
+
+    #[test]
+    fn test_ext_write() {
+        if Command::new("debugfs").output().is_err() {
+            eprintln!("e2fsprogs not installed; skipping");
+            return;
+        }
+        let mut fs_image = Vec::new();
+        flate2::read::GzDecoder::new(&include_bytes!("../../tests/fixtures/fs/ext4.img.gz")[..])
+            .read_to_end(&mut fs_image)
+            .unwrap();
+        // MBR with the ext4 fixture as its one partition, at 1 MiB
+        let mut disk = vec![0u8; 1024 * 1024];
+        disk[446 + 4] = 0x83;
+        disk[446 + 8..446 + 12].copy_from_slice(&2048u32.to_le_bytes());
+        disk[446 + 12..446 + 16].copy_from_slice(&((fs_image.len() / 512) as u32).to_le_bytes());
+        disk[510..512].copy_from_slice(&[0x55, 0xaa]);
+        disk.extend_from_slice(&fs_image);
+        let id = std::process::id();
+        let path = std::env::temp_dir().join(format!("pervie-inject-ext-{}", id));
+        fs::write(&path, &disk).unwrap();
+        let source = std::env::temp_dir().join(format!("pervie inject motd {}", id));
+        fs::write(&source, "Provisioned by pervie\n").unwrap();
+
+        let device_path = path.to_string_lossy().into_owned();
+        let files = [
+            Injection {
+                source: source.clone(),
+                partition: PartitionSelector::Number(1),
+                dest: "/etc/motd".to_string(),
+            },
+            Injection {
+                source: source.clone(),
+                partition: PartitionSelector::Number(1),
+                dest: "/etc/ssh/authorized keys".to_string(),
+            },
+        ];
+        let targets = resolve(&device_path, &files).unwrap();
+        assert_eq!(targets.len(), 1);
+        assert_eq!(targets[0].kind, Some(FsKind::Ext));
+        // Writing again replaces the files
+        write(&device_path, &targets[0]).unwrap();
+        write(&device_path, &targets[0]).unwrap();
+
+        let fs = format!("{}?offset={}", device_path, 1024 * 1024);
+        let cat = Command::new("debugfs")
+            .args(["-R", "cat \"/etc/ssh/authorized keys\"", &fs])
+            .output()
+            .unwrap();
+        let fsck = Command::new("e2fsck")
+            .args(["-f", "-n", &fs])
+            .output()
+            .unwrap();
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&source).unwrap();
+        assert_eq!(cat.stdout, b"Provisioned by pervie\n");
+        assert!(fsck.status.success());
+    }
+
+    #[test]
+    fn test_fat_write() {
+        let source = std::env::temp_dir().join(format!("pervie-inject-fat-{}", std::process::id()));
+        fs::write(&source, "ssh-ed25519 AAAA ops@lab\n").unwrap();
+        let file = Injection {
+            source: source.clone(),
+            partition: PartitionSelector::Number(1),
+            dest: "/keys/deploy/id.pub".to_string(),
+        };
+
+        let mut disk = Cursor::new(vec![0u8; 8 * 1024 * 1024]);
+        fatfs::format_volume(&mut disk, fatfs::FormatVolumeOptions::new()).unwrap();
+        let fs = FileSystem::new(disk, FsOptions::new()).unwrap();
+        fs.root_dir()
+            .create_dir("keys")
+            .unwrap()
+            .create_file("old")
+            .unwrap()
+            .write_all(b"kept")
+            .unwrap();
+        fat_write(&fs, &file).unwrap();
+        // Writing again replaces the file rather than appending
+        fat_write(&fs, &file).unwrap();
+
+        let written = fs.root_dir().open_file("keys/deploy/id.pub").unwrap();
+        let result = verify(&file, sha256(written).unwrap());
+        let other = Injection {
+            dest: "/keys/old".to_string(),
+            ..file.clone()
+        };
+        let kept = fs.root_dir().open_file("keys/old").unwrap();
+        let mismatch = verify(&other, sha256(kept).unwrap());
+        fs::remove_file(&source).unwrap();
+        result.unwrap();
+        assert!(mismatch.is_err());
+    }
+}
@@ -0,0 +1,367 @@
+use std::io::{Read, Seek};
+use std::sync::Arc;
+
+use anyhow::Result;
+use tokio::runtime::Handle;
+
+use super::backup::Compression;
+use super::filesystem::{self, FsKind};
+use super::isofs;
+use super::partition::{self, GPT_EFI_SYSTEM, Partition, TableKind, le32, read_at};
+use super::source::{ImageSource, SourceReader};
+use super::windows;
+
+const ISO_SECTOR: u64 = 2048;
+/// Volume descriptors start at sector 16 of an ISO
+const FIRST_DESCRIPTOR: u64 = 16;
+const MAX_DESCRIPTORS: u64 = 64;
+const MBR_EFI_SYSTEM: &str = "ef";
+
+/// What kind of image is about to be written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    /// ISO 9660 with a partition table, so a stick boots like the disc
+    HybridIso,
+    /// ISO 9660 without a partition table, which only boots from a disc
+    Iso,
+    /// A Windows installer, unpacked onto FAT32 rather than copied
+    WindowsIso,
+    DiskImage,
+    /// A bare filesystem without a partition table
+    Filesystem(FsKind),
+    Compressed(&'static str),
+    VirtualDisk(&'static str),
+    Unknown,
+}
+
+impl ImageFormat {
+    pub fn describe(self) -> String {
+        match self {
+            ImageFormat::HybridIso => "Hybrid ISO".to_string(),
+            ImageFormat::Iso => "ISO 9660 (not hybrid)".to_string(),
+            ImageFormat::WindowsIso => "Windows installer ISO".to_string(),
+            ImageFormat::DiskImage => "Raw disk image".to_string(),
+            ImageFormat::Filesystem(kind) => format!("Bare {} filesystem", kind.display_name()),
+            ImageFormat::Compressed(name) => format!("{}-compressed file", name),
+            ImageFormat::VirtualDisk(name) => format!("{} virtual disk", name),
+            ImageFormat::Unknown => "Unknown".to_string(),
+        }
+    }
+
+    /// The compression unpacked while flashing; `None` means written as is
+    pub fn compression(self) -> Compression {
+        match self {
+            ImageFormat::Compressed(name) => Compression::ALL
+                .into_iter()
+                .find(|compression| compression.display_name() == name)
+                .unwrap_or(Compression::None),
+            _ => Compression::None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct InspectedPartition {
+    pub partition: Partition,
+    pub kind: Option<FsKind>,
+    /// The ISO 9660 filesystem of a hybrid image, which `kind` doesn't cover
+    pub iso9660: bool,
+    pub label: Option<String>,
+}
+
+impl InspectedPartition {
+    pub fn is_efi_system(&self) -> bool {
+        self.partition.type_id == MBR_EFI_SYSTEM || self.partition.type_id == GPT_EFI_SYSTEM
+    }
+
+    pub fn filesystem_name(&self) -> &'static str {
+        match self.kind {
+            Some(kind) => kind.display_name(),
+            None if self.iso9660 => "ISO 9660",
+            None => "unknown",
+        }
+    }
+}
+
+/// What the start of an image says about how it boots
+#[derive(Debug, Clone, PartialEq)]
+pub struct Inspection {
+    pub format: ImageFormat,
+    pub table: Option<TableKind>,
+    pub partitions: Vec<InspectedPartition>,
+    /// Platforms in the El Torito boot catalog, e.g. "BIOS" and "UEFI"
+    pub el_torito: Vec<&'static str>,
+    pub label: Option<String>,
+    /// MBR boot code that a PC BIOS runs from a stick
+    pub bios: bool,
+    /// An EFI system partition that UEFI firmware boots from a stick
+    pub uefi: bool,
+    pub warnings: Vec<String>,
+}
+
+/// Inspect an image through ranged reads, without downloading it
+pub async fn inspect_source(source: Arc<dyn ImageSource>, size: u64) -> Result<Inspection> {
+    let runtime = Handle::current();
+    tokio::task::spawn_blocking(move || {
+        let mut reader = SourceReader::new(source, size, runtime);
+        inspect(&mut reader, size)
+    })
+    .await?
+}
+
+/// Work out an image's format, partitions and boot entries from its
+/// first few megabytes, plus the first sectors of each partition
+pub fn inspect<R: Read + Seek>(reader: &mut R, size: u64) -> Result<Inspection> {
+    let head = read_at(reader, 0, 512)?;
+    let mut inspection = Inspection {
+        format: ImageFormat::Unknown,
+        table: None,
+        partitions: Vec::new(),
+        el_torito: Vec::new(),
+        label: None,
+        bios: false,
+        uefi: false,
+        warnings: Vec::new(),
+    };
+    if let Some(format) = container_format(&head) {
+        inspection.format = format;
+        inspection.warnings.push(match format {
+            _ if format.compression() != Compression::None => {
+                "Decompressed while writing, so what's inside can't be checked first".to_string()
+            }
+            ImageFormat::Compressed(_) => {
+                "Written as is, this won't boot: decompress it first".to_string()
+            }
+            _ => "Written as is, this won't boot: convert it to a raw image first".to_string(),
+        });
+        return Ok(inspection);
+    }
+
+    let iso_label = iso_label(reader, 0)?;
+    let is_iso = iso_label.is_some();
+    if is_iso {
+        inspection.el_torito = el_torito(reader)?;
+    }
+    let table = partition::read_partition_table(reader, size)?;
+    if let Some(table) = &table {
+        inspection.table = Some(table.kind);
+        for partition in &table.partitions {
+            let iso9660 = partition.start == 0 && is_iso;
+            let (kind, label) = if iso9660 {
+                (None, iso_label.clone())
+            } else {
+                (
+                    filesystem::detect(reader, partition.start)?,
+                    filesystem::label(reader, partition.start)?,
+                )
+            };
+            inspection.partitions.push(InspectedPartition {
+                partition: partition.clone(),
+                kind,
+                iso9660,
+                label: label.filter(|l| !l.is_empty()),
+            });
+        }
+        inspection.bios = head[..440].iter().any(|b| *b != 0);
+        inspection.uefi = inspection.partitions.iter().any(|p| p.is_efi_system());
+    }
+
+    inspection.format = match (&table, is_iso) {
+        (Some(_), true) => ImageFormat::HybridIso,
+        (None, true) => {
+            let windows = isofs::read_tree(reader)
+                .is_ok_and(|(_, entries)| windows::is_windows_installer(&entries));
+            if windows {
+                ImageFormat::WindowsIso
+            } else {
+                ImageFormat::Iso
+            }
+        }
+        (Some(_), false) => ImageFormat::DiskImage,
+        (None, false) => match filesystem::detect(reader, 0)? {
+            Some(kind) => ImageFormat::Filesystem(kind),
+            None => ImageFormat::Unknown,
+        },
+    };
+    inspection.label =
+        iso_label.or_else(|| inspection.partitions.iter().find_map(|p| p.label.clone()));
+
+    let warning = match inspection.format {
+        ImageFormat::Iso => {
+            Some("Not a hybrid ISO: El Torito only boots from a disc, so the stick won't boot")
+        }
+        ImageFormat::Filesystem(_) => {
+            Some("No partition table or boot code, so the stick won't boot")
+        }
+        ImageFormat::Unknown => {
+            Some("No partition table or filesystem found; this may not be a disk image")
+        }
+        // Boards like the Raspberry Pi boot from their own firmware
+        ImageFormat::HybridIso | ImageFormat::DiskImage if !inspection.bios && !inspection.uefi => {
+            Some("No BIOS boot code or EFI system partition, so PCs won't boot it")
+        }
+        _ => None,
+    };
+    inspection.warnings.extend(warning.map(str::to_string));
+    Ok(inspection)
+}
+
+/// Recognise a compressed file or a virtual machine disk by its magic
+fn container_format(head: &[u8]) -> Option<ImageFormat> {
+    let formats: [(&[u8], ImageFormat); 9] = [
+        (&[0x1f, 0x8b], ImageFormat::Compressed("gzip")),
+        (b"\xfd7zXZ\x00", ImageFormat::Compressed("xz")),
+        (&[0x28, 0xb5, 0x2f, 0xfd], ImageFormat::Compressed("zstd")),
+        (b"BZh", ImageFormat::Compressed("bzip2")),
+        (b"PK\x03\x04", ImageFormat::Compressed("zip")),
+        (b"7z\xbc\xaf\x27\x1c", ImageFormat::Compressed("7-Zip")),
+        (b"QFI\xfb", ImageFormat::VirtualDisk("qcow2")),
+        (b"vhdxfile", ImageFormat::VirtualDisk("VHDX")),
+        (b"KDMV", ImageFormat::VirtualDisk("VMDK")),
+    ];
+    formats
+        .iter()
+        .find(|(magic, _)| head.starts_with(magic))
+        .map(|(_, format)| *format)
+}
+
+/// The volume identifier of an ISO 9660 filesystem at `offset`
+fn iso_label<R: Read + Seek>(reader: &mut R, offset: u64) -> Result<Option<String>> {
+    let desc = read_at(
+        reader,
+        offset + FIRST_DESCRIPTOR * ISO_SECTOR,
+        ISO_SECTOR as usize,
+    )?;
+    if &desc[1..6] != b"CD001" {
+        return Ok(None);
+    }
+    let label = String::from_utf8_lossy(&desc[40..72]).trim().to_string();
+    Ok(Some(label))
+}
+
+/// Platforms with a bootable entry in the El Torito boot catalog
+fn el_torito<R: Read + Seek>(reader: &mut R) -> Result<Vec<&'static str>> {
+    let mut catalog_lba = None;
+    for sector in FIRST_DESCRIPTOR..FIRST_DESCRIPTOR + MAX_DESCRIPTORS {
+        let desc = read_at(reader, sector * ISO_SECTOR, ISO_SECTOR as usize)?;
+        if &desc[1..6] != b"CD001" || desc[0] == 255 {
+            break;
+        }
+        if desc[0] == 0 && desc[7..30] == *b"EL TORITO SPECIFICATION" {
+            catalog_lba = Some(u64::from(le32(&desc[71..])));
+            break;
+        }
+    }
+    let Some(lba) = catalog_lba else {
+        return Ok(Vec::new());
+    };
+
+    let catalog = read_at(reader, lba * ISO_SECTOR, ISO_SECTOR as usize)?;
+    // Validation entry, then the default entry for the validation entry's platform
+    if catalog[0] != 1 || catalog[30..32] != [0x55, 0xaa] {
+        return Ok(Vec::new());
+    }
+    let mut platforms = Vec::new();
+    let mut add = |platform: u8| {
+        let name = platform_name(platform);
+        if !platforms.contains(&name) {
+            platforms.push(name);
+        }
+    };
+    if catalog[32] == 0x88 {
+        add(catalog[1]);
+    }
+    // Section headers (0x90, or 0x91 for the last) each list more entries
+    let mut pos = 64;
+    while pos + 32 <= catalog.len() && matches!(catalog[pos], 0x90 | 0x91) {
+        let last = catalog[pos] == 0x91;
+        let platform = catalog[pos + 1];
+        let count = usize::from(u16::from_le_bytes([catalog[pos + 2], catalog[pos + 3]]));
+        pos += 32;
+        for _ in 0..count {
+            if pos + 32 > catalog.len() {
+                break;
+            }
+            if catalog[pos] == 0x88 {
+                add(platform);
+            }
+            pos += 32;
+        }
+        if last {
+            break;
+        }
+    }
+    Ok(platforms)
+}
+
+fn platform_name(platform: u8) -> &'static str {
+    match platform {
+        0 => "BIOS",
+        1 => "PowerPC",
+        2 => "Mac",
+        0xef => "UEFI",
+        _ => "other",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_inspect_hybrid_iso() {
+        let mut image = vec![0u8; 64 * 2048];
+        // isohybrid: boot code, the ISO as partition 1 and an ESP after it
+        image[0] = 0xeb;
+        for (slot, kind, start, len) in [(0, 0x17u8, 0u32, 128u32), (1, 0xef, 128, 64)] {
+            let e = 446 + slot * 16;
+            image[e + 4] = kind;
+            image[e + 8..e + 12].copy_from_slice(&start.to_le_bytes());
+            image[e + 12..e + 16].copy_from_slice(&len.to_le_bytes());
+        }
+        image[510..512].copy_from_slice(&[0x55, 0xaa]);
+
+        let desc = |sector: usize| sector * 2048;
+        image[desc(16)] = 1;
+        image[desc(16) + 1..desc(16) + 6].copy_from_slice(b"CD001");
+        image[desc(16) + 40..desc(16) + 72].copy_from_slice(&[b' '; 32]);
+        image[desc(16) + 40..desc(16) + 48].copy_from_slice(b"TESTLIVE");
+        image[desc(17) + 1..desc(17) + 6].copy_from_slice(b"CD001");
+        image[desc(17) + 7..desc(17) + 30].copy_from_slice(b"EL TORITO SPECIFICATION");
+        image[desc(17) + 71..desc(17) + 75].copy_from_slice(&20u32.to_le_bytes());
+        image[desc(18)] = 255;
+        image[desc(18) + 1..desc(18) + 6].copy_from_slice(b"CD001");
+
+        let catalog = desc(20);
+        image[catalog] = 1;
+        image[catalog + 30..catalog + 32].copy_from_slice(&[0x55, 0xaa]);
+        image[catalog + 32] = 0x88;
+        image[catalog + 64] = 0x91;
+        image[catalog + 65] = 0xef;
+        image[catalog + 66] = 1;
+        image[catalog + 96] = 0x88;
+
+        let size = image.len() as u64;
+        let inspection = inspect(&mut Cursor::new(&image), size).unwrap();
+        assert_eq!(inspection.format, ImageFormat::HybridIso);
+        assert_eq!(inspection.table, Some(TableKind::Mbr));
+        assert_eq!(inspection.el_torito, vec!["BIOS", "UEFI"]);
+        assert_eq!(inspection.label.as_deref(), Some("TESTLIVE"));
+        assert!(inspection.bios && inspection.uefi);
+        assert!(inspection.partitions[0].iso9660);
+        assert!(inspection.warnings.is_empty());
+
+        // Without the partition table it only boots from a disc
+        image[..512].fill(0);
+        let inspection = inspect(&mut Cursor::new(&image), size).unwrap();
+        assert_eq!(inspection.format, ImageFormat::Iso);
+        assert_eq!(inspection.warnings.len(), 1);
+
+        let mut xz = b"\xfd7zXZ\x00".to_vec();
+        xz.resize(4096, 0);
+        let inspection = inspect(&mut Cursor::new(&xz), 4096).unwrap();
+        assert_eq!(inspection.format, ImageFormat::Compressed("xz"));
+        assert_eq!(inspection.warnings.len(), 1);
+    }
+}
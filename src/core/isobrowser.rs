@@ -0,0 +1,253 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{Context, Result, bail};
+use futures_util::StreamExt;
+use tokio::io::AsyncWriteExt;
+use tokio::runtime::Handle;
+
+use super::isofs::{self, IsoEntry, IsoKind};
+use super::source::{ImageSource, SourceReader};
+
+/// One line in the ISO contents tree
+#[derive(Debug, Clone, PartialEq)]
+pub struct IsoNode {
+    pub entry: IsoEntry,
+    pub depth: usize,
+    pub expanded: bool,
+}
+
+/// The files inside an image, flattened in display order like the mirror
+/// browser. Everything is read up front; directories just fold.
+#[derive(Debug, Clone, Default)]
+pub struct IsoTree {
+    /// URL or local path of the image
+    pub image: String,
+    pub kind: Option<IsoKind>,
+    pub entries: Vec<IsoEntry>,
+    pub nodes: Vec<IsoNode>,
+    pub selected: usize,
+    pub loading: bool,
+    pub error: Option<String>,
+    /// Destination being typed for the selected file
+    pub extract_input: Option<String>,
+    /// File currently being extracted
+    pub extracting: Option<String>,
+    pub notice: Option<String>,
+}
+
+impl IsoTree {
+    pub fn new(image: String) -> Self {
+        Self {
+            image,
+            loading: true,
+            ..Default::default()
+        }
+    }
+
+    pub fn load(&mut self, kind: IsoKind, entries: Vec<IsoEntry>) {
+        self.loading = false;
+        self.kind = Some(kind);
+        self.entries = entries;
+        self.nodes = self
+            .children("")
+            .into_iter()
+            .map(|entry| IsoNode {
+                entry,
+                depth: 0,
+                expanded: false,
+            })
+            .collect();
+        self.selected = 0;
+    }
+
+    /// Entries directly inside the directory at `path`, directories first
+    fn children(&self, path: &str) -> Vec<IsoEntry> {
+        let mut children: Vec<IsoEntry> = self
+            .entries
+            .iter()
+            .filter(|e| match e.path.rsplit_once('/') {
+                Some((parent, _)) => parent == path,
+                None => path.is_empty(),
+            })
+            .cloned()
+            .collect();
+        children.sort_by(|a, b| {
+            b.is_dir
+                .cmp(&a.is_dir)
+                .then_with(|| a.name().to_lowercase().cmp(&b.name().to_lowercase()))
+        });
+        children
+    }
+
+    /// Unfold the directory node at `index`
+    pub fn expand(&mut self, index: usize) {
+        let Some(node) = self.nodes.get(index) else {
+            return;
+        };
+        if !node.entry.is_dir || node.expanded {
+            return;
+        }
+        let depth = node.depth + 1;
+        let children: Vec<IsoNode> = self
+            .children(&node.entry.path)
+            .into_iter()
+            .map(|entry| IsoNode {
+                entry,
+                depth,
+                expanded: false,
+            })
+            .collect();
+        self.nodes[index].expanded = true;
+        self.nodes.splice(index + 1..index + 1, children);
+    }
+
+    /// Remove the children of an expanded directory node
+    pub fn collapse(&mut self, index: usize) {
+        let Some(node) = self.nodes.get_mut(index) else {
+            return;
+        };
+        node.expanded = false;
+        let depth = node.depth;
+        let end = self.nodes[index + 1..]
+            .iter()
+            .position(|n| n.depth <= depth)
+            .map_or(self.nodes.len(), |i| i + index + 1);
+        self.nodes.drain(index + 1..end);
+    }
+
+    /// Where the node at `index` sits under its parent directory, if any
+    pub fn parent(&self, index: usize) -> Option<usize> {
+        let depth = self.nodes.get(index)?.depth;
+        self.nodes[..index].iter().rposition(|n| n.depth < depth)
+    }
+
+    pub fn selected_node(&self) -> Option<&IsoNode> {
+        self.nodes.get(self.selected)
+    }
+
+    pub fn select_next(&mut self) {
+        if !self.nodes.is_empty() {
+            self.selected = (self.selected + 1) % self.nodes.len();
+        }
+    }
+
+    pub fn select_previous(&mut self) {
+        if !self.nodes.is_empty() {
+            if self.selected == 0 {
+                self.selected = self.nodes.len() - 1;
+            } else {
+                self.selected -= 1;
+            }
+        }
+    }
+}
+
+/// Results of background work for the ISO browser
+#[derive(Debug, Clone, PartialEq)]
+pub enum IsoUpdate {
+    Loaded(Result<(IsoKind, Vec<IsoEntry>), String>),
+    /// Where the file went, or why it didn't
+    Extracted(Result<String, String>),
+}
+
+/// Read the directory tree through ranged reads, without downloading the image
+pub async fn read_contents(source: Arc<dyn ImageSource>) -> Result<(IsoKind, Vec<IsoEntry>)> {
+    let size = source.probe().await?.total_bytes;
+    let runtime = Handle::current();
+    tokio::task::spawn_blocking(move || {
+        let mut reader = SourceReader::new(source, size, runtime);
+        isofs::read_tree(&mut reader)
+    })
+    .await?
+}
+
+/// Suggested place to extract a file to: the working directory
+pub fn default_destination(entry: &IsoEntry) -> String {
+    let dir = std::env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
+    dir.join(entry.name()).to_string_lossy().into_owned()
+}
+
+/// Copy one file out of the image, streaming each of its extents
+pub async fn extract(source: Arc<dyn ImageSource>, entry: &IsoEntry, dest: &Path) -> Result<u64> {
+    if entry.is_dir {
+        bail!("{} is a directory", entry.path);
+    }
+    // Pervie runs as root, so never write over an existing file
+    let mut file = tokio::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(dest)
+        .await
+        .with_context(|| format!("Cannot create {}", dest.display()))?;
+    let mut written = 0;
+    for extent in entry.ranges(0, entry.size) {
+        let mut stream = source.open(extent.offset).await?;
+        let mut remaining = extent.len;
+        while remaining > 0 {
+            let Some(chunk) = stream.next().await else {
+                bail!("The image ended inside {}", entry.path);
+            };
+            let chunk = chunk?;
+            let take = chunk.len().min(remaining as usize);
+            file.write_all(&chunk[..take]).await?;
+            remaining -= take as u64;
+        }
+        written += extent.len;
+    }
+    file.sync_all().await?;
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(path: &str, is_dir: bool) -> IsoEntry {
+        IsoEntry {
+            path: path.to_string(),
+            is_dir,
+            size: 0,
+            extents: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_iso_tree() {
+        let mut tree = IsoTree::new("/tmp/live.iso".to_string());
+        tree.load(
+            IsoKind::RockRidge,
+            vec![
+                entry("README.diskdefines", false),
+                entry("casper", true),
+                entry("casper/vmlinuz", false),
+                entry("boot", true),
+                entry("boot/grub", true),
+                entry("boot/grub/grub.cfg", false),
+            ],
+        );
+        let paths = |tree: &IsoTree| -> Vec<String> {
+            tree.nodes.iter().map(|n| n.entry.path.clone()).collect()
+        };
+        assert_eq!(paths(&tree), ["boot", "casper", "README.diskdefines"]);
+
+        tree.expand(0);
+        tree.expand(1);
+        assert_eq!(
+            paths(&tree),
+            [
+                "boot",
+                "boot/grub",
+                "boot/grub/grub.cfg",
+                "casper",
+                "README.diskdefines"
+            ]
+        );
+        assert_eq!(tree.parent(2), Some(1));
+        assert_eq!(tree.parent(3), None);
+
+        tree.collapse(0);
+        assert_eq!(paths(&tree), ["boot", "casper", "README.diskdefines"]);
+        assert!(!tree.nodes[0].expanded);
+    }
+}
@@ -0,0 +1,692 @@
+use std::io::{Read, Seek};
+
+use anyhow::{Context, Result, bail};
+
+use super::partition::{le32, le64, read_at};
+
+const ISO_SECTOR: u64 = 2048;
+/// Directories nested deeper than this are assumed to be a loop
+const MAX_DEPTH: usize = 32;
+/// More entries than this means a corrupt directory
+const MAX_ENTRIES: usize = 1 << 20;
+
+const UDF_TAG_ANCHOR: u16 = 2;
+const UDF_TAG_PARTITION: u16 = 5;
+const UDF_TAG_LOGICAL_VOLUME: u16 = 6;
+const UDF_TAG_TERMINATOR: u16 = 8;
+const UDF_TAG_FILE_SET: u16 = 256;
+const UDF_TAG_FILE_ID: u16 = 257;
+const UDF_TAG_FILE_ENTRY: u16 = 261;
+const UDF_TAG_EXTENDED_FILE_ENTRY: u16 = 266;
+
+/// Filesystem an ISO's contents were read from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IsoKind {
+    Udf,
+    /// ISO 9660 with POSIX names from Rock Ridge extensions
+    RockRidge,
+    Joliet,
+    Iso9660,
+}
+
+/// A contiguous run of a file's data inside the image
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Extent {
+    pub offset: u64,
+    pub len: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct IsoEntry {
+    /// Slash-separated, without a leading slash
+    pub path: String,
+    pub is_dir: bool,
+    pub size: u64,
+    /// Where the data sits in the image, in file order
+    pub extents: Vec<Extent>,
+}
+
+impl IsoEntry {
+    pub fn name(&self) -> &str {
+        self.path.rsplit('/').next().unwrap_or(&self.path)
+    }
+
+    /// Image ranges holding `len` bytes of the file starting at `offset`
+    pub fn ranges(&self, mut offset: u64, mut len: u64) -> Vec<Extent> {
+        let mut ranges = Vec::new();
+        for extent in &self.extents {
+            if len == 0 {
+                break;
+            }
+            if offset >= extent.len {
+                offset -= extent.len;
+                continue;
+            }
+            let take = (extent.len - offset).min(len);
+            ranges.push(Extent {
+                offset: extent.offset + offset,
+                len: take,
+            });
+            offset = 0;
+            len -= take;
+        }
+        ranges
+    }
+
+    /// Read part of the file out of the image
+    pub fn read<R: Read + Seek>(&self, reader: &mut R, offset: u64, len: usize) -> Result<Vec<u8>> {
+        let mut data = Vec::with_capacity(len);
+        for range in self.ranges(offset, len as u64) {
+            data.extend(read_at(reader, range.offset, range.len as usize)?);
+        }
+        if data.len() < len {
+            bail!("{} ends before byte {}", self.path, offset + len as u64);
+        }
+        Ok(data)
+    }
+}
+
+/// Every file and directory in an ISO, preferring UDF (which Windows
+/// images rely on) over Rock Ridge, Joliet and plain ISO 9660 names
+pub fn read_tree<R: Read + Seek>(reader: &mut R) -> Result<(IsoKind, Vec<IsoEntry>)> {
+    if let Some(entries) = udf::read_tree(reader)? {
+        return Ok((IsoKind::Udf, entries));
+    }
+    iso9660::read_tree(reader)?.context("No ISO 9660 or UDF filesystem found")
+}
+
+/// Find an entry by path, ignoring case as Windows and UEFI firmware do
+pub fn find<'a>(entries: &'a [IsoEntry], path: &str) -> Option<&'a IsoEntry> {
+    entries.iter().find(|e| e.path.eq_ignore_ascii_case(path))
+}
+
+fn le16(bytes: &[u8]) -> u16 {
+    u16::from_le_bytes([bytes[0], bytes[1]])
+}
+
+fn utf16_be(bytes: &[u8]) -> String {
+    let units: Vec<u16> = bytes
+        .chunks_exact(2)
+        .map(|c| u16::from_be_bytes([c[0], c[1]]))
+        .collect();
+    String::from_utf16_lossy(&units)
+}
+
+fn join(parent: &str, name: &str) -> String {
+    if parent.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}/{}", parent, name)
+    }
+}
+
+mod iso9660 {
+    use super::*;
+
+    /// Descriptor set starts at sector 16 and ends with type 255
+    const FIRST_DESCRIPTOR: u64 = 16;
+    const MAX_DESCRIPTORS: u64 = 64;
+
+    pub fn read_tree<R: Read + Seek>(reader: &mut R) -> Result<Option<(IsoKind, Vec<IsoEntry>)>> {
+        let mut primary = None;
+        let mut joliet = None;
+        for sector in FIRST_DESCRIPTOR..FIRST_DESCRIPTOR + MAX_DESCRIPTORS {
+            let desc = read_at(reader, sector * ISO_SECTOR, ISO_SECTOR as usize)?;
+            if &desc[1..6] != b"CD001" {
+                break;
+            }
+            match desc[0] {
+                1 if primary.is_none() => primary = Some(desc),
+                // Joliet is a supplementary descriptor with a UCS-2 escape sequence
+                2 if matches!(&desc[88..91], b"%/@" | b"%/C" | b"%/E") => joliet = Some(desc),
+                255 => break,
+                _ => {}
+            }
+        }
+        let rock_ridge = match &primary {
+            Some(desc) => has_rock_ridge(reader, desc)?,
+            None => false,
+        };
+        let (kind, desc) = match (joliet, primary) {
+            (_, Some(desc)) if rock_ridge => (IsoKind::RockRidge, desc),
+            (Some(desc), _) => (IsoKind::Joliet, desc),
+            (None, Some(desc)) => (IsoKind::Iso9660, desc),
+            (None, None) => return Ok(None),
+        };
+
+        let root = &desc[156..190];
+        let mut entries = Vec::new();
+        read_dir(
+            reader,
+            u64::from(le32(&root[2..])),
+            u64::from(le32(&root[10..])),
+            kind,
+            "",
+            0,
+            &mut entries,
+        )?;
+        Ok(Some((kind, entries)))
+    }
+
+    fn read_dir<R: Read + Seek>(
+        reader: &mut R,
+        lba: u64,
+        size: u64,
+        kind: IsoKind,
+        parent: &str,
+        depth: usize,
+        entries: &mut Vec<IsoEntry>,
+    ) -> Result<()> {
+        if depth > MAX_DEPTH {
+            bail!("Directories nested too deeply under {}", parent);
+        }
+        let data = read_at(reader, lba * ISO_SECTOR, size as usize)?;
+        let mut pos = 0;
+        let mut subdirs = Vec::new();
+        while pos < data.len() {
+            let len = data[pos] as usize;
+            if len == 0 {
+                // Records never straddle sectors; the rest of this one is padding
+                pos = (pos / ISO_SECTOR as usize + 1) * ISO_SECTOR as usize;
+                continue;
+            }
+            if len < 34 || pos + len > data.len() {
+                bail!("Corrupt directory record in {}", parent);
+            }
+            let record = &data[pos..pos + len];
+            pos += len;
+
+            let name_len = record[32] as usize;
+            let raw_name = &record[33..(33 + name_len).min(len)];
+            // "\0" and "\1" are the directory itself and its parent
+            if raw_name == [0] || raw_name == [1] {
+                continue;
+            }
+            let alternate = match kind {
+                IsoKind::RockRidge => rock_ridge_name(record),
+                _ => None,
+            };
+            let name = match kind {
+                IsoKind::Joliet => utf16_be(raw_name),
+                _ => String::from_utf8_lossy(raw_name).into_owned(),
+            };
+            let name = match &alternate {
+                Some(name) => name.as_str(),
+                None => {
+                    let name = name.split(';').next().unwrap_or_default();
+                    name.strip_suffix('.').unwrap_or(name)
+                }
+            };
+            let flags = record[25];
+            let extent = Extent {
+                offset: u64::from(le32(&record[2..])) * ISO_SECTOR,
+                len: u64::from(le32(&record[10..])),
+            };
+            let path = join(parent, name);
+
+            if flags & 0x02 != 0 {
+                subdirs.push((path.clone(), extent));
+                entries.push(IsoEntry {
+                    path,
+                    is_dir: true,
+                    size: 0,
+                    extents: Vec::new(),
+                });
+                continue;
+            }
+            // Files over 4 GiB are split over records with the same name
+            if let Some(last) = entries.last_mut()
+                && last.path == path
+                && !last.is_dir
+            {
+                last.size += extent.len;
+                last.extents.push(extent);
+            } else {
+                entries.push(IsoEntry {
+                    path,
+                    is_dir: false,
+                    size: extent.len,
+                    extents: vec![extent],
+                });
+            }
+            if entries.len() > MAX_ENTRIES {
+                bail!("Too many files in the image");
+            }
+        }
+
+        for (path, extent) in subdirs {
+            read_dir(
+                reader,
+                extent.offset / ISO_SECTOR,
+                extent.len,
+                kind,
+                &path,
+                depth + 1,
+                entries,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// System use entries of a directory record, as (signature, data)
+    fn system_use(record: &[u8]) -> Vec<(&[u8], &[u8])> {
+        let name_len = record[32] as usize;
+        // The name is padded to an even length
+        let mut pos = 33 + name_len + (name_len + 1) % 2;
+        let mut entries = Vec::new();
+        while pos + 4 <= record.len() {
+            let len = record[pos + 2] as usize;
+            if len < 4 || pos + len > record.len() {
+                break;
+            }
+            entries.push((&record[pos..pos + 2], &record[pos + 4..pos + len]));
+            pos += len;
+        }
+        entries
+    }
+
+    /// SUSP puts an `SP` entry in the root directory's first record
+    fn has_rock_ridge<R: Read + Seek>(reader: &mut R, desc: &[u8]) -> Result<bool> {
+        let root = read_at(reader, u64::from(le32(&desc[158..])) * ISO_SECTOR, 256)?;
+        let len = root[0] as usize;
+        if len < 34 {
+            return Ok(false);
+        }
+        Ok(system_use(&root[..len])
+            .iter()
+            .any(|(sig, data)| *sig == b"SP" && data.starts_with(&[0xbe, 0xef])))
+    }
+
+    /// The name from Rock Ridge `NM` entries, which may be split over several
+    fn rock_ridge_name(record: &[u8]) -> Option<String> {
+        let mut name = Vec::new();
+        let mut found = false;
+        for (sig, data) in system_use(record) {
+            // Flags: 1 continues in the next entry, 2 and 4 are "." and ".."
+            if sig != b"NM" || data.is_empty() || data[0] & 0x06 != 0 {
+                continue;
+            }
+            name.extend_from_slice(&data[1..]);
+            found = true;
+            if data[0] & 0x01 == 0 {
+                break;
+            }
+        }
+        (found && !name.is_empty()).then(|| String::from_utf8_lossy(&name).into_owned())
+    }
+}
+
+mod udf {
+    use super::*;
+
+    /// Anchor volume descriptor pointer, always at sector 256
+    const ANCHOR_SECTOR: u64 = 256;
+
+    struct Volume {
+        block_size: u64,
+        /// Byte offset of logical block 0 of the partition
+        partition_start: u64,
+    }
+
+    impl Volume {
+        fn block(&self, lbn: u32) -> u64 {
+            self.partition_start + u64::from(lbn) * self.block_size
+        }
+    }
+
+    fn tag_id(desc: &[u8]) -> u16 {
+        le16(desc)
+    }
+
+    pub fn read_tree<R: Read + Seek>(reader: &mut R) -> Result<Option<Vec<IsoEntry>>> {
+        let anchor = read_at(reader, ANCHOR_SECTOR * ISO_SECTOR, ISO_SECTOR as usize)?;
+        if tag_id(&anchor) != UDF_TAG_ANCHOR || le32(&anchor[12..]) != ANCHOR_SECTOR as u32 {
+            return Ok(None);
+        }
+        let vds_len = u64::from(le32(&anchor[16..]));
+        let vds_start = u64::from(le32(&anchor[20..]));
+
+        let mut partition_start = None;
+        let mut logical_volume = None;
+        for i in 0..(vds_len / ISO_SECTOR).min(64) {
+            let desc = read_at(reader, (vds_start + i) * ISO_SECTOR, ISO_SECTOR as usize)?;
+            match tag_id(&desc) {
+                UDF_TAG_PARTITION if partition_start.is_none() => {
+                    partition_start = Some(u64::from(le32(&desc[188..])));
+                }
+                UDF_TAG_LOGICAL_VOLUME if logical_volume.is_none() => {
+                    logical_volume = Some((u64::from(le32(&desc[212..])), le32(&desc[252..])));
+                }
+                UDF_TAG_TERMINATOR => break,
+                _ => {}
+            }
+        }
+        let (Some(partition_start), Some((block_size, fsd_lbn))) =
+            (partition_start, logical_volume)
+        else {
+            bail!("UDF volume is missing its partition or logical volume descriptor");
+        };
+        if !matches!(block_size, 512 | 1024 | 2048 | 4096) {
+            bail!("Unsupported UDF block size {}", block_size);
+        }
+        let volume = Volume {
+            block_size,
+            partition_start: partition_start * block_size,
+        };
+
+        let fsd = read_at(reader, volume.block(fsd_lbn), block_size as usize)?;
+        if tag_id(&fsd) != UDF_TAG_FILE_SET {
+            bail!("UDF file set descriptor not found");
+        }
+        let root_icb = le32(&fsd[404..]);
+
+        let mut entries = Vec::new();
+        let (_, root_extents, _) = read_file_entry(reader, &volume, root_icb)?;
+        read_dir(reader, &volume, &root_extents, "", 0, &mut entries)?;
+        Ok(Some(entries))
+    }
+
+    /// Returns whether the entry is a directory, where its data is, and its size
+    fn read_file_entry<R: Read + Seek>(
+        reader: &mut R,
+        volume: &Volume,
+        lbn: u32,
+    ) -> Result<(bool, Vec<Extent>, u64)> {
+        let block = volume.block(lbn);
+        let entry = read_at(reader, block, volume.block_size as usize)?;
+        let (ea_len_at, descriptors_at) = match tag_id(&entry) {
+            UDF_TAG_FILE_ENTRY => (168, 176),
+            UDF_TAG_EXTENDED_FILE_ENTRY => (208, 216),
+            id => bail!(
+                "Expected a UDF file entry at block {}, found tag {}",
+                lbn,
+                id
+            ),
+        };
+        let is_dir = entry[27] == 4;
+        let allocation = le16(&entry[34..]) & 0x7;
+        let size = le64(&entry[56..]);
+        let ea_len = le32(&entry[ea_len_at..]) as usize;
+        let ad_len = le32(&entry[ea_len_at + 4..]) as usize;
+        let start = descriptors_at + ea_len;
+        let descriptors = entry
+            .get(start..start + ad_len)
+            .context("UDF allocation descriptors overrun their block")?;
+
+        let mut extents = Vec::new();
+        match allocation {
+            // Short and long allocation descriptors
+            0 | 1 => {
+                let step = if allocation == 0 { 8 } else { 16 };
+                for ad in descriptors.chunks_exact(step) {
+                    let len = le32(ad);
+                    let (kind, len) = (len >> 30, u64::from(len & 0x3fff_ffff));
+                    if len == 0 {
+                        break;
+                    }
+                    if kind == 3 {
+                        bail!("Chained UDF allocation descriptors are not supported");
+                    }
+                    extents.push(Extent {
+                        offset: volume.block(le32(&ad[4..])),
+                        len,
+                    });
+                }
+            }
+            // Data embedded in the entry itself
+            3 => extents.push(Extent {
+                offset: block + start as u64,
+                len: ad_len as u64,
+            }),
+            _ => bail!("Unsupported UDF allocation type {}", allocation),
+        }
+
+        // The last extent is rounded up to whole blocks
+        let mut remaining = size;
+        for extent in &mut extents {
+            extent.len = extent.len.min(remaining);
+            remaining -= extent.len;
+        }
+        extents.retain(|e| e.len > 0);
+        Ok((is_dir, extents, size))
+    }
+
+    fn read_dir<R: Read + Seek>(
+        reader: &mut R,
+        volume: &Volume,
+        extents: &[Extent],
+        parent: &str,
+        depth: usize,
+        entries: &mut Vec<IsoEntry>,
+    ) -> Result<()> {
+        if depth > MAX_DEPTH {
+            bail!("Directories nested too deeply under {}", parent);
+        }
+        let mut data = Vec::new();
+        for extent in extents {
+            data.extend(read_at(reader, extent.offset, extent.len as usize)?);
+        }
+
+        let mut pos = 0;
+        while pos + 38 <= data.len() {
+            let fid = &data[pos..];
+            if tag_id(fid) != UDF_TAG_FILE_ID {
+                bail!("Corrupt UDF directory {}", parent);
+            }
+            let characteristics = fid[18];
+            let name_len = fid[19] as usize;
+            let icb = le32(&fid[24..]);
+            let impl_len = le16(&fid[36..]) as usize;
+            let record_len = (38 + impl_len + name_len).div_ceil(4) * 4;
+            let name = fid
+                .get(38 + impl_len..38 + impl_len + name_len)
+                .context("UDF file identifier overruns its directory")?;
+            pos += record_len;
+
+            // Deleted and parent entries
+            if characteristics & 0x0c != 0 || name.is_empty() {
+                continue;
+            }
+            let name = decode_name(name);
+            let path = join(parent, &name);
+            let (is_dir, extents, size) = read_file_entry(reader, volume, icb)?;
+            if is_dir {
+                entries.push(IsoEntry {
+                    path: path.clone(),
+                    is_dir: true,
+                    size: 0,
+                    extents: Vec::new(),
+                });
+                read_dir(reader, volume, &extents, &path, depth + 1, entries)?;
+            } else {
+                entries.push(IsoEntry {
+                    path,
+                    is_dir: false,
+                    size,
+                    extents,
+                });
+            }
+            if entries.len() > MAX_ENTRIES {
+                bail!("Too many files in the image");
+            }
+        }
+        Ok(())
+    }
+
+    /// OSTA compressed Unicode: a width byte, then 8-bit or UTF-16BE characters
+    fn decode_name(raw: &[u8]) -> String {
+        match raw[0] {
+            16 => utf16_be(&raw[1..]),
+            _ => raw[1..].iter().map(|b| *b as char).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn tag(block: &mut [u8], id: u16) {
+        block[..2].copy_from_slice(&id.to_le_bytes());
+    }
+
+    fn file_entry(is_dir: bool, size: u64, extents: &[(u32, u32)]) -> Vec<u8> {
+        let mut entry = vec![0u8; 2048];
+        tag(&mut entry, UDF_TAG_FILE_ENTRY);
+        entry[27] = if is_dir { 4 } else { 5 };
+        entry[56..64].copy_from_slice(&size.to_le_bytes());
+        entry[172..176].copy_from_slice(&(extents.len() as u32 * 8).to_le_bytes());
+        for (i, (len, lbn)) in extents.iter().enumerate() {
+            entry[176 + i * 8..180 + i * 8].copy_from_slice(&len.to_le_bytes());
+            entry[180 + i * 8..184 + i * 8].copy_from_slice(&lbn.to_le_bytes());
+        }
+        entry
+    }
+
+    fn file_id(name: &str, is_dir: bool, icb: u32) -> Vec<u8> {
+        let mut fid = vec![0u8; 38];
+        tag(&mut fid, UDF_TAG_FILE_ID);
+        fid[18] = if is_dir { 2 } else { 0 };
+        fid[24..28].copy_from_slice(&icb.to_le_bytes());
+        if !name.is_empty() {
+            fid.push(8);
+            fid.extend(name.as_bytes());
+            fid[19] = name.len() as u8 + 1;
+        } else {
+            fid[18] |= 0x08;
+        }
+        fid.resize(fid.len().div_ceil(4) * 4, 0);
+        fid
+    }
+
+    /// A UDF image with `sources/install.wim` spread over two extents
+    fn sample_udf() -> Vec<u8> {
+        let partition = 260u32;
+        let mut image = vec![0u8; (partition as usize + 16) * 2048];
+        let mut put = |sector: u32, data: &[u8]| {
+            let at = sector as usize * 2048;
+            image[at..at + data.len()].copy_from_slice(data);
+        };
+
+        let mut anchor = vec![0u8; 2048];
+        tag(&mut anchor, UDF_TAG_ANCHOR);
+        anchor[12..16].copy_from_slice(&256u32.to_le_bytes());
+        anchor[16..20].copy_from_slice(&(3 * 2048u32).to_le_bytes());
+        anchor[20..24].copy_from_slice(&257u32.to_le_bytes());
+        put(256, &anchor);
+        let mut pd = vec![0u8; 2048];
+        tag(&mut pd, UDF_TAG_PARTITION);
+        pd[188..192].copy_from_slice(&partition.to_le_bytes());
+        put(257, &pd);
+        let mut lvd = vec![0u8; 2048];
+        tag(&mut lvd, UDF_TAG_LOGICAL_VOLUME);
+        lvd[212..216].copy_from_slice(&2048u32.to_le_bytes());
+        put(258, &lvd);
+        let mut term = vec![0u8; 2048];
+        tag(&mut term, UDF_TAG_TERMINATOR);
+        put(259, &term);
+
+        // Blocks: 0 file set, 1 root entry, 2 root data, 3 sources entry,
+        // 4 sources data, 5 install.wim entry, 6 and 9 its data, 7 bootmgr entry, 8 its data
+        let mut fsd = vec![0u8; 2048];
+        tag(&mut fsd, UDF_TAG_FILE_SET);
+        fsd[404..408].copy_from_slice(&1u32.to_le_bytes());
+        put(partition, &fsd);
+
+        let root: Vec<u8> = [
+            file_id("", true, 1),
+            file_id("sources", true, 3),
+            file_id("bootmgr", false, 7),
+        ]
+        .concat();
+        put(
+            partition + 1,
+            &file_entry(true, root.len() as u64, &[(root.len() as u32, 2)]),
+        );
+        put(partition + 2, &root);
+        let sources: Vec<u8> = [file_id("", true, 1), file_id("install.wim", false, 5)].concat();
+        put(
+            partition + 3,
+            &file_entry(true, sources.len() as u64, &[(sources.len() as u32, 4)]),
+        );
+        put(partition + 4, &sources);
+        put(
+            partition + 5,
+            &file_entry(false, 3000, &[(2048, 6), (952, 9)]),
+        );
+        put(partition + 6, &[b'A'; 2048]);
+        put(partition + 9, &[b'B'; 952]);
+        put(partition + 7, &file_entry(false, 5, &[(5, 8)]));
+        put(partition + 8, b"hello");
+        image
+    }
+
+    #[test]
+    fn test_read_udf_tree() {
+        let image = sample_udf();
+        let mut reader = Cursor::new(&image);
+        let (kind, entries) = read_tree(&mut reader).unwrap();
+        assert_eq!(kind, IsoKind::Udf);
+        let paths: Vec<&str> = entries.iter().map(|e| e.path.as_str()).collect();
+        assert_eq!(paths, vec!["sources", "sources/install.wim", "bootmgr"]);
+
+        let wim = find(&entries, "SOURCES/INSTALL.WIM").unwrap();
+        assert_eq!(wim.size, 3000);
+        assert_eq!(wim.extents.len(), 2);
+        let data = wim.read(&mut reader, 2040, 16).unwrap();
+        assert_eq!(data, [&[b'A'; 8][..], &[b'B'; 8][..]].concat());
+
+        let bootmgr = find(&entries, "bootmgr").unwrap();
+        assert_eq!(bootmgr.read(&mut reader, 0, 5).unwrap(), b"hello");
+    }
+
+    fn dir_record(name: &[u8], lba: u32, size: u32, is_dir: bool, system_use: &[u8]) -> Vec<u8> {
+        let mut record = vec![0u8; 33];
+        record[2..6].copy_from_slice(&lba.to_le_bytes());
+        record[10..14].copy_from_slice(&size.to_le_bytes());
+        record[25] = if is_dir { 2 } else { 0 };
+        record[32] = name.len() as u8;
+        record.extend(name);
+        if name.len().is_multiple_of(2) {
+            record.push(0);
+        }
+        record.extend(system_use);
+        record[0] = record.len() as u8;
+        record
+    }
+
+    fn susp(sig: &[u8], data: &[u8]) -> Vec<u8> {
+        [sig, &[data.len() as u8 + 4, 1], data].concat()
+    }
+
+    #[test]
+    fn test_read_rock_ridge_tree() {
+        let mut image = vec![0u8; 24 * 2048];
+        image[16 * 2048] = 1;
+        image[16 * 2048 + 1..16 * 2048 + 6].copy_from_slice(b"CD001");
+        let root = dir_record(&[0], 20, 2048, true, &[]);
+        image[16 * 2048 + 156..16 * 2048 + 190].copy_from_slice(&root[..34]);
+        image[17 * 2048] = 255;
+        image[17 * 2048 + 1..17 * 2048 + 6].copy_from_slice(b"CD001");
+
+        // The name is split over two NM entries
+        let name = [susp(b"NM", b"\x01release_"), susp(b"NM", b"\x00notes.txt")].concat();
+        let records = [
+            dir_record(&[0], 20, 2048, true, &susp(b"SP", &[0xbe, 0xef, 0])),
+            dir_record(&[1], 20, 2048, true, &[]),
+            dir_record(b"RELEASE_.TXT;1", 22, 5, false, &name),
+            dir_record(b"README.;1", 23, 2, false, &[]),
+        ]
+        .concat();
+        image[20 * 2048..20 * 2048 + records.len()].copy_from_slice(&records);
+        image[22 * 2048..22 * 2048 + 5].copy_from_slice(b"hello");
+
+        let mut reader = Cursor::new(&image);
+        let (kind, entries) = read_tree(&mut reader).unwrap();
+        assert_eq!(kind, IsoKind::RockRidge);
+        let paths: Vec<&str> = entries.iter().map(|e| e.path.as_str()).collect();
+        assert_eq!(paths, vec!["release_notes.txt", "README"]);
+        assert_eq!(entries[0].read(&mut reader, 0, 5).unwrap(), b"hello");
+    }
+}
@@ -0,0 +1,547 @@
+use anyhow::{Result, anyhow};
+use reqwest::Url;
+use serde_json::Value;
+
+use crate::core::net::HttpClient;
+
+/// Suffixes of files that can be written to a device as-is or after
+/// decompression, plus Metalink and torrent files describing such an image
+const IMAGE_SUFFIXES: &[&str] = &[
+    ".iso",
+    ".img",
+    ".img.xz",
+    ".img.gz",
+    ".img.zst",
+    ".raw",
+    ".raw.xz",
+    ".raw.gz",
+    ".raw.zst",
+    ".meta4",
+    ".metalink",
+    ".torrent",
+];
+
+/// One file or directory in a mirror's autoindex listing
+#[derive(Debug, Clone, PartialEq)]
+pub struct DirEntry {
+    pub name: String,
+    pub url: String,
+    pub is_dir: bool,
+    pub size_bytes: Option<u64>,
+    pub modified: Option<String>,
+}
+
+impl DirEntry {
+    pub fn is_image(&self) -> bool {
+        let name = self.name.to_lowercase();
+        !self.is_dir && IMAGE_SUFFIXES.iter().any(|suffix| name.ends_with(suffix))
+    }
+
+    pub fn is_checksum(&self) -> bool {
+        let name = self.name.to_lowercase();
+        let is_signature = [".sign", ".gpg", ".asc"].iter().any(|s| name.ends_with(s));
+        !self.is_dir
+            && !is_signature
+            && (name.ends_with(".sha256")
+                || name.ends_with(".sha256sum")
+                || name.starts_with("sha256sums")
+                || name.ends_with("checksum"))
+    }
+}
+
+/// Fetch and parse a directory listing. Asks for JSON first so servers that
+/// can serve both (e.g. Caddy) skip the HTML scraping.
+pub async fn fetch_listing(client: &HttpClient, url: &str) -> Result<Vec<DirEntry>> {
+    let resp = client
+        .get(url)
+        .header(
+            reqwest::header::ACCEPT,
+            "application/json, text/html;q=0.9, */*;q=0.1",
+        )
+        .send()
+        .await?;
+    if !resp.status().is_success() {
+        return Err(anyhow!("Failed to list {}: {}", url, resp.status()));
+    }
+    // Follow redirects such as /debian -> /debian/ so relative links resolve
+    let base = resp.url().to_string();
+    let body = resp.text().await?;
+    parse_listing(&body, &base)
+}
+
+/// Parse an Apache/nginx/lighttpd HTML autoindex or an nginx/Caddy JSON listing.
+/// Only entries below `base_url` are returned, so parent and sort links are dropped.
+pub fn parse_listing(body: &str, base_url: &str) -> Result<Vec<DirEntry>> {
+    let base = Url::parse(base_url)?;
+    let trimmed = body.trim_start();
+    let mut entries = if trimmed.starts_with('[') || trimmed.starts_with('{') {
+        parse_json_listing(trimmed, &base)?
+    } else {
+        parse_html_listing(body, &base)
+    };
+
+    entries.sort_by(|a, b| b.is_dir.cmp(&a.is_dir).then_with(|| a.name.cmp(&b.name)));
+    entries.dedup_by(|a, b| a.url == b.url);
+    Ok(entries)
+}
+
+/// Checksum file in the same listing that most likely covers `image`
+pub fn sibling_checksum<'a>(entries: &'a [DirEntry], image: &DirEntry) -> Option<&'a DirEntry> {
+    let exact = [
+        format!("{}.sha256", image.name),
+        format!("{}.sha256sum", image.name),
+    ];
+    entries
+        .iter()
+        .find(|e| exact.contains(&e.name))
+        .or_else(|| {
+            entries.iter().find(|e| {
+                let name = e.name.to_lowercase();
+                name == "sha256sums" || name == "sha256sums.txt"
+            })
+        })
+        .or_else(|| entries.iter().find(|e| e.is_checksum()))
+}
+
+fn parse_json_listing(body: &str, base: &Url) -> Result<Vec<DirEntry>> {
+    let value: Value = serde_json::from_str(body)?;
+    let items = value
+        .as_array()
+        .ok_or_else(|| anyhow!("Unexpected JSON listing format"))?;
+
+    let mut entries = Vec::new();
+    for item in items {
+        let Some(name) = item.get("name").and_then(Value::as_str) else {
+            continue;
+        };
+        // nginx: "type": "directory"; Caddy: "is_dir": true
+        let is_dir = item.get("type").and_then(Value::as_str) == Some("directory")
+            || item.get("is_dir").and_then(Value::as_bool) == Some(true);
+        let name = name.trim_end_matches('/');
+        let href = match item.get("url").and_then(Value::as_str) {
+            Some(url) => url.to_string(),
+            None if is_dir => format!("{}/", encode_path_segment(name)),
+            None => encode_path_segment(name),
+        };
+
+        let Some(url) = child_url(base, &href) else {
+            continue;
+        };
+        entries.push(DirEntry {
+            name: name.to_string(),
+            url,
+            is_dir,
+            size_bytes: if is_dir {
+                None
+            } else {
+                item.get("size").and_then(Value::as_u64)
+            },
+            modified: item
+                .get("mtime")
+                .or_else(|| item.get("mod_time"))
+                .and_then(Value::as_str)
+                .map(str::to_string),
+        });
+    }
+    Ok(entries)
+}
+
+fn parse_html_listing(body: &str, base: &Url) -> Vec<DirEntry> {
+    let lower = body.to_ascii_lowercase();
+    let mut entries = Vec::new();
+    let mut pos = 0;
+
+    while let Some(start) = lower[pos..].find("<a ").map(|i| i + pos) {
+        let Some(tag_end) = lower[start..].find('>').map(|i| i + start) else {
+            break;
+        };
+        let Some(text_end) = lower[tag_end..].find("</a>").map(|i| i + tag_end) else {
+            break;
+        };
+        pos = text_end + 4;
+
+        let Some(href) = attribute(&body[start..tag_end], "href") else {
+            continue;
+        };
+        let href = href.replace("&amp;", "&");
+        if href.starts_with('?') || href.starts_with('#') {
+            continue;
+        }
+        let Some(url) = child_url(base, &href) else {
+            continue;
+        };
+
+        let is_dir = href.ends_with('/');
+        let name = href
+            .trim_end_matches('/')
+            .rsplit('/')
+            .next()
+            .map(percent_decode)
+            .unwrap_or_default();
+        if name.is_empty() {
+            continue;
+        }
+
+        // Date and size follow the link on the same row, as table cells (Apache,
+        // lighttpd) or as plain text (nginx, Apache <pre> listings)
+        let row_end = [lower[pos..].find('\n'), lower[pos..].find("<a ")]
+            .into_iter()
+            .flatten()
+            .min()
+            .map(|i| i + pos)
+            .unwrap_or(body.len());
+        let (modified, size_bytes) = parse_row_details(&strip_tags(&body[pos..row_end]));
+
+        entries.push(DirEntry {
+            name,
+            url,
+            is_dir,
+            size_bytes: if is_dir { None } else { size_bytes },
+            modified,
+        });
+    }
+
+    entries
+}
+
+/// Value of a (single or double quoted) attribute inside an HTML tag
+fn attribute(tag: &str, name: &str) -> Option<String> {
+    let lower = tag.to_ascii_lowercase();
+    let mut search = 0;
+    while let Some(found) = lower[search..].find(name).map(|i| i + search) {
+        search = found + name.len();
+        let preceded_ok = found == 0 || lower.as_bytes()[found - 1].is_ascii_whitespace();
+        let rest = tag[search..].trim_start();
+        if !preceded_ok || !rest.starts_with('=') {
+            continue;
+        }
+        let value = rest[1..].trim_start();
+        let quote = value.chars().next()?;
+        if quote == '"' || quote == '\'' {
+            let end = value[1..].find(quote)?;
+            return Some(value[1..=end].to_string());
+        }
+        let end = value
+            .find(|c: char| c.is_whitespace() || c == '>')
+            .unwrap_or(value.len());
+        return Some(value[..end].to_string());
+    }
+    None
+}
+
+/// Resolve `href` against the listing URL, keeping only direct descendants
+fn child_url(base: &Url, href: &str) -> Option<String> {
+    let url = base.join(href).ok()?;
+    let url = url.as_str();
+    let base = base.as_str();
+    let base_dir = &base[..base.rfind('/').map_or(base.len(), |i| i + 1)];
+    (url.starts_with(base_dir) && url.len() > base_dir.len()).then(|| url.to_string())
+}
+
+fn strip_tags(html: &str) -> String {
+    let mut out = String::with_capacity(html.len());
+    let mut in_tag = false;
+    for c in html.chars() {
+        match c {
+            '<' => {
+                in_tag = true;
+                out.push(' ');
+            }
+            '>' => in_tag = false,
+            _ if !in_tag => out.push(c),
+            _ => {}
+        }
+    }
+    out.replace("&nbsp;", " ")
+}
+
+/// Pull "date time" and a size (bytes or 754M-style) out of the text after a link
+fn parse_row_details(text: &str) -> (Option<String>, Option<u64>) {
+    let tokens: Vec<&str> = text.split_whitespace().collect();
+    let date_index = tokens
+        .iter()
+        .position(|t| t.chars().filter(|c| *c == '-').count() == 2 && t.len() >= 8);
+
+    let modified = date_index.map(|i| match tokens.get(i + 1) {
+        Some(time) if time.contains(':') => format!("{} {}", tokens[i], time),
+        _ => tokens[i].to_string(),
+    });
+
+    let size = tokens
+        .iter()
+        .skip(date_index.map_or(0, |i| i + 1))
+        .find_map(|t| parse_size(t));
+
+    (modified, size)
+}
+
+fn parse_size(token: &str) -> Option<u64> {
+    if let Ok(bytes) = token.parse::<u64>() {
+        return Some(bytes);
+    }
+    // Row text comes from arbitrary HTML, so the suffix may be any character
+    let (split, suffix) = token.char_indices().next_back()?;
+    let number = &token[..split];
+    let multiplier: u64 = match suffix {
+        'K' | 'k' => 1024,
+        'M' => 1024 * 1024,
+        'G' => 1024 * 1024 * 1024,
+        'T' => 1024 * 1024 * 1024 * 1024,
+        _ => return None,
+    };
+    number
+        .parse::<f64>()
+        .ok()
+        .map(|n| (n * multiplier as f64) as u64)
+}
+
+fn percent_decode(input: &str) -> String {
+    let bytes = input.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%'
+            && let Some(byte) = input
+                .get(i + 1..i + 3)
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        {
+            out.push(byte);
+            i += 3;
+            continue;
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+fn encode_path_segment(name: &str) -> String {
+    let mut out = String::with_capacity(name.len());
+    for byte in name.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                out.push(byte as char)
+            }
+            _ => out.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    out
+}
+
+/// One line in the mirror browser tree
+#[derive(Debug, Clone, PartialEq)]
+pub struct MirrorNode {
+    pub entry: DirEntry,
+    pub depth: usize,
+    pub expanded: bool,
+}
+
+/// Lazily loaded directory tree of a mirror, flattened in display order
+#[derive(Debug, Clone, Default)]
+pub struct MirrorTree {
+    pub base_url: String,
+    pub nodes: Vec<MirrorNode>,
+    pub selected: usize,
+    /// URL of the directory currently being fetched
+    pub loading: Option<String>,
+    pub error: Option<String>,
+}
+
+impl MirrorTree {
+    pub fn new(base_url: String) -> Self {
+        Self {
+            loading: Some(base_url.clone()),
+            base_url,
+            ..Default::default()
+        }
+    }
+
+    /// Insert a fetched listing under the directory node it belongs to
+    pub fn insert_listing(&mut self, url: &str, entries: Vec<DirEntry>) {
+        if url == self.base_url {
+            self.nodes = entries
+                .into_iter()
+                .map(|entry| MirrorNode {
+                    entry,
+                    depth: 0,
+                    expanded: false,
+                })
+                .collect();
+            self.selected = 0;
+            return;
+        }
+
+        let Some(parent) = self.nodes.iter().position(|n| n.entry.url == url) else {
+            return;
+        };
+        let depth = self.nodes[parent].depth + 1;
+        self.nodes[parent].expanded = true;
+        let children = entries.into_iter().map(|entry| MirrorNode {
+            entry,
+            depth,
+            expanded: false,
+        });
+        self.nodes.splice(parent + 1..parent + 1, children);
+    }
+
+    /// Remove the children of an expanded directory node
+    pub fn collapse(&mut self, index: usize) {
+        let Some(node) = self.nodes.get_mut(index) else {
+            return;
+        };
+        node.expanded = false;
+        let depth = node.depth;
+        let end = self.nodes[index + 1..]
+            .iter()
+            .position(|n| n.depth <= depth)
+            .map_or(self.nodes.len(), |i| i + index + 1);
+        self.nodes.drain(index + 1..end);
+    }
+
+    /// Entries sharing a directory with the node at `index`
+    pub fn siblings(&self, index: usize) -> Vec<DirEntry> {
+        let Some(node) = self.nodes.get(index) else {
+            return Vec::new();
+        };
+        let start = self.nodes[..index]
+            .iter()
+            .rposition(|n| n.depth < node.depth)
+            .map_or(0, |i| i + 1);
+        self.nodes[start..]
+            .iter()
+            .take_while(|n| n.depth >= node.depth)
+            .filter(|n| n.depth == node.depth)
+            .map(|n| n.entry.clone())
+            .collect()
+    }
+
+    pub fn selected_node(&self) -> Option<&MirrorNode> {
+        self.nodes.get(self.selected)
+    }
+
+    pub fn select_next(&mut self) {
+        if !self.nodes.is_empty() {
+            self.selected = (self.selected + 1) % self.nodes.len();
+        }
+    }
+
+    pub fn select_previous(&mut self) {
+        if !self.nodes.is_empty() {
+            if self.selected == 0 {
+                self.selected = self.nodes.len() - 1;
+            } else {
+                self.selected -= 1;
+            }
+        }
+    }
+}
+
+/// Result of fetching one directory for the mirror browser
+#[derive(Debug, Clone, PartialEq)]
+pub struct MirrorListing {
+    pub url: String,
+    pub result: Result<Vec<DirEntry>, String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE: &str = "https://mirror.example.org/debian-cd/13.2.0/amd64/iso-cd/";
+
+    fn fixture(name: &str) -> String {
+        let path = format!(
+            "{}/tests/fixtures/autoindex/{}",
+            env!("CARGO_MANIFEST_DIR"),
+            name
+        );
+        std::fs::read_to_string(path).unwrap()
+    }
+
+    fn names(entries: &[DirEntry]) -> Vec<&str> {
+        entries.iter().map(|e| e.name.as_str()).collect()
+    }
+
+    #[test]
+    fn test_parse_apache_listing() {
+        let entries = parse_listing(&fixture("apache.html"), BASE).unwrap();
+        assert_eq!(
+            names(&entries),
+            vec![
+                "older",
+                "SHA256SUMS",
+                "SHA256SUMS.sign",
+                "SHA512SUMS",
+                "debian-13.2.0-amd64-netinst.iso",
+                "debian-edu-13.2.0-amd64-netinst.iso",
+            ]
+        );
+        let iso = &entries[4];
+        assert!(iso.is_image());
+        assert_eq!(iso.size_bytes, Some(754 * 1024 * 1024));
+        assert_eq!(iso.modified.as_deref(), Some("2025-11-15 12:18"));
+        assert_eq!(iso.url, format!("{}debian-13.2.0-amd64-netinst.iso", BASE));
+        assert!(entries[0].is_dir);
+        assert_eq!(sibling_checksum(&entries, iso).unwrap().name, "SHA256SUMS");
+    }
+
+    #[test]
+    fn test_parse_nginx_html_listing() {
+        let entries = parse_listing(&fixture("nginx.html"), BASE).unwrap();
+        assert_eq!(
+            names(&entries),
+            vec![
+                "nightly",
+                "README.ru.txt",
+                "raspios arm64.img.xz",
+                "raspios arm64.img.xz.sha256",
+            ]
+        );
+        // Localised units aren't understood, but mustn't break the row
+        assert_eq!(entries[1].size_bytes, None);
+        assert_eq!(entries[2].size_bytes, Some(1_209_548_512));
+        assert!(entries[2].is_image());
+        assert!(entries[3].is_checksum());
+        assert_eq!(
+            sibling_checksum(&entries, &entries[2]).unwrap().name,
+            "raspios arm64.img.xz.sha256"
+        );
+    }
+
+    #[test]
+    fn test_parse_json_listings() {
+        let nginx = parse_listing(&fixture("nginx.json"), BASE).unwrap();
+        assert_eq!(
+            names(&nginx),
+            vec!["releases", "archlinux-x86_64.iso", "sha256sums.txt"]
+        );
+        assert_eq!(nginx[1].size_bytes, Some(1_428_586_496));
+        assert_eq!(nginx[0].url, format!("{}releases/", BASE));
+
+        let caddy = parse_listing(&fixture("caddy.json"), BASE).unwrap();
+        assert_eq!(names(&caddy), vec!["old", "appliance.img.zst"]);
+        assert!(caddy[0].is_dir);
+        assert_eq!(caddy[1].size_bytes, Some(524_288_000));
+    }
+
+    #[test]
+    fn test_tree_expand_and_collapse() {
+        let dir = |name: &str, url: &str| DirEntry {
+            name: name.to_string(),
+            url: url.to_string(),
+            is_dir: true,
+            size_bytes: None,
+            modified: None,
+        };
+        let mut tree = MirrorTree::new(BASE.to_string());
+        tree.insert_listing(BASE, vec![dir("a", "a/"), dir("b", "b/")]);
+        tree.insert_listing("a/", vec![dir("c", "a/c/")]);
+        assert_eq!(tree.nodes.len(), 3);
+        assert_eq!(tree.nodes[1].depth, 1);
+        assert_eq!(tree.siblings(2).len(), 2);
+
+        tree.collapse(0);
+        assert_eq!(tree.nodes.len(), 2);
+        assert!(!tree.nodes[0].expanded);
+    }
+}
@@ -1,7 +1,40 @@
+pub mod backup;
+pub mod catalog;
+pub mod cloudinit;
+pub mod config;
 pub mod disk_ops;
+pub mod exfat;
+pub mod filesystem;
 pub mod flasher;
-
-use self::flasher::FlashProgress;
+pub mod grow;
+pub mod history;
+pub mod identify;
+pub mod inject;
+pub mod inspect;
+pub mod isobrowser;
+pub mod isofs;
+pub mod mirror;
+pub mod multiboot;
+pub mod net;
+pub mod partition;
+pub mod persistence;
+pub mod rpi;
+pub mod shrink;
+pub mod signatures;
+pub mod source;
+pub mod wim;
+pub mod windows;
+pub mod wipe;
+
+use self::backup::BackupProgress;
+use self::flasher::{FlashProgress, Preflight};
+use self::identify::Identification;
+use self::isobrowser::IsoUpdate;
+use self::mirror::MirrorListing;
+use self::multiboot::MultibootStick;
+use self::persistence::PersistenceOffer;
+use self::rpi::PiBoot;
+use self::wipe::WipeProgress;
 
 use thiserror::Error;
 
@@ -12,7 +45,6 @@ pub struct Device {
     pub name: String,
     pub size_bytes: u64,
     pub filesystem: String,
-    pub label: String,
     pub mount_point: Option<String>,
     pub is_protected: bool,
     pub is_removable: bool,
@@ -26,21 +58,132 @@ pub enum AppState {
     FormattingMenu,
     ConfirmDestructive(String),
     ConfirmFlash(String),
+    /// Picking the device to clone `App::clone_source` onto
+    CloneTargetSelection,
+    ConfirmClone(String),
     IsoSelection,
+    CustomUrlInput,
+    MirrorUrlInput,
+    MirrorBrowser,
+    MirrorListingLoaded(Box<MirrorListing>),
+    /// Typing the URL or path of an image to look inside
+    IsoContentsInput,
+    /// Browsing the files in `App::iso_tree`
+    IsoContents,
+    IsoContentsUpdate(Box<IsoUpdate>),
+    /// A drive was compared with the catalog
+    Identified(Box<Identification>),
+    PreflightDone(Box<FlashJob>),
     Flashing(FlashProgress),
+    BackupSetup,
+    BackingUp(BackupProgress),
+    WipeMenu,
+    ConfirmWipe(String),
+    Wiping(WipeProgress),
+    /// Listing and managing the ISOs on `App::multiboot`
+    MultibootMenu,
+    /// Typing the path of a local ISO to add to the stick
+    MultibootFileInput,
+    /// No multi-boot stick found; confirm turning the device into one
+    ConfirmMultiboot(String),
+    MultibootReady(Box<MultibootStick>),
+    /// Editing the cloud-init seed of `App::flash_job`
+    SeedEditor,
+    /// Editing the files injected by `App::flash_job`
+    InjectionEditor,
+    /// Raspberry Pi OS was flashed; fill in its headless setup
+    PiSetup(Box<PiBoot>),
+    /// A live image was flashed with room to spare; ask about persistence
+    PersistenceOffer(Box<PersistenceOffer>),
     InProgress(String),
     Error(String),
     Success(String),
 }
 
 /// Represents an ISO image available for flashing
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Iso {
     pub name: String,
     pub version: String,
     pub arch: String,
     pub url: String,
     pub variety: String,
+    pub size_bytes: Option<u64>,
+    pub sha256: Option<String>,
+    /// Checksum file to consult before the usual sibling locations
+    pub checksum_url: Option<String>,
+    /// A disk image whose last partition should grow to fill the device;
+    /// the default for the checkbox in the flash confirmation
+    pub grow_to_fill: bool,
+    pub release_date: Option<String>,
+    pub description: String,
+}
+
+impl Iso {
+    /// Entry for an image the user typed in rather than picked from the catalog
+    pub fn from_custom_url(url: &str) -> Self {
+        let file_name = url
+            .rsplit('/')
+            .next()
+            .filter(|name| !name.is_empty())
+            .unwrap_or(url);
+        // `distro.iso.meta4` describes `distro.iso`
+        let file_name = [".meta4", ".metalink", ".torrent"]
+            .iter()
+            .find_map(|suffix| file_name.strip_suffix(suffix))
+            .unwrap_or(file_name);
+        let magnet_name = source::torrent::Magnet::parse(url)
+            .ok()
+            .and_then(|magnet| magnet.name);
+        let file_name = magnet_name.as_deref().unwrap_or(file_name);
+        Iso {
+            name: file_name.to_string(),
+            version: String::new(),
+            arch: String::new(),
+            url: url.to_string(),
+            variety: "Custom URL".to_string(),
+            size_bytes: None,
+            sha256: None,
+            checksum_url: None,
+            grow_to_fill: false,
+            release_date: None,
+            description: String::new(),
+        }
+    }
+}
+
+/// An image that passed pre-flight and waits for the user to confirm
+#[derive(Debug, Clone, PartialEq)]
+pub struct FlashJob {
+    pub iso: Iso,
+    pub preflight: Preflight,
+    /// Entered by URL, so it goes into the history once flashed
+    pub is_custom: bool,
+    /// A Windows installer, unpacked onto FAT32 instead of written as is
+    pub windows: Option<windows::WindowsMedia>,
+    /// NoCloud seed written to a `CIDATA` partition after the image
+    pub seed: Option<cloudinit::Seed>,
+    /// Files dropped into the written image's partitions
+    pub injections: Vec<inject::Injection>,
+    /// Grow the last partition into the rest of the device after writing
+    pub grow: bool,
+    /// Format, partitions and boot entries read from the image's start
+    pub inspection: Option<inspect::Inspection>,
+    /// The drive was identified as holding exactly this image
+    pub already_on_device: bool,
+    /// Only write the blocks that differ from what the drive holds
+    pub delta: bool,
+}
+
+impl FlashJob {
+    /// How the image is compressed, going by its magic; unpacked while flashing
+    pub fn compression(&self) -> backup::Compression {
+        self.inspection
+            .as_ref()
+            .map_or(backup::Compression::None, |inspection| {
+                inspection.format.compression()
+            })
+    }
 }
 
 /// Supported filesystem types
@@ -48,18 +191,17 @@ pub struct Iso {
 pub enum FileSystemType {
     Fat32,
     ExFat,
-    Ntfs,
     Ext4,
     Apfs,
 }
 
 impl FileSystemType {
     /// Get the filesystem name as used by diskutil
+    #[cfg(target_os = "macos")]
     pub fn as_diskutil_format(&self) -> &'static str {
         match self {
             FileSystemType::Fat32 => "FAT32",
             FileSystemType::ExFat => "ExFAT",
-            FileSystemType::Ntfs => "NTFS",
             FileSystemType::Ext4 => "ExFAT", // Not directly supported, fallback
             FileSystemType::Apfs => "APFS",
         }
@@ -70,7 +212,6 @@ impl FileSystemType {
         match self {
             FileSystemType::Fat32 => "FAT32",
             FileSystemType::ExFat => "exFAT",
-            FileSystemType::Ntfs => "NTFS",
             FileSystemType::Ext4 => "ext4",
             FileSystemType::Apfs => "APFS",
         }
@@ -89,24 +230,19 @@ impl FileSystemType {
 /// Errors that can occur during disk operations
 #[derive(Error, Debug)]
 pub enum DiskError {
-    #[error("Device is protected (system drive)")]
-    ProtectedDevice,
-
     #[error("Device is busy or in use")]
     DeviceBusy,
 
     #[error("Insufficient privileges - run as root/admin")]
     InsufficientPrivileges,
 
+    #[cfg(target_os = "macos")]
     #[error("Device not found: {0}")]
     DeviceNotFound(String),
 
     #[error("Unsupported filesystem: {0}")]
     UnsupportedFilesystem(String),
 
-    #[error("Platform not supported")]
-    PlatformNotSupported,
-
     #[error("Command failed: {0}")]
     CommandFailed(String),
 
@@ -0,0 +1,434 @@
+use std::ffi::CString;
+use std::fs::{self, File};
+use std::io::Write;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Arc;
+
+use anyhow::{Context, Result, bail};
+use tokio::sync::mpsc::UnboundedSender;
+
+use super::disk_ops::PartitionSpec;
+use super::flasher::{Flasher, ImageSink};
+use super::isofs;
+use super::source::ImageSource;
+use super::{AppState, FileSystemType};
+use crate::utils::bytes_to_human;
+
+/// Label of the exFAT partition holding the ISOs
+pub const DATA_LABEL: &str = "PERVIE";
+const ESP_LABEL: &str = "PERVIE-EFI";
+const ESP_SIZE: u64 = 64 * 1024 * 1024;
+/// Partition numbers in the layout `layout` creates
+pub const ESP_PARTITION: u32 = 1;
+pub const DATA_PARTITION: u32 = 2;
+const ISO_DIR: &str = "isos";
+/// Marks the data partition as ours and holds the generated menu
+const MENU_DIR: &str = "pervie";
+const MENU_FILE: &str = "grub.cfg";
+const PART_SUFFIX: &str = ".part";
+const LOOPBACK_CFG: &str = "boot/grub/loopback.cfg";
+/// GRUB builds, the removable-media file name firmware boots, and the
+/// loader an ISO carries for the same architecture
+const GRUB_TARGETS: [(&str, &str); 2] =
+    [("x86_64-efi", "BOOTX64.EFI"), ("arm64-efi", "BOOTAA64.EFI")];
+const GRUB_MODULES: &str = "part_gpt part_msdos fat exfat iso9660 udf loopback search \
+                            configfile normal linux chain echo test regexp all_video";
+
+/// How the generated menu boots an ISO
+#[derive(Debug, Clone, PartialEq)]
+pub enum BootMethod {
+    /// The ISO ships `boot/grub/loopback.cfg` for booting from a file
+    Loopback,
+    /// Hand over to the ISO's own EFI loader at this path
+    Chainload(String),
+    /// Nothing GRUB knows how to start
+    Unsupported,
+}
+
+impl BootMethod {
+    pub fn display_name(&self) -> &'static str {
+        match self {
+            BootMethod::Loopback => "loopback",
+            BootMethod::Chainload(_) => "EFI chainload",
+            BootMethod::Unsupported => "not bootable",
+        }
+    }
+}
+
+/// An ISO on the data partition
+#[derive(Debug, Clone, PartialEq)]
+pub struct StickIso {
+    pub name: String,
+    pub size: u64,
+    pub boot: BootMethod,
+}
+
+/// A mounted multi-boot stick and what's on it
+#[derive(Debug, Clone, PartialEq)]
+pub struct MultibootStick {
+    /// Whole device the stick was opened from
+    pub device_path: String,
+    pub mount_point: PathBuf,
+    pub isos: Vec<StickIso>,
+    pub free_bytes: u64,
+    pub total_bytes: u64,
+    /// Outcome of the last change, shown above the list
+    pub notice: Option<String>,
+}
+
+/// Partitions of a fresh stick: a small EFI system partition for GRUB and
+/// exFAT for the rest, so ISOs over 4 GiB fit
+pub fn layout() -> Vec<PartitionSpec> {
+    vec![
+        PartitionSpec {
+            fs_type: FileSystemType::Fat32,
+            label: ESP_LABEL.to_string(),
+            size: Some(ESP_SIZE),
+            efi_system: true,
+        },
+        PartitionSpec {
+            fs_type: FileSystemType::ExFat,
+            label: DATA_LABEL.to_string(),
+            size: None,
+            efi_system: false,
+        },
+    ]
+}
+
+/// Put GRUB on the mounted EFI system partition. Its built-in config finds
+/// the data partition by the menu file on it.
+pub fn install_bootloader(esp_mount: &Path) -> Result<()> {
+    let boot_dir = esp_mount.join("EFI").join("BOOT");
+    fs::create_dir_all(&boot_dir).context("Failed to create EFI/BOOT")?;
+    let config = std::env::temp_dir().join(format!("pervie-grub-{}.cfg", std::process::id()));
+    fs::write(
+        &config,
+        format!(
+            "search --no-floppy --set=root --file /{dir}/{file}\n\
+             set prefix=($root)/{dir}\n\
+             configfile /{dir}/{file}\n",
+            dir = MENU_DIR,
+            file = MENU_FILE
+        ),
+    )?;
+
+    let mut installed = Vec::new();
+    let mut last_error = String::new();
+    for (target, file_name) in GRUB_TARGETS {
+        let output = boot_dir.join(file_name);
+        let mut args = vec![
+            format!("--format={}", target),
+            format!("--output={}", output.display()),
+            format!("--install-modules={}", GRUB_MODULES),
+            "--locales=".to_string(),
+            "--fonts=".to_string(),
+        ];
+        args.push(format!("boot/grub/grub.cfg={}", config.display()));
+        // Debian ships grub-mkstandalone, Fedora grub2-mkstandalone
+        for tool in ["grub-mkstandalone", "grub2-mkstandalone"] {
+            match Command::new(tool).args(&args).output() {
+                Ok(out) if out.status.success() => {
+                    installed.push(target);
+                    break;
+                }
+                Ok(out) => last_error = String::from_utf8_lossy(&out.stderr).trim().to_string(),
+                Err(_) => {}
+            }
+        }
+    }
+    let _ = fs::remove_file(&config);
+
+    if installed.is_empty() {
+        bail!(
+            "Couldn't build the GRUB bootloader: {}\nInstall grub-mkstandalone and the \
+             x86_64-efi modules (grub-efi-amd64-bin or grub2-efi-x64-modules)",
+            if last_error.is_empty() {
+                "grub-mkstandalone not found"
+            } else {
+                &last_error
+            }
+        );
+    }
+    Ok(())
+}
+
+/// Turn the mounted data partition into an empty multi-boot stick
+pub fn init(data_mount: &Path) -> Result<()> {
+    fs::create_dir_all(data_mount.join(ISO_DIR))?;
+    fs::create_dir_all(data_mount.join(MENU_DIR))?;
+    write_menu(data_mount, &[])
+}
+
+/// Read what's on a mounted data partition, dropping downloads that never
+/// finished
+pub fn scan(device_path: &str, data_mount: &Path) -> Result<MultibootStick> {
+    if !data_mount.join(MENU_DIR).is_dir() {
+        bail!("{} is not a multi-boot stick", device_path);
+    }
+    let iso_dir = data_mount.join(ISO_DIR);
+    fs::create_dir_all(&iso_dir)?;
+
+    let mut isos = Vec::new();
+    for entry in fs::read_dir(&iso_dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.ends_with(PART_SUFFIX) {
+            let _ = fs::remove_file(entry.path());
+            continue;
+        }
+        let metadata = entry.metadata()?;
+        if !metadata.is_file() || name.starts_with('.') {
+            continue;
+        }
+        isos.push(StickIso {
+            boot: boot_method(&entry.path()),
+            name,
+            size: metadata.len(),
+        });
+    }
+    isos.sort_by_key(|iso| iso.name.to_lowercase());
+
+    let (free_bytes, total_bytes) = space(data_mount)?;
+    Ok(MultibootStick {
+        device_path: device_path.to_string(),
+        mount_point: data_mount.to_path_buf(),
+        isos,
+        free_bytes,
+        total_bytes,
+        notice: None,
+    })
+}
+
+/// Look inside an ISO for something GRUB can boot
+fn boot_method(path: &Path) -> BootMethod {
+    let Ok(mut file) = File::open(path) else {
+        return BootMethod::Unsupported;
+    };
+    let Ok((_, entries)) = isofs::read_tree(&mut file) else {
+        return BootMethod::Unsupported;
+    };
+    if isofs::find(&entries, LOOPBACK_CFG).is_some() {
+        return BootMethod::Loopback;
+    }
+    GRUB_TARGETS
+        .iter()
+        .map(|(_, file_name)| format!("EFI/BOOT/{}", file_name))
+        .find_map(|loader| {
+            isofs::find(&entries, &loader).map(|e| BootMethod::Chainload(e.path.clone()))
+        })
+        .unwrap_or(BootMethod::Unsupported)
+}
+
+/// The GRUB menu listing every bootable ISO
+pub fn menu(isos: &[StickIso]) -> String {
+    let mut menu = String::from("set timeout=10\nset default=0\n");
+    for iso in isos {
+        let title = iso.name.replace('"', "'");
+        let path = format!("/{}/{}", ISO_DIR, iso.name.replace('"', "\\\""));
+        let body = match &iso.boot {
+            BootMethod::Loopback => format!(
+                "    set iso_path=\"{path}\"\n    export iso_path\n    loopback loop \"$iso_path\"\n    \
+                 set root=(loop)\n    configfile /{LOOPBACK_CFG}\n"
+            ),
+            BootMethod::Chainload(loader) => {
+                format!("    loopback loop \"{path}\"\n    chainloader (loop)/{loader}\n")
+            }
+            BootMethod::Unsupported => continue,
+        };
+        menu.push_str(&format!("\nmenuentry \"{}\" {{\n{}}}\n", title, body));
+    }
+    menu
+}
+
+/// Regenerate the boot menu, replacing the old one in a single rename
+pub fn write_menu(data_mount: &Path, isos: &[StickIso]) -> Result<()> {
+    let path = data_mount.join(MENU_DIR).join(MENU_FILE);
+    let temp = path.with_extension("cfg.new");
+    let mut file = File::create(&temp).context("Failed to write the boot menu")?;
+    file.write_all(menu(isos).as_bytes())?;
+    file.sync_all()?;
+    fs::rename(&temp, &path).context("Failed to write the boot menu")?;
+    Ok(())
+}
+
+/// File name an image is stored under: the last part of its URL or path
+pub fn file_name_for(url: &str) -> String {
+    let name = url
+        .split(['?', '#'])
+        .next()
+        .unwrap_or(url)
+        .rsplit('/')
+        .find(|s| !s.is_empty())
+        .unwrap_or("image");
+    let name: String = name
+        .chars()
+        .map(|c| {
+            if c.is_control() || "\\:*?\"<>|".contains(c) {
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect();
+    if name.to_lowercase().ends_with(".iso") {
+        name
+    } else {
+        format!("{}.iso", name)
+    }
+}
+
+/// Copy an image onto the stick and add it to the menu. The file only
+/// takes its name once every byte is written and checked.
+pub async fn add_iso(
+    flasher: &Flasher,
+    source: Arc<dyn ImageSource>,
+    expected_sha256: Option<String>,
+    stick: &MultibootStick,
+    name: &str,
+    progress_tx: UnboundedSender<AppState>,
+) -> Result<MultibootStick> {
+    let size = source.probe().await?.total_bytes;
+    if size > stick.free_bytes {
+        bail!(
+            "{} is {} but the stick only has {} free",
+            name,
+            bytes_to_human(size),
+            bytes_to_human(stick.free_bytes)
+        );
+    }
+    let path = stick.mount_point.join(ISO_DIR).join(name);
+    let partial = stick
+        .mount_point
+        .join(ISO_DIR)
+        .join(format!("{}{}", name, PART_SUFFIX));
+    let file = File::create(&partial)
+        .with_context(|| format!("Failed to create {}", partial.display()))?;
+    let label = format!("Copying to the stick as {}", name);
+
+    let result = flasher
+        .stream_to(
+            source,
+            expected_sha256,
+            Box::new(FileSink { file }),
+            move |_| Some(label.clone()),
+            progress_tx,
+        )
+        .await;
+    if let Err(e) = result {
+        let _ = fs::remove_file(&partial);
+        return Err(e);
+    }
+    fs::rename(&partial, &path)
+        .with_context(|| format!("Failed to rename {}", partial.display()))?;
+    refresh(stick)
+}
+
+/// Delete an ISO and take it out of the menu
+pub fn remove_iso(stick: &MultibootStick, name: &str) -> Result<MultibootStick> {
+    let path = stick.mount_point.join(ISO_DIR).join(name);
+    fs::remove_file(&path).with_context(|| format!("Failed to remove {}", path.display()))?;
+    refresh(stick)
+}
+
+/// Rescan after a change and rewrite the menu to match
+fn refresh(stick: &MultibootStick) -> Result<MultibootStick> {
+    let stick = scan(&stick.device_path, &stick.mount_point)?;
+    write_menu(&stick.mount_point, &stick.isos)?;
+    Ok(stick)
+}
+
+/// Free and total bytes of the filesystem mounted at `mount`
+fn space(mount: &Path) -> Result<(u64, u64)> {
+    let path = CString::new(mount.as_os_str().as_bytes())?;
+    // SAFETY: statvfs only writes into `stat`, and `path` is NUL-terminated
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return Err(std::io::Error::last_os_error())
+            .with_context(|| format!("Failed to read free space of {}", mount.display()));
+    }
+    let block = stat.f_frsize as u64;
+    Ok((stat.f_bavail as u64 * block, stat.f_blocks as u64 * block))
+}
+
+/// Writes an image into a regular file
+struct FileSink {
+    file: File,
+}
+
+impl ImageSink for FileSink {
+    fn write(&mut self, chunk: &[u8]) -> Result<()> {
+        self.file
+            .write_all(chunk)
+            .context("Failed to write to the stick")
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.file.sync_all().context("Failed to sync the stick")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_menu() {
+        let isos = vec![
+            StickIso {
+                name: "ubuntu.iso".to_string(),
+                size: 1,
+                boot: BootMethod::Loopback,
+            },
+            StickIso {
+                name: "notes.iso".to_string(),
+                size: 1,
+                boot: BootMethod::Unsupported,
+            },
+            StickIso {
+                name: "rescue.iso".to_string(),
+                size: 1,
+                boot: BootMethod::Chainload("EFI/BOOT/BOOTX64.EFI".to_string()),
+            },
+        ];
+        let menu = menu(&isos);
+        assert!(menu.contains(
+            "menuentry \"ubuntu.iso\" {\n    set iso_path=\"/isos/ubuntu.iso\"\n    export iso_path\n"
+        ));
+        assert!(menu.contains("configfile /boot/grub/loopback.cfg\n}"));
+        assert!(menu.contains(
+            "loopback loop \"/isos/rescue.iso\"\n    chainloader (loop)/EFI/BOOT/BOOTX64.EFI\n"
+        ));
+        assert!(!menu.contains("notes.iso"));
+
+        assert_eq!(
+            file_name_for("https://example.com/a/debian.iso?x=1"),
+            "debian.iso"
+        );
+        assert_eq!(file_name_for("/home/me/images/arch"), "arch.iso");
+    }
+
+    #[test]
+    fn test_scan_and_remove() {
+        let dir = std::env::temp_dir().join(format!("pervie-multiboot-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        assert!(scan("/dev/sdz", &dir).is_err());
+
+        init(&dir).unwrap();
+        fs::write(dir.join(ISO_DIR).join("b.iso"), vec![0u8; 4096]).unwrap();
+        fs::write(dir.join(ISO_DIR).join("A.iso"), vec![0u8; 100]).unwrap();
+        fs::write(dir.join(ISO_DIR).join("c.iso.part"), b"half").unwrap();
+        let stick = scan("/dev/sdz", &dir).unwrap();
+        let names: Vec<&str> = stick.isos.iter().map(|i| i.name.as_str()).collect();
+        assert_eq!(names, vec!["A.iso", "b.iso"]);
+        assert_eq!(stick.isos[1].boot, BootMethod::Unsupported);
+        assert!(!dir.join(ISO_DIR).join("c.iso.part").exists());
+        assert!(stick.total_bytes > 0);
+
+        let stick = remove_iso(&stick, "A.iso").unwrap();
+        assert_eq!(stick.isos.len(), 1);
+        assert!(dir.join(MENU_DIR).join(MENU_FILE).is_file());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,256 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use reqwest::header::{HeaderName, HeaderValue};
+use reqwest::{Certificate, Client, IntoUrl, Method, NoProxy, Proxy, RequestBuilder, Url};
+
+use crate::core::config::NetworkConfig;
+
+/// Credentials and headers to attach to requests for matching hosts
+#[derive(Debug, Clone, Default, PartialEq)]
+struct HostRule {
+    /// Exact host, or `.domain` for the domain and its subdomains
+    pattern: String,
+    bearer: Option<String>,
+    basic: Option<(String, String)>,
+    headers: Vec<(String, String)>,
+}
+
+impl HostRule {
+    fn matches(&self, host: &str) -> bool {
+        match self.pattern.strip_prefix('.') {
+            Some(domain) => host == domain || host.ends_with(&self.pattern),
+            None => host == self.pattern,
+        }
+    }
+}
+
+/// HTTP client built from the `[network]` config. Requests made through it
+/// pick up the per-host tokens, logins and headers; sources that sign their
+/// own requests (S3, OCI) use [`HttpClient::inner`] and only share the
+/// proxy, certificate and timeout settings.
+#[derive(Clone, Default)]
+pub struct HttpClient {
+    client: Client,
+    rules: Arc<Vec<HostRule>>,
+}
+
+impl HttpClient {
+    pub fn new(config: &NetworkConfig) -> Result<Self> {
+        let user_agent = config
+            .user_agent
+            .clone()
+            .unwrap_or_else(|| format!("pervie/{}", env!("CARGO_PKG_VERSION")));
+        let mut builder = Client::builder().user_agent(user_agent);
+
+        if let Some(proxy) = &config.proxy {
+            let no_proxy = NoProxy::from_string(&config.no_proxy.join(","));
+            builder = builder.proxy(
+                Proxy::all(proxy)
+                    .with_context(|| format!("Invalid proxy {}", proxy))?
+                    .no_proxy(no_proxy),
+            );
+        }
+        for path in &config.ca_certificates {
+            let pem = std::fs::read(path)
+                .with_context(|| format!("Failed to read CA bundle {}", path.display()))?;
+            for certificate in Certificate::from_pem_bundle(&pem)
+                .with_context(|| format!("Invalid CA bundle {}", path.display()))?
+            {
+                builder = builder.add_root_certificate(certificate);
+            }
+        }
+        if let Some(secs) = config.connect_timeout_secs {
+            builder = builder.connect_timeout(Duration::from_secs(secs));
+        }
+        if let Some(secs) = config.read_timeout_secs {
+            builder = builder.read_timeout(Duration::from_secs(secs));
+        }
+
+        let mut rules: Vec<HostRule> = config
+            .hosts
+            .iter()
+            .map(|(pattern, host)| HostRule {
+                pattern: pattern.to_lowercase(),
+                bearer: host.token.clone().or_else(|| {
+                    host.token_env
+                        .as_ref()
+                        .and_then(|name| std::env::var(name).ok())
+                }),
+                basic: host
+                    .username
+                    .clone()
+                    .map(|username| (username, host.password.clone().unwrap_or_default())),
+                headers: host
+                    .headers
+                    .iter()
+                    .map(|(name, value)| (name.clone(), value.clone()))
+                    .collect(),
+            })
+            .collect();
+        // Hosts configured explicitly win over netrc entries
+        if config.netrc
+            && let Some(contents) = netrc_path().and_then(|p| std::fs::read_to_string(p).ok())
+        {
+            rules.extend(parse_netrc(&contents));
+        }
+        for rule in &rules {
+            for (name, value) in &rule.headers {
+                HeaderName::try_from(name.as_str())
+                    .with_context(|| format!("Invalid header name {}", name))?;
+                HeaderValue::try_from(value.as_str())
+                    .with_context(|| format!("Invalid value for header {}", name))?;
+            }
+        }
+
+        Ok(Self {
+            client: builder.build()?,
+            rules: Arc::new(rules),
+        })
+    }
+
+    /// The underlying client, without per-host credentials
+    pub fn inner(&self) -> &Client {
+        &self.client
+    }
+
+    pub fn get<U: IntoUrl + AsRef<str>>(&self, url: U) -> RequestBuilder {
+        self.request(Method::GET, url)
+    }
+
+    pub fn head<U: IntoUrl + AsRef<str>>(&self, url: U) -> RequestBuilder {
+        self.request(Method::HEAD, url)
+    }
+
+    pub fn request<U: IntoUrl + AsRef<str>>(&self, method: Method, url: U) -> RequestBuilder {
+        let host = Url::parse(url.as_ref())
+            .ok()
+            .and_then(|u| u.host_str().map(str::to_lowercase));
+        let mut request = self.client.request(method, url);
+        let Some(rule) = host.and_then(|host| self.rules.iter().find(|r| r.matches(&host))) else {
+            return request;
+        };
+        if let Some(token) = &rule.bearer {
+            request = request.bearer_auth(token);
+        } else if let Some((username, password)) = &rule.basic {
+            request = request.basic_auth(username, Some(password));
+        }
+        for (name, value) in &rule.headers {
+            request = request.header(name, value);
+        }
+        request
+    }
+}
+
+fn netrc_path() -> Option<PathBuf> {
+    std::env::var_os("NETRC")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".netrc")))
+}
+
+/// Logins from a netrc file. `default` entries are ignored: sending one
+/// password to every host is rarely what anyone wants from a flashing tool.
+fn parse_netrc(contents: &str) -> Vec<HostRule> {
+    let mut rules = Vec::new();
+    let mut tokens = netrc_tokens(contents).into_iter();
+    let mut current: Option<(String, Option<String>, Option<String>)> = None;
+    let mut finish = |entry: Option<(String, Option<String>, Option<String>)>| {
+        if let Some((host, Some(login), password)) = entry {
+            rules.push(HostRule {
+                pattern: host.to_lowercase(),
+                basic: Some((login, password.unwrap_or_default())),
+                ..Default::default()
+            });
+        }
+    };
+
+    while let Some(token) = tokens.next() {
+        match token {
+            "machine" => {
+                finish(current.take());
+                current = tokens.next().map(|host| (host.to_string(), None, None));
+            }
+            "default" => finish(current.take()),
+            "login" => {
+                if let (Some(entry), Some(login)) = (current.as_mut(), tokens.next()) {
+                    entry.1 = Some(login.to_string());
+                }
+            }
+            "password" => {
+                if let (Some(entry), Some(password)) = (current.as_mut(), tokens.next()) {
+                    entry.2 = Some(password.to_string());
+                }
+            }
+            "account" => {
+                tokens.next();
+            }
+            _ => {}
+        }
+    }
+    finish(current);
+    rules
+}
+
+/// Tokens of a netrc file without comments and `macdef` macros. A macro's
+/// name ends its line and its body runs to the next blank line.
+fn netrc_tokens(contents: &str) -> Vec<&str> {
+    let mut tokens = Vec::new();
+    let mut in_macro = false;
+    for line in contents.lines() {
+        if in_macro {
+            in_macro = !line.trim().is_empty();
+            continue;
+        }
+        if line.trim_start().starts_with('#') {
+            continue;
+        }
+        for token in line.split_whitespace() {
+            if token == "macdef" {
+                in_macro = true;
+                break;
+            }
+            tokens.push(token);
+        }
+    }
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_netrc() {
+        let rules = parse_netrc(
+            "machine artifacts.corp login ci password s3cret\n# comment\nmachine other.corp\n  login bob\ndefault login anon password x\n",
+        );
+        assert_eq!(rules.len(), 2);
+        assert!(rules[0].matches("artifacts.corp"));
+        assert_eq!(
+            rules[0].basic,
+            Some(("ci".to_string(), "s3cret".to_string()))
+        );
+        assert_eq!(rules[1].basic, Some(("bob".to_string(), String::new())));
+
+        // Entries after a macro are still read; its body isn't
+        let rules = parse_netrc(
+            "machine a.corp login a\nmacdef init\ncd /pub\nmachine fake.corp login x\n\nmachine b.corp login b password p\n",
+        );
+        let hosts: Vec<&str> = rules.iter().map(|r| r.pattern.as_str()).collect();
+        assert_eq!(hosts, ["a.corp", "b.corp"]);
+        assert_eq!(rules[1].basic, Some(("b".to_string(), "p".to_string())));
+    }
+
+    #[test]
+    fn test_host_rule_matching() {
+        let rule = HostRule {
+            pattern: ".corp".to_string(),
+            ..Default::default()
+        };
+        assert!(rule.matches("corp"));
+        assert!(rule.matches("artifacts.corp"));
+        assert!(!rule.matches("notcorp"));
+    }
+}
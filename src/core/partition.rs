@@ -0,0 +1,1055 @@
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+
+use anyhow::{Context, Result, bail};
+
+use super::FileSystemType;
+use super::disk_ops::PartitionSpec;
+
+/// Sector size MBR offsets are counted in
+pub const SECTOR_SIZE: u64 = 512;
+const GPT_SIGNATURE: &[u8; 8] = b"EFI PART";
+const MBR_PROTECTIVE: u8 = 0xee;
+/// Entries in a GPT we create, and the bytes each takes
+const GPT_ENTRIES: usize = 128;
+const GPT_ENTRY_SIZE: usize = 128;
+/// Type GUID of Windows data partitions (FAT, exFAT, NTFS)
+pub const GPT_BASIC_DATA: &str = "EBD0A0A2-B9E5-4433-87C0-68B6B72699C7";
+/// Type GUID of EFI system partitions
+pub const GPT_EFI_SYSTEM: &str = "C12A7328-F81F-11D2-BA4B-00A0C93EC93B";
+/// Type GUID of Linux filesystem partitions
+const GPT_LINUX_DATA: &str = "0FC63DAF-8483-4772-8E79-3D69D8477DE4";
+/// MBR type bytes for the filesystems appended partitions get
+const MBR_LINUX: u8 = 0x83;
+const MBR_FAT32_LBA: u8 = 0x0c;
+const MBR_NTFS_EXFAT: u8 = 0x07;
+/// Logical partitions chained further than this are treated as a loop
+const MAX_LOGICAL_PARTITIONS: u32 = 128;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TableKind {
+    Mbr,
+    Gpt,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Partition {
+    /// 1-based, as the OS numbers them (logical MBR partitions start at 5)
+    pub number: u32,
+    /// Offset from the start of the disk in bytes
+    pub start: u64,
+    pub size: u64,
+    /// MBR type byte in hex (`0c`) or GPT type GUID
+    pub type_id: String,
+    /// GPT partition name; empty for MBR
+    pub name: String,
+}
+
+impl Partition {
+    pub fn end(&self) -> u64 {
+        self.start + self.size
+    }
+}
+
+impl PartitionTable {
+    /// Bytes the backup GPT needs at the end of the disk
+    pub fn gpt_backup_len(&self) -> Option<u64> {
+        (self.kind == TableKind::Gpt)
+            .then(|| {
+                self.reserved
+                    .get(1)
+                    .map(|r| r.end - r.start + self.sector_size)
+            })
+            .flatten()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PartitionTable {
+    pub kind: TableKind,
+    /// Logical block size the table was written for
+    pub sector_size: u64,
+    pub partitions: Vec<Partition>,
+    /// Bytes the table itself occupies: the MBR, GPT headers and entry
+    /// arrays, extended boot records
+    pub reserved: Vec<Range<u64>>,
+}
+
+/// Read the partition table at the start of a disk or image. `disk_size`
+/// locates the backup GPT. Returns `None` when there is no table.
+pub fn read_partition_table<R: Read + Seek>(
+    reader: &mut R,
+    disk_size: u64,
+) -> Result<Option<PartitionTable>> {
+    let mbr = read_at(reader, 0, SECTOR_SIZE as usize)?;
+    if mbr[510..512] != [0x55, 0xaa] {
+        return Ok(None);
+    }
+    let entries = mbr_entries(&mbr);
+    if entries.iter().any(|e| e.kind == MBR_PROTECTIVE) {
+        // 4Kn disks put the GPT header in their second 4096-byte sector
+        for sector_size in [SECTOR_SIZE, 4096] {
+            if let Some(table) = read_gpt(reader, sector_size, disk_size)? {
+                return Ok(Some(table));
+            }
+        }
+    }
+    // A FAT boot sector also ends in 55 aa, with boot code where the entries would be
+    if entries.is_empty()
+        || entries
+            .iter()
+            .any(|e| e.status & 0x7f != 0 || e.start_lba * SECTOR_SIZE >= disk_size)
+    {
+        return Ok(None);
+    }
+
+    let mut table = PartitionTable {
+        kind: TableKind::Mbr,
+        sector_size: SECTOR_SIZE,
+        partitions: Vec::new(),
+        reserved: Vec::new(),
+    };
+    table.reserved.push(0..SECTOR_SIZE);
+    for entry in &entries {
+        if matches!(entry.kind, 0x05 | 0x0f | 0x85) {
+            read_logical_partitions(reader, entry.start_lba, &mut table)?;
+            continue;
+        }
+        table.partitions.push(Partition {
+            number: entry.slot,
+            start: entry.start_lba * SECTOR_SIZE,
+            size: entry.sectors * SECTOR_SIZE,
+            type_id: format!("{:02x}", entry.kind),
+            name: String::new(),
+        });
+    }
+    table.partitions.sort_by_key(|p| p.number);
+    Ok(Some(table))
+}
+
+struct MbrEntry {
+    slot: u32,
+    status: u8,
+    kind: u8,
+    start_lba: u64,
+    sectors: u64,
+}
+
+/// Non-empty entries of an MBR or EBR sector
+fn mbr_entries(sector: &[u8]) -> Vec<MbrEntry> {
+    (0..4)
+        .filter_map(|i| {
+            let e = &sector[446 + i * 16..446 + (i + 1) * 16];
+            let entry = MbrEntry {
+                slot: i as u32 + 1,
+                status: e[0],
+                kind: e[4],
+                start_lba: u64::from(le32(&e[8..])),
+                sectors: u64::from(le32(&e[12..])),
+            };
+            (entry.kind != 0 && entry.sectors != 0).then_some(entry)
+        })
+        .collect()
+}
+
+/// Follow the chain of extended boot records inside an extended partition
+fn read_logical_partitions<R: Read + Seek>(
+    reader: &mut R,
+    extended_lba: u64,
+    table: &mut PartitionTable,
+) -> Result<()> {
+    let mut ebr_lba = extended_lba;
+    for number in 5..5 + MAX_LOGICAL_PARTITIONS {
+        let ebr = read_at(reader, ebr_lba * SECTOR_SIZE, SECTOR_SIZE as usize)?;
+        if ebr[510..512] != [0x55, 0xaa] {
+            break;
+        }
+        table
+            .reserved
+            .push(ebr_lba * SECTOR_SIZE..(ebr_lba + 1) * SECTOR_SIZE);
+        let entries = mbr_entries(&ebr);
+        let Some(logical) = entries.first() else {
+            break;
+        };
+        // Logical partitions start relative to their EBR
+        table.partitions.push(Partition {
+            number,
+            start: (ebr_lba + logical.start_lba) * SECTOR_SIZE,
+            size: logical.sectors * SECTOR_SIZE,
+            type_id: format!("{:02x}", logical.kind),
+            name: String::new(),
+        });
+        // ... and the link to the next EBR relative to the extended partition
+        match entries.get(1) {
+            Some(next) if next.start_lba != 0 => ebr_lba = extended_lba + next.start_lba,
+            _ => break,
+        }
+    }
+    Ok(())
+}
+
+fn read_gpt<R: Read + Seek>(
+    reader: &mut R,
+    sector_size: u64,
+    disk_size: u64,
+) -> Result<Option<PartitionTable>> {
+    let header = read_at(reader, sector_size, 92)?;
+    if &header[..8] != GPT_SIGNATURE {
+        return Ok(None);
+    }
+    let entries_lba = le64(&header[72..]);
+    let entry_count = le32(&header[80..]).min(1024) as usize;
+    let entry_size = le32(&header[84..]) as usize;
+    if !(128..=4096).contains(&entry_size) {
+        return Ok(None);
+    }
+    let entries_len = entry_count * entry_size;
+    let entries = read_at(reader, entries_lba * sector_size, entries_len)?;
+
+    let entries_bytes = (entries_len as u64).div_ceil(sector_size) * sector_size;
+    let mut reserved = vec![
+        0..sector_size * 2,
+        entries_lba * sector_size..entries_lba * sector_size + entries_bytes,
+    ];
+    // Backup entries and header sit at the very end of the disk
+    if disk_size > entries_bytes + sector_size {
+        reserved.push(disk_size - entries_bytes - sector_size..disk_size);
+    }
+
+    let partitions = entries
+        .chunks_exact(entry_size)
+        .enumerate()
+        .filter(|(_, e)| e[..16].iter().any(|b| *b != 0))
+        .map(|(i, e)| {
+            let first = le64(&e[32..]);
+            let last = le64(&e[40..]);
+            let name: Vec<u16> = e[56..128]
+                .chunks_exact(2)
+                .map(|c| u16::from_le_bytes([c[0], c[1]]))
+                .take_while(|c| *c != 0)
+                .collect();
+            Partition {
+                number: i as u32 + 1,
+                start: first * sector_size,
+                size: (last + 1).saturating_sub(first) * sector_size,
+                type_id: format_guid(&e[..16]),
+                name: String::from_utf16_lossy(&name),
+            }
+        })
+        .collect();
+
+    Ok(Some(PartitionTable {
+        kind: TableKind::Gpt,
+        sector_size,
+        partitions,
+        reserved,
+    }))
+}
+
+/// Rewrite both GPT headers for a disk that is now `disk_size` bytes,
+/// moving the backup table to the new end. `resize` sets partition
+/// `number` to end at a byte offset first.
+pub fn update_gpt<F: Read + Write + Seek>(
+    file: &mut F,
+    disk_size: u64,
+    resize: Option<(u32, u64)>,
+) -> Result<()> {
+    rewrite_gpt(file, disk_size, |entries, layout| {
+        if let Some((number, end)) = resize {
+            let entry = layout.entry(entries, number)?;
+            entry[40..48].copy_from_slice(&(end.div_ceil(layout.sector_size) - 1).to_le_bytes());
+        }
+        Ok(())
+    })
+}
+
+/// Unused space after an image of `image_size` bytes written to a device
+/// of `disk_size` bytes, as a MiB-aligned byte range past every partition
+/// in the image's table and clear of a backup GPT at the end of the disk.
+pub fn free_space_after<R: Read + Seek>(
+    reader: &mut R,
+    disk_size: u64,
+    image_size: u64,
+) -> Result<Range<u64>> {
+    const ALIGN: u64 = 1024 * 1024;
+    let used = read_partition_table(reader, disk_size)?
+        .iter()
+        .flat_map(|t| &t.partitions)
+        .map(Partition::end)
+        .fold(image_size, u64::max);
+    let start = used.div_ceil(ALIGN) * ALIGN;
+    let end = disk_size.saturating_sub(33 * SECTOR_SIZE) / ALIGN * ALIGN;
+    Ok(start..end.max(start))
+}
+
+/// Add a partition of `size` bytes (or up to the end of the disk) at byte
+/// `start` to the table on a device, in the first free slot, typed for
+/// `fs_type`. A GPT's backup moves to the end of the disk first. Returns
+/// the new partition's number.
+pub fn append_partition(
+    device_path: &str,
+    start: u64,
+    size: Option<u64>,
+    fs_type: FileSystemType,
+    name: &str,
+) -> Result<u32> {
+    let mut device = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(device_path)
+        .with_context(|| format!("Failed to open device {}", device_path))?;
+    let disk_size = device.seek(SeekFrom::End(0))?;
+    let Some(table) = read_partition_table(&mut device, disk_size)? else {
+        bail!("No partition table on {}", device_path);
+    };
+
+    let number = match table.kind {
+        TableKind::Gpt => {
+            let mut number = 0;
+            rewrite_gpt(&mut device, disk_size, |entries, layout| {
+                let slot = entries
+                    .chunks_exact(layout.entry_size)
+                    .position(|e| e[..16].iter().all(|b| *b == 0))
+                    .context("The GPT has no free entry")?;
+                number = slot as u32 + 1;
+                let first = start.div_ceil(layout.sector_size);
+                let last = match size {
+                    Some(size) => (start + size) / layout.sector_size - 1,
+                    None => layout.last_usable,
+                };
+                if first > last || last > layout.last_usable {
+                    bail!("No space left after the image");
+                }
+                let type_guid = match fs_type {
+                    FileSystemType::Ext4 => GPT_LINUX_DATA,
+                    _ => GPT_BASIC_DATA,
+                };
+                let entry = layout.entry(entries, number)?;
+                entry[..16].copy_from_slice(&parse_guid(type_guid).unwrap());
+                entry[16..32].copy_from_slice(&random_guid()?);
+                entry[32..40].copy_from_slice(&first.to_le_bytes());
+                entry[40..48].copy_from_slice(&last.to_le_bytes());
+                for (j, unit) in name.encode_utf16().take(36).enumerate() {
+                    entry[56 + j * 2..58 + j * 2].copy_from_slice(&unit.to_le_bytes());
+                }
+                Ok(())
+            })?;
+            number
+        }
+        TableKind::Mbr => {
+            let mut mbr = read_at(&mut device, 0, SECTOR_SIZE as usize)?;
+            let slot = (0..4)
+                .find(|slot| mbr[446 + slot * 16 + 4] == 0)
+                .context("All four MBR partition slots are taken")?;
+            let first = start.div_ceil(SECTOR_SIZE);
+            let end = match size {
+                Some(size) => (start + size) / SECTOR_SIZE,
+                None => disk_size / SECTOR_SIZE,
+            };
+            let sectors = end
+                .checked_sub(first)
+                .filter(|s| *s > 0 && end <= disk_size / SECTOR_SIZE)
+                .context("No space left after the image")?;
+            let e = 446 + slot * 16;
+            mbr[e] = 0;
+            // CHS can't address this far; LBA-aware readers ignore it
+            mbr[e + 1..e + 4].copy_from_slice(&[0xfe, 0xff, 0xff]);
+            mbr[e + 4] = match fs_type {
+                FileSystemType::Ext4 => MBR_LINUX,
+                FileSystemType::Fat32 => MBR_FAT32_LBA,
+                _ => MBR_NTFS_EXFAT,
+            };
+            mbr[e + 5..e + 8].copy_from_slice(&[0xfe, 0xff, 0xff]);
+            mbr[e + 8..e + 12].copy_from_slice(&(first as u32).to_le_bytes());
+            let sectors = sectors.min(u64::from(u32::MAX)) as u32;
+            mbr[e + 12..e + 16].copy_from_slice(&sectors.to_le_bytes());
+            device.seek(SeekFrom::Start(0))?;
+            device.write_all(&mbr)?;
+            slot as u32 + 1
+        }
+    };
+    device.sync_all().context("Failed to sync device")?;
+    Ok(number)
+}
+
+/// Where a GPT's entries live, for edits made through `rewrite_gpt`
+struct GptLayout {
+    sector_size: u64,
+    entry_size: usize,
+    entry_count: usize,
+    last_usable: u64,
+}
+
+impl GptLayout {
+    fn entry<'a>(&self, entries: &'a mut [u8], number: u32) -> Result<&'a mut [u8]> {
+        let index = (number as usize)
+            .checked_sub(1)
+            .filter(|i| *i < self.entry_count)
+            .with_context(|| format!("No GPT entry {}", number))?;
+        Ok(&mut entries[index * self.entry_size..(index + 1) * self.entry_size])
+    }
+}
+
+/// Extend the last partition up to the end of the disk; for GPT, up to the
+/// backup table, which moves to the real end. Returns the partition at its
+/// new size, or `None` when it can't grow (a logical MBR partition, or no
+/// space after it).
+pub fn grow_last_partition<F: Read + Write + Seek>(
+    file: &mut F,
+    disk_size: u64,
+) -> Result<Option<Partition>> {
+    let Some(table) = read_partition_table(file, disk_size)? else {
+        return Ok(None);
+    };
+    let Some(last) = table.partitions.iter().max_by_key(|p| p.end()).cloned() else {
+        return Ok(None);
+    };
+
+    let new_end = match table.kind {
+        TableKind::Gpt => {
+            let mut new_end = last.end();
+            rewrite_gpt(file, disk_size, |entries, layout| {
+                let end = (layout.last_usable + 1) * layout.sector_size;
+                if end > new_end {
+                    let entry = layout.entry(entries, last.number)?;
+                    entry[40..48].copy_from_slice(&layout.last_usable.to_le_bytes());
+                    new_end = end;
+                }
+                Ok(())
+            })?;
+            new_end
+        }
+        TableKind::Mbr => {
+            if last.number > 4 {
+                return Ok(None);
+            }
+            let limit = (u64::from(u32::MAX) + last.start / SECTOR_SIZE) * SECTOR_SIZE;
+            let end = disk_size.min(limit) / SECTOR_SIZE * SECTOR_SIZE;
+            if end > last.end() {
+                let sectors = ((end - last.start) / SECTOR_SIZE) as u32;
+                file.seek(SeekFrom::Start(446 + u64::from(last.number - 1) * 16 + 12))?;
+                file.write_all(&sectors.to_le_bytes())?;
+                file.flush()?;
+            }
+            end
+        }
+    };
+    if new_end <= last.end() {
+        return Ok(None);
+    }
+    Ok(Some(Partition {
+        size: new_end - last.start,
+        ..last
+    }))
+}
+
+/// Let `edit` change the partition entries, then write both tables for a
+/// disk of `disk_size` bytes with the backup at its end
+fn rewrite_gpt<F: Read + Write + Seek>(
+    file: &mut F,
+    disk_size: u64,
+    edit: impl FnOnce(&mut [u8], &GptLayout) -> Result<()>,
+) -> Result<()> {
+    let mut found = None;
+    for sector_size in [SECTOR_SIZE, 4096] {
+        let header = read_at(file, sector_size, 512)?;
+        if &header[..8] == GPT_SIGNATURE {
+            found = Some((sector_size, header));
+            break;
+        }
+    }
+    let Some((sector_size, header)) = found else {
+        bail!("No GPT header found");
+    };
+    let header_size = le32(&header[12..]) as usize;
+    if !(92..=512).contains(&header_size) {
+        bail!("Unsupported GPT header size {}", header_size);
+    }
+    let mut header = header[..header_size].to_vec();
+    let old_backup_lba = le64(&header[32..]);
+    let entries_lba = le64(&header[72..]);
+    let entry_count = le32(&header[80..]) as usize;
+    let entry_size = le32(&header[84..]) as usize;
+    if !(128..=4096).contains(&entry_size) || entry_count > 1024 {
+        bail!("Unsupported GPT entry layout");
+    }
+    let mut entries = read_at(file, entries_lba * sector_size, entry_count * entry_size)?;
+
+    let last_lba = disk_size / sector_size - 1;
+    let entries_sectors = (entries.len() as u64).div_ceil(sector_size);
+    let backup_entries_lba = last_lba - entries_sectors;
+    let last_usable = backup_entries_lba - 1;
+
+    let layout = GptLayout {
+        sector_size,
+        entry_size,
+        entry_count,
+        last_usable,
+    };
+    edit(&mut entries, &layout)?;
+    for (i, entry) in entries.chunks_exact(entry_size).enumerate() {
+        if entry[..16].iter().any(|b| *b != 0) && le64(&entry[40..]) > last_usable {
+            bail!("Partition {} runs past the end of the disk", i + 1);
+        }
+    }
+
+    header[32..40].copy_from_slice(&last_lba.to_le_bytes());
+    header[48..56].copy_from_slice(&last_usable.to_le_bytes());
+    header[88..92].copy_from_slice(&crc32fast::hash(&entries).to_le_bytes());
+    write_gpt_header(file, sector_size, &mut header)?;
+    file.seek(SeekFrom::Start(entries_lba * sector_size))?;
+    file.write_all(&entries)?;
+
+    // The backup header points back at the primary and at its own entries
+    header[24..32].copy_from_slice(&last_lba.to_le_bytes());
+    header[32..40].copy_from_slice(&1u64.to_le_bytes());
+    header[72..80].copy_from_slice(&backup_entries_lba.to_le_bytes());
+    file.seek(SeekFrom::Start(backup_entries_lba * sector_size))?;
+    file.write_all(&entries)?;
+    write_gpt_header(file, last_lba * sector_size, &mut header)?;
+
+    // A stale backup header left mid-disk would confuse repair tools
+    if old_backup_lba > 1 && old_backup_lba < backup_entries_lba {
+        file.seek(SeekFrom::Start(old_backup_lba * sector_size))?;
+        file.write_all(&vec![0; sector_size as usize])?;
+    }
+
+    let mut mbr = read_at(file, 0, SECTOR_SIZE as usize)?;
+    for slot in 0..4 {
+        let e = 446 + slot * 16;
+        if mbr[e + 4] == MBR_PROTECTIVE {
+            let sectors = last_lba.min(u64::from(u32::MAX)) as u32;
+            mbr[e + 12..e + 16].copy_from_slice(&sectors.to_le_bytes());
+        }
+    }
+    file.seek(SeekFrom::Start(0))?;
+    file.write_all(&mbr)?;
+    file.flush()?;
+    Ok(())
+}
+
+/// A partition for `build_gpt`, in bytes from the start of the disk
+#[derive(Debug, Clone, PartialEq)]
+pub struct NewPartition<'a> {
+    pub start: u64,
+    pub size: u64,
+    pub type_guid: &'a str,
+    pub name: &'a str,
+}
+
+/// A fresh GPT for a disk of `disk_size` bytes with 512-byte sectors: the
+/// protective MBR, both headers and both entry arrays, as (offset, bytes)
+/// to write
+pub fn build_gpt(disk_size: u64, partitions: &[NewPartition]) -> Result<Vec<(u64, Vec<u8>)>> {
+    let entries_sectors = (GPT_ENTRIES * GPT_ENTRY_SIZE) as u64 / SECTOR_SIZE;
+    let first_usable = 2 + entries_sectors;
+    let Some(last_lba) = (disk_size / SECTOR_SIZE).checked_sub(1) else {
+        bail!("Disk is too small for a GPT");
+    };
+    let backup_entries_lba = last_lba - entries_sectors;
+    let last_usable = backup_entries_lba - 1;
+    if partitions.len() > GPT_ENTRIES || last_usable < first_usable {
+        bail!("Disk is too small for a GPT");
+    }
+
+    let mut entries = vec![0u8; GPT_ENTRIES * GPT_ENTRY_SIZE];
+    for (i, p) in partitions.iter().enumerate() {
+        let first = p.start / SECTOR_SIZE;
+        let last = (p.start + p.size) / SECTOR_SIZE - 1;
+        if p.start % SECTOR_SIZE != 0 || p.size % SECTOR_SIZE != 0 || p.size == 0 {
+            bail!("Partition {} is not sector-aligned", i + 1);
+        }
+        if first < first_usable || last > last_usable {
+            bail!("Partition {} doesn't fit on the disk", i + 1);
+        }
+        let type_guid =
+            parse_guid(p.type_guid).with_context(|| format!("Bad GUID {}", p.type_guid))?;
+        let entry = &mut entries[i * GPT_ENTRY_SIZE..(i + 1) * GPT_ENTRY_SIZE];
+        entry[..16].copy_from_slice(&type_guid);
+        entry[16..32].copy_from_slice(&random_guid()?);
+        entry[32..40].copy_from_slice(&first.to_le_bytes());
+        entry[40..48].copy_from_slice(&last.to_le_bytes());
+        for (j, unit) in p.name.encode_utf16().take(36).enumerate() {
+            entry[56 + j * 2..58 + j * 2].copy_from_slice(&unit.to_le_bytes());
+        }
+    }
+
+    let mut header = vec![0u8; 92];
+    header[..8].copy_from_slice(GPT_SIGNATURE);
+    header[8..12].copy_from_slice(&0x0001_0000u32.to_le_bytes());
+    header[12..16].copy_from_slice(&92u32.to_le_bytes());
+    header[24..32].copy_from_slice(&1u64.to_le_bytes());
+    header[32..40].copy_from_slice(&last_lba.to_le_bytes());
+    header[40..48].copy_from_slice(&first_usable.to_le_bytes());
+    header[48..56].copy_from_slice(&last_usable.to_le_bytes());
+    header[56..72].copy_from_slice(&random_guid()?);
+    header[72..80].copy_from_slice(&2u64.to_le_bytes());
+    header[80..84].copy_from_slice(&(GPT_ENTRIES as u32).to_le_bytes());
+    header[84..88].copy_from_slice(&(GPT_ENTRY_SIZE as u32).to_le_bytes());
+    header[88..92].copy_from_slice(&crc32fast::hash(&entries).to_le_bytes());
+    let mut backup = header.clone();
+    backup[24..32].copy_from_slice(&last_lba.to_le_bytes());
+    backup[32..40].copy_from_slice(&1u64.to_le_bytes());
+    backup[72..80].copy_from_slice(&backup_entries_lba.to_le_bytes());
+    seal_gpt_header(&mut header);
+    seal_gpt_header(&mut backup);
+
+    let mut mbr = vec![0u8; SECTOR_SIZE as usize];
+    let e = 446;
+    mbr[e + 1..e + 4].copy_from_slice(&[0x00, 0x02, 0x00]);
+    mbr[e + 4] = MBR_PROTECTIVE;
+    mbr[e + 5..e + 8].copy_from_slice(&[0xff, 0xff, 0xff]);
+    mbr[e + 8..e + 12].copy_from_slice(&1u32.to_le_bytes());
+    let sectors = last_lba.min(u64::from(u32::MAX)) as u32;
+    mbr[e + 12..e + 16].copy_from_slice(&sectors.to_le_bytes());
+    mbr[510..512].copy_from_slice(&[0x55, 0xaa]);
+
+    Ok(vec![
+        (0, mbr),
+        (SECTOR_SIZE, header),
+        (2 * SECTOR_SIZE, entries.clone()),
+        (backup_entries_lba * SECTOR_SIZE, entries),
+        (last_lba * SECTOR_SIZE, backup),
+    ])
+}
+
+/// After an image lands on a bigger disk its backup GPT sits mid-disk;
+/// move it to the real end. Returns whether anything changed.
+pub fn relocate_gpt_backup(device_path: &str) -> Result<bool> {
+    let mut device = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(device_path)
+        .with_context(|| format!("Failed to open device {}", device_path))?;
+    let disk_size = device.seek(SeekFrom::End(0))?;
+    let Some(table) = read_partition_table(&mut device, disk_size)? else {
+        return Ok(false);
+    };
+    if table.kind != TableKind::Gpt {
+        return Ok(false);
+    }
+    let header = read_at(&mut device, table.sector_size, 92)?;
+    if le64(&header[32..]) == disk_size / table.sector_size - 1 {
+        return Ok(false);
+    }
+    update_gpt(&mut device, disk_size, None)?;
+    device.sync_all()?;
+    Ok(true)
+}
+
+/// Replace the partition table on a device with a fresh GPT. Partitions
+/// start on MiB boundaries from 1 MiB on; one without a size takes the
+/// rest of the disk.
+pub fn create_gpt(device_path: &str, partitions: &[PartitionSpec]) -> Result<()> {
+    const ALIGN: u64 = 1024 * 1024;
+    let mut device = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(device_path)
+        .with_context(|| format!("Failed to open device {}", device_path))?;
+    let disk_size = device.seek(SeekFrom::End(0))?;
+    // The backup entries and header take the last 33 sectors
+    let end = disk_size.saturating_sub(33 * SECTOR_SIZE) / ALIGN * ALIGN;
+
+    let mut start = ALIGN;
+    let mut layout = Vec::new();
+    for spec in partitions {
+        let size = match spec.size {
+            Some(size) => size.div_ceil(ALIGN) * ALIGN,
+            None => end.saturating_sub(start),
+        };
+        if size == 0 || start + size > end {
+            bail!("{} is too small for this layout", device_path);
+        }
+        layout.push(NewPartition {
+            start,
+            size,
+            type_guid: if spec.efi_system {
+                GPT_EFI_SYSTEM
+            } else {
+                GPT_BASIC_DATA
+            },
+            name: &spec.label,
+        });
+        start += size;
+    }
+
+    for (offset, data) in build_gpt(disk_size, &layout)? {
+        device.seek(SeekFrom::Start(offset))?;
+        device.write_all(&data)?;
+    }
+    device.sync_all().context("Failed to sync device")?;
+    Ok(())
+}
+
+/// Write a GPT header with its CRC recomputed
+fn write_gpt_header<F: Write + Seek>(file: &mut F, offset: u64, header: &mut [u8]) -> Result<()> {
+    seal_gpt_header(header);
+    file.seek(SeekFrom::Start(offset))?;
+    file.write_all(header)?;
+    Ok(())
+}
+
+fn seal_gpt_header(header: &mut [u8]) {
+    header[16..20].fill(0);
+    let crc = crc32fast::hash(header);
+    header[16..20].copy_from_slice(&crc.to_le_bytes());
+}
+
+/// A version 4 GUID, in on-disk byte order
+fn random_guid() -> Result<[u8; 16]> {
+    let mut guid = [0u8; 16];
+    std::fs::File::open("/dev/urandom")
+        .and_then(|mut f| f.read_exact(&mut guid))
+        .context("Failed to read /dev/urandom")?;
+    guid[7] = (guid[7] & 0x0f) | 0x40;
+    guid[8] = (guid[8] & 0x3f) | 0x80;
+    Ok(guid)
+}
+
+/// Inverse of `format_guid`
+pub fn parse_guid(text: &str) -> Option<[u8; 16]> {
+    let hex: String = text.chars().filter(|c| *c != '-').collect();
+    if hex.len() != 32 || text.len() != 36 {
+        return None;
+    }
+    let mut bytes = [0u8; 16];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(hex.get(i * 2..i * 2 + 2)?, 16).ok()?;
+    }
+    // First three groups are stored little-endian
+    bytes[..4].reverse();
+    bytes[4..6].reverse();
+    bytes[6..8].reverse();
+    Some(bytes)
+}
+
+/// GUIDs are stored with their first three groups little-endian
+pub fn format_guid(bytes: &[u8]) -> String {
+    format!(
+        "{:08X}-{:04X}-{:04X}-{:02X}{:02X}-{}",
+        le32(bytes),
+        u16::from_le_bytes([bytes[4], bytes[5]]),
+        u16::from_le_bytes([bytes[6], bytes[7]]),
+        bytes[8],
+        bytes[9],
+        bytes[10..16]
+            .iter()
+            .map(|b| format!("{:02X}", b))
+            .collect::<String>()
+    )
+}
+
+/// Read `len` bytes at `offset`; short reads past the end come back zero-filled
+pub fn read_at<R: Read + Seek>(reader: &mut R, offset: u64, len: usize) -> Result<Vec<u8>> {
+    reader.seek(SeekFrom::Start(offset))?;
+    let mut buf = Vec::with_capacity(len);
+    reader.take(len as u64).read_to_end(&mut buf)?;
+    buf.resize(len, 0);
+    Ok(buf)
+}
+
+pub fn le32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+pub fn le64(bytes: &[u8]) -> u64 {
+    let mut buf = [0u8; 8];
+    buf.copy_from_slice(&bytes[..8]);
+    u64::from_le_bytes(buf)
+}
+
+/// One partition of a device, seen as a file of its own
+pub struct PartitionIo {
+    device: File,
+    start: u64,
+    size: u64,
+    pos: u64,
+}
+
+impl PartitionIo {
+    pub fn new(device: File, partition: &Partition) -> Self {
+        Self {
+            device,
+            start: partition.start,
+            size: partition.size,
+            pos: 0,
+        }
+    }
+
+    fn remaining(&self, len: usize) -> usize {
+        (self.size.saturating_sub(self.pos)).min(len as u64) as usize
+    }
+}
+
+impl Read for PartitionIo {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.remaining(buf.len());
+        self.device.seek(SeekFrom::Start(self.start + self.pos))?;
+        let n = self.device.read(&mut buf[..len])?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl Write for PartitionIo {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = self.remaining(buf.len());
+        if len == 0 && !buf.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::WriteZero,
+                "write past the end of the partition",
+            ));
+        }
+        self.device.seek(SeekFrom::Start(self.start + self.pos))?;
+        let n = self.device.write(&buf[..len])?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.device.flush()
+    }
+}
+
+impl Seek for PartitionIo {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.size.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.pos.checked_add_signed(offset),
+        };
+        self.pos = pos.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "seek before the partition")
+        })?;
+        Ok(self.pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn mbr_entry(disk: &mut [u8], sector: usize, slot: usize, kind: u8, start: u32, len: u32) {
+        let e = sector * 512 + 446 + slot * 16;
+        disk[e + 4] = kind;
+        disk[e + 8..e + 12].copy_from_slice(&start.to_le_bytes());
+        disk[e + 12..e + 16].copy_from_slice(&len.to_le_bytes());
+        disk[sector * 512 + 510..sector * 512 + 512].copy_from_slice(&[0x55, 0xaa]);
+    }
+
+    #[test]
+    fn test_mbr_with_logical_partitions() {
+        let mut disk = vec![0u8; 512 * 64];
+        mbr_entry(&mut disk, 0, 0, 0x0c, 2, 8);
+        mbr_entry(&mut disk, 0, 1, 0x05, 16, 40);
+        // First EBR at 16: logical at +2, next EBR at extended+20
+        mbr_entry(&mut disk, 16, 0, 0x83, 2, 10);
+        mbr_entry(&mut disk, 16, 1, 0x05, 20, 12);
+        mbr_entry(&mut disk, 36, 0, 0x82, 2, 8);
+
+        let table = read_partition_table(&mut Cursor::new(&disk), disk.len() as u64)
+            .unwrap()
+            .unwrap();
+        assert_eq!(table.kind, TableKind::Mbr);
+        let layout: Vec<_> = table
+            .partitions
+            .iter()
+            .map(|p| (p.number, p.start / 512, p.size / 512, p.type_id.as_str()))
+            .collect();
+        assert_eq!(
+            layout,
+            vec![(1, 2, 8, "0c"), (5, 18, 10, "83"), (6, 38, 8, "82")]
+        );
+        assert_eq!(
+            table.reserved,
+            vec![0..512, 16 * 512..17 * 512, 36 * 512..37 * 512]
+        );
+    }
+
+    /// 64 KiB GPT disk with an ESP named "boot" in entry 2, sectors 40-79
+    fn sample_gpt() -> Vec<u8> {
+        let mut disk = vec![0u8; 512 * 128];
+        mbr_entry(&mut disk, 0, 0, MBR_PROTECTIVE, 1, 127);
+        disk[512..520].copy_from_slice(GPT_SIGNATURE);
+        disk[512 + 72..512 + 80].copy_from_slice(&2u64.to_le_bytes());
+        disk[512 + 80..512 + 84].copy_from_slice(&128u32.to_le_bytes());
+        disk[512 + 84..512 + 88].copy_from_slice(&128u32.to_le_bytes());
+        let esp = [
+            0x28, 0x73, 0x2a, 0xc1, 0x1f, 0xf8, 0xd2, 0x11, 0xba, 0x4b, 0x00, 0xa0, 0xc9, 0x3e,
+            0xc9, 0x3b,
+        ];
+        let entry = 1024 + 128;
+        disk[entry..entry + 16].copy_from_slice(&esp);
+        disk[entry + 32..entry + 40].copy_from_slice(&40u64.to_le_bytes());
+        disk[entry + 40..entry + 48].copy_from_slice(&79u64.to_le_bytes());
+        for (i, c) in "boot".encode_utf16().enumerate() {
+            disk[entry + 56 + i * 2..entry + 58 + i * 2].copy_from_slice(&c.to_le_bytes());
+        }
+        disk
+    }
+
+    #[test]
+    fn test_gpt() {
+        let disk = sample_gpt();
+        let table = read_partition_table(&mut Cursor::new(&disk), disk.len() as u64)
+            .unwrap()
+            .unwrap();
+        assert_eq!(table.kind, TableKind::Gpt);
+        assert_eq!(table.partitions.len(), 1);
+        let esp = &table.partitions[0];
+        assert_eq!((esp.number, esp.start, esp.size), (2, 40 * 512, 40 * 512));
+        assert_eq!(esp.type_id, "C12A7328-F81F-11D2-BA4B-00A0C93EC93B");
+        assert_eq!(esp.name, "boot");
+        assert_eq!(
+            table.reserved[2],
+            disk.len() as u64 - 33 * 512..disk.len() as u64
+        );
+
+        assert_eq!(
+            read_partition_table(&mut Cursor::new(vec![0u8; 1024]), 1024).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_relocate_gpt_backup() {
+        // As if the disk had been cloned onto one 64 sectors bigger
+        let mut disk = sample_gpt();
+        disk[512 + 12..512 + 16].copy_from_slice(&92u32.to_le_bytes());
+        disk[512 + 32..512 + 40].copy_from_slice(&127u64.to_le_bytes());
+        disk.resize(512 * 192, 0);
+        let path = std::env::temp_dir().join(format!("pervie-gpt-{}.img", std::process::id()));
+        std::fs::write(&path, &disk).unwrap();
+        let device = path.to_string_lossy();
+
+        assert!(relocate_gpt_backup(&device).unwrap());
+        assert!(!relocate_gpt_backup(&device).unwrap());
+        let disk = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let backup = &disk[191 * 512..191 * 512 + 92];
+        assert_eq!(&backup[..8], GPT_SIGNATURE);
+        assert_eq!((le64(&backup[24..]), le64(&backup[32..])), (191, 1));
+        assert_eq!(le64(&backup[72..]), 191 - 32);
+        let mut check = backup.to_vec();
+        check[16..20].fill(0);
+        assert_eq!(crc32fast::hash(&check), le32(&backup[16..]));
+        assert_eq!(
+            disk[159 * 512 + 128..159 * 512 + 256],
+            disk[1024 + 128..1024 + 256]
+        );
+        assert_eq!(le32(&disk[446 + 12..]), 191);
+        let table = read_partition_table(&mut Cursor::new(&disk), disk.len() as u64)
+            .unwrap()
+            .unwrap();
+        assert_eq!(table.partitions[0].start, 40 * 512);
+    }
+
+    #[test]
+    fn test_grow_last_partition() {
+        let mut gpt = sample_gpt();
+        gpt[512 + 12..512 + 16].copy_from_slice(&92u32.to_le_bytes());
+        gpt[512 + 32..512 + 40].copy_from_slice(&127u64.to_le_bytes());
+        gpt.resize(512 * 256, 0);
+        let mut mbr = vec![0u8; 512 * 256];
+        mbr_entry(&mut mbr, 0, 0, 0x0c, 8, 32);
+        mbr_entry(&mut mbr, 0, 1, 0x83, 64, 64);
+
+        let mut ends = Vec::new();
+        for disk in [gpt, mbr] {
+            let mut disk = Cursor::new(disk);
+            let grown = grow_last_partition(&mut disk, 256 * 512).unwrap().unwrap();
+            // Already as big as it gets
+            assert!(grow_last_partition(&mut disk, 256 * 512).unwrap().is_none());
+            let table = read_partition_table(&mut disk, 256 * 512).unwrap().unwrap();
+            let last = table.partitions.iter().max_by_key(|p| p.end()).unwrap();
+            assert_eq!(last, &grown);
+            ends.push((grown.number, grown.start / 512, grown.end() / 512));
+        }
+        assert_eq!(ends, vec![(2, 40, 256 - 33), (2, 64, 256)]);
+    }
+
+    #[test]
+    fn test_append_partition() {
+        // A hybrid image flashed onto a bigger disk: the GPT's backup sits mid-disk
+        let mut gpt = sample_gpt();
+        gpt[512 + 12..512 + 16].copy_from_slice(&92u32.to_le_bytes());
+        gpt[512 + 32..512 + 40].copy_from_slice(&127u64.to_le_bytes());
+        gpt.resize(512 * 256, 0);
+        let mut mbr = vec![0u8; 512 * 256];
+        mbr_entry(&mut mbr, 0, 0, 0x17, 0, 64);
+        mbr_entry(&mut mbr, 0, 1, 0xef, 64, 16);
+
+        let path = std::env::temp_dir().join(format!("pervie-append-{}.img", std::process::id()));
+        let device = path.to_string_lossy();
+        let mut layouts = Vec::new();
+        for disk in [gpt, mbr] {
+            std::fs::write(&path, &disk).unwrap();
+            let number =
+                append_partition(&device, 128 * 512, None, FileSystemType::Ext4, "casper-rw")
+                    .unwrap();
+            let disk = std::fs::read(&path).unwrap();
+            let table = read_partition_table(&mut Cursor::new(&disk), disk.len() as u64)
+                .unwrap()
+                .unwrap();
+            let added = table
+                .partitions
+                .iter()
+                .find(|p| p.number == number)
+                .unwrap();
+            layouts.push((
+                number,
+                added.start / 512,
+                added.end() / 512,
+                added.name.clone(),
+            ));
+        }
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            layouts,
+            vec![
+                (1, 128, 256 - 33, "casper-rw".to_string()),
+                (3, 128, 256, String::new())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_create_gpt() {
+        let size = 16 * 1024 * 1024u64;
+        let path = std::env::temp_dir().join(format!("pervie-new-gpt-{}.img", std::process::id()));
+        std::fs::write(&path, vec![0u8; size as usize]).unwrap();
+        let spec = |size, efi_system| PartitionSpec {
+            fs_type: crate::core::FileSystemType::Fat32,
+            label: "P".to_string(),
+            size,
+            efi_system,
+        };
+        let device = path.to_string_lossy();
+        create_gpt(
+            &device,
+            &[spec(Some(3 * 1024 * 1024 + 1), true), spec(None, false)],
+        )
+        .unwrap();
+        assert!(create_gpt(&device, &[spec(Some(size), false)]).is_err());
+        let disk = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let table = read_partition_table(&mut Cursor::new(&disk), size)
+            .unwrap()
+            .unwrap();
+        let layout: Vec<(u64, u64, &str)> = table
+            .partitions
+            .iter()
+            .map(|p| (p.start >> 20, p.size >> 20, p.type_id.as_str()))
+            .collect();
+        assert_eq!(
+            layout,
+            vec![(1, 4, GPT_EFI_SYSTEM), (5, 10, GPT_BASIC_DATA)]
+        );
+    }
+}
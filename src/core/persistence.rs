@@ -0,0 +1,116 @@
+use std::fs::{self, File};
+use std::io::{Seek, SeekFrom};
+use std::path::Path;
+
+use anyhow::{Context, Result};
+
+use super::partition;
+use super::{FileSystemType, isofs};
+
+/// Less room than this isn't worth a partition
+pub const MIN_SIZE: u64 = 256 * 1024 * 1024;
+/// Keeps every change to the root filesystem, as live-boot expects
+const PERSISTENCE_CONF: &str = "/ union\n";
+
+/// Which live system is on the stick, and so how it finds its persistence
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LiveFlavor {
+    /// Ubuntu and its flavours
+    Casper,
+    /// Debian live images and their derivatives
+    LiveBoot,
+}
+
+impl LiveFlavor {
+    /// Filesystem label the live system looks for
+    pub fn label(&self) -> &'static str {
+        match self {
+            LiveFlavor::Casper => "casper-rw",
+            LiveFlavor::LiveBoot => "persistence",
+        }
+    }
+
+    /// Kernel option that turns persistence on
+    pub fn boot_option(&self) -> &'static str {
+        match self {
+            LiveFlavor::Casper => "persistent",
+            LiveFlavor::LiveBoot => "persistence",
+        }
+    }
+
+    pub fn display_name(&self) -> &'static str {
+        match self {
+            LiveFlavor::Casper => "Ubuntu live (casper)",
+            LiveFlavor::LiveBoot => "Debian live (live-boot)",
+        }
+    }
+}
+
+/// Space left after a freshly flashed live image
+#[derive(Debug, Clone, PartialEq)]
+pub struct PersistenceOffer {
+    pub device_path: String,
+    pub flavor: LiveFlavor,
+    /// Byte range the new partition would take
+    pub start: u64,
+    pub size: u64,
+    /// What the flash did, reported whether or not the offer is taken
+    pub flashed: String,
+}
+
+/// Look at a device just flashed with an image of `image_size` bytes:
+/// a live ISO with at least `MIN_SIZE` after it can get persistence
+pub fn detect(device_path: &str, image_size: u64) -> Result<Option<PersistenceOffer>> {
+    let mut device =
+        File::open(device_path).with_context(|| format!("Failed to open {}", device_path))?;
+    let disk_size = device.seek(SeekFrom::End(0))?;
+
+    let Ok((_, entries)) = isofs::read_tree(&mut device) else {
+        return Ok(None);
+    };
+    let is_dir = |path| isofs::find(&entries, path).is_some_and(|e| e.is_dir);
+    let flavor = if is_dir("casper") {
+        LiveFlavor::Casper
+    } else if is_dir("live") {
+        LiveFlavor::LiveBoot
+    } else {
+        return Ok(None);
+    };
+
+    // Hybrid ISOs carry their own table; the new partition goes after it all
+    if partition::read_partition_table(&mut device, disk_size)?.is_none() {
+        return Ok(None);
+    }
+    let free = partition::free_space_after(&mut device, disk_size, image_size)?;
+    if free.end - free.start < MIN_SIZE {
+        return Ok(None);
+    }
+
+    Ok(Some(PersistenceOffer {
+        device_path: device_path.to_string(),
+        flavor,
+        start: free.start,
+        size: free.end - free.start,
+        flashed: String::new(),
+    }))
+}
+
+/// Add the persistence partition to the image's table. Returns its number.
+pub fn add_partition(offer: &PersistenceOffer) -> Result<u32> {
+    partition::append_partition(
+        &offer.device_path,
+        offer.start,
+        None,
+        FileSystemType::Ext4,
+        offer.flavor.label(),
+    )
+}
+
+/// live-boot only uses a persistence partition that says what to keep
+pub fn write_conf(flavor: LiveFlavor, mount_point: &Path) -> Result<()> {
+    if flavor == LiveFlavor::LiveBoot {
+        fs::write(mount_point.join("persistence.conf"), PERSISTENCE_CONF)
+            .context("Failed to write persistence.conf")?;
+    }
+    Ok(())
+}
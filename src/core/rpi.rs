@@ -0,0 +1,525 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+
+use anyhow::{Context, Result, anyhow};
+use fatfs::{FileSystem, FsOptions, ReadWriteSeek};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+
+use super::partition::{self, Partition, PartitionIo};
+
+const PRESETS_FILE: &str = "rpi_presets.json";
+/// Every Raspberry Pi OS image says where it came from here
+const ISSUE_FILE: &str = "issue.txt";
+const ISSUE_MARKER: &str = "Raspberry Pi reference";
+/// Only Bookworm and later boot the Pi 5, and they mount the boot
+/// partition at /boot/firmware instead of /boot
+const PI5_DTB: &str = "bcm2712-rpi-5-b.dtb";
+const FIRSTRUN: &str = "firstrun.sh";
+const IMAGER_CUSTOM: &str = "/usr/lib/raspberrypi-sys-mods/imager_custom";
+/// Rounds /etc/shadow uses by default
+const CRYPT_ROUNDS: usize = 5000;
+
+/// Headless setup for a Raspberry Pi OS image, as Raspberry Pi Imager's
+/// OS customisation writes it. Saved as a preset without any plaintext.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PiSettings {
+    /// Preset name
+    pub name: String,
+    pub hostname: String,
+    pub username: String,
+    /// As typed; only its hash is written or saved
+    #[serde(skip)]
+    pub password: String,
+    /// SHA-512 crypt hash, as in /etc/shadow
+    pub password_hash: String,
+    pub ssh: bool,
+    /// A public key, or the path of a `.pub` file to read it from
+    pub authorized_key: String,
+    pub wifi_ssid: String,
+    /// As typed; only the derived PSK is written or saved
+    #[serde(skip)]
+    pub wifi_password: String,
+    /// WPA PSK derived from the passphrase and SSID, in hex
+    pub wifi_psk: String,
+    /// Two-letter regulatory country, e.g. `GB`
+    pub wifi_country: String,
+}
+
+/// Fields of the settings form, in order
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PiField {
+    Preset,
+    Hostname,
+    Username,
+    Password,
+    Ssh,
+    AuthorizedKey,
+    WifiSsid,
+    WifiPassword,
+    WifiCountry,
+}
+
+impl PiField {
+    pub const ALL: [PiField; 9] = [
+        PiField::Preset,
+        PiField::Hostname,
+        PiField::Username,
+        PiField::Password,
+        PiField::Ssh,
+        PiField::AuthorizedKey,
+        PiField::WifiSsid,
+        PiField::WifiPassword,
+        PiField::WifiCountry,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            PiField::Preset => "Preset name",
+            PiField::Hostname => "Hostname",
+            PiField::Username => "Username",
+            PiField::Password => "Password",
+            PiField::Ssh => "Enable SSH",
+            PiField::AuthorizedKey => "SSH public key",
+            PiField::WifiSsid => "Wi-Fi SSID",
+            PiField::WifiPassword => "Wi-Fi password",
+            PiField::WifiCountry => "Wi-Fi country",
+        }
+    }
+}
+
+impl PiSettings {
+    /// Text of a form field; `None` for the SSH switch
+    pub fn text_mut(&mut self, field: PiField) -> Option<&mut String> {
+        match field {
+            PiField::Preset => Some(&mut self.name),
+            PiField::Hostname => Some(&mut self.hostname),
+            PiField::Username => Some(&mut self.username),
+            PiField::Password => Some(&mut self.password),
+            PiField::Ssh => None,
+            PiField::AuthorizedKey => Some(&mut self.authorized_key),
+            PiField::WifiSsid => Some(&mut self.wifi_ssid),
+            PiField::WifiPassword => Some(&mut self.wifi_password),
+            PiField::WifiCountry => Some(&mut self.wifi_country),
+        }
+    }
+
+    /// Why the settings can't be written, if they can't
+    pub fn validate(&self) -> Result<(), String> {
+        let hostname = &self.hostname;
+        if !hostname.is_empty()
+            && (hostname.len() > 63
+                || hostname.starts_with('-')
+                || !hostname
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-'))
+        {
+            return Err("Hostname may only use letters, digits and -".to_string());
+        }
+        let has_password = !self.password.is_empty() || !self.password_hash.is_empty();
+        if self.username.is_empty() && has_password {
+            return Err("A password needs a username".to_string());
+        }
+        if !self.username.is_empty() {
+            if !has_password {
+                return Err("The user needs a password".to_string());
+            }
+            if !self
+                .username
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_')
+                || self.username.starts_with(|c: char| !c.is_ascii_lowercase())
+            {
+                return Err("Usernames are lowercase letters, digits, - and _".to_string());
+            }
+        }
+        if !self.wifi_ssid.is_empty() {
+            let len = self.wifi_password.len();
+            if self.wifi_psk.is_empty() && !(8..=63).contains(&len) {
+                return Err("Wi-Fi passwords are 8 to 63 characters".to_string());
+            }
+            if self.wifi_country.len() != 2
+                || !self.wifi_country.chars().all(|c| c.is_ascii_uppercase())
+            {
+                return Err("Wi-Fi country is a two-letter code such as GB".to_string());
+            }
+        }
+        Ok(())
+    }
+
+    /// Replace the typed passwords with their hashes and read the public
+    /// key file, so the settings can be saved or written
+    pub fn seal(&mut self) -> Result<()> {
+        if !self.password.is_empty() {
+            let params = sha_crypt::Sha512Params::new(CRYPT_ROUNDS)
+                .map_err(|e| anyhow!("Invalid crypt rounds: {:?}", e))?;
+            self.password_hash = sha_crypt::sha512_simple(&self.password, &params)
+                .map_err(|e| anyhow!("Failed to hash the password: {:?}", e))?;
+            self.password.clear();
+        }
+        if !self.wifi_password.is_empty() {
+            self.wifi_psk = wpa_psk(&self.wifi_ssid, &self.wifi_password);
+            self.wifi_password.clear();
+        }
+        let key = self.authorized_key.trim();
+        if !key.is_empty() && !is_public_key(key) {
+            let path = crate::utils::expand_home(key);
+            self.authorized_key = fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read {}", path.display()))?
+                .trim()
+                .to_string();
+        }
+        Ok(())
+    }
+}
+
+/// Saved settings, most recently saved first
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PiPresets {
+    pub presets: Vec<PiSettings>,
+}
+
+impl PiPresets {
+    /// Load presets from the config directory. Missing or unreadable files yield none.
+    pub fn load() -> Self {
+        Self::path()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) -> std::io::Result<()> {
+        let Some(path) = Self::path() else {
+            return Ok(());
+        };
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let contents = serde_json::to_string_pretty(self).map_err(std::io::Error::other)?;
+        fs::write(path, contents)
+    }
+
+    /// Store sealed `settings` under their name, replacing a preset of the same name
+    pub fn push(&mut self, settings: &PiSettings) {
+        self.presets.retain(|preset| preset.name != settings.name);
+        self.presets.insert(0, settings.clone());
+    }
+
+    fn path() -> Option<PathBuf> {
+        crate::utils::config_dir().map(|dir| dir.join(PRESETS_FILE))
+    }
+}
+
+/// The boot partition of a freshly flashed Raspberry Pi OS image
+#[derive(Debug, Clone, PartialEq)]
+pub struct PiBoot {
+    pub device_path: String,
+    pub partition: Partition,
+    /// What the flash did, reported whether or not the setup is written
+    pub flashed: String,
+}
+
+/// Look for Raspberry Pi OS on a device just flashed
+pub fn detect(device_path: &str) -> Result<Option<PiBoot>> {
+    let mut device =
+        File::open(device_path).with_context(|| format!("Failed to open {}", device_path))?;
+    let disk_size = device.seek(SeekFrom::End(0))?;
+    let Some(table) = partition::read_partition_table(&mut device, disk_size)? else {
+        return Ok(None);
+    };
+    // The boot partition is FAT and comes first
+    let Some(boot) = table.partitions.into_iter().next() else {
+        return Ok(None);
+    };
+    if !matches!(boot.type_id.as_str(), "0b" | "0c" | "0e") {
+        return Ok(None);
+    }
+
+    let slice = PartitionIo::new(device, &boot);
+    let Ok(fs) = FileSystem::new(slice, FsOptions::new()) else {
+        return Ok(None);
+    };
+    let mut issue = String::new();
+    let is_pi_os = fs
+        .root_dir()
+        .open_file(ISSUE_FILE)
+        .and_then(|mut file| file.read_to_string(&mut issue))
+        .is_ok()
+        && issue.contains(ISSUE_MARKER);
+    if !is_pi_os {
+        return Ok(None);
+    }
+
+    Ok(Some(PiBoot {
+        device_path: device_path.to_string(),
+        partition: boot,
+        flashed: String::new(),
+    }))
+}
+
+/// Write sealed `settings` onto the boot partition. Returns the files written.
+pub fn apply(boot: &PiBoot, settings: &PiSettings) -> Result<Vec<&'static str>> {
+    let device = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(&boot.device_path)
+        .with_context(|| format!("Failed to open {}", boot.device_path))?;
+    let fs = FileSystem::new(PartitionIo::new(device, &boot.partition), FsOptions::new())
+        .context("Failed to open the boot partition")?;
+    let written = write_boot_files(&fs, settings)?;
+    fs.unmount().context("Failed to flush the boot partition")?;
+    Ok(written)
+}
+
+fn write_boot_files<T: ReadWriteSeek>(
+    fs: &FileSystem<T>,
+    settings: &PiSettings,
+) -> Result<Vec<&'static str>> {
+    let root = fs.root_dir();
+    let mut written = Vec::new();
+    let write = |name: &str, contents: &str| -> Result<()> {
+        let mut file = root
+            .create_file(name)
+            .with_context(|| format!("Failed to create {}", name))?;
+        file.truncate()?;
+        file.write_all(contents.as_bytes())
+            .with_context(|| format!("Failed to write {}", name))
+    };
+
+    if settings.ssh {
+        write("ssh", "")?;
+        written.push("ssh");
+    }
+    if !settings.username.is_empty() {
+        write(
+            "userconf.txt",
+            &format!("{}:{}\n", settings.username, settings.password_hash),
+        )?;
+        written.push("userconf.txt");
+    }
+
+    let boot_dir = if root.open_file(PI5_DTB).is_ok() {
+        "/boot/firmware"
+    } else {
+        "/boot"
+    };
+    let Some(script) = firstrun_script(settings, boot_dir) else {
+        return Ok(written);
+    };
+    write(FIRSTRUN, &script)?;
+    written.push(FIRSTRUN);
+
+    let mut cmdline = String::new();
+    root.open_file("cmdline.txt")
+        .and_then(|mut file| file.read_to_string(&mut cmdline))
+        .context("Failed to read cmdline.txt")?;
+    let mut cmdline = cmdline.trim_end().to_string();
+    if !cmdline.contains("systemd.run=") {
+        cmdline.push_str(&format!(
+            " systemd.run={}/{} systemd.run_success_action=reboot systemd.unit=kernel-command-line.target",
+            boot_dir, FIRSTRUN
+        ));
+    }
+    cmdline.push('\n');
+    write("cmdline.txt", &cmdline)?;
+    written.push("cmdline.txt");
+    Ok(written)
+}
+
+/// One-shot script run at first boot for what has no file of its own:
+/// hostname, authorized keys and Wi-Fi. Uses the image's `imager_custom`
+/// helper when it has one, as Imager's script does.
+fn firstrun_script(settings: &PiSettings, boot_dir: &str) -> Option<String> {
+    let key = settings.authorized_key.trim();
+    if settings.hostname.is_empty() && key.is_empty() && settings.wifi_ssid.is_empty() {
+        return None;
+    }
+
+    let mut script = String::from("#!/bin/bash\n\nset +e\n\n");
+    if !settings.hostname.is_empty() {
+        let hostname = &settings.hostname;
+        script.push_str(&format!(
+            r#"CURRENT_HOSTNAME=$(tr -d " \t\n\r" </etc/hostname)
+if [ -f {helper} ]; then
+   {helper} set_hostname {hostname}
+else
+   echo {hostname} >/etc/hostname
+   sed -i "s/127.0.1.1.*$CURRENT_HOSTNAME/127.0.1.1\t{hostname}/g" /etc/hosts
+fi
+
+"#,
+            helper = IMAGER_CUSTOM,
+        ));
+    }
+    if !key.is_empty() {
+        let key = shell_quote(key);
+        script.push_str(&format!(
+            r#"FIRSTUSER=$(getent passwd 1000 | cut -d: -f1)
+FIRSTUSERHOME=$(getent passwd 1000 | cut -d: -f6)
+if [ -f {helper} ]; then
+   {helper} enable_ssh -k {key}
+else
+   install -o "$FIRSTUSER" -m 700 -d "$FIRSTUSERHOME/.ssh"
+   printf '%s\n' {key} >"$FIRSTUSERHOME/.ssh/authorized_keys"
+   chown "$FIRSTUSER:" "$FIRSTUSERHOME/.ssh/authorized_keys"
+   chmod 600 "$FIRSTUSERHOME/.ssh/authorized_keys"
+   echo 'PasswordAuthentication no' >>/etc/ssh/sshd_config
+   systemctl enable ssh
+fi
+
+"#,
+            helper = IMAGER_CUSTOM,
+        ));
+    }
+    if !settings.wifi_ssid.is_empty() {
+        let ssid_hex: String = settings
+            .wifi_ssid
+            .bytes()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        script.push_str(&format!(
+            r#"if [ -f {helper} ]; then
+   {helper} set_wlan {ssid} {psk} {country}
+else
+cat >/etc/wpa_supplicant/wpa_supplicant.conf <<'WPAEOF'
+country={country}
+ctrl_interface=DIR=/var/run/wpa_supplicant GROUP=netdev
+ap_scan=1
+
+update_config=1
+network={{
+	ssid={ssid_hex}
+	psk={psk}
+}}
+WPAEOF
+   chmod 600 /etc/wpa_supplicant/wpa_supplicant.conf
+   rfkill unblock wifi
+   for filename in /var/lib/systemd/rfkill/*:wlan; do
+       echo 0 >"$filename"
+   done
+fi
+
+"#,
+            helper = IMAGER_CUSTOM,
+            ssid = shell_quote(&settings.wifi_ssid),
+            psk = settings.wifi_psk,
+            country = settings.wifi_country,
+        ));
+    }
+    script.push_str(&format!(
+        "rm -f {dir}/{file}\nsed -i 's| systemd.run.*||g' {dir}/cmdline.txt\nexit 0\n",
+        dir = boot_dir,
+        file = FIRSTRUN
+    ));
+    Some(script)
+}
+
+fn is_public_key(text: &str) -> bool {
+    ["ssh-", "ecdsa-", "sk-"]
+        .iter()
+        .any(|prefix| text.starts_with(prefix))
+}
+
+fn shell_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', r"'\''"))
+}
+
+/// WPA2 pre-shared key: PBKDF2-HMAC-SHA1 of the passphrase salted with the
+/// SSID, 4096 rounds, 32 bytes
+fn wpa_psk(ssid: &str, passphrase: &str) -> String {
+    let prf = || {
+        Hmac::<Sha1>::new_from_slice(passphrase.as_bytes()).expect("HMAC accepts any key length")
+    };
+    let mut key = Vec::with_capacity(40);
+    for block in 1u32..=2 {
+        let mut mac = prf();
+        mac.update(ssid.as_bytes());
+        mac.update(&block.to_be_bytes());
+        let mut u = mac.finalize().into_bytes();
+        let mut t = u;
+        for _ in 1..4096 {
+            let mut mac = prf();
+            mac.update(&u);
+            u = mac.finalize().into_bytes();
+            t.iter_mut().zip(u.iter()).for_each(|(t, u)| *t ^= u);
+        }
+        key.extend_from_slice(&t);
+    }
+    key[..32].iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_wpa_psk() {
+        // IEEE 802.11i test vector
+        assert_eq!(
+            wpa_psk("IEEE", "password"),
+            "f42c6fc52df0ebef9ebb4b90b38a5f902e83fe1b135a70e23aed762e9710a12e"
+        );
+    }
+
+    #[test]
+    fn test_write_boot_files() {
+        let mut disk = Cursor::new(vec![0u8; 8 * 1024 * 1024]);
+        fatfs::format_volume(&mut disk, fatfs::FormatVolumeOptions::new()).unwrap();
+        let fs = FileSystem::new(disk, FsOptions::new()).unwrap();
+        fs.root_dir()
+            .create_file("cmdline.txt")
+            .unwrap()
+            .write_all(b"console=tty1 root=PARTUUID=1234-02 rootwait\n")
+            .unwrap();
+
+        let mut settings = PiSettings {
+            hostname: "pi-lab".to_string(),
+            username: "ops".to_string(),
+            password: "secret".to_string(),
+            ssh: true,
+            authorized_key: "ssh-ed25519 AAAA ops@lab".to_string(),
+            wifi_ssid: "Lab's".to_string(),
+            wifi_password: "hunter22".to_string(),
+            wifi_country: "GB".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(settings.validate(), Ok(()));
+        settings.seal().unwrap();
+        assert!(settings.password.is_empty());
+        assert!(sha_crypt::sha512_check("secret", &settings.password_hash).is_ok());
+
+        let written = write_boot_files(&fs, &settings).unwrap();
+        assert_eq!(written, ["ssh", "userconf.txt", FIRSTRUN, "cmdline.txt"]);
+
+        let read = |name: &str| {
+            let mut contents = String::new();
+            fs.root_dir()
+                .open_file(name)
+                .unwrap()
+                .read_to_string(&mut contents)
+                .unwrap();
+            contents
+        };
+        assert_eq!(
+            read("userconf.txt"),
+            format!("ops:{}\n", settings.password_hash)
+        );
+        assert_eq!(
+            read("cmdline.txt"),
+            "console=tty1 root=PARTUUID=1234-02 rootwait systemd.run=/boot/firstrun.sh \
+             systemd.run_success_action=reboot systemd.unit=kernel-command-line.target\n"
+        );
+        let script = read(FIRSTRUN);
+        assert!(script.contains("set_hostname pi-lab"));
+        assert!(script.contains(r"set_wlan 'Lab'\''s'"));
+        assert!(script.contains(&format!("psk={}", settings.wifi_psk)));
+
+        // Applying twice must not stack a second systemd.run
+        write_boot_files(&fs, &settings).unwrap();
+        assert_eq!(read("cmdline.txt").matches("systemd.run=").count(), 1);
+    }
+}
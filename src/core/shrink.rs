@@ -0,0 +1,396 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use anyhow::{Context, Result, bail};
+
+use super::filesystem::{self, FsKind};
+use super::partition::{self, SECTOR_SIZE, TableKind};
+
+const COPY_CHUNK: usize = 1024 * 1024;
+
+/// Grows the root partition and filesystem on first boot, then removes
+/// itself. `@UUID@` is replaced with the filesystem UUID.
+const EXPAND_SCRIPT: &str = r#"#!/bin/sh
+# Installed by Pervie: grow this filesystem to fill the card, once
+set -e
+dev=$(blkid -U @UUID@)
+part=$(cat "/sys/class/block/${dev##*/}/partition")
+disk=/dev/$(lsblk -no pkname "$dev")
+if command -v growpart >/dev/null; then
+    growpart "$disk" "$part" || true
+else
+    sfdisk --relocate gpt-bkp-mv "$disk" 2>/dev/null || true
+    echo ", +" | sfdisk --no-reread --force -N "$part" "$disk"
+fi
+partx -u "$disk" || true
+resize2fs "$dev"
+systemctl disable pervie-expand.service
+rm -f /etc/systemd/system/pervie-expand.service "$0"
+"#;
+
+const EXPAND_UNIT: &str = "[Unit]
+Description=Grow the root filesystem to fill the disk
+After=local-fs.target
+
+[Service]
+Type=oneshot
+ExecStart=/usr/local/sbin/pervie-expand
+
+[Install]
+WantedBy=multi-user.target
+";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShrinkSummary {
+    pub old_size: u64,
+    pub new_size: u64,
+    pub filesystem: FsKind,
+    /// Whether the first-boot expand hook was installed
+    pub auto_expand: bool,
+}
+
+/// Shrink the last partition of a disk image to what its filesystem uses
+/// and truncate the image after it. ext2/3/4 is shrunk with `resize2fs`,
+/// which moves data down; FAT is cut after its last used cluster.
+pub fn shrink_image(path: &Path, auto_expand: bool) -> Result<ShrinkSummary> {
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(path)
+        .with_context(|| format!("Cannot open {}", path.display()))?;
+    let old_size = file.seek(SeekFrom::End(0))?;
+    let table = partition::read_partition_table(&mut file, old_size)?
+        .context("The image has no partition table to shrink")?;
+    let last = table
+        .partitions
+        .iter()
+        .max_by_key(|p| p.end())
+        .context("The image has no partitions")?
+        .clone();
+    if table.kind == TableKind::Mbr && last.number > 4 {
+        bail!("Shrinking a logical partition isn't supported");
+    }
+
+    let kind = filesystem::detect(&mut file, last.start)?;
+    let (kind, auto_expand) = match kind {
+        Some(FsKind::Ext) => {
+            shrink_ext(&mut file, path, last.start, last.size, auto_expand)?;
+            (FsKind::Ext, auto_expand)
+        }
+        Some(kind @ (FsKind::Fat12 | FsKind::Fat16 | FsKind::Fat32)) => {
+            filesystem::shrink_fat(&mut file, last.start)?;
+            (kind, false)
+        }
+        Some(kind) => bail!(
+            "Can't shrink {}; only ext2/3/4 and FAT are supported",
+            kind.display_name()
+        ),
+        None => bail!("No filesystem found in partition {}", last.number),
+    };
+    let fs_size = filesystem::filesystem_size(&mut file, last.start)?
+        .context("Can't read the shrunk filesystem's size")?;
+    let new_end =
+        (last.start + fs_size.div_ceil(table.sector_size) * table.sector_size).min(last.end());
+
+    let new_size = match table.kind {
+        TableKind::Mbr => {
+            let sectors = ((new_end - last.start) / SECTOR_SIZE) as u32;
+            let entry = 446 + u64::from(last.number - 1) * 16;
+            file.seek(SeekFrom::Start(entry + 12))?;
+            file.write_all(&sectors.to_le_bytes())?;
+            file.set_len(new_end)?;
+            new_end
+        }
+        TableKind::Gpt => {
+            let new_size = new_end + table.gpt_backup_len().unwrap_or(0);
+            file.set_len(new_size)?;
+            partition::update_gpt(&mut file, new_size, Some((last.number, new_end)))?;
+            new_size
+        }
+    };
+    file.sync_all()?;
+
+    Ok(ShrinkSummary {
+        old_size,
+        new_size,
+        filesystem: kind,
+        auto_expand,
+    })
+}
+
+/// Shrink an ext filesystem with e2fsprogs. resize2fs truncates a regular
+/// file to the new filesystem size even when given `?offset=`, so it works
+/// on a sparse copy of the partition that is copied back afterwards.
+fn shrink_ext(
+    image: &mut File,
+    path: &Path,
+    offset: u64,
+    size: u64,
+    auto_expand: bool,
+) -> Result<()> {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".fs");
+    let fs_path = PathBuf::from(name);
+    let result = (|| {
+        let mut fs_file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&fs_path)
+            .with_context(|| format!("Cannot create {}", fs_path.display()))?;
+        copy_range(image, offset, &mut fs_file, 0, size)?;
+        fs_file.set_len(size)?;
+        drop(fs_file);
+
+        let device = fs_path.to_string_lossy().into_owned();
+        if auto_expand {
+            install_expand_hook(&device)?;
+        }
+        // resize2fs insists on a fresh check; exit code 1 means errors were fixed
+        let status = run_e2fsprogs(Command::new("e2fsck").args(["-f", "-y", &device]))?;
+        if status.code().is_none_or(|code| code > 1) {
+            bail!("e2fsck found errors it couldn't fix");
+        }
+        let status = run_e2fsprogs(Command::new("resize2fs").args(["-M", &device]))?;
+        if !status.success() {
+            bail!("resize2fs failed to shrink the filesystem");
+        }
+
+        let mut fs_file = File::open(&fs_path)?;
+        let new_size = fs_file.seek(SeekFrom::End(0))?.min(size);
+        copy_range(&mut fs_file, 0, image, offset, new_size)
+    })();
+    let _ = fs::remove_file(&fs_path);
+    result
+}
+
+/// Copy `len` bytes between files. Zero chunks become holes in a fresh
+/// file, but are written out over existing data.
+fn copy_range(
+    from: &mut File,
+    from_offset: u64,
+    to: &mut File,
+    to_offset: u64,
+    len: u64,
+) -> Result<()> {
+    let fresh = to.metadata()?.len() == 0;
+    let mut buf = vec![0u8; COPY_CHUNK];
+    let mut done = 0;
+    while done < len {
+        let n = (len - done).min(COPY_CHUNK as u64) as usize;
+        from.seek(SeekFrom::Start(from_offset + done))?;
+        from.read_exact(&mut buf[..n])?;
+        if !(fresh && buf[..n].iter().all(|b| *b == 0)) {
+            to.seek(SeekFrom::Start(to_offset + done))?;
+            to.write_all(&buf[..n])?;
+        }
+        done += n as u64;
+    }
+    Ok(())
+}
+
+pub fn run_e2fsprogs(command: &mut Command) -> Result<std::process::ExitStatus> {
+    let program = command.get_program().to_string_lossy().into_owned();
+    command
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .with_context(|| format!("Failed to run {}; is e2fsprogs installed?", program))
+}
+
+/// Drop a systemd unit into the image that grows the filesystem on first boot
+fn install_expand_hook(device: &str) -> Result<()> {
+    let superblock = partition::read_at(&mut File::open(device)?, 1024, 256)?;
+    let uuid = format_uuid(&superblock[0x68..0x78]);
+
+    let debugfs = |request: &str| -> Result<String> {
+        let output = Command::new("debugfs")
+            .args(["-w", "-R", request, device])
+            .output()
+            .context("Failed to run debugfs; is e2fsprogs installed?")?;
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    };
+    if !debugfs("ls -l /etc/systemd/system")?.contains("multi-user.target.wants") {
+        bail!("The image doesn't look like a systemd root filesystem; can't auto-expand");
+    }
+
+    let dir = std::env::temp_dir().join(format!("pervie-expand-{}", std::process::id()));
+    fs::create_dir_all(&dir)?;
+    let script = dir.join("pervie-expand");
+    let unit = dir.join("pervie-expand.service");
+    fs::write(&script, EXPAND_SCRIPT.replace("@UUID@", &uuid))?;
+    fs::write(&unit, EXPAND_UNIT)?;
+
+    let requests = [
+        "mkdir /usr".to_string(),
+        "mkdir /usr/local".to_string(),
+        "mkdir /usr/local/sbin".to_string(),
+        "rm /usr/local/sbin/pervie-expand".to_string(),
+        format!("write {} /usr/local/sbin/pervie-expand", script.display()),
+        "sif /usr/local/sbin/pervie-expand mode 0100755".to_string(),
+        "rm /etc/systemd/system/pervie-expand.service".to_string(),
+        format!("write {} /etc/systemd/system/pervie-expand.service", unit.display()),
+        "rm /etc/systemd/system/multi-user.target.wants/pervie-expand.service".to_string(),
+        "symlink /etc/systemd/system/multi-user.target.wants/pervie-expand.service /etc/systemd/system/pervie-expand.service".to_string(),
+    ];
+    let result = requests.iter().try_for_each(|r| debugfs(r).map(drop));
+    let _ = fs::remove_dir_all(&dir);
+    result?;
+
+    if !debugfs("stat /usr/local/sbin/pervie-expand")?.contains("Inode:") {
+        bail!("Failed to install the auto-expand hook");
+    }
+    Ok(())
+}
+
+fn format_uuid(bytes: &[u8]) -> String {
+    let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Read};
+
+    fn temp_image(name: &str, contents: &[u8]) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("pervie-shrink-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_shrink_fat_on_gpt() {
+        // GPT with one 4 MiB FAT16 partition at 1 MiB, two clusters in use
+        let mut disk = vec![0u8; (2048 + 8192 + 33) * 512];
+        disk[446 + 4] = 0xee;
+        disk[446 + 8..446 + 12].copy_from_slice(&1u32.to_le_bytes());
+        disk[446 + 12..446 + 16].copy_from_slice(&(2048u32 + 8192 + 32).to_le_bytes());
+        disk[510..512].copy_from_slice(&[0x55, 0xaa]);
+        let header = &mut disk[512..1024];
+        header[..8].copy_from_slice(b"EFI PART");
+        header[12..16].copy_from_slice(&92u32.to_le_bytes());
+        header[72..80].copy_from_slice(&2u64.to_le_bytes());
+        header[80..84].copy_from_slice(&128u32.to_le_bytes());
+        header[84..88].copy_from_slice(&128u32.to_le_bytes());
+        let entry = &mut disk[1024..1152];
+        entry[..16].fill(0xaa);
+        entry[32..40].copy_from_slice(&2048u64.to_le_bytes());
+        entry[40..48].copy_from_slice(&(2048u64 + 8191).to_le_bytes());
+
+        let boot = &mut disk[2048 * 512..2049 * 512];
+        boot[11..13].copy_from_slice(&512u16.to_le_bytes());
+        boot[13] = 1;
+        boot[14..16].copy_from_slice(&1u16.to_le_bytes());
+        boot[16] = 2;
+        boot[17..19].copy_from_slice(&512u16.to_le_bytes());
+        boot[19..21].copy_from_slice(&8192u16.to_le_bytes());
+        boot[21] = 0xf8;
+        boot[22..24].copy_from_slice(&32u16.to_le_bytes());
+        boot[510..512].copy_from_slice(&[0x55, 0xaa]);
+        let fat = 2049 * 512;
+        disk[fat..fat + 8].copy_from_slice(&[0xf8, 0xff, 0xff, 0xff, 3, 0, 0xff, 0xff]);
+
+        let path = temp_image("fat.img", &disk);
+        let summary = shrink_image(&path, true).unwrap();
+        // FAT16 can't drop below 4085 clusters without turning into FAT12
+        let new_end = (2048 + 97 + 4085) * 512;
+        assert_eq!(summary.filesystem, FsKind::Fat16);
+        assert!(!summary.auto_expand);
+        assert_eq!(summary.new_size, new_end + 33 * 512);
+
+        let image = fs::read(&path).unwrap();
+        assert_eq!(image.len() as u64, summary.new_size);
+        let table = partition::read_partition_table(&mut Cursor::new(&image), summary.new_size)
+            .unwrap()
+            .unwrap();
+        assert_eq!(table.partitions[0].end(), new_end);
+        let backup = &image[image.len() - 512..image.len() - 420];
+        assert_eq!(&backup[..8], b"EFI PART");
+        let mut check = backup.to_vec();
+        check[16..20].fill(0);
+        assert_eq!(crc32fast::hash(&check), partition::le32(&backup[16..]));
+        assert_eq!(partition::le64(&backup[72..]) * 512, new_end);
+        assert_eq!(
+            filesystem::filesystem_size(&mut Cursor::new(&image), 2048 * 512).unwrap(),
+            Some((97 + 4085) * 512)
+        );
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_shrink_ext_with_auto_expand() {
+        if Command::new("resize2fs").output().is_err() {
+            eprintln!("e2fsprogs not installed; skipping");
+            return;
+        }
+        let mut fs_image = Vec::new();
+        flate2::read::GzDecoder::new(&include_bytes!("../../tests/fixtures/fs/ext4.img.gz")[..])
+            .read_to_end(&mut fs_image)
+            .unwrap();
+        let mut disk = vec![0u8; 1024 * 1024];
+        disk[446 + 4] = 0x83;
+        disk[446 + 8..446 + 12].copy_from_slice(&2048u32.to_le_bytes());
+        disk[446 + 12..446 + 16].copy_from_slice(&((fs_image.len() / 512) as u32).to_le_bytes());
+        disk[510..512].copy_from_slice(&[0x55, 0xaa]);
+        disk.extend_from_slice(&fs_image);
+
+        let path = temp_image("ext.img", &disk);
+        let device = format!("{}?offset={}", path.display(), 1024 * 1024);
+        for dir in ["/etc", "/etc/systemd", "/etc/systemd/system"] {
+            Command::new("debugfs")
+                .args(["-w", "-R", &format!("mkdir {}", dir), &device])
+                .output()
+                .unwrap();
+        }
+        assert!(shrink_image(&path, true).is_err());
+        Command::new("debugfs")
+            .args([
+                "-w",
+                "-R",
+                "mkdir /etc/systemd/system/multi-user.target.wants",
+                &device,
+            ])
+            .output()
+            .unwrap();
+
+        let summary = shrink_image(&path, true).unwrap();
+        assert_eq!(summary.filesystem, FsKind::Ext);
+        assert!(summary.auto_expand);
+        assert!(summary.new_size < disk.len() as u64);
+        assert_eq!(fs::metadata(&path).unwrap().len(), summary.new_size);
+
+        let image = fs::read(&path).unwrap();
+        let file_blocks = image[1024 * 1024..]
+            .chunks(1024)
+            .filter(|block| block.iter().all(|b| *b == 0xa5))
+            .count();
+        assert_eq!(file_blocks, 100);
+        let fsck = Command::new("e2fsck")
+            .args(["-f", "-n", &device])
+            .output()
+            .unwrap();
+        assert!(fsck.status.success());
+        let listing = Command::new("debugfs")
+            .args([
+                "-R",
+                "ls -l /etc/systemd/system/multi-user.target.wants",
+                &device,
+            ])
+            .output()
+            .unwrap();
+        assert!(String::from_utf8_lossy(&listing.stdout).contains("pervie-expand.service"));
+        fs::remove_file(&path).unwrap();
+    }
+}
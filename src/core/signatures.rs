@@ -0,0 +1,201 @@
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io::{Read, Seek, SeekFrom, Write};
+
+use anyhow::{Context, Result};
+
+use super::partition::{self, read_at};
+
+const ZFS_UBERBLOCK_MAGIC: u64 = 0x00ba_b10c;
+const ZFS_LABEL_SIZE: u64 = 256 * 1024;
+const MD_MAGIC: u32 = 0xa92b_4efc;
+
+/// Magic bytes that make blkid, udev or firmware recognise a table or
+/// filesystem, found where `wipefs` would look
+#[derive(Debug, Clone, PartialEq)]
+pub struct Signature {
+    pub name: &'static str,
+    /// Byte offset on the device
+    pub offset: u64,
+    pub len: usize,
+    /// Set when found inside a partition of the old table
+    pub partition: Option<u32>,
+}
+
+impl fmt::Display for Signature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at {:#x}", self.name, self.offset)?;
+        if let Some(number) = self.partition {
+            write!(f, " (partition {})", number)?;
+        }
+        Ok(())
+    }
+}
+
+/// Signatures at the start and end of a region: the whole device, or one
+/// of its partitions
+fn find_in<R: Read + Seek>(
+    reader: &mut R,
+    start: u64,
+    size: u64,
+    partition: Option<u32>,
+) -> Result<Vec<Signature>> {
+    let mut found = Vec::new();
+    let mut check = |reader: &mut R, name, offset: u64, magic: &[u8]| -> Result<()> {
+        if offset + magic.len() as u64 <= size
+            && read_at(reader, start + offset, magic.len())? == magic
+        {
+            found.push(Signature {
+                name,
+                offset: start + offset,
+                len: magic.len(),
+                partition,
+            });
+        }
+        Ok(())
+    };
+
+    let is_disk = partition.is_none();
+    if is_disk {
+        check(reader, "dos", 510, &[0x55, 0xaa])?;
+        for sector_size in [512, 4096] {
+            check(reader, "gpt", sector_size, b"EFI PART")?;
+            if size >= 2 * sector_size {
+                check(reader, "gpt (backup)", size - sector_size, b"EFI PART")?;
+            }
+        }
+    }
+    check(reader, "ext4", 1024 + 0x38, &[0x53, 0xef])?;
+    check(reader, "vfat", 54, b"FAT12   ")?;
+    check(reader, "vfat", 54, b"FAT16   ")?;
+    check(reader, "vfat", 82, b"FAT32   ")?;
+    check(reader, "exfat", 3, b"EXFAT   ")?;
+    check(reader, "ntfs", 3, b"NTFS    ")?;
+    check(reader, "iso9660", 0x8001, b"CD001")?;
+    check(reader, "xfs", 0, b"XFSB")?;
+    check(reader, "btrfs", 0x10040, b"_BHRfS_M")?;
+    check(reader, "crypto_LUKS", 0, b"LUKS\xba\xbe")?;
+    check(reader, "LVM2_member", 512 + 24, b"LVM2 001")?;
+    for page_size in [4096, 8192, 16384, 65536] {
+        check(reader, "swap", page_size - 10, b"SWAPSPACE2")?;
+    }
+
+    // Linux RAID: 0.90 and 1.0 superblocks at the end, 1.1 at the start, 1.2 at 4 KiB
+    let md = MD_MAGIC.to_le_bytes();
+    if size >= 128 * 1024 {
+        check(reader, "linux_raid_member", (size & !0xffff) - 0x10000, &md)?;
+        check(
+            reader,
+            "linux_raid_member",
+            ((size / 512 - 16) & !7) * 512,
+            &md,
+        )?;
+    }
+    check(reader, "linux_raid_member", 0, &md)?;
+    check(reader, "linux_raid_member", 4096, &md)?;
+
+    // ZFS keeps four labels, two at each end, each with an array of uberblocks
+    let labels_end = size / ZFS_LABEL_SIZE * ZFS_LABEL_SIZE;
+    let mut labels = vec![0, ZFS_LABEL_SIZE];
+    if labels_end >= 4 * ZFS_LABEL_SIZE {
+        labels.extend([labels_end - 2 * ZFS_LABEL_SIZE, labels_end - ZFS_LABEL_SIZE]);
+    }
+    for label in labels {
+        let ring = read_at(reader, start + label + 128 * 1024, 128 * 1024)?;
+        for (i, uberblock) in ring.chunks_exact(1024).enumerate() {
+            let magic = partition::le64(uberblock);
+            if magic == ZFS_UBERBLOCK_MAGIC || magic.swap_bytes() == ZFS_UBERBLOCK_MAGIC {
+                found.push(Signature {
+                    name: "zfs_member",
+                    offset: start + label + 128 * 1024 + i as u64 * 1024,
+                    len: 8,
+                    partition,
+                });
+            }
+        }
+    }
+    Ok(found)
+}
+
+/// Every signature on a device, including those inside the partitions of
+/// the table currently on it
+pub fn find_signatures<R: Read + Seek>(reader: &mut R, size: u64) -> Result<Vec<Signature>> {
+    let mut found = find_in(reader, 0, size, None)?;
+    if let Some(table) = partition::read_partition_table(reader, size)? {
+        for p in table.partitions.iter().filter(|p| p.end() <= size) {
+            found.extend(find_in(reader, p.start, p.size, Some(p.number))?);
+        }
+    }
+    Ok(found)
+}
+
+/// One line for the completion message, or empty when nothing was wiped
+pub fn describe(wiped: &[Signature]) -> String {
+    if wiped.is_empty() {
+        return String::new();
+    }
+    let list: Vec<String> = wiped.iter().map(Signature::to_string).collect();
+    format!("\nWiped old signatures: {}", list.join(", "))
+}
+
+/// Zero the magic bytes of every known signature, like `wipefs --all`.
+/// Returns what was wiped.
+pub fn wipe_signatures(device_path: &str) -> Result<Vec<Signature>> {
+    wipe_signatures_from(device_path, 0)
+}
+
+/// Like [`wipe_signatures`], but leave alone signatures before `start`,
+/// e.g. where a differential write will compare the image anyway
+pub fn wipe_signatures_from(device_path: &str, start: u64) -> Result<Vec<Signature>> {
+    let mut device = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(device_path)
+        .with_context(|| format!("Failed to open device {}", device_path))?;
+    let size = device.seek(SeekFrom::End(0))?;
+    let mut found = find_signatures(&mut device, size)?;
+    found.retain(|signature| signature.offset >= start);
+    for signature in &found {
+        device.seek(SeekFrom::Start(signature.offset))?;
+        device.write_all(&vec![0; signature.len])?;
+    }
+    if !found.is_empty() {
+        device.sync_all().context("Failed to sync device")?;
+    }
+    Ok(found)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_find_signatures() {
+        let size = 4 * 1024 * 1024u64;
+        let mut disk = vec![0u8; size as usize];
+        // MBR with one partition at 1 MiB holding an ext4 superblock magic
+        disk[446 + 4] = 0x83;
+        disk[446 + 8..446 + 12].copy_from_slice(&2048u32.to_le_bytes());
+        disk[446 + 12..446 + 16].copy_from_slice(&4096u32.to_le_bytes());
+        disk[510..512].copy_from_slice(&[0x55, 0xaa]);
+        let sb = 1024 * 1024 + 1024 + 0x38;
+        disk[sb..sb + 2].copy_from_slice(&[0x53, 0xef]);
+        // Leftovers from an older layout: a backup GPT and a ZFS label at the end
+        disk[size as usize - 512..size as usize - 504].copy_from_slice(b"EFI PART");
+        let uberblock = size as usize - 256 * 1024 + 128 * 1024 + 3 * 1024;
+        disk[uberblock..uberblock + 8].copy_from_slice(&ZFS_UBERBLOCK_MAGIC.to_be_bytes());
+
+        let found = find_signatures(&mut Cursor::new(&disk), size).unwrap();
+        let names: Vec<String> = found.iter().map(|s| s.to_string()).collect();
+        assert_eq!(
+            names,
+            vec![
+                "dos at 0x1fe".to_string(),
+                "gpt (backup) at 0x3ffe00".to_string(),
+                "zfs_member at 0x3e0c00".to_string(),
+                "ext4 at 0x100438 (partition 1)".to_string(),
+            ]
+        );
+    }
+}
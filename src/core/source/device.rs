@@ -0,0 +1,85 @@
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use tokio::sync::mpsc;
+
+use super::{ByteStream, ImageSource, SourceInfo};
+
+const CHUNK_SIZE: usize = 1024 * 1024;
+
+/// Another block device or a local file, read from start to end; used for
+/// cloning and for copying local ISOs onto a multi-boot stick
+pub struct DeviceSource {
+    path: String,
+    /// Size reported by the OS, for devices that can't be seeked to their end
+    size: u64,
+}
+
+impl DeviceSource {
+    pub fn new(path: String, size: u64) -> Self {
+        Self { path, size }
+    }
+}
+
+#[async_trait]
+impl ImageSource for DeviceSource {
+    fn describe(&self) -> String {
+        self.path.clone()
+    }
+
+    fn is_remote(&self) -> bool {
+        false
+    }
+
+    async fn probe(&self) -> Result<SourceInfo> {
+        let mut device = File::open(&self.path)
+            .with_context(|| format!("Failed to open device {}", self.path))?;
+        let total_bytes = match device.seek(SeekFrom::End(0)) {
+            Ok(size) if size > 0 => size,
+            _ => self.size,
+        };
+        Ok(SourceInfo {
+            total_bytes,
+            sha256: None,
+            pieces: None,
+        })
+    }
+
+    async fn open(&self, offset: u64) -> Result<ByteStream> {
+        let mut device = File::open(&self.path)
+            .with_context(|| format!("Failed to open device {}", self.path))?;
+        device.seek(SeekFrom::Start(offset))?;
+
+        // Reads block, so they run on their own thread
+        let (tx, rx) = mpsc::channel(4);
+        let path = self.path.clone();
+        std::thread::spawn(move || {
+            let mut position = offset;
+            loop {
+                let mut buf = vec![0u8; CHUNK_SIZE];
+                let item = match device.read(&mut buf) {
+                    Ok(0) => break,
+                    Ok(n) => {
+                        buf.truncate(n);
+                        position += n as u64;
+                        Ok(buf)
+                    }
+                    Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                    Err(e) => Err(anyhow::Error::new(e)
+                        .context(format!("Failed to read {} at byte {}", path, position))),
+                };
+                let failed = item.is_err();
+                if tx.blocking_send(item).is_err() || failed {
+                    break;
+                }
+            }
+        });
+
+        let stream = futures_util::stream::unfold(rx, |mut rx| async move {
+            rx.recv().await.map(|item| (item, rx))
+        });
+        Ok(Box::pin(stream))
+    }
+}
@@ -0,0 +1,134 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use anyhow::{Result, anyhow};
+use async_trait::async_trait;
+use futures_util::StreamExt;
+use reqwest::StatusCode;
+
+use super::{ByteStream, ImageSource, SourceInfo};
+use crate::core::net::HttpClient;
+
+/// Plain HTTP(S) image, optionally mirrored on several hosts
+pub struct HttpSource {
+    client: HttpClient,
+    mirrors: Vec<String>,
+    /// Index of the mirror currently in use
+    current: AtomicUsize,
+}
+
+impl HttpSource {
+    pub fn new(client: HttpClient, mirrors: Vec<String>) -> Self {
+        Self {
+            client,
+            mirrors,
+            current: AtomicUsize::new(0),
+        }
+    }
+
+    /// Mirrors in the order they should be tried, starting with the current one
+    fn mirror_order(&self) -> impl Iterator<Item = &String> {
+        let start = self.current.load(Ordering::Relaxed);
+        self.mirrors
+            .iter()
+            .cycle()
+            .skip(start)
+            .take(self.mirrors.len())
+    }
+
+    /// GET from `offset` to the inclusive `last` byte, or to the end
+    async fn get_range(&self, offset: u64, last: Option<u64>) -> Result<ByteStream> {
+        let mut last_error = anyhow!("No URL to download");
+        for (i, url) in self.mirror_order().enumerate() {
+            let range = match last {
+                Some(last) => Some(format!("bytes={}-{}", offset, last)),
+                None if offset > 0 => Some(format!("bytes={}-", offset)),
+                None => None,
+            };
+            let mut request = self.client.get(url);
+            if let Some(range) = range {
+                request = request.header(reqwest::header::RANGE, range);
+            }
+
+            match request.send().await {
+                Ok(resp) if resp.status() == StatusCode::PARTIAL_CONTENT || offset == 0 => {
+                    if !resp.status().is_success() {
+                        last_error = anyhow!("Failed to download {}: {}", url, resp.status());
+                        continue;
+                    }
+                    if i > 0 {
+                        let start = self.current.load(Ordering::Relaxed);
+                        self.current
+                            .store((start + i) % self.mirrors.len(), Ordering::Relaxed);
+                    }
+                    let stream = resp
+                        .bytes_stream()
+                        .map(|chunk| chunk.map(|c| c.to_vec()).map_err(Into::into));
+                    return Ok(Box::pin(stream));
+                }
+                Ok(resp) => {
+                    last_error = anyhow!(
+                        "{} cannot resume at byte {} ({})",
+                        url,
+                        offset,
+                        resp.status()
+                    );
+                }
+                Err(e) => last_error = e.into(),
+            }
+        }
+        Err(last_error)
+    }
+}
+
+#[async_trait]
+impl ImageSource for HttpSource {
+    fn describe(&self) -> String {
+        self.mirror_order().next().cloned().unwrap_or_default()
+    }
+
+    async fn probe(&self) -> Result<SourceInfo> {
+        let mut last_error = anyhow!("No URL to probe");
+        for url in self.mirror_order() {
+            let head_resp = match self.client.head(url).send().await {
+                Ok(resp) => resp,
+                Err(e) => {
+                    last_error = e.into();
+                    continue;
+                }
+            };
+            if !head_resp.status().is_success() {
+                last_error = anyhow!("Failed to access URL: {}", head_resp.status());
+                continue;
+            }
+
+            let total_bytes = head_resp
+                .headers()
+                .get(reqwest::header::CONTENT_LENGTH)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.parse::<u64>().ok())
+                .ok_or_else(|| anyhow!("Could not retrieve content length from URL"))?;
+
+            return Ok(SourceInfo {
+                total_bytes,
+                sha256: None,
+                pieces: None,
+            });
+        }
+        Err(last_error)
+    }
+
+    async fn open(&self, offset: u64) -> Result<ByteStream> {
+        self.get_range(offset, None).await
+    }
+
+    async fn open_range(&self, offset: u64, len: u64) -> Result<ByteStream> {
+        self.get_range(offset, Some(offset + len.max(1) - 1)).await
+    }
+
+    fn mark_failed(&self) {
+        if self.mirrors.len() > 1 {
+            let next = (self.current.load(Ordering::Relaxed) + 1) % self.mirrors.len();
+            self.current.store(next, Ordering::Relaxed);
+        }
+    }
+}
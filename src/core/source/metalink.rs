@@ -0,0 +1,310 @@
+use anyhow::{Context, Result, anyhow};
+use async_trait::async_trait;
+use quick_xml::Reader;
+use quick_xml::events::{BytesStart, Event};
+use tokio::sync::OnceCell;
+
+use super::http::HttpSource;
+use super::{ByteStream, ImageSource, PieceAlgorithm, PieceHashes, SourceInfo};
+use crate::core::net::HttpClient;
+
+/// Largest Metalink document we are willing to parse
+const MAX_METALINK_BYTES: usize = 4 * 1024 * 1024;
+
+/// The parts of a Metalink (RFC 5854 `.meta4`, or the older v3 `.metalink`)
+/// file entry that matter for flashing
+#[derive(Debug, Clone, PartialEq)]
+pub struct Metalink {
+    pub name: String,
+    pub size: u64,
+    pub sha256: Option<String>,
+    /// Mirror URLs, most preferred first
+    pub urls: Vec<String>,
+    pub pieces: Option<PieceHashes>,
+}
+
+/// Whether a URL points at a Metalink document rather than an image
+pub fn is_metalink_url(url: &str) -> bool {
+    let path = url.split(['?', '#']).next().unwrap_or(url).to_lowercase();
+    path.ends_with(".meta4") || path.ends_with(".metalink")
+}
+
+/// Image described by a Metalink file, downloaded from its mirrors in order
+/// of priority
+pub struct MetalinkSource {
+    client: HttpClient,
+    url: String,
+    resolved: OnceCell<(Metalink, HttpSource)>,
+}
+
+impl MetalinkSource {
+    pub fn new(client: HttpClient, url: &str) -> Self {
+        Self {
+            client,
+            url: url.to_string(),
+            resolved: OnceCell::new(),
+        }
+    }
+
+    async fn resolve(&self) -> Result<(Metalink, HttpSource)> {
+        let resp = self.client.get(&self.url).send().await?;
+        if !resp.status().is_success() {
+            return Err(anyhow!(
+                "Failed to fetch Metalink {}: {}",
+                self.url,
+                resp.status()
+            ));
+        }
+        let body = resp.bytes().await?;
+        if body.len() > MAX_METALINK_BYTES {
+            return Err(anyhow!("Metalink {} is too large", self.url));
+        }
+
+        let metalink = parse_metalink(&String::from_utf8_lossy(&body))
+            .with_context(|| format!("Invalid Metalink {}", self.url))?;
+        let mirrors = HttpSource::new(self.client.clone(), metalink.urls.clone());
+        Ok((metalink, mirrors))
+    }
+
+    async fn mirrors(&self) -> Result<&HttpSource> {
+        let (_, mirrors) = self.resolved.get_or_try_init(|| self.resolve()).await?;
+        Ok(mirrors)
+    }
+}
+
+#[async_trait]
+impl ImageSource for MetalinkSource {
+    fn describe(&self) -> String {
+        match self.resolved.get() {
+            Some((_, mirrors)) => mirrors.describe(),
+            None => self.url.clone(),
+        }
+    }
+
+    async fn probe(&self) -> Result<SourceInfo> {
+        let (metalink, _) = self.resolved.get_or_try_init(|| self.resolve()).await?;
+        Ok(SourceInfo {
+            total_bytes: metalink.size,
+            sha256: metalink.sha256.clone(),
+            pieces: metalink.pieces.clone(),
+        })
+    }
+
+    async fn open(&self, offset: u64) -> Result<ByteStream> {
+        self.mirrors().await?.open(offset).await
+    }
+
+    async fn open_range(&self, offset: u64, len: u64) -> Result<ByteStream> {
+        self.mirrors().await?.open_range(offset, len).await
+    }
+
+    fn mark_failed(&self) {
+        if let Some((_, mirrors)) = self.resolved.get() {
+            mirrors.mark_failed();
+        }
+    }
+}
+
+/// Entry being collected while walking a `<file>` element
+#[derive(Default)]
+struct FileEntry {
+    name: String,
+    size: Option<u64>,
+    sha256: Option<String>,
+    /// (priority, url); lower priority values are preferred
+    urls: Vec<(u32, String)>,
+    piece_algorithm: Option<PieceAlgorithm>,
+    piece_length: Option<u64>,
+    piece_hashes: Vec<String>,
+}
+
+/// Parse a Metalink v4 or v3 document. With several `<file>` entries the
+/// first one that looks like a disk image wins.
+pub fn parse_metalink(xml: &str) -> Result<Metalink> {
+    let mut reader = Reader::from_str(xml);
+    reader.config_mut().trim_text(true);
+
+    let mut files: Vec<FileEntry> = Vec::new();
+    let mut current: Option<FileEntry> = None;
+    let mut stack: Vec<String> = Vec::new();
+    // Attributes of the element whose text comes next
+    let mut hash_type = String::new();
+    let mut url_priority = 0;
+
+    loop {
+        match reader.read_event()? {
+            Event::Start(e) => {
+                let name = local_name(&e);
+                match name.as_str() {
+                    "file" => {
+                        current = Some(FileEntry {
+                            name: attribute(&e, "name")?.unwrap_or_default(),
+                            ..Default::default()
+                        })
+                    }
+                    "pieces" => {
+                        if let Some(file) = current.as_mut() {
+                            file.piece_algorithm =
+                                attribute(&e, "type")?.and_then(|t| PieceAlgorithm::from_name(&t));
+                            file.piece_length =
+                                attribute(&e, "length")?.and_then(|l| l.parse().ok());
+                        }
+                    }
+                    "hash" => hash_type = attribute(&e, "type")?.unwrap_or_default(),
+                    "url" => {
+                        // v4 `priority`: 1 is best. v3 `preference`: 100 is best.
+                        url_priority = match attribute(&e, "priority")? {
+                            Some(p) => p.parse().unwrap_or(999_999),
+                            None => attribute(&e, "preference")?
+                                .and_then(|p| p.parse::<u32>().ok())
+                                .map_or(999_999, |p| 101u32.saturating_sub(p)),
+                        };
+                    }
+                    _ => {}
+                }
+                stack.push(name);
+            }
+            Event::End(_) => {
+                if stack.pop().as_deref() == Some("file")
+                    && let Some(file) = current.take()
+                {
+                    files.push(file);
+                }
+            }
+            Event::Text(t) => {
+                let text = t.unescape()?.trim().to_string();
+                let Some(file) = current.as_mut() else {
+                    continue;
+                };
+                let in_pieces = stack.iter().any(|s| s == "pieces");
+                match stack.last().map(String::as_str) {
+                    Some("size") => file.size = text.parse().ok(),
+                    Some("hash") if in_pieces => file.piece_hashes.push(text.to_lowercase()),
+                    Some("hash")
+                        if PieceAlgorithm::from_name(&hash_type)
+                            == Some(PieceAlgorithm::Sha256) =>
+                    {
+                        file.sha256 = Some(text.to_lowercase())
+                    }
+                    Some("url") => file.urls.push((url_priority, text)),
+                    _ => {}
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+
+    let image_index = files
+        .iter()
+        .position(|f| {
+            let name = f.name.to_lowercase();
+            [".iso", ".img", ".raw"].iter().any(|s| name.ends_with(s))
+        })
+        .unwrap_or(0);
+    if files.is_empty() {
+        return Err(anyhow!("No <file> entries"));
+    }
+    let mut file = files.swap_remove(image_index);
+
+    let size = file
+        .size
+        .ok_or_else(|| anyhow!("{} has no <size>", file.name))?;
+    file.urls.sort_by_key(|(priority, _)| *priority);
+    let urls: Vec<String> = file
+        .urls
+        .into_iter()
+        .map(|(_, url)| url)
+        .filter(|url| url.starts_with("http://") || url.starts_with("https://"))
+        .collect();
+    if urls.is_empty() {
+        return Err(anyhow!("{} has no HTTP mirrors", file.name));
+    }
+
+    let pieces = match (file.piece_algorithm, file.piece_length) {
+        (Some(algorithm), Some(length)) if length > 0 => Some(PieceHashes {
+            algorithm,
+            length,
+            hashes: file.piece_hashes,
+        })
+        .filter(|p| p.hashes.len() as u64 == size.div_ceil(length)),
+        _ => None,
+    };
+
+    Ok(Metalink {
+        name: file.name,
+        size,
+        sha256: file.sha256,
+        urls,
+        pieces,
+    })
+}
+
+fn local_name(e: &BytesStart) -> String {
+    String::from_utf8_lossy(e.local_name().as_ref()).to_string()
+}
+
+fn attribute(e: &BytesStart, name: &str) -> Result<Option<String>> {
+    Ok(match e.try_get_attribute(name)? {
+        Some(attr) => Some(attr.unescape_value()?.to_string()),
+        None => None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(name: &str) -> String {
+        let path = format!(
+            "{}/tests/fixtures/metalink/{}",
+            env!("CARGO_MANIFEST_DIR"),
+            name
+        );
+        std::fs::read_to_string(path).unwrap()
+    }
+
+    #[test]
+    fn test_parse_meta4() {
+        let metalink = parse_metalink(&fixture("opensuse.meta4")).unwrap();
+        assert_eq!(metalink.name, "openSUSE-Leap-16.0-NET-x86_64.iso");
+        assert_eq!(metalink.size, 10_485_760);
+        assert_eq!(
+            metalink.sha256.as_deref(),
+            Some("4d3f8e6a1b2c3d4e5f60718293a4b5c6d7e8f90112233445566778899aabbccd")
+        );
+        assert_eq!(
+            metalink.urls,
+            vec![
+                "https://mirror.example.de/opensuse/openSUSE-Leap-16.0-NET-x86_64.iso",
+                "https://mirror.example.org/opensuse/openSUSE-Leap-16.0-NET-x86_64.iso",
+            ]
+        );
+        let pieces = metalink.pieces.unwrap();
+        assert_eq!(pieces.algorithm, PieceAlgorithm::Sha256);
+        assert_eq!(pieces.length, 4_194_304);
+        assert_eq!(pieces.hashes.len(), 3);
+    }
+
+    #[test]
+    fn test_parse_metalink_v3() {
+        let metalink = parse_metalink(&fixture("fedora.metalink")).unwrap();
+        assert_eq!(metalink.size, 2_097_152);
+        assert_eq!(
+            metalink.urls[0],
+            "https://mirror1.example.com/fedora/Fedora-Server-netinst-x86_64.iso"
+        );
+        let pieces = metalink.pieces.unwrap();
+        assert_eq!(pieces.algorithm, PieceAlgorithm::Sha1);
+        assert_eq!(pieces.hashes.len(), 2);
+    }
+
+    #[test]
+    fn test_is_metalink_url() {
+        assert!(is_metalink_url("https://example.org/a.iso.meta4"));
+        assert!(is_metalink_url(
+            "https://example.org/a.iso.metalink?mirrorlist"
+        ));
+        assert!(!is_metalink_url("https://example.org/a.iso"));
+    }
+}
@@ -0,0 +1,210 @@
+pub mod device;
+pub mod http;
+pub mod metalink;
+pub mod oci;
+pub mod s3;
+pub mod torrent;
+
+use std::collections::HashMap;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::pin::Pin;
+use std::sync::Arc;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use futures_util::{Stream, StreamExt};
+use tokio::runtime::Handle;
+
+use crate::core::net::HttpClient;
+
+/// Chunks of image data as they arrive
+pub type ByteStream = Pin<Box<dyn Stream<Item = Result<Vec<u8>>> + Send>>;
+
+/// What a source knows about its image before streaming it
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceInfo {
+    pub total_bytes: u64,
+    /// SHA-256 published by the source itself (e.g. an S3 object checksum
+    /// or an OCI blob digest)
+    pub sha256: Option<String>,
+    /// Per-piece hashes for verifying the image while it streams
+    pub pieces: Option<PieceHashes>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PieceAlgorithm {
+    Sha1,
+    Sha256,
+}
+
+impl PieceAlgorithm {
+    /// Parse a hash name as Metalink spells it (`sha-256`, or `sha256` in v3)
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "sha-1" | "sha1" => Some(Self::Sha1),
+            "sha-256" | "sha256" => Some(Self::Sha256),
+            _ => None,
+        }
+    }
+}
+
+/// Hashes of consecutive `length`-byte pieces; the last piece may be shorter
+#[derive(Debug, Clone, PartialEq)]
+pub struct PieceHashes {
+    pub algorithm: PieceAlgorithm,
+    pub length: u64,
+    /// Lowercase hex, one per piece
+    pub hashes: Vec<String>,
+}
+
+/// Something the flasher can stream an image from
+#[async_trait]
+pub trait ImageSource: Send + Sync {
+    /// Short description for progress and error messages
+    fn describe(&self) -> String;
+
+    /// Size (and checksum, if the source has one) of the image
+    async fn probe(&self) -> Result<SourceInfo>;
+
+    /// Stream the image starting at byte `offset`. Called again with the
+    /// number of bytes already written to resume after a failed stream.
+    async fn open(&self, offset: u64) -> Result<ByteStream>;
+
+    /// Stream `len` bytes starting at `offset`, for random reads. Sources that
+    /// can bound a request should, so each read doesn't start a full download.
+    async fn open_range(&self, offset: u64, _len: u64) -> Result<ByteStream> {
+        self.open(offset).await
+    }
+
+    /// The last stream failed; switch to another mirror if there is one
+    fn mark_failed(&self) {}
+
+    /// Live detail for the progress panel, such as swarm peers and pieces
+    fn status(&self) -> Option<String> {
+        None
+    }
+
+    /// Comes over the network, so the download rate limit applies
+    fn is_remote(&self) -> bool {
+        true
+    }
+}
+
+/// Block size `SourceReader` fetches and caches
+const READ_BLOCK: u64 = 64 * 1024;
+/// Cached blocks kept before the cache starts over
+const MAX_CACHED_BLOCKS: usize = 1024;
+
+/// Random access to a source for reading filesystem metadata inside an
+/// image without downloading it: each uncached block is one ranged stream.
+/// Blocking, so use it from a blocking thread.
+pub struct SourceReader {
+    source: Arc<dyn ImageSource>,
+    runtime: Handle,
+    size: u64,
+    position: u64,
+    cache: HashMap<u64, Vec<u8>>,
+}
+
+impl SourceReader {
+    pub fn new(source: Arc<dyn ImageSource>, size: u64, runtime: Handle) -> Self {
+        Self {
+            source,
+            runtime,
+            size,
+            position: 0,
+            cache: HashMap::new(),
+        }
+    }
+
+    fn block(&mut self, index: u64) -> io::Result<&[u8]> {
+        if !self.cache.contains_key(&index) {
+            let start = index * READ_BLOCK;
+            let len = READ_BLOCK.min(self.size - start) as usize;
+            let source = self.source.clone();
+            let data = self
+                .runtime
+                .block_on(async move {
+                    let mut stream = source.open_range(start, len as u64).await?;
+                    let mut data = Vec::with_capacity(len);
+                    while data.len() < len {
+                        match stream.next().await {
+                            Some(chunk) => data.extend(chunk?),
+                            None => break,
+                        }
+                    }
+                    data.truncate(len);
+                    anyhow::Ok(data)
+                })
+                .map_err(io::Error::other)?;
+            if self.cache.len() >= MAX_CACHED_BLOCKS {
+                self.cache.clear();
+            }
+            self.cache.insert(index, data);
+        }
+        Ok(&self.cache[&index])
+    }
+}
+
+impl Read for SourceReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position >= self.size || buf.is_empty() {
+            return Ok(0);
+        }
+        let position = self.position;
+        let block = self.block(position / READ_BLOCK)?;
+        let start = (position % READ_BLOCK) as usize;
+        if start >= block.len() {
+            return Ok(0);
+        }
+        let n = buf.len().min(block.len() - start);
+        buf[..n].copy_from_slice(&block[start..start + n]);
+        self.position += n as u64;
+        Ok(n)
+    }
+}
+
+impl Seek for SourceReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(delta) => self.size.checked_add_signed(delta),
+            SeekFrom::Current(delta) => self.position.checked_add_signed(delta),
+        };
+        self.position = position
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "seek before start"))?;
+        Ok(self.position)
+    }
+}
+
+/// Pick the source implementation for an image URL
+pub fn from_url(url: &str, client: &HttpClient) -> Result<Arc<dyn ImageSource>> {
+    if url.starts_with('/') {
+        return Ok(Arc::new(device::DeviceSource::new(url.to_string(), 0)));
+    }
+    if url.starts_with("s3://") {
+        return Ok(Arc::new(s3::S3Source::from_url(
+            url,
+            client.inner().clone(),
+        )?));
+    }
+    if torrent::is_torrent_url(url) {
+        return Ok(Arc::new(torrent::TorrentSource::from_url(
+            url,
+            client.clone(),
+        )?));
+    }
+    if metalink::is_metalink_url(url) {
+        return Ok(Arc::new(metalink::MetalinkSource::new(client.clone(), url)));
+    }
+    if url.starts_with("oci://") {
+        return Ok(Arc::new(oci::OciSource::from_url(
+            url,
+            client.inner().clone(),
+        )?));
+    }
+    Ok(Arc::new(http::HttpSource::new(
+        client.clone(),
+        vec![url.to_string()],
+    )))
+}
@@ -0,0 +1,645 @@
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use anyhow::{Context, Result, anyhow};
+use async_trait::async_trait;
+use base64::Engine;
+use futures_util::StreamExt;
+use reqwest::header::{ACCEPT, WWW_AUTHENTICATE};
+use reqwest::{Client, RequestBuilder, Response, StatusCode};
+use serde::Deserialize;
+use serde_json::Value;
+use tokio::sync::OnceCell;
+
+use super::{ByteStream, ImageSource, SourceInfo};
+use crate::core::catalog;
+
+const MANIFEST_TYPES: &[&str] = &[
+    "application/vnd.oci.image.index.v1+json",
+    "application/vnd.oci.image.manifest.v1+json",
+    "application/vnd.docker.distribution.manifest.list.v2+json",
+    "application/vnd.docker.distribution.manifest.v2+json",
+];
+
+/// Layer titles that mark a disk image, checked before media types
+const IMAGE_TITLE_SUFFIXES: &[&str] = &[".iso", ".img", ".raw"];
+
+/// Media type fragments used for disk image layers
+/// (e.g. `application/x-iso9660-image`, `application/vnd.acme.disk.raw`)
+const IMAGE_MEDIA_HINTS: &[&str] = &["iso9660", "disk", "raw", "efi.img"];
+
+const TITLE_ANNOTATION: &str = "org.opencontainers.image.title";
+
+/// `oci://registry/repository:tag` or `oci://registry/repository@sha256:...`
+#[derive(Debug, Clone, PartialEq)]
+pub struct OciReference {
+    pub registry: String,
+    pub repository: String,
+    /// Tag or digest
+    pub reference: String,
+}
+
+impl OciReference {
+    pub fn parse(url: &str) -> Result<Self> {
+        let rest = url
+            .strip_prefix("oci://")
+            .ok_or_else(|| anyhow!("Expected oci://registry/repository:tag, got {}", url))?;
+        let (registry, path) = rest
+            .split_once('/')
+            .filter(|(registry, path)| !registry.is_empty() && !path.is_empty())
+            .ok_or_else(|| anyhow!("Expected oci://registry/repository:tag, got {}", url))?;
+
+        let (repository, reference) = if let Some((repo, digest)) = path.split_once('@') {
+            (repo, digest)
+        } else {
+            match path.rsplit_once(':') {
+                // A colon before the last `/` would be part of the repository name
+                Some((repo, tag)) if !tag.contains('/') => (repo, tag),
+                _ => (path, "latest"),
+            }
+        };
+        if repository.is_empty() || reference.is_empty() {
+            return Err(anyhow!(
+                "Expected oci://registry/repository:tag, got {}",
+                url
+            ));
+        }
+
+        // Docker Hub serves its API from another host and namespaces official images
+        let (registry, repository) = match registry {
+            "docker.io" | "index.docker.io" => (
+                "registry-1.docker.io".to_string(),
+                if repository.contains('/') {
+                    repository.to_string()
+                } else {
+                    format!("library/{}", repository)
+                },
+            ),
+            _ => (registry.to_string(), repository.to_string()),
+        };
+
+        Ok(Self {
+            registry,
+            repository,
+            reference: reference.to_string(),
+        })
+    }
+
+    /// Registries on the local machine (e.g. a `registry:2` container) are plain HTTP
+    fn base_url(&self) -> String {
+        let host = self
+            .registry
+            .rsplit_once(':')
+            .filter(|(_, port)| port.chars().all(|c| c.is_ascii_digit()))
+            .map_or(self.registry.as_str(), |(host, _)| host);
+        let scheme = match host {
+            "localhost" | "127.0.0.1" | "[::1]" => "http",
+            _ => "https",
+        };
+        format!("{}://{}", scheme, self.registry)
+    }
+}
+
+/// Content descriptor from a manifest or index
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Descriptor {
+    pub media_type: String,
+    pub digest: String,
+    pub size: u64,
+    #[serde(default)]
+    pub annotations: std::collections::HashMap<String, String>,
+    #[serde(default)]
+    pub platform: Option<Platform>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Platform {
+    pub architecture: String,
+    #[serde(default)]
+    pub os: String,
+}
+
+/// How to authenticate against the registry
+#[derive(Debug, Clone, PartialEq)]
+enum Auth {
+    Anonymous,
+    Basic {
+        username: String,
+        password: String,
+    },
+    /// Registry token from the Docker config, sent as-is
+    Bearer(String),
+}
+
+/// Disk image stored as a layer of an OCI artifact
+pub struct OciSource {
+    client: Client,
+    reference: OciReference,
+    auth: Auth,
+    /// Bearer token obtained from the registry's token service
+    token: Mutex<Option<String>>,
+    layer: OnceCell<Descriptor>,
+}
+
+impl OciSource {
+    /// Resolve an `oci://` URL, picking up credentials from the Docker config
+    /// (`$DOCKER_CONFIG/config.json` or `~/.docker/config.json`) if present.
+    pub fn from_url(url: &str, client: Client) -> Result<Self> {
+        let reference = OciReference::parse(url)?;
+        let auth = docker_config()
+            .map(|config| registry_auth(&config, &reference.registry))
+            .unwrap_or(Auth::Anonymous);
+        Ok(Self {
+            client,
+            reference,
+            auth,
+            token: Mutex::new(None),
+            layer: OnceCell::new(),
+        })
+    }
+
+    fn api_url(&self, kind: &str, reference: &str) -> String {
+        format!(
+            "{}/v2/{}/{}/{}",
+            self.reference.base_url(),
+            self.reference.repository,
+            kind,
+            reference
+        )
+    }
+
+    fn authorize(&self, request: RequestBuilder) -> RequestBuilder {
+        if let Some(token) = self.token.lock().unwrap().as_ref() {
+            return request.bearer_auth(token);
+        }
+        match &self.auth {
+            Auth::Anonymous => request,
+            Auth::Basic { username, password } => request.basic_auth(username, Some(password)),
+            Auth::Bearer(token) => request.bearer_auth(token),
+        }
+    }
+
+    /// Send a request, answering a 401 challenge once (token service or basic auth)
+    async fn send(&self, build: impl Fn() -> RequestBuilder) -> Result<Response> {
+        let resp = self.authorize(build()).send().await?;
+        if resp.status() != StatusCode::UNAUTHORIZED {
+            return Ok(resp);
+        }
+
+        let challenge = resp
+            .headers()
+            .get(WWW_AUTHENTICATE)
+            .and_then(|v| v.to_str().ok())
+            .map(parse_challenge)
+            .ok_or_else(|| anyhow!("{} requires authentication", self.reference.registry))?;
+
+        match challenge.0.as_str() {
+            "bearer" => {
+                let token = self.fetch_token(&challenge.1).await?;
+                *self.token.lock().unwrap() = Some(token);
+            }
+            "basic" if matches!(self.auth, Auth::Basic { .. }) => {
+                return Err(anyhow!(
+                    "{} rejected the credentials from the Docker config",
+                    self.reference.registry
+                ));
+            }
+            _ => {
+                return Err(anyhow!(
+                    "{} requires credentials; run `docker login {}` first",
+                    self.reference.registry,
+                    self.reference.registry
+                ));
+            }
+        }
+        Ok(self.authorize(build()).send().await?)
+    }
+
+    /// Get a pull token from the realm named in a Bearer challenge
+    async fn fetch_token(&self, params: &[(String, String)]) -> Result<String> {
+        let param = |name: &str| {
+            params
+                .iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.clone())
+        };
+        let realm = param("realm").ok_or_else(|| anyhow!("Auth challenge has no realm"))?;
+        let scope = param("scope")
+            .unwrap_or_else(|| format!("repository:{}:pull", self.reference.repository));
+
+        let mut query = vec![("scope", scope)];
+        if let Some(service) = param("service") {
+            query.push(("service", service));
+        }
+        let mut request = self.client.get(&realm).query(&query);
+        if let Auth::Basic { username, password } = &self.auth {
+            request = request.basic_auth(username, Some(password));
+        }
+
+        let resp = request.send().await?;
+        if !resp.status().is_success() {
+            return Err(anyhow!(
+                "Token request to {} failed: {}",
+                realm,
+                resp.status()
+            ));
+        }
+        let body: Value = serde_json::from_str(&resp.text().await?)?;
+        body.get("token")
+            .or_else(|| body.get("access_token"))
+            .and_then(Value::as_str)
+            .map(str::to_string)
+            .ok_or_else(|| anyhow!("Token service returned no token"))
+    }
+
+    async fn fetch_manifest(&self, reference: &str) -> Result<Value> {
+        let url = self.api_url("manifests", reference);
+        let resp = self
+            .send(|| {
+                self.client
+                    .get(&url)
+                    .header(ACCEPT, MANIFEST_TYPES.join(", "))
+            })
+            .await?;
+        if !resp.status().is_success() {
+            return Err(anyhow!(
+                "Failed to fetch manifest {}: {}",
+                self.describe(),
+                resp.status()
+            ));
+        }
+        serde_json::from_str(&resp.text().await?).context("Manifest is not valid JSON")
+    }
+
+    /// Follow an index to the manifest for this machine, then pick the image layer
+    async fn resolve_layer(&self) -> Result<Descriptor> {
+        let mut manifest = self.fetch_manifest(&self.reference.reference).await?;
+        if let Some(manifests) = manifest.get("manifests") {
+            let entries: Vec<Descriptor> = serde_json::from_value(manifests.clone())?;
+            let entry = pick_manifest(&entries)
+                .ok_or_else(|| anyhow!("Image index for {} is empty", self.describe()))?;
+            manifest = self.fetch_manifest(&entry.digest).await?;
+        }
+
+        let layers: Vec<Descriptor> = manifest
+            .get("layers")
+            .map(|layers| serde_json::from_value(layers.clone()))
+            .transpose()?
+            .unwrap_or_default();
+        pick_layer(&layers).cloned()
+    }
+
+    async fn layer(&self) -> Result<&Descriptor> {
+        self.layer.get_or_try_init(|| self.resolve_layer()).await
+    }
+}
+
+#[async_trait]
+impl ImageSource for OciSource {
+    fn describe(&self) -> String {
+        let separator = if self.reference.reference.contains(':') {
+            '@'
+        } else {
+            ':'
+        };
+        format!(
+            "oci://{}/{}{}{}",
+            self.reference.registry, self.reference.repository, separator, self.reference.reference
+        )
+    }
+
+    async fn probe(&self) -> Result<SourceInfo> {
+        let layer = self.layer().await?;
+        Ok(SourceInfo {
+            total_bytes: layer.size,
+            // Blobs are content-addressed, so the digest doubles as the checksum
+            sha256: layer.digest.strip_prefix("sha256:").map(str::to_string),
+            pieces: None,
+        })
+    }
+
+    async fn open(&self, offset: u64) -> Result<ByteStream> {
+        let layer = self.layer().await?;
+        let url = self.api_url("blobs", &layer.digest);
+        let resp = self
+            .send(|| {
+                let request = self.client.get(&url);
+                if offset > 0 {
+                    request.header(reqwest::header::RANGE, format!("bytes={}-", offset))
+                } else {
+                    request
+                }
+            })
+            .await?;
+
+        let expected = if offset > 0 {
+            StatusCode::PARTIAL_CONTENT
+        } else {
+            StatusCode::OK
+        };
+        if resp.status() != expected {
+            return Err(anyhow!(
+                "Failed to download {}: {}",
+                self.describe(),
+                resp.status()
+            ));
+        }
+
+        let stream = resp
+            .bytes_stream()
+            .map(|chunk| chunk.map(|c| c.to_vec()).map_err(Into::into));
+        Ok(Box::pin(stream))
+    }
+}
+
+/// Prefer the entry built for this machine's architecture
+fn pick_manifest(entries: &[Descriptor]) -> Option<&Descriptor> {
+    let host = catalog::host_arch();
+    entries
+        .iter()
+        .find(|entry| {
+            entry
+                .platform
+                .as_ref()
+                .is_some_and(|p| catalog::normalize_arch(&p.architecture) == host)
+        })
+        .or_else(|| entries.iter().find(|entry| entry.platform.is_none()))
+        .or_else(|| entries.first())
+}
+
+/// Pick the disk image layer: by title annotation, then by media type, then
+/// the only layer. Tarred container layers can't be written to a device.
+fn pick_layer(layers: &[Descriptor]) -> Result<&Descriptor> {
+    let by_title = layers.iter().find(|layer| {
+        layer
+            .annotations
+            .get(TITLE_ANNOTATION)
+            .map(|title| title.to_lowercase())
+            .is_some_and(|title| IMAGE_TITLE_SUFFIXES.iter().any(|s| title.ends_with(s)))
+    });
+    let by_media_type = || {
+        layers.iter().find(|layer| {
+            let media_type = layer.media_type.to_lowercase();
+            IMAGE_MEDIA_HINTS
+                .iter()
+                .any(|hint| media_type.contains(hint))
+        })
+    };
+    let only = || match layers {
+        [layer] if !layer.media_type.contains("tar") => Some(layer),
+        _ => None,
+    };
+
+    by_title
+        .or_else(by_media_type)
+        .or_else(only)
+        .ok_or_else(|| {
+            let found: Vec<&str> = layers.iter().map(|l| l.media_type.as_str()).collect();
+            anyhow!(
+                "No disk image layer in manifest (layers: {})",
+                if found.is_empty() {
+                    "none".to_string()
+                } else {
+                    found.join(", ")
+                }
+            )
+        })
+}
+
+/// Split a `WWW-Authenticate` header into its lowercased scheme and parameters
+fn parse_challenge(header: &str) -> (String, Vec<(String, String)>) {
+    let (scheme, rest) = header.trim().split_once(' ').unwrap_or((header.trim(), ""));
+    let mut params = Vec::new();
+    let mut chars = rest.chars().peekable();
+    loop {
+        while chars.next_if(|c| *c == ',' || c.is_whitespace()).is_some() {}
+        let key: String = std::iter::from_fn(|| chars.next_if(|c| *c != '=')).collect();
+        if key.is_empty() || chars.next().is_none() {
+            break;
+        }
+        let value: String = if chars.next_if_eq(&'"').is_some() {
+            let value = std::iter::from_fn(|| chars.next_if(|c| *c != '"')).collect();
+            chars.next();
+            value
+        } else {
+            std::iter::from_fn(|| chars.next_if(|c| *c != ',')).collect()
+        };
+        params.push((key.trim().to_lowercase(), value));
+    }
+    (scheme.to_lowercase(), params)
+}
+
+fn docker_config() -> Option<Value> {
+    let dir = std::env::var_os("DOCKER_CONFIG")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".docker")))?;
+    let contents = std::fs::read_to_string(dir.join("config.json")).ok()?;
+    serde_json::from_str(&contents).ok()
+}
+
+/// Credentials for `registry` from a Docker `config.json`. Credential helpers
+/// aren't consulted.
+fn registry_auth(config: &Value, registry: &str) -> Auth {
+    let keys: Vec<String> = if registry == "registry-1.docker.io" {
+        vec![
+            "https://index.docker.io/v1/".to_string(),
+            "docker.io".to_string(),
+        ]
+    } else {
+        vec![registry.to_string(), format!("https://{}", registry)]
+    };
+    let Some(entry) = keys
+        .iter()
+        .find_map(|key| config.get("auths").and_then(|auths| auths.get(key)))
+    else {
+        return Auth::Anonymous;
+    };
+    let field = |name: &str| {
+        entry
+            .get(name)
+            .and_then(Value::as_str)
+            .filter(|v| !v.is_empty())
+    };
+
+    if let Some(token) = field("registrytoken") {
+        return Auth::Bearer(token.to_string());
+    }
+    let decoded = field("auth")
+        .and_then(|auth| base64::engine::general_purpose::STANDARD.decode(auth).ok())
+        .and_then(|raw| String::from_utf8(raw).ok());
+    if let Some((username, password)) = decoded.as_deref().and_then(|d| d.split_once(':')) {
+        return Auth::Basic {
+            username: username.to_string(),
+            password: password.to_string(),
+        };
+    }
+    match (field("username"), field("password")) {
+        (Some(username), Some(password)) => Auth::Basic {
+            username: username.to_string(),
+            password: password.to_string(),
+        },
+        _ => Auth::Anonymous,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn test_parse_reference() {
+        let r = OciReference::parse("oci://localhost:5000/appliances/edge:1.4").unwrap();
+        assert_eq!(r.registry, "localhost:5000");
+        assert_eq!(r.repository, "appliances/edge");
+        assert_eq!(r.reference, "1.4");
+        assert_eq!(r.base_url(), "http://localhost:5000");
+
+        let r = OciReference::parse("oci://ghcr.io/acme/edge@sha256:abc").unwrap();
+        assert_eq!(r.reference, "sha256:abc");
+        assert_eq!(r.base_url(), "https://ghcr.io");
+
+        let r = OciReference::parse("oci://docker.io/alpine").unwrap();
+        assert_eq!(r.registry, "registry-1.docker.io");
+        assert_eq!(r.repository, "library/alpine");
+        assert_eq!(r.reference, "latest");
+
+        assert!(OciReference::parse("oci://ghcr.io").is_err());
+    }
+
+    #[test]
+    fn test_pick_layer() {
+        let layers: Vec<Descriptor> = serde_json::from_str(
+            r#"[
+                {"mediaType": "application/vnd.oci.image.layer.v1.tar+gzip", "digest": "sha256:a", "size": 1},
+                {"mediaType": "application/octet-stream", "digest": "sha256:b", "size": 2,
+                 "annotations": {"org.opencontainers.image.title": "edge-1.4.img"}},
+                {"mediaType": "application/x-iso9660-image", "digest": "sha256:c", "size": 3}
+            ]"#,
+        )
+        .unwrap();
+        assert_eq!(pick_layer(&layers).unwrap().digest, "sha256:b");
+        assert_eq!(pick_layer(&layers[2..]).unwrap().digest, "sha256:c");
+        assert!(pick_layer(&layers[..1]).is_err());
+    }
+
+    #[test]
+    fn test_parse_challenge() {
+        let (scheme, params) = parse_challenge(
+            r#"Bearer realm="https://auth.example.com/token",service="registry.example.com",scope="repository:a/b:pull,push""#,
+        );
+        assert_eq!(scheme, "bearer");
+        assert_eq!(
+            params,
+            vec![
+                (
+                    "realm".to_string(),
+                    "https://auth.example.com/token".to_string()
+                ),
+                ("service".to_string(), "registry.example.com".to_string()),
+                ("scope".to_string(), "repository:a/b:pull,push".to_string()),
+            ]
+        );
+    }
+
+    /// Minimal stand-in for a registry behind a token service: manifests and
+    /// blobs need `Bearer test-token`, which `/token` hands out anonymously.
+    async fn serve_registry(blob: Vec<u8>, digest: String) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let manifest = serde_json::json!({
+            "schemaVersion": 2,
+            "mediaType": "application/vnd.oci.image.manifest.v1+json",
+            "artifactType": "application/vnd.acme.appliance",
+            "config": {"mediaType": "application/vnd.oci.empty.v1+json", "digest": "sha256:44136fa355b3678a1146ad16f7e8649e94fb4fc21fe77e8310c060f61caaff8a", "size": 2},
+            "layers": [{
+                "mediaType": "application/octet-stream",
+                "digest": digest,
+                "size": blob.len(),
+                "annotations": {"org.opencontainers.image.title": "edge.img"}
+            }]
+        })
+        .to_string();
+
+        tokio::spawn(async move {
+            loop {
+                let Ok((mut socket, _)) = listener.accept().await else {
+                    break;
+                };
+                let mut buf = vec![0; 4096];
+                let n = socket.read(&mut buf).await.unwrap_or(0);
+                let request = String::from_utf8_lossy(&buf[..n]).to_string();
+                let path = request.split_whitespace().nth(1).unwrap_or("").to_string();
+                let authorized = request.contains("Bearer test-token");
+                let range_start = request
+                    .lines()
+                    .find_map(|l| l.strip_prefix("range: bytes="))
+                    .and_then(|r| r.trim_end_matches('-').parse::<usize>().ok());
+
+                let (status, extra, body): (&str, String, Vec<u8>) = if path.starts_with("/token") {
+                    (
+                        "200 OK",
+                        String::new(),
+                        br#"{"token":"test-token"}"#.to_vec(),
+                    )
+                } else if !authorized {
+                    (
+                        "401 Unauthorized",
+                        format!(
+                            "WWW-Authenticate: Bearer realm=\"http://{}/token\",service=\"test\"\r\n",
+                            addr
+                        ),
+                        Vec::new(),
+                    )
+                } else if path.contains("/manifests/") {
+                    ("200 OK", String::new(), manifest.clone().into_bytes())
+                } else if path.contains("/blobs/") {
+                    match range_start {
+                        Some(start) => {
+                            ("206 Partial Content", String::new(), blob[start..].to_vec())
+                        }
+                        None => ("200 OK", String::new(), blob.clone()),
+                    }
+                } else {
+                    ("404 Not Found", String::new(), Vec::new())
+                };
+
+                let head = format!(
+                    "HTTP/1.1 {}\r\n{}Content-Length: {}\r\nConnection: close\r\n\r\n",
+                    status,
+                    extra,
+                    body.len()
+                );
+                let _ = socket.write_all(head.as_bytes()).await;
+                let _ = socket.write_all(&body).await;
+            }
+        });
+        format!("oci://{}/appliances/edge:1.4", addr)
+    }
+
+    #[tokio::test]
+    async fn test_pull_from_local_registry() {
+        use sha2::{Digest, Sha256};
+
+        let blob: Vec<u8> = (0..=255u8).cycle().take(10_000).collect();
+        let hex = format!("{:x}", Sha256::digest(&blob));
+        let url = serve_registry(blob.clone(), format!("sha256:{}", hex)).await;
+
+        let source = OciSource {
+            auth: Auth::Anonymous,
+            ..OciSource::from_url(&url, Client::new()).unwrap()
+        };
+        let info = source.probe().await.unwrap();
+        assert_eq!(info.total_bytes, blob.len() as u64);
+        assert_eq!(info.sha256.as_deref(), Some(hex.as_str()));
+
+        let mut stream = source.open(4096).await.unwrap();
+        let mut received = Vec::new();
+        while let Some(chunk) = stream.next().await {
+            received.extend(chunk.unwrap());
+        }
+        assert_eq!(received, blob[4096..]);
+    }
+}
@@ -0,0 +1,232 @@
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+use anyhow::{Result, anyhow};
+
+/// Nested containers deeper than this are rejected rather than recursed into
+const MAX_DEPTH: usize = 64;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Int(i64),
+    Bytes(Vec<u8>),
+    List(Vec<Value>),
+    Dict(BTreeMap<Vec<u8>, Value>),
+}
+
+impl Value {
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Dict(dict) => dict.get(key.as_bytes()),
+            _ => None,
+        }
+    }
+
+    pub fn as_int(&self) -> Option<i64> {
+        match self {
+            Value::Int(i) => Some(*i),
+            _ => None,
+        }
+    }
+
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Value::Bytes(b) => Some(b),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        self.as_bytes().and_then(|b| std::str::from_utf8(b).ok())
+    }
+
+    pub fn as_list(&self) -> Option<&[Value]> {
+        match self {
+            Value::List(list) => Some(list),
+            _ => None,
+        }
+    }
+
+    /// Dictionary from string keys, for building messages
+    pub fn dict<const N: usize>(entries: [(&str, Value); N]) -> Value {
+        Value::Dict(
+            entries
+                .into_iter()
+                .map(|(key, value)| (key.as_bytes().to_vec(), value))
+                .collect(),
+        )
+    }
+}
+
+/// Decode a complete bencoded document
+pub fn decode(data: &[u8]) -> Result<Value> {
+    let (value, used) = decode_prefix(data)?;
+    if used != data.len() {
+        return Err(anyhow!("Trailing data after bencoded value"));
+    }
+    Ok(value)
+}
+
+/// Decode the value at the start of `data`, returning it and its length.
+/// Metadata messages carry raw bytes right after their bencoded header.
+pub fn decode_prefix(data: &[u8]) -> Result<(Value, usize)> {
+    let mut decoder = Decoder { data, pos: 0 };
+    let value = decoder.value(0)?;
+    Ok((value, decoder.pos))
+}
+
+/// Byte range of `key`'s value in a top-level dictionary. The info hash is
+/// taken over the original bytes, which re-encoding might not reproduce.
+pub fn dict_value_span(data: &[u8], key: &str) -> Result<Option<Range<usize>>> {
+    let mut decoder = Decoder { data, pos: 0 };
+    decoder.expect(b'd')?;
+    while decoder.peek()? != b'e' {
+        let name = decoder.bytes()?;
+        let start = decoder.pos;
+        decoder.value(1)?;
+        if name == key.as_bytes() {
+            return Ok(Some(start..decoder.pos));
+        }
+    }
+    Ok(None)
+}
+
+pub fn encode(value: &Value) -> Vec<u8> {
+    let mut out = Vec::new();
+    encode_into(value, &mut out);
+    out
+}
+
+fn encode_into(value: &Value, out: &mut Vec<u8>) {
+    match value {
+        Value::Int(i) => out.extend_from_slice(format!("i{}e", i).as_bytes()),
+        Value::Bytes(b) => {
+            out.extend_from_slice(format!("{}:", b.len()).as_bytes());
+            out.extend_from_slice(b);
+        }
+        Value::List(list) => {
+            out.push(b'l');
+            list.iter().for_each(|v| encode_into(v, out));
+            out.push(b'e');
+        }
+        Value::Dict(dict) => {
+            out.push(b'd');
+            for (key, v) in dict {
+                encode_into(&Value::Bytes(key.clone()), out);
+                encode_into(v, out);
+            }
+            out.push(b'e');
+        }
+    }
+}
+
+struct Decoder<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl Decoder<'_> {
+    fn peek(&self) -> Result<u8> {
+        self.data
+            .get(self.pos)
+            .copied()
+            .ok_or_else(|| anyhow!("Unexpected end of bencoded data"))
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<()> {
+        if self.peek()? != byte {
+            return Err(anyhow!("Expected '{}' at byte {}", byte as char, self.pos));
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    /// Digits up to (and consuming) `terminator`
+    fn number(&mut self, terminator: u8) -> Result<i64> {
+        let end = self.data[self.pos..]
+            .iter()
+            .position(|b| *b == terminator)
+            .ok_or_else(|| anyhow!("Unterminated number at byte {}", self.pos))?;
+        let digits = std::str::from_utf8(&self.data[self.pos..self.pos + end])?;
+        let number = digits
+            .parse()
+            .map_err(|_| anyhow!("Invalid number '{}'", digits))?;
+        self.pos += end + 1;
+        Ok(number)
+    }
+
+    fn bytes(&mut self) -> Result<Vec<u8>> {
+        let len = usize::try_from(self.number(b':')?)?;
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|end| *end <= self.data.len())
+            .ok_or_else(|| anyhow!("String runs past end of data"))?;
+        let bytes = self.data[self.pos..end].to_vec();
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn value(&mut self, depth: usize) -> Result<Value> {
+        if depth > MAX_DEPTH {
+            return Err(anyhow!("Bencoded data nested too deeply"));
+        }
+        match self.peek()? {
+            b'i' => {
+                self.pos += 1;
+                Ok(Value::Int(self.number(b'e')?))
+            }
+            b'l' => {
+                self.pos += 1;
+                let mut list = Vec::new();
+                while self.peek()? != b'e' {
+                    list.push(self.value(depth + 1)?);
+                }
+                self.pos += 1;
+                Ok(Value::List(list))
+            }
+            b'd' => {
+                self.pos += 1;
+                let mut dict = BTreeMap::new();
+                while self.peek()? != b'e' {
+                    let key = self.bytes()?;
+                    dict.insert(key, self.value(depth + 1)?);
+                }
+                self.pos += 1;
+                Ok(Value::Dict(dict))
+            }
+            b'0'..=b'9' => Ok(Value::Bytes(self.bytes()?)),
+            other => Err(anyhow!(
+                "Unexpected '{}' at byte {}",
+                other as char,
+                self.pos
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip_and_span() {
+        let data = b"d8:announce14:http://tracker4:infod6:lengthi42e4:name5:a.isoee";
+        let value = decode(data).unwrap();
+        assert_eq!(
+            value.get("announce").unwrap().as_str(),
+            Some("http://tracker")
+        );
+        assert_eq!(
+            value.get("info").unwrap().get("length").unwrap().as_int(),
+            Some(42)
+        );
+        assert_eq!(encode(&value), data.to_vec());
+
+        let span = dict_value_span(data, "info").unwrap().unwrap();
+        assert_eq!(&data[span], b"d6:lengthi42e4:name5:a.isoe");
+
+        assert!(decode(b"l4:spam").is_err());
+        assert!(decode(b"5:ab").is_err());
+    }
+}
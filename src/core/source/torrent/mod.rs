@@ -0,0 +1,1060 @@
+pub mod bencode;
+mod peer;
+mod tracker;
+
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+
+use anyhow::{Context, Result, anyhow};
+use async_trait::async_trait;
+use futures_util::future::join_all;
+use reqwest::Url;
+use sha1::{Digest, Sha1};
+use tokio::sync::{Notify, OnceCell, mpsc};
+use tokio::task::JoinSet;
+use tokio::time::{Instant, sleep_until, timeout};
+
+use self::bencode::Value;
+use self::peer::{Bitfield, Message, PeerConnection, PeerWriter};
+use super::{ByteStream, ImageSource, PieceAlgorithm, PieceHashes, SourceInfo};
+use crate::core::net::HttpClient;
+
+/// Request size every client accepts
+const BLOCK_SIZE: u64 = 16 * 1024;
+/// Block requests kept in flight per peer
+const PIPELINE_DEPTH: usize = 16;
+const MAX_PEERS: usize = 40;
+/// How far ahead of the writer pieces may be downloaded
+const WINDOW_BYTES: u64 = 64 * 1024 * 1024;
+/// Pieces at the head of the window that idle peers may download in
+/// parallel with a slower peer, so one slow peer can't stall the device writer
+const HEAD_DUPLICATES: u32 = 4;
+/// Longest wait for the next block of a piece before giving up on the peer
+const BLOCK_TIMEOUT: Duration = Duration::from_secs(30);
+const MAX_METADATA_BYTES: usize = 16 * 1024 * 1024;
+/// Announce rounds without any usable peer before giving up
+const MAX_EMPTY_ROUNDS: u32 = 4;
+
+/// Whether a URL is a magnet link or points at a `.torrent` file
+pub fn is_torrent_url(url: &str) -> bool {
+    let path = url.split(['?', '#']).next().unwrap_or(url).to_lowercase();
+    url.starts_with("magnet:") || path.ends_with(".torrent")
+}
+
+/// The single-file torrent an image is shared as
+#[derive(Debug, Clone, PartialEq)]
+pub struct Metainfo {
+    pub info_hash: [u8; 20],
+    pub name: String,
+    pub length: u64,
+    pub piece_length: u64,
+    pub pieces: Vec<[u8; 20]>,
+    pub trackers: Vec<String>,
+}
+
+impl Metainfo {
+    /// Parse a `.torrent` file
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let torrent = bencode::decode(data)?;
+        let span = bencode::dict_value_span(data, "info")?
+            .ok_or_else(|| anyhow!("Torrent has no info dictionary"))?;
+
+        // announce-list tiers take precedence over the single announce URL
+        let mut trackers: Vec<String> = torrent
+            .get("announce-list")
+            .and_then(Value::as_list)
+            .unwrap_or_default()
+            .iter()
+            .filter_map(Value::as_list)
+            .flatten()
+            .filter_map(Value::as_str)
+            .map(str::to_string)
+            .collect();
+        if let Some(announce) = torrent.get("announce").and_then(Value::as_str)
+            && !trackers.iter().any(|t| t == announce)
+        {
+            trackers.push(announce.to_string());
+        }
+
+        Self::from_info(&data[span], trackers)
+    }
+
+    /// Build from the raw bencoded info dictionary (from a torrent file or
+    /// fetched from peers for a magnet link)
+    fn from_info(info_bytes: &[u8], trackers: Vec<String>) -> Result<Self> {
+        let info = bencode::decode(info_bytes)?;
+        if info.get("files").is_some() {
+            return Err(anyhow!(
+                "Multi-file torrents are not supported; use the torrent for a single image"
+            ));
+        }
+        let name = info
+            .get("name")
+            .and_then(Value::as_str)
+            .unwrap_or("image")
+            .to_string();
+        let length = info
+            .get("length")
+            .and_then(Value::as_int)
+            .and_then(|l| u64::try_from(l).ok())
+            .ok_or_else(|| anyhow!("Torrent info has no length"))?;
+        let piece_length = info
+            .get("piece length")
+            .and_then(Value::as_int)
+            .and_then(|l| u64::try_from(l).ok())
+            .filter(|l| *l > 0)
+            .ok_or_else(|| anyhow!("Torrent info has no piece length"))?;
+        let hashes = info
+            .get("pieces")
+            .and_then(Value::as_bytes)
+            .filter(|p| p.len() % 20 == 0)
+            .ok_or_else(|| anyhow!("Torrent info has no piece hashes"))?;
+        let pieces: Vec<[u8; 20]> = hashes
+            .chunks_exact(20)
+            .map(|hash| hash.try_into().expect("chunk is 20 bytes"))
+            .collect();
+        if pieces.len() as u64 != length.div_ceil(piece_length) {
+            return Err(anyhow!("Torrent piece count does not match its length"));
+        }
+
+        Ok(Self {
+            info_hash: Sha1::digest(info_bytes).into(),
+            name,
+            length,
+            piece_length,
+            pieces,
+            trackers,
+        })
+    }
+
+    fn piece_count(&self) -> u32 {
+        self.pieces.len() as u32
+    }
+
+    fn piece_len(&self, index: u32) -> u64 {
+        let start = u64::from(index) * self.piece_length;
+        self.piece_length.min(self.length - start)
+    }
+}
+
+/// Parsed `magnet:?xt=urn:btih:...` link
+#[derive(Debug, Clone, PartialEq)]
+pub struct Magnet {
+    pub info_hash: [u8; 20],
+    pub name: Option<String>,
+    pub trackers: Vec<String>,
+}
+
+impl Magnet {
+    pub fn parse(uri: &str) -> Result<Self> {
+        let url = Url::parse(uri).context("Invalid magnet link")?;
+        if url.scheme() != "magnet" {
+            return Err(anyhow!("Not a magnet link"));
+        }
+        let mut info_hash = None;
+        let mut name = None;
+        let mut trackers = Vec::new();
+        for (key, value) in url.query_pairs() {
+            match key.as_ref() {
+                "xt" => {
+                    if let Some(hash) = value.strip_prefix("urn:btih:") {
+                        info_hash = decode_info_hash(hash);
+                    }
+                }
+                "dn" => name = Some(value.to_string()),
+                "tr" => trackers.push(value.to_string()),
+                _ => {}
+            }
+        }
+        Ok(Self {
+            info_hash: info_hash
+                .ok_or_else(|| anyhow!("Magnet link has no BitTorrent v1 info hash"))?,
+            name,
+            trackers,
+        })
+    }
+}
+
+/// Info hashes come as 40 hex digits or 32 base32 characters
+fn decode_info_hash(hash: &str) -> Option<[u8; 20]> {
+    let bytes: Vec<u8> = match hash.len() {
+        40 => (0..40)
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hash[i..i + 2], 16).ok())
+            .collect::<Option<_>>()?,
+        32 => {
+            let mut bits = 0u32;
+            let mut bit_count = 0;
+            let mut out = Vec::with_capacity(20);
+            for c in hash.to_ascii_uppercase().bytes() {
+                let value = match c {
+                    b'A'..=b'Z' => c - b'A',
+                    b'2'..=b'7' => c - b'2' + 26,
+                    _ => return None,
+                };
+                // Only the bits not yet emitted matter
+                bits = ((bits << 5) | u32::from(value)) & 0xffff;
+                bit_count += 5;
+                if bit_count >= 8 {
+                    bit_count -= 8;
+                    out.push((bits >> bit_count) as u8);
+                }
+            }
+            out
+        }
+        _ => return None,
+    };
+    bytes.try_into().ok()
+}
+
+/// Info dictionaries fetched for magnet links, so pre-flight and the flash
+/// itself don't each ask the swarm for them
+fn metadata_cache() -> &'static Mutex<HashMap<[u8; 20], Metainfo>> {
+    static CACHE: OnceLock<Mutex<HashMap<[u8; 20], Metainfo>>> = OnceLock::new();
+    CACHE.get_or_init(Default::default)
+}
+
+enum Origin {
+    TorrentFile(String),
+    Magnet(Magnet),
+}
+
+/// Swarm counters shown in the progress panel
+#[derive(Default)]
+struct SwarmStatus {
+    peers: AtomicUsize,
+    pieces_done: AtomicU32,
+    pieces_total: AtomicU32,
+}
+
+/// Image shared over BitTorrent, from a `.torrent` URL or a magnet link.
+/// Pieces are fetched roughly in order and handed out sequentially so they
+/// can go straight to the device.
+pub struct TorrentSource {
+    client: HttpClient,
+    origin: Origin,
+    peer_id: [u8; 20],
+    metainfo: OnceCell<Arc<Metainfo>>,
+    status: Arc<SwarmStatus>,
+}
+
+impl TorrentSource {
+    pub fn from_url(url: &str, client: HttpClient) -> Result<Self> {
+        let origin = if url.starts_with("magnet:") {
+            Origin::Magnet(Magnet::parse(url)?)
+        } else {
+            Origin::TorrentFile(url.to_string())
+        };
+        Ok(Self {
+            client,
+            origin,
+            peer_id: peer_id(),
+            metainfo: OnceCell::new(),
+            status: Arc::new(SwarmStatus::default()),
+        })
+    }
+
+    async fn metainfo(&self) -> Result<Arc<Metainfo>> {
+        self.metainfo
+            .get_or_try_init(|| async {
+                let metainfo = match &self.origin {
+                    Origin::TorrentFile(url) => self.fetch_torrent(url).await?,
+                    Origin::Magnet(magnet) => self.fetch_metadata(magnet).await?,
+                };
+                self.status
+                    .pieces_total
+                    .store(metainfo.piece_count(), Ordering::Relaxed);
+                Ok(Arc::new(metainfo))
+            })
+            .await
+            .cloned()
+    }
+
+    async fn fetch_torrent(&self, url: &str) -> Result<Metainfo> {
+        let resp = self.client.get(url).send().await?;
+        if !resp.status().is_success() {
+            return Err(anyhow!("Failed to fetch {}: {}", url, resp.status()));
+        }
+        let body = resp.bytes().await?;
+        if body.len() > MAX_METADATA_BYTES {
+            return Err(anyhow!("{} is too large to be a torrent file", url));
+        }
+        Metainfo::parse(&body).with_context(|| format!("Invalid torrent file {}", url))
+    }
+
+    /// Get the info dictionary for a magnet link from peers (BEP 9)
+    async fn fetch_metadata(&self, magnet: &Magnet) -> Result<Metainfo> {
+        if let Some(cached) = metadata_cache().lock().unwrap().get(&magnet.info_hash) {
+            return Ok(cached.clone());
+        }
+        if magnet.trackers.is_empty() {
+            return Err(anyhow!(
+                "Magnet link has no trackers (DHT-only magnets are not supported)"
+            ));
+        }
+
+        let (peers, _) = announce_all(
+            &self.client,
+            &magnet.trackers,
+            &magnet.info_hash,
+            &self.peer_id,
+            1,
+        )
+        .await;
+        let mut candidates: VecDeque<SocketAddr> = peers.into_iter().collect();
+        let mut attempts = JoinSet::new();
+        let mut last_error = anyhow!("No peers found for the magnet link");
+        loop {
+            while attempts.len() < 8
+                && let Some(addr) = candidates.pop_front()
+            {
+                let (info_hash, peer_id) = (magnet.info_hash, self.peer_id);
+                attempts.spawn(timeout(
+                    Duration::from_secs(30),
+                    metadata_from_peer(addr, info_hash, peer_id),
+                ));
+            }
+            let Some(joined) = attempts.join_next().await else {
+                return Err(last_error);
+            };
+            match joined {
+                Ok(Ok(Ok(info_bytes))) => {
+                    let metainfo = Metainfo::from_info(&info_bytes, magnet.trackers.clone())?;
+                    metadata_cache()
+                        .lock()
+                        .unwrap()
+                        .insert(magnet.info_hash, metainfo.clone());
+                    return Ok(metainfo);
+                }
+                Ok(Ok(Err(e))) => last_error = e,
+                Ok(Err(_)) => last_error = anyhow!("Peer timed out sending metadata"),
+                Err(e) => last_error = e.into(),
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl ImageSource for TorrentSource {
+    fn describe(&self) -> String {
+        match (self.metainfo.get(), &self.origin) {
+            (Some(metainfo), _) => format!("torrent {}", metainfo.name),
+            (None, Origin::TorrentFile(url)) => url.clone(),
+            (None, Origin::Magnet(magnet)) => format!(
+                "magnet {}",
+                magnet
+                    .name
+                    .clone()
+                    .unwrap_or_else(|| hex(&magnet.info_hash))
+            ),
+        }
+    }
+
+    async fn probe(&self) -> Result<SourceInfo> {
+        let metainfo = self.metainfo().await?;
+        Ok(SourceInfo {
+            total_bytes: metainfo.length,
+            sha256: None,
+            pieces: Some(PieceHashes {
+                algorithm: PieceAlgorithm::Sha1,
+                length: metainfo.piece_length,
+                hashes: metainfo.pieces.iter().map(|hash| hex(hash)).collect(),
+            }),
+        })
+    }
+
+    async fn open(&self, offset: u64) -> Result<ByteStream> {
+        let metainfo = self.metainfo().await?;
+        if offset >= metainfo.length {
+            return Ok(Box::pin(futures_util::stream::empty()));
+        }
+        let first_piece = (offset / metainfo.piece_length) as u32;
+        let skip = (offset % metainfo.piece_length) as usize;
+
+        let swarm = Arc::new(Swarm {
+            scheduler: Mutex::new(Scheduler::new(&metainfo, first_piece)),
+            metainfo,
+            client: self.client.clone(),
+            peer_id: self.peer_id,
+            status: self.status.clone(),
+            progress: Notify::new(),
+        });
+        let (tx, rx) = mpsc::channel(4);
+        tokio::spawn(run_swarm(swarm, first_piece, skip, tx));
+
+        let stream = futures_util::stream::unfold(rx, |mut rx| async move {
+            rx.recv().await.map(|item| (item, rx))
+        });
+        Ok(Box::pin(stream))
+    }
+
+    fn status(&self) -> Option<String> {
+        let total = self.status.pieces_total.load(Ordering::Relaxed);
+        (total > 0).then(|| {
+            format!(
+                "{} peers · piece {}/{}",
+                self.status.peers.load(Ordering::Relaxed),
+                self.status.pieces_done.load(Ordering::Relaxed),
+                total
+            )
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum PieceState {
+    Needed,
+    /// Being downloaded by this many peers
+    InFlight(u32),
+    Done,
+}
+
+/// Hands out pieces in order, never further than the window ahead of the writer
+struct Scheduler {
+    states: Vec<PieceState>,
+    /// Next piece the writer needs
+    head: u32,
+    window: u32,
+}
+
+impl Scheduler {
+    fn new(metainfo: &Metainfo, first_piece: u32) -> Self {
+        Self {
+            states: vec![PieceState::Needed; metainfo.pieces.len()],
+            head: first_piece,
+            window: (WINDOW_BYTES / metainfo.piece_length).max(4) as u32,
+        }
+    }
+
+    /// Lowest needed piece the peer has; failing that, a piece at the head
+    /// of the window that another peer is already fetching
+    fn pick(&mut self, have: &Bitfield) -> Option<u32> {
+        let end = (self.head + self.window).min(self.states.len() as u32);
+        let needed = (self.head..end)
+            .find(|i| self.states[*i as usize] == PieceState::Needed && have.has(*i));
+        if let Some(index) = needed {
+            self.states[index as usize] = PieceState::InFlight(1);
+            return Some(index);
+        }
+
+        let head_end = (self.head + HEAD_DUPLICATES).min(end);
+        let duplicate = (self.head..head_end).find(|i| {
+            matches!(self.states[*i as usize], PieceState::InFlight(n) if n < 3) && have.has(*i)
+        })?;
+        if let PieceState::InFlight(n) = self.states[duplicate as usize] {
+            self.states[duplicate as usize] = PieceState::InFlight(n + 1);
+        }
+        Some(duplicate)
+    }
+
+    /// A peer gave up on a piece
+    fn release(&mut self, index: u32) {
+        let state = &mut self.states[index as usize];
+        *state = match *state {
+            PieceState::InFlight(n) if n > 1 => PieceState::InFlight(n - 1),
+            PieceState::InFlight(_) => PieceState::Needed,
+            other => other,
+        };
+    }
+
+    /// Returns false if another peer already delivered this piece
+    fn complete(&mut self, index: u32) -> bool {
+        let was_done = self.states[index as usize] == PieceState::Done;
+        self.states[index as usize] = PieceState::Done;
+        !was_done
+    }
+}
+
+/// One download session, from `open` until the stream is dropped
+struct Swarm {
+    metainfo: Arc<Metainfo>,
+    client: HttpClient,
+    peer_id: [u8; 20],
+    status: Arc<SwarmStatus>,
+    scheduler: Mutex<Scheduler>,
+    /// Signalled when the window moves or a piece is released
+    progress: Notify,
+}
+
+/// Find peers, run a worker per peer, and pass finished pieces to `tx` in order
+async fn run_swarm(
+    swarm: Arc<Swarm>,
+    first_piece: u32,
+    mut skip: usize,
+    tx: mpsc::Sender<Result<Vec<u8>>>,
+) {
+    let metainfo = swarm.metainfo.clone();
+    let (done_tx, mut done_rx) = mpsc::unbounded_channel::<(u32, Vec<u8>)>();
+    let mut workers = JoinSet::new();
+    let mut known: HashSet<SocketAddr> = HashSet::new();
+    let mut candidates: VecDeque<SocketAddr> = VecDeque::new();
+    let mut finished: BTreeMap<u32, Vec<u8>> = BTreeMap::new();
+    let mut next = first_piece;
+    let mut next_announce = Instant::now();
+    let mut empty_rounds = 0;
+
+    while next < metainfo.piece_count() {
+        if Instant::now() >= next_announce {
+            let left = metainfo.length - u64::from(next) * metainfo.piece_length;
+            let (peers, interval) = announce_all(
+                &swarm.client,
+                &metainfo.trackers,
+                &metainfo.info_hash,
+                &swarm.peer_id,
+                left,
+            )
+            .await;
+            candidates.extend(peers.into_iter().filter(|addr| known.insert(*addr)));
+            // Ask again soon while the swarm looks thin
+            let wait = if workers.len() + candidates.len() < 5 {
+                interval.min(30)
+            } else {
+                interval
+            };
+            next_announce = Instant::now() + Duration::from_secs(wait);
+        }
+
+        while workers.len() < MAX_PEERS
+            && let Some(addr) = candidates.pop_front()
+        {
+            workers.spawn(peer_worker(swarm.clone(), addr, done_tx.clone()));
+        }
+        if workers.is_empty() {
+            empty_rounds += 1;
+            if empty_rounds > MAX_EMPTY_ROUNDS {
+                let _ = tx
+                    .send(Err(anyhow!(
+                        "No peers are sharing {} right now",
+                        metainfo.name
+                    )))
+                    .await;
+                return;
+            }
+        } else {
+            empty_rounds = 0;
+        }
+
+        tokio::select! {
+            Some((index, data)) = done_rx.recv() => {
+                if index >= next {
+                    finished.insert(index, data);
+                }
+                while let Some(mut data) = finished.remove(&next) {
+                    if skip > 0 {
+                        data.drain(..skip.min(data.len()));
+                        skip = 0;
+                    }
+                    if tx.send(Ok(data)).await.is_err() {
+                        return;
+                    }
+                    next += 1;
+                    swarm.scheduler.lock().unwrap().head = next;
+                    swarm.status.pieces_done.store(next, Ordering::Relaxed);
+                    swarm.progress.notify_waiters();
+                }
+            }
+            Some(_) = workers.join_next(), if !workers.is_empty() => {}
+            _ = sleep_until(next_announce) => {}
+            // The flasher dropped the stream; dropping `workers` stops every peer
+            _ = tx.closed() => return,
+        }
+    }
+}
+
+/// Keeps the connected peer count accurate however a worker exits
+struct PeerCount(Arc<SwarmStatus>);
+
+impl PeerCount {
+    fn new(status: Arc<SwarmStatus>) -> Self {
+        status.peers.fetch_add(1, Ordering::Relaxed);
+        Self(status)
+    }
+}
+
+impl Drop for PeerCount {
+    fn drop(&mut self) {
+        self.0.peers.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Download pieces from one peer until it fails or the torrent is done
+async fn peer_worker(
+    swarm: Arc<Swarm>,
+    addr: SocketAddr,
+    done_tx: mpsc::UnboundedSender<(u32, Vec<u8>)>,
+) -> Result<()> {
+    let conn = PeerConnection::connect(addr, &swarm.metainfo.info_hash, &swarm.peer_id).await?;
+    let _count = PeerCount::new(swarm.status.clone());
+    let (mut rx, mut writer) = conn.split();
+    writer.send(&Message::Interested).await?;
+
+    let mut have = Bitfield::default();
+    let mut choked = true;
+    loop {
+        // Register for window updates before looking for work, so one that
+        // lands in between isn't missed
+        let progress = swarm.progress.notified();
+        tokio::pin!(progress);
+        progress.as_mut().enable();
+
+        let picked = if choked {
+            None
+        } else {
+            swarm.scheduler.lock().unwrap().pick(&have)
+        };
+        if let Some(index) = picked {
+            let result =
+                download_piece(&swarm, &mut writer, &mut rx, index, &mut have, &mut choked).await;
+            match result {
+                Ok(Some(data))
+                    if Sha1::digest(&data)[..] == swarm.metainfo.pieces[index as usize] =>
+                {
+                    if swarm.scheduler.lock().unwrap().complete(index) {
+                        let _ = done_tx.send((index, data));
+                    }
+                    continue;
+                }
+                Ok(Some(_)) => {
+                    release(&swarm, index);
+                    return Err(anyhow!("{} sent a corrupt piece {}", addr, index));
+                }
+                Ok(None) => {
+                    release(&swarm, index);
+                    continue;
+                }
+                Err(e) => {
+                    release(&swarm, index);
+                    return Err(e);
+                }
+            }
+        }
+
+        tokio::select! {
+            message = rx.recv() => {
+                let message = message.ok_or_else(|| anyhow!("{} disconnected", addr))??;
+                handle_state_message(message, &mut have, &mut choked);
+            }
+            _ = &mut progress => {}
+        }
+    }
+}
+
+fn release(swarm: &Swarm, index: u32) {
+    swarm.scheduler.lock().unwrap().release(index);
+    swarm.progress.notify_waiters();
+}
+
+fn handle_state_message(message: Message, have: &mut Bitfield, choked: &mut bool) {
+    match message {
+        Message::Choke => *choked = true,
+        Message::Unchoke => *choked = false,
+        Message::Have(index) => have.set(index),
+        Message::Bitfield(bits) => *have = Bitfield::from_bytes(bits),
+        _ => {}
+    }
+}
+
+/// Fetch one piece block by block. `None` if the peer choked us midway.
+async fn download_piece(
+    swarm: &Swarm,
+    writer: &mut PeerWriter,
+    rx: &mut mpsc::Receiver<Result<Message>>,
+    index: u32,
+    have: &mut Bitfield,
+    choked: &mut bool,
+) -> Result<Option<Vec<u8>>> {
+    let piece_len = swarm.metainfo.piece_len(index);
+    let block_count = piece_len.div_ceil(BLOCK_SIZE) as usize;
+    let mut data = vec![0u8; piece_len as usize];
+    let mut received = vec![false; block_count];
+    let mut received_count = 0;
+    let mut requested = 0;
+    let mut outstanding = 0;
+
+    while received_count < block_count {
+        while outstanding < PIPELINE_DEPTH && requested < block_count {
+            let begin = requested as u64 * BLOCK_SIZE;
+            writer
+                .send(&Message::Request {
+                    index,
+                    begin: begin as u32,
+                    length: BLOCK_SIZE.min(piece_len - begin) as u32,
+                })
+                .await?;
+            requested += 1;
+            outstanding += 1;
+        }
+
+        let message = timeout(BLOCK_TIMEOUT, rx.recv())
+            .await
+            .map_err(|_| anyhow!("Peer stalled on piece {}", index))?
+            .ok_or_else(|| anyhow!("Peer disconnected"))??;
+        match message {
+            Message::Piece {
+                index: piece,
+                begin,
+                block,
+            } if piece == index => {
+                let begin = u64::from(begin);
+                let block_index = (begin / BLOCK_SIZE) as usize;
+                let expected = BLOCK_SIZE.min(piece_len.saturating_sub(begin));
+                if begin % BLOCK_SIZE != 0
+                    || block_index >= block_count
+                    || block.len() as u64 != expected
+                {
+                    return Err(anyhow!("Peer sent a malformed block"));
+                }
+                if !received[block_index] {
+                    data[begin as usize..begin as usize + block.len()].copy_from_slice(&block);
+                    received[block_index] = true;
+                    received_count += 1;
+                }
+                outstanding = outstanding.saturating_sub(1);
+            }
+            Message::Choke => {
+                // A choking peer drops our queued requests
+                *choked = true;
+                return Ok(None);
+            }
+            other => handle_state_message(other, have, choked),
+        }
+    }
+    Ok(Some(data))
+}
+
+/// Get the raw info dictionary from one peer over `ut_metadata`
+async fn metadata_from_peer(
+    addr: SocketAddr,
+    info_hash: [u8; 20],
+    peer_id: [u8; 20],
+) -> Result<Vec<u8>> {
+    let mut conn = PeerConnection::connect(addr, &info_hash, &peer_id).await?;
+    if !conn.supports_extensions {
+        return Err(anyhow!("{} cannot send metadata", addr));
+    }
+    conn.send_extension_handshake().await?;
+
+    let extensions = loop {
+        if let Message::Extended { id: 0, payload } = conn.receive().await? {
+            break peer::parse_extension_handshake(&payload)?;
+        }
+    };
+    let (Some(their_id), Some(size)) = (extensions.ut_metadata, extensions.metadata_size) else {
+        return Err(anyhow!("{} cannot send metadata", addr));
+    };
+    if size == 0 || size > MAX_METADATA_BYTES {
+        return Err(anyhow!("{} announced {} bytes of metadata", addr, size));
+    }
+
+    let mut metadata = Vec::with_capacity(size);
+    for piece in 0..size.div_ceil(BLOCK_SIZE as usize) {
+        let request = Value::dict([
+            ("msg_type", Value::Int(0)),
+            ("piece", Value::Int(piece as i64)),
+        ]);
+        conn.send(&Message::Extended {
+            id: their_id,
+            payload: bencode::encode(&request),
+        })
+        .await?;
+
+        loop {
+            let Message::Extended {
+                id: peer::UT_METADATA_ID,
+                payload,
+            } = conn.receive().await?
+            else {
+                continue;
+            };
+            let (header, used) = bencode::decode_prefix(&payload)?;
+            match header.get("msg_type").and_then(Value::as_int) {
+                Some(1) if header.get("piece").and_then(Value::as_int) == Some(piece as i64) => {
+                    metadata.extend_from_slice(&payload[used..]);
+                    break;
+                }
+                Some(2) => return Err(anyhow!("{} refused to send metadata", addr)),
+                _ => {}
+            }
+        }
+    }
+
+    if metadata.len() != size || Sha1::digest(&metadata)[..] != info_hash {
+        return Err(anyhow!("{} sent metadata that does not match", addr));
+    }
+    Ok(metadata)
+}
+
+/// Announce to every tracker at once; returns the peers they know of and the
+/// shortest re-announce interval
+async fn announce_all(
+    client: &HttpClient,
+    trackers: &[String],
+    info_hash: &[u8; 20],
+    peer_id: &[u8; 20],
+    left: u64,
+) -> (Vec<SocketAddr>, u64) {
+    let announces = trackers.iter().map(|url| {
+        timeout(
+            Duration::from_secs(20),
+            tracker::announce(client, url, info_hash, peer_id, left),
+        )
+    });
+    let mut peers = Vec::new();
+    let mut interval = 1800;
+    for result in join_all(announces).await {
+        if let Ok(Ok(announce)) = result {
+            peers.extend(announce.peers);
+            interval = interval.min(announce.interval.max(10));
+        }
+    }
+    (peers, interval)
+}
+
+/// Azureus-style peer id: client tag followed by random-ish digits
+fn peer_id() -> [u8; 20] {
+    let seed = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos()
+        ^ u128::from(std::process::id());
+    let mut id = *b"-PV0001-000000000000";
+    for (i, byte) in Sha1::digest(seed.to_le_bytes())[..12].iter().enumerate() {
+        id[8 + i] = b'0' + byte % 10;
+    }
+    id
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::StreamExt;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    const PIECE_LENGTH: usize = 32 * 1024;
+
+    fn test_torrent(data: &[u8], tracker: &str) -> (Vec<u8>, Vec<u8>) {
+        let hashes: Vec<u8> = data
+            .chunks(PIECE_LENGTH)
+            .flat_map(|piece| Sha1::digest(piece).to_vec())
+            .collect();
+        let info = Value::dict([
+            ("length", Value::Int(data.len() as i64)),
+            ("name", Value::Bytes(b"appliance.img".to_vec())),
+            ("piece length", Value::Int(PIECE_LENGTH as i64)),
+            ("pieces", Value::Bytes(hashes)),
+        ]);
+        let torrent = Value::dict([
+            ("announce", Value::Bytes(tracker.as_bytes().to_vec())),
+            ("info", info.clone()),
+        ]);
+        (bencode::encode(&torrent), bencode::encode(&info))
+    }
+
+    /// Seeder stand-in: has every piece, unchokes immediately, answers block
+    /// requests and `ut_metadata` requests
+    async fn serve_seeder(listener: TcpListener, data: Vec<u8>, info: Vec<u8>) {
+        let info_hash: [u8; 20] = Sha1::digest(&info).into();
+        loop {
+            let Ok((socket, _)) = listener.accept().await else {
+                break;
+            };
+            let (data, info) = (data.clone(), info.clone());
+            tokio::spawn(async move {
+                let _ = seed_one(socket, &data, &info, info_hash).await;
+            });
+        }
+    }
+
+    async fn seed_one(
+        mut socket: TcpStream,
+        data: &[u8],
+        info: &[u8],
+        info_hash: [u8; 20],
+    ) -> Result<()> {
+        let mut handshake = [0u8; 68];
+        socket.read_exact(&mut handshake).await?;
+        let mut reply = handshake;
+        reply[20..28].copy_from_slice(&[0, 0, 0, 0, 0, 0x10, 0, 0]);
+        reply[28..48].copy_from_slice(&info_hash);
+        reply[48..68].copy_from_slice(b"-SEED01-000000000000");
+        socket.write_all(&reply).await?;
+
+        let pieces = data.len().div_ceil(PIECE_LENGTH);
+        let bitfield = (0..pieces.div_ceil(8))
+            .map(|byte| (0xff00u16 >> (pieces - byte * 8).min(8)) as u8)
+            .collect();
+        socket
+            .write_all(&Message::Bitfield(bitfield).encode())
+            .await?;
+        socket.write_all(&Message::Unchoke.encode()).await?;
+
+        loop {
+            let mut len = [0u8; 4];
+            socket.read_exact(&mut len).await?;
+            let mut body = vec![0u8; u32::from_be_bytes(len) as usize];
+            socket.read_exact(&mut body).await?;
+            let reply = match Message::decode(&body)? {
+                Message::Request {
+                    index,
+                    begin,
+                    length,
+                } => {
+                    let start = index as usize * PIECE_LENGTH + begin as usize;
+                    Message::Piece {
+                        index,
+                        begin,
+                        block: data[start..start + length as usize].to_vec(),
+                    }
+                }
+                Message::Extended { id: 0, .. } => {
+                    let handshake = Value::dict([
+                        ("m", Value::dict([("ut_metadata", Value::Int(3))])),
+                        ("metadata_size", Value::Int(info.len() as i64)),
+                    ]);
+                    Message::Extended {
+                        id: 0,
+                        payload: bencode::encode(&handshake),
+                    }
+                }
+                Message::Extended { id: 3, payload } => {
+                    let request = bencode::decode(&payload)?;
+                    let piece = request.get("piece").and_then(Value::as_int).unwrap() as usize;
+                    let chunk = &info[piece * 16384..info.len().min((piece + 1) * 16384)];
+                    let header = Value::dict([
+                        ("msg_type", Value::Int(1)),
+                        ("piece", Value::Int(piece as i64)),
+                        ("total_size", Value::Int(info.len() as i64)),
+                    ]);
+                    Message::Extended {
+                        id: peer::UT_METADATA_ID,
+                        payload: [bencode::encode(&header), chunk.to_vec()].concat(),
+                    }
+                }
+                _ => continue,
+            };
+            socket.write_all(&reply.encode()).await?;
+        }
+    }
+
+    /// HTTP tracker stand-in that always returns the seeder, and also serves
+    /// the `.torrent` file
+    async fn serve_tracker(listener: TcpListener, seeder: SocketAddr, torrent: Vec<u8>) {
+        let SocketAddr::V4(seeder) = seeder else {
+            panic!("seeder must listen on IPv4");
+        };
+        let compact = [&seeder.ip().octets()[..], &seeder.port().to_be_bytes()].concat();
+        let announce = bencode::encode(&Value::dict([
+            ("interval", Value::Int(60)),
+            ("peers", Value::Bytes(compact)),
+        ]));
+        loop {
+            let Ok((mut socket, _)) = listener.accept().await else {
+                break;
+            };
+            let mut buf = vec![0; 4096];
+            let n = socket.read(&mut buf).await.unwrap_or(0);
+            let request = String::from_utf8_lossy(&buf[..n]).to_string();
+            let body = if request.starts_with("GET /announce") {
+                announce.clone()
+            } else {
+                torrent.clone()
+            };
+            let head = format!(
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                body.len()
+            );
+            let _ = socket.write_all(head.as_bytes()).await;
+            let _ = socket.write_all(&body).await;
+        }
+    }
+
+    /// Start a tracker and seeder; returns the tracker address and info dict
+    async fn start_swarm(data: &[u8]) -> (SocketAddr, Vec<u8>) {
+        let tracker = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let seeder = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let (tracker_addr, seeder_addr) =
+            (tracker.local_addr().unwrap(), seeder.local_addr().unwrap());
+        let (torrent, info) = test_torrent(data, &format!("http://{}/announce", tracker_addr));
+
+        tokio::spawn(serve_seeder(seeder, data.to_vec(), info.clone()));
+        tokio::spawn(serve_tracker(tracker, seeder_addr, torrent));
+        (tracker_addr, info)
+    }
+
+    async fn collect(mut stream: ByteStream) -> Vec<u8> {
+        let mut received = Vec::new();
+        while let Some(chunk) = stream.next().await {
+            received.extend(chunk.unwrap());
+        }
+        received
+    }
+
+    fn test_data() -> Vec<u8> {
+        (0..100_000u32).map(|i| (i * 7 % 251) as u8).collect()
+    }
+
+    #[tokio::test]
+    async fn test_download_from_torrent_file() {
+        let data = test_data();
+        let (tracker, _) = start_swarm(&data).await;
+        let source = TorrentSource::from_url(
+            &format!("http://{}/appliance.img.torrent", tracker),
+            HttpClient::default(),
+        )
+        .unwrap();
+
+        let info = source.probe().await.unwrap();
+        assert_eq!(info.total_bytes, data.len() as u64);
+        assert_eq!(info.pieces.unwrap().hashes.len(), 4);
+
+        assert_eq!(collect(source.open(0).await.unwrap()).await, data);
+        // Resuming mid-piece starts at the exact byte
+        assert_eq!(
+            collect(source.open(40_000).await.unwrap()).await,
+            data[40_000..]
+        );
+        assert!(source.status().unwrap().ends_with("piece 4/4"));
+    }
+
+    #[tokio::test]
+    async fn test_download_from_magnet() {
+        let data: Vec<u8> = test_data().into_iter().rev().collect();
+        let (tracker, info) = start_swarm(&data).await;
+        let magnet = format!(
+            "magnet:?xt=urn:btih:{}&dn=appliance.img&tr=http%3A%2F%2F{}%2Fannounce",
+            hex(&Sha1::digest(&info)),
+            tracker
+        );
+        let source = TorrentSource::from_url(&magnet, HttpClient::default()).unwrap();
+
+        assert_eq!(source.probe().await.unwrap().total_bytes, data.len() as u64);
+        assert_eq!(collect(source.open(0).await.unwrap()).await, data);
+    }
+
+    #[test]
+    fn test_parse_magnet() {
+        let magnet = Magnet::parse(
+            "magnet:?xt=urn:btih:CIQGBKOUY6QVCFZU3CQDP7MFHUE4ELTQ&dn=debian.iso&tr=udp%3A%2F%2Ftracker.example.org%3A6969",
+        )
+        .unwrap();
+        assert_eq!(
+            hex(&magnet.info_hash),
+            "122060a9d4c7a1511734d8a037fd853d09c22e70"
+        );
+        assert_eq!(magnet.name.as_deref(), Some("debian.iso"));
+        assert_eq!(magnet.trackers, vec!["udp://tracker.example.org:6969"]);
+
+        assert!(Magnet::parse("magnet:?dn=nothing").is_err());
+        assert!(is_torrent_url(
+            "https://cdimage.example.org/debian.iso.torrent"
+        ));
+    }
+}
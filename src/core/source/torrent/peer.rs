@@ -0,0 +1,346 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use anyhow::{Result, anyhow};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::net::tcp::OwnedWriteHalf;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time::timeout;
+
+use super::bencode::{self, Value};
+
+const PROTOCOL: &[u8] = b"BitTorrent protocol";
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+/// Peers send keep-alives every two minutes; anything quieter is gone
+const READ_TIMEOUT: Duration = Duration::from_secs(150);
+/// Largest message we accept: a 16 KiB block plus generous headroom
+const MAX_MESSAGE_LEN: usize = 1024 * 1024;
+/// Id we ask peers to use when sending us `ut_metadata` messages (BEP 9)
+pub const UT_METADATA_ID: u8 = 1;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Message {
+    KeepAlive,
+    Choke,
+    Unchoke,
+    Interested,
+    NotInterested,
+    Have(u32),
+    Bitfield(Vec<u8>),
+    Request {
+        index: u32,
+        begin: u32,
+        length: u32,
+    },
+    Piece {
+        index: u32,
+        begin: u32,
+        block: Vec<u8>,
+    },
+    Cancel {
+        index: u32,
+        begin: u32,
+        length: u32,
+    },
+    /// Extension protocol message (BEP 10); id 0 is the extension handshake
+    Extended {
+        id: u8,
+        payload: Vec<u8>,
+    },
+    /// Anything else (DHT port, fast extension, ...), ignored
+    Other(u8),
+}
+
+impl Message {
+    pub fn encode(&self) -> Vec<u8> {
+        let request = |id: u8, index: u32, begin: u32, length: u32| {
+            let mut body = vec![id];
+            body.extend_from_slice(&index.to_be_bytes());
+            body.extend_from_slice(&begin.to_be_bytes());
+            body.extend_from_slice(&length.to_be_bytes());
+            body
+        };
+        let body = match self {
+            Message::KeepAlive => Vec::new(),
+            Message::Choke => vec![0],
+            Message::Unchoke => vec![1],
+            Message::Interested => vec![2],
+            Message::NotInterested => vec![3],
+            Message::Have(index) => [&[4][..], &index.to_be_bytes()].concat(),
+            Message::Bitfield(bits) => [&[5][..], bits].concat(),
+            Message::Request {
+                index,
+                begin,
+                length,
+            } => request(6, *index, *begin, *length),
+            Message::Piece {
+                index,
+                begin,
+                block,
+            } => [
+                &[7][..],
+                &index.to_be_bytes(),
+                &begin.to_be_bytes(),
+                block.as_slice(),
+            ]
+            .concat(),
+            Message::Cancel {
+                index,
+                begin,
+                length,
+            } => request(8, *index, *begin, *length),
+            Message::Extended { id, payload } => [&[20, *id][..], payload].concat(),
+            Message::Other(id) => vec![*id],
+        };
+        [&(body.len() as u32).to_be_bytes()[..], &body].concat()
+    }
+
+    /// Decode a message body (without its length prefix)
+    pub fn decode(body: &[u8]) -> Result<Self> {
+        let Some((&id, rest)) = body.split_first() else {
+            return Ok(Message::KeepAlive);
+        };
+        let u32_at = |offset: usize| -> Result<u32> {
+            rest.get(offset..offset + 4)
+                .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+                .ok_or_else(|| anyhow!("Truncated peer message {}", id))
+        };
+        Ok(match id {
+            0 => Message::Choke,
+            1 => Message::Unchoke,
+            2 => Message::Interested,
+            3 => Message::NotInterested,
+            4 => Message::Have(u32_at(0)?),
+            5 => Message::Bitfield(rest.to_vec()),
+            6 => Message::Request {
+                index: u32_at(0)?,
+                begin: u32_at(4)?,
+                length: u32_at(8)?,
+            },
+            7 => Message::Piece {
+                index: u32_at(0)?,
+                begin: u32_at(4)?,
+                block: rest[8..].to_vec(),
+            },
+            8 => Message::Cancel {
+                index: u32_at(0)?,
+                begin: u32_at(4)?,
+                length: u32_at(8)?,
+            },
+            20 => Message::Extended {
+                id: *rest
+                    .first()
+                    .ok_or_else(|| anyhow!("Truncated extended message"))?,
+                payload: rest[1..].to_vec(),
+            },
+            other => Message::Other(other),
+        })
+    }
+}
+
+/// What the remote peer told us in its extension handshake
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PeerExtensions {
+    /// Id to send our `ut_metadata` requests with
+    pub ut_metadata: Option<u8>,
+    pub metadata_size: Option<usize>,
+}
+
+/// An established peer wire connection (BEP 3)
+pub struct PeerConnection {
+    stream: TcpStream,
+    pub supports_extensions: bool,
+}
+
+impl PeerConnection {
+    /// Connect and exchange handshakes, checking the peer serves `info_hash`
+    pub async fn connect(
+        addr: SocketAddr,
+        info_hash: &[u8; 20],
+        peer_id: &[u8; 20],
+    ) -> Result<Self> {
+        let mut stream = timeout(CONNECT_TIMEOUT, TcpStream::connect(addr))
+            .await
+            .map_err(|_| anyhow!("Timed out connecting to {}", addr))??;
+        stream.set_nodelay(true)?;
+
+        let mut reserved = [0u8; 8];
+        reserved[5] |= 0x10; // extension protocol
+        let mut handshake = vec![PROTOCOL.len() as u8];
+        handshake.extend_from_slice(PROTOCOL);
+        handshake.extend_from_slice(&reserved);
+        handshake.extend_from_slice(info_hash);
+        handshake.extend_from_slice(peer_id);
+        stream.write_all(&handshake).await?;
+
+        let mut reply = [0u8; 68];
+        timeout(CONNECT_TIMEOUT, stream.read_exact(&mut reply))
+            .await
+            .map_err(|_| anyhow!("{} did not complete the handshake", addr))??;
+        if reply[0] as usize != PROTOCOL.len() || &reply[1..20] != PROTOCOL {
+            return Err(anyhow!("{} does not speak BitTorrent", addr));
+        }
+        if &reply[28..48] != info_hash {
+            return Err(anyhow!("{} serves a different torrent", addr));
+        }
+
+        Ok(Self {
+            stream,
+            supports_extensions: reply[25] & 0x10 != 0,
+        })
+    }
+
+    pub async fn send(&mut self, message: &Message) -> Result<()> {
+        self.stream.write_all(&message.encode()).await?;
+        Ok(())
+    }
+
+    pub async fn receive(&mut self) -> Result<Message> {
+        read_message(&mut self.stream).await
+    }
+
+    /// Hand incoming messages to a background reader so they can be awaited
+    /// alongside other events without losing a half-read message
+    pub fn split(self) -> (mpsc::Receiver<Result<Message>>, PeerWriter) {
+        let (mut read_half, write_half) = self.stream.into_split();
+        let (tx, rx) = mpsc::channel(64);
+        let reader = tokio::spawn(async move {
+            loop {
+                let message = read_message(&mut read_half).await;
+                let failed = message.is_err();
+                if tx.send(message).await.is_err() || failed {
+                    break;
+                }
+            }
+        });
+        (rx, PeerWriter { write_half, reader })
+    }
+
+    /// Send our extension handshake, advertising `ut_metadata`
+    pub async fn send_extension_handshake(&mut self) -> Result<()> {
+        let handshake = Value::dict([(
+            "m",
+            Value::dict([("ut_metadata", Value::Int(i64::from(UT_METADATA_ID)))]),
+        )]);
+        self.send(&Message::Extended {
+            id: 0,
+            payload: bencode::encode(&handshake),
+        })
+        .await
+    }
+}
+
+/// Sending side of a split connection; stops the reader when dropped
+pub struct PeerWriter {
+    write_half: OwnedWriteHalf,
+    reader: JoinHandle<()>,
+}
+
+impl PeerWriter {
+    pub async fn send(&mut self, message: &Message) -> Result<()> {
+        self.write_half.write_all(&message.encode()).await?;
+        Ok(())
+    }
+}
+
+impl Drop for PeerWriter {
+    fn drop(&mut self) {
+        self.reader.abort();
+    }
+}
+
+async fn read_message(reader: &mut (impl AsyncRead + Unpin)) -> Result<Message> {
+    let mut len = [0u8; 4];
+    timeout(READ_TIMEOUT, reader.read_exact(&mut len))
+        .await
+        .map_err(|_| anyhow!("Peer went silent"))??;
+    let len = u32::from_be_bytes(len) as usize;
+    if len > MAX_MESSAGE_LEN {
+        return Err(anyhow!("Peer sent a {} byte message", len));
+    }
+    let mut body = vec![0u8; len];
+    timeout(READ_TIMEOUT, reader.read_exact(&mut body))
+        .await
+        .map_err(|_| anyhow!("Peer went silent"))??;
+    Message::decode(&body)
+}
+
+pub fn parse_extension_handshake(payload: &[u8]) -> Result<PeerExtensions> {
+    let handshake = bencode::decode(payload)?;
+    Ok(PeerExtensions {
+        ut_metadata: handshake
+            .get("m")
+            .and_then(|m| m.get("ut_metadata"))
+            .and_then(Value::as_int)
+            .and_then(|id| u8::try_from(id).ok())
+            .filter(|id| *id != 0),
+        metadata_size: handshake
+            .get("metadata_size")
+            .and_then(Value::as_int)
+            .and_then(|size| usize::try_from(size).ok()),
+    })
+}
+
+/// Piece availability announced by a peer
+#[derive(Debug, Clone, Default)]
+pub struct Bitfield {
+    bits: Vec<u8>,
+}
+
+impl Bitfield {
+    pub fn from_bytes(bits: Vec<u8>) -> Self {
+        Self { bits }
+    }
+
+    pub fn has(&self, piece: u32) -> bool {
+        let byte = piece as usize / 8;
+        self.bits
+            .get(byte)
+            .is_some_and(|b| b & (0x80 >> (piece % 8)) != 0)
+    }
+
+    pub fn set(&mut self, piece: u32) {
+        let byte = piece as usize / 8;
+        if self.bits.len() <= byte {
+            self.bits.resize(byte + 1, 0);
+        }
+        self.bits[byte] |= 0x80 >> (piece % 8);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_message_roundtrip() {
+        let messages = [
+            Message::Have(7),
+            Message::Request {
+                index: 1,
+                begin: 16384,
+                length: 16384,
+            },
+            Message::Piece {
+                index: 3,
+                begin: 0,
+                block: vec![1, 2, 3],
+            },
+            Message::Extended {
+                id: 0,
+                payload: b"de".to_vec(),
+            },
+        ];
+        for message in messages {
+            let encoded = message.encode();
+            assert_eq!(Message::decode(&encoded[4..]).unwrap(), message);
+        }
+
+        let mut bitfield = Bitfield::from_bytes(vec![0b1010_0000]);
+        assert!(bitfield.has(0) && !bitfield.has(1) && bitfield.has(2));
+        bitfield.set(9);
+        assert!(bitfield.has(9) && !bitfield.has(8));
+    }
+}
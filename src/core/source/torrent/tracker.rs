@@ -0,0 +1,271 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+
+use anyhow::{Result, anyhow};
+use tokio::net::UdpSocket;
+use tokio::time::timeout;
+
+use super::bencode::{self, Value};
+use crate::core::net::HttpClient;
+
+/// Port we report to trackers. Pervie only downloads, so nothing listens on it.
+const ANNOUNCE_PORT: u16 = 6881;
+const UDP_TIMEOUT: Duration = Duration::from_secs(5);
+/// Magic constant that opens every UDP tracker conversation (BEP 15)
+const UDP_PROTOCOL_ID: u64 = 0x0417_2710_1980;
+
+/// What one announce returned
+#[derive(Debug, Clone, PartialEq)]
+pub struct Announce {
+    pub peers: Vec<SocketAddr>,
+    /// Seconds the tracker wants between announces
+    pub interval: u64,
+}
+
+/// Ask a tracker for peers. `left` is the number of bytes still needed.
+pub async fn announce(
+    client: &HttpClient,
+    tracker: &str,
+    info_hash: &[u8; 20],
+    peer_id: &[u8; 20],
+    left: u64,
+) -> Result<Announce> {
+    if let Some(rest) = tracker.strip_prefix("udp://") {
+        let host = rest.split('/').next().unwrap_or(rest);
+        return announce_udp(host, info_hash, peer_id, left).await;
+    }
+    if tracker.starts_with("http://") || tracker.starts_with("https://") {
+        return announce_http(client, tracker, info_hash, peer_id, left).await;
+    }
+    Err(anyhow!("Unsupported tracker {}", tracker))
+}
+
+async fn announce_http(
+    client: &HttpClient,
+    tracker: &str,
+    info_hash: &[u8; 20],
+    peer_id: &[u8; 20],
+    left: u64,
+) -> Result<Announce> {
+    // The raw hashes must be percent-encoded byte by byte, so build the query by hand
+    let separator = if tracker.contains('?') { '&' } else { '?' };
+    let url = format!(
+        "{}{}info_hash={}&peer_id={}&port={}&uploaded=0&downloaded=0&left={}&compact=1&numwant=50&event=started",
+        tracker,
+        separator,
+        percent_encode(info_hash),
+        percent_encode(peer_id),
+        ANNOUNCE_PORT,
+        left
+    );
+    let resp = client
+        .get(&url)
+        .timeout(Duration::from_secs(15))
+        .send()
+        .await?;
+    if !resp.status().is_success() {
+        return Err(anyhow!("Tracker {} returned {}", tracker, resp.status()));
+    }
+    parse_http_response(&resp.bytes().await?)
+}
+
+/// Parse a tracker's bencoded announce response, compact or not
+pub fn parse_http_response(body: &[u8]) -> Result<Announce> {
+    let response = bencode::decode(body)?;
+    if let Some(reason) = response.get("failure reason").and_then(Value::as_str) {
+        return Err(anyhow!("Tracker refused announce: {}", reason));
+    }
+
+    let mut peers = Vec::new();
+    match response.get("peers") {
+        Some(Value::Bytes(compact)) => peers.extend(compact_peers_v4(compact)),
+        Some(Value::List(list)) => {
+            for peer in list {
+                let ip = peer
+                    .get("ip")
+                    .and_then(Value::as_str)
+                    .and_then(|ip| ip.parse::<IpAddr>().ok());
+                let port = peer
+                    .get("port")
+                    .and_then(Value::as_int)
+                    .and_then(|p| u16::try_from(p).ok());
+                if let (Some(ip), Some(port)) = (ip, port) {
+                    peers.push(SocketAddr::new(ip, port));
+                }
+            }
+        }
+        _ => {}
+    }
+    if let Some(compact) = response.get("peers6").and_then(Value::as_bytes) {
+        for entry in compact.chunks_exact(18) {
+            let mut ip = [0u8; 16];
+            ip.copy_from_slice(&entry[..16]);
+            let port = u16::from_be_bytes([entry[16], entry[17]]);
+            peers.push(SocketAddr::new(IpAddr::V6(Ipv6Addr::from(ip)), port));
+        }
+    }
+
+    Ok(Announce {
+        peers,
+        interval: response
+            .get("interval")
+            .and_then(Value::as_int)
+            .and_then(|i| u64::try_from(i).ok())
+            .unwrap_or(1800),
+    })
+}
+
+async fn announce_udp(
+    host: &str,
+    info_hash: &[u8; 20],
+    peer_id: &[u8; 20],
+    left: u64,
+) -> Result<Announce> {
+    let addr = tokio::net::lookup_host(host)
+        .await?
+        .next()
+        .ok_or_else(|| anyhow!("Could not resolve tracker {}", host))?;
+    let bind: SocketAddr = if addr.is_ipv4() {
+        (Ipv4Addr::UNSPECIFIED, 0).into()
+    } else {
+        (Ipv6Addr::UNSPECIFIED, 0).into()
+    };
+    let socket = UdpSocket::bind(bind).await?;
+    socket.connect(addr).await?;
+    let transaction_id = transaction_id();
+
+    // Connect: get a connection id to use for the announce
+    let mut request = Vec::with_capacity(16);
+    request.extend_from_slice(&UDP_PROTOCOL_ID.to_be_bytes());
+    request.extend_from_slice(&0u32.to_be_bytes());
+    request.extend_from_slice(&transaction_id.to_be_bytes());
+    let reply = udp_exchange(&socket, &request, 16).await?;
+    check_udp_reply(&reply, 0, transaction_id)?;
+    let connection_id = &reply[8..16];
+
+    let mut request = Vec::with_capacity(98);
+    request.extend_from_slice(connection_id);
+    request.extend_from_slice(&1u32.to_be_bytes());
+    request.extend_from_slice(&transaction_id.to_be_bytes());
+    request.extend_from_slice(info_hash);
+    request.extend_from_slice(peer_id);
+    request.extend_from_slice(&0u64.to_be_bytes()); // downloaded
+    request.extend_from_slice(&left.to_be_bytes());
+    request.extend_from_slice(&0u64.to_be_bytes()); // uploaded
+    request.extend_from_slice(&2u32.to_be_bytes()); // event: started
+    request.extend_from_slice(&0u32.to_be_bytes()); // ip: sender's
+    request.extend_from_slice(&transaction_id.to_be_bytes()); // key
+    request.extend_from_slice(&50i32.to_be_bytes()); // numwant
+    request.extend_from_slice(&ANNOUNCE_PORT.to_be_bytes());
+    let reply = udp_exchange(&socket, &request, 20).await?;
+    check_udp_reply(&reply, 1, transaction_id)?;
+
+    let interval = u32::from_be_bytes([reply[8], reply[9], reply[10], reply[11]]);
+    let peers = if addr.is_ipv4() {
+        compact_peers_v4(&reply[20..])
+    } else {
+        reply[20..]
+            .chunks_exact(18)
+            .map(|entry| {
+                let mut ip = [0u8; 16];
+                ip.copy_from_slice(&entry[..16]);
+                SocketAddr::new(
+                    IpAddr::V6(Ipv6Addr::from(ip)),
+                    u16::from_be_bytes([entry[16], entry[17]]),
+                )
+            })
+            .collect()
+    };
+    Ok(Announce {
+        peers,
+        interval: u64::from(interval),
+    })
+}
+
+/// Send a request and wait for a reply of at least `min_len` bytes, retrying once
+async fn udp_exchange(socket: &UdpSocket, request: &[u8], min_len: usize) -> Result<Vec<u8>> {
+    let mut buf = vec![0u8; 2048];
+    for _ in 0..2 {
+        socket.send(request).await?;
+        if let Ok(received) = timeout(UDP_TIMEOUT, socket.recv(&mut buf)).await {
+            let len = received?;
+            if len >= min_len {
+                buf.truncate(len);
+                return Ok(buf);
+            }
+        }
+    }
+    Err(anyhow!("UDP tracker did not answer"))
+}
+
+fn check_udp_reply(reply: &[u8], action: u32, transaction_id: u32) -> Result<()> {
+    let got_action = u32::from_be_bytes([reply[0], reply[1], reply[2], reply[3]]);
+    let got_transaction = u32::from_be_bytes([reply[4], reply[5], reply[6], reply[7]]);
+    if got_transaction != transaction_id {
+        return Err(anyhow!("UDP tracker answered another request"));
+    }
+    if got_action == 3 {
+        return Err(anyhow!(
+            "Tracker refused announce: {}",
+            String::from_utf8_lossy(&reply[8..])
+        ));
+    }
+    if got_action != action {
+        return Err(anyhow!("Unexpected UDP tracker action {}", got_action));
+    }
+    Ok(())
+}
+
+fn compact_peers_v4(compact: &[u8]) -> Vec<SocketAddr> {
+    compact
+        .chunks_exact(6)
+        .map(|entry| {
+            SocketAddr::new(
+                IpAddr::V4(Ipv4Addr::new(entry[0], entry[1], entry[2], entry[3])),
+                u16::from_be_bytes([entry[4], entry[5]]),
+            )
+        })
+        .collect()
+}
+
+fn transaction_id() -> u32 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .subsec_nanos()
+        ^ std::process::id()
+}
+
+fn percent_encode(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (*b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_http_response() {
+        let body = b"d8:intervali900e5:peers12:\x7f\x00\x00\x01\x1a\xe1\x0a\x00\x00\x02\x1a\xe2e";
+        let announce = parse_http_response(body).unwrap();
+        assert_eq!(announce.interval, 900);
+        assert_eq!(
+            announce.peers,
+            vec![
+                "127.0.0.1:6881".parse().unwrap(),
+                "10.0.0.2:6882".parse().unwrap()
+            ]
+        );
+
+        let refused = parse_http_response(b"d14:failure reason12:unregisterede");
+        assert!(refused.unwrap_err().to_string().contains("unregistered"));
+    }
+}
@@ -0,0 +1,277 @@
+use anyhow::{Result, bail};
+
+use super::partition::{le32, le64};
+
+pub const HEADER_SIZE: usize = 208;
+const MAGIC: &[u8; 8] = b"MSWIM\0\0\0";
+const HEADER_FLAG_SPANNED: u32 = 0x08;
+const RESOURCE_METADATA: u8 = 0x02;
+const RESOURCE_COMPRESSED: u8 = 0x04;
+const RESOURCE_SOLID: u8 = 0x10;
+/// Resource header, part number, reference count and SHA-1
+const LOOKUP_ENTRY_SIZE: usize = 50;
+
+/// Where a resource sits in a WIM: stored size and flags, offset, and
+/// uncompressed size
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ResourceHeader {
+    size: u64,
+    flags: u8,
+    offset: u64,
+    original_size: u64,
+}
+
+impl ResourceHeader {
+    fn parse(bytes: &[u8]) -> Self {
+        Self {
+            size: le64(bytes) & 0x00ff_ffff_ffff_ffff,
+            flags: bytes[7],
+            offset: le64(&bytes[8..]),
+            original_size: le64(&bytes[16..]),
+        }
+    }
+
+    fn to_bytes(self) -> [u8; 24] {
+        let mut bytes = [0u8; 24];
+        bytes[..8].copy_from_slice(&self.size.to_le_bytes());
+        bytes[7] = self.flags;
+        bytes[8..16].copy_from_slice(&self.offset.to_le_bytes());
+        bytes[16..24].copy_from_slice(&self.original_size.to_le_bytes());
+        bytes
+    }
+}
+
+/// Bytes of the original WIM that go into a part unchanged
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResourceCopy {
+    pub source: u64,
+    pub len: u64,
+    /// Offset in the part
+    pub dest: u64,
+}
+
+/// One `.swm` file of a split WIM
+#[derive(Debug, Clone, PartialEq)]
+pub struct SplitPart {
+    pub name: String,
+    pub size: u64,
+    /// Header, lookup table and XML data, at their offsets in the part
+    pub metadata: Vec<(u64, Vec<u8>)>,
+    pub copies: Vec<ResourceCopy>,
+}
+
+/// Plan splitting a WIM into `.swm` parts of at most `max_part` bytes, the
+/// way `wimlib-imagex split` does: resources are copied as they are, and
+/// each part gets its own header and lookup table. `read` fetches bytes of
+/// the original WIM. Parts are named `<stem>.swm`, `<stem>2.swm`, ...
+pub fn plan_split(
+    read: &mut dyn FnMut(u64, usize) -> Result<Vec<u8>>,
+    stem: &str,
+    max_part: u64,
+) -> Result<Vec<SplitPart>> {
+    let header = read(0, HEADER_SIZE)?;
+    if &header[..8] != MAGIC {
+        bail!("Not a WIM file");
+    }
+    let flags = le32(&header[16..]);
+    let total_parts = u16::from_le_bytes([header[42], header[43]]);
+    if flags & HEADER_FLAG_SPANNED != 0 || total_parts != 1 {
+        bail!("WIM is already split");
+    }
+    let lookup = ResourceHeader::parse(&header[48..]);
+    let xml = ResourceHeader::parse(&header[72..]);
+    let boot_metadata = ResourceHeader::parse(&header[96..]);
+    if lookup.flags & RESOURCE_COMPRESSED != 0 {
+        bail!("Compressed WIM lookup tables are not supported");
+    }
+
+    let table = read(lookup.offset, lookup.size as usize)?;
+    let xml_data = read(xml.offset, xml.size as usize)?;
+    let mut entries: Vec<&[u8]> = table.chunks_exact(LOOKUP_ENTRY_SIZE).collect();
+    if entries
+        .iter()
+        .any(|e| ResourceHeader::parse(e).flags & RESOURCE_SOLID != 0)
+    {
+        bail!("Solid-compressed WIMs (ESD) can't be split");
+    }
+    // Image metadata must all be in the first part; the rest keeps its order
+    entries.sort_by_key(|e| {
+        let resource = ResourceHeader::parse(e);
+        (resource.flags & RESOURCE_METADATA == 0, resource.offset)
+    });
+
+    // Fill parts one resource at a time
+    let mut assigned: Vec<Vec<&[u8]>> = vec![Vec::new()];
+    let mut used = HEADER_SIZE as u64;
+    for entry in entries {
+        let size = ResourceHeader::parse(entry).size;
+        let current = assigned.last_mut().unwrap();
+        if !current.is_empty() && used + size > max_part {
+            assigned.push(vec![entry]);
+            used = HEADER_SIZE as u64 + size;
+        } else {
+            current.push(entry);
+            used += size;
+        }
+    }
+    if assigned.len() > u16::MAX as usize {
+        bail!("WIM would need too many parts");
+    }
+
+    let total_parts = assigned.len() as u16;
+    let mut parts = Vec::new();
+    for (index, entries) in assigned.into_iter().enumerate() {
+        let part_number = index as u16 + 1;
+        let mut copies = Vec::new();
+        let mut table = Vec::new();
+        let mut part_boot_metadata = ResourceHeader::parse(&[0; 24]);
+        let mut position = HEADER_SIZE as u64;
+        for entry in entries {
+            let mut resource = ResourceHeader::parse(entry);
+            copies.push(ResourceCopy {
+                source: resource.offset,
+                len: resource.size,
+                dest: position,
+            });
+            if resource == boot_metadata {
+                part_boot_metadata = ResourceHeader {
+                    offset: position,
+                    ..resource
+                };
+            }
+            resource.offset = position;
+            position += resource.size;
+            table.extend(resource.to_bytes());
+            table.extend(part_number.to_le_bytes());
+            table.extend(&entry[26..]);
+        }
+
+        let part_lookup = ResourceHeader {
+            size: table.len() as u64,
+            flags: 0,
+            offset: position,
+            original_size: table.len() as u64,
+        };
+        let part_xml = ResourceHeader {
+            offset: position + table.len() as u64,
+            ..xml
+        };
+        let mut part_header = header.clone();
+        part_header[16..20].copy_from_slice(&(flags | HEADER_FLAG_SPANNED).to_le_bytes());
+        part_header[40..42].copy_from_slice(&part_number.to_le_bytes());
+        part_header[42..44].copy_from_slice(&total_parts.to_le_bytes());
+        part_header[48..72].copy_from_slice(&part_lookup.to_bytes());
+        part_header[72..96].copy_from_slice(&part_xml.to_bytes());
+        part_header[96..120].copy_from_slice(&part_boot_metadata.to_bytes());
+        // The integrity table covers the whole WIM, so it doesn't carry over
+        part_header[124..148].fill(0);
+
+        let name = match part_number {
+            1 => format!("{}.swm", stem),
+            n => format!("{}{}.swm", stem, n),
+        };
+        parts.push(SplitPart {
+            name,
+            size: part_xml.offset + xml_data.len() as u64,
+            metadata: vec![
+                (0, part_header),
+                (part_lookup.offset, table),
+                (part_xml.offset, xml_data.clone()),
+            ],
+            copies,
+        });
+    }
+    Ok(parts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resource(size: u64, flags: u8, offset: u64) -> ResourceHeader {
+        ResourceHeader {
+            size,
+            flags,
+            offset,
+            original_size: size,
+        }
+    }
+
+    /// A WIM with three file resources and one image metadata resource
+    fn sample_wim() -> Vec<u8> {
+        let mut wim = vec![0u8; HEADER_SIZE];
+        wim[..8].copy_from_slice(MAGIC);
+        wim[42..44].copy_from_slice(&1u16.to_le_bytes());
+        let mut table = Vec::new();
+        for (i, (size, flags)) in [(100, 0), (300, 0), (50, RESOURCE_METADATA), (200, 0)]
+            .into_iter()
+            .enumerate()
+        {
+            let offset = wim.len() as u64;
+            wim.extend(vec![i as u8 + 1; size as usize]);
+            table.extend(resource(size, flags, offset).to_bytes());
+            table.extend(1u16.to_le_bytes());
+            table.extend(1u32.to_le_bytes());
+            table.extend([i as u8; 20]);
+            if flags == RESOURCE_METADATA {
+                wim[96..120].copy_from_slice(&resource(size, flags, offset).to_bytes());
+            }
+        }
+        let lookup = resource(table.len() as u64, 0, wim.len() as u64);
+        wim.extend(table);
+        let xml = resource(6, 0, wim.len() as u64);
+        wim.extend(b"<WIM/>");
+        wim[48..72].copy_from_slice(&lookup.to_bytes());
+        wim[72..96].copy_from_slice(&xml.to_bytes());
+        wim
+    }
+
+    #[test]
+    fn test_plan_split() {
+        let wim = sample_wim();
+        let mut read = |offset: u64, len: usize| -> Result<Vec<u8>> {
+            Ok(wim[offset as usize..offset as usize + len].to_vec())
+        };
+        let parts = plan_split(&mut read, "install", 700).unwrap();
+        let names: Vec<&str> = parts.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, vec!["install.swm", "install2.swm"]);
+
+        // Metadata first, then resources in their original order
+        let sources: Vec<Vec<u64>> = parts
+            .iter()
+            .map(|p| p.copies.iter().map(|c| c.source).collect())
+            .collect();
+        assert_eq!(sources, vec![vec![608, 208, 308], vec![658]]);
+
+        for (i, part) in parts.iter().enumerate() {
+            let mut file = vec![0u8; part.size as usize];
+            for (offset, data) in &part.metadata {
+                file[*offset as usize..*offset as usize + data.len()].copy_from_slice(data);
+            }
+            for copy in &part.copies {
+                let (src, dest) = (copy.source as usize, copy.dest as usize);
+                file[dest..dest + copy.len as usize]
+                    .copy_from_slice(&wim[src..src + copy.len as usize]);
+            }
+            assert_eq!(&file[..8], MAGIC);
+            assert_eq!(file[40..44], [i as u8 + 1, 0, 2, 0]);
+            assert_ne!(le32(&file[16..]) & HEADER_FLAG_SPANNED, 0);
+
+            // Every lookup entry points at its own data in this part
+            let lookup = ResourceHeader::parse(&file[48..]);
+            let table = &file[lookup.offset as usize..(lookup.offset + lookup.size) as usize];
+            for entry in table.chunks_exact(LOOKUP_ENTRY_SIZE) {
+                let resource = ResourceHeader::parse(entry);
+                assert_eq!(u16::from_le_bytes([entry[24], entry[25]]), i as u16 + 1);
+                let data = &file[resource.offset as usize..][..resource.size as usize];
+                assert!(data.iter().all(|b| *b == entry[30] + 1));
+            }
+            let xml = ResourceHeader::parse(&file[72..]);
+            assert_eq!(&file[xml.offset as usize..][..6], b"<WIM/>");
+        }
+        // Only the first part knows where the boot image's metadata is
+        let boot = ResourceHeader::parse(&parts[0].metadata[0].1[96..]);
+        assert_eq!((boot.offset, boot.size), (208, 50));
+        assert_eq!(ResourceHeader::parse(&parts[1].metadata[0].1[96..]).size, 0);
+    }
+}
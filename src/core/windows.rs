@@ -0,0 +1,984 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom};
+use std::os::unix::fs::FileExt;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result, bail};
+use tokio::runtime::Handle;
+use tokio::sync::mpsc::UnboundedSender;
+
+use super::AppState;
+use super::flasher::{Flasher, ImageSink};
+use super::isofs::{self, IsoEntry};
+use super::partition::{self, GPT_BASIC_DATA, NewPartition, SECTOR_SIZE};
+use super::source::{ImageSource, SourceReader};
+use super::wim::{self, SplitPart};
+
+/// Largest file FAT32 can hold
+const FAT32_MAX_FILE: u64 = u32::MAX as u64;
+/// `.swm` parts stay well below the FAT32 limit, as wimlib and Rufus keep them
+const SWM_PART_SIZE: u64 = 3800 * 1024 * 1024;
+const PARTITION_ALIGN: u64 = 1024 * 1024;
+const FAT_RESERVED_SECTORS: u64 = 32;
+const FAT_COPIES: u64 = 2;
+/// Fewer clusters than this and the filesystem would be read as FAT16
+const FAT32_MIN_CLUSTERS: u64 = 65525;
+const FAT_END_OF_CHAIN: u32 = 0x0fff_ffff;
+const VOLUME_LABEL: &[u8; 11] = b"WINSETUP   ";
+const DIR_ENTRY_SIZE: usize = 32;
+const ATTR_VOLUME_ID: u8 = 0x08;
+const ATTR_DIRECTORY: u8 = 0x10;
+const ATTR_LONG_NAME: u8 = 0x0f;
+/// 1980-01-01, the FAT epoch; the ISO's own timestamps aren't carried over
+const DOS_DATE: u16 = (1 << 5) | 1;
+
+/// A Windows installer ISO, found by `detect`
+#[derive(Debug, Clone, PartialEq)]
+pub struct WindowsMedia {
+    pub entries: Vec<IsoEntry>,
+}
+
+impl WindowsMedia {
+    /// Why the installer can't go onto FAT32: a file over the 4 GiB limit
+    /// that isn't a WIM, such as a large `install.esd`. Only WIMs can be
+    /// split, and there is no NTFS fallback.
+    pub fn unwritable(&self) -> Option<String> {
+        self.entries
+            .iter()
+            .find(|e| !e.is_dir && e.size > FAT32_MAX_FILE && wim_stem(e.name()).is_none())
+            .map(too_large)
+    }
+}
+
+/// The file name without `.wim`, for naming its `.swm` parts
+fn wim_stem(name: &str) -> Option<&str> {
+    name.len()
+        .checked_sub(4)
+        .filter(|at| name.is_char_boundary(*at) && name[*at..].eq_ignore_ascii_case(".wim"))
+        .map(|at| &name[..at])
+}
+
+fn too_large(entry: &IsoEntry) -> String {
+    format!(
+        "{} is {}, over FAT32's 4 GiB file limit. Only .wim files can be split \
+         into .swm parts, and NTFS installer sticks aren't supported.",
+        entry.path,
+        crate::utils::bytes_to_human(entry.size)
+    )
+}
+
+/// What `write_media` put on the device
+#[derive(Debug, Clone, PartialEq)]
+pub struct MediaSummary {
+    pub files: usize,
+    /// Number of `.swm` parts an oversized `install.wim` was split into
+    pub split_parts: usize,
+}
+
+/// Whether an ISO's files are those of Windows Setup
+pub fn is_windows_installer(entries: &[IsoEntry]) -> bool {
+    isofs::find(entries, "sources/boot.wim").is_some()
+        && [
+            "sources/install.wim",
+            "sources/install.esd",
+            "sources/install.swm",
+        ]
+        .iter()
+        .any(|path| isofs::find(entries, path).is_some())
+}
+
+/// Check whether an image is a Windows installer ISO. Those aren't hybrid
+/// images: written byte for byte, nothing boots from the stick.
+pub async fn detect(source: Arc<dyn ImageSource>, size: u64) -> Result<Option<WindowsMedia>> {
+    let runtime = Handle::current();
+    tokio::task::spawn_blocking(move || {
+        let mut reader = SourceReader::new(source, size, runtime);
+        // A partition table means a hybrid image, which is flashed as it is
+        if partition::read_partition_table(&mut reader, size)?.is_some() {
+            return Ok(None);
+        }
+        let Ok((_, entries)) = isofs::read_tree(&mut reader) else {
+            return Ok(None);
+        };
+        Ok(is_windows_installer(&entries).then_some(WindowsMedia { entries }))
+    })
+    .await?
+}
+
+/// Partition the device as GPT with one FAT32 partition and unpack the ISO
+/// onto it in a single pass over the image
+pub async fn write_media(
+    flasher: &Flasher,
+    source: Arc<dyn ImageSource>,
+    expected_sha256: Option<String>,
+    media: WindowsMedia,
+    device_path: &str,
+    progress_tx: UnboundedSender<AppState>,
+) -> Result<MediaSummary> {
+    let mut device = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(device_path)
+        .with_context(|| format!("Failed to open device {}", device_path))?;
+    let disk_size = device.seek(SeekFrom::End(0))?;
+
+    // Splitting install.wim needs its header and lookup table up front
+    let info = source.probe().await?;
+    let runtime = Handle::current();
+    let reader_source = source.clone();
+    let plan = tokio::task::spawn_blocking(move || {
+        let mut reader = SourceReader::new(reader_source, info.total_bytes, runtime);
+        MediaPlan::new(&media.entries, &mut reader, disk_size)
+    })
+    .await??;
+    let plan = Arc::new(plan);
+
+    let sink = MediaWriter::new(device, plan.clone());
+    let labels = plan.clone();
+    flasher
+        .stream_to(
+            source,
+            expected_sha256,
+            Box::new(sink),
+            move |offset| labels.file_at(offset),
+            progress_tx,
+        )
+        .await?;
+    Ok(MediaSummary {
+        files: plan.file_count,
+        split_parts: plan.split_parts,
+    })
+}
+
+/// Image bytes that belong at a place on the device
+#[derive(Debug, Clone, Copy)]
+struct Copy {
+    image: u64,
+    len: u64,
+    device: u64,
+    /// Index into `MediaPlan::files`, and where in that file the bytes go
+    file: usize,
+    file_offset: u64,
+}
+
+/// Where every byte goes on the stick: the FAT32 structures are built in
+/// memory, and each file's ISO extents map onto its clusters
+pub struct MediaPlan {
+    /// Partition table, boot sectors, FATs, directories and `.swm` headers
+    writes: Vec<(u64, Vec<u8>)>,
+    /// Sorted by image offset
+    copies: Vec<Copy>,
+    /// Path and size of each file
+    files: Vec<(String, u64)>,
+    file_count: usize,
+    split_parts: usize,
+}
+
+/// A file or directory as it will be on the FAT32 partition
+struct Node<'a> {
+    path: String,
+    size: u64,
+    content: Content<'a>,
+}
+
+enum Content<'a> {
+    Dir,
+    File(&'a IsoEntry),
+    /// A `.swm` part of this `install.wim`
+    Part(&'a IsoEntry, SplitPart),
+}
+
+impl MediaPlan {
+    pub fn new<R: Read + Seek>(
+        entries: &[IsoEntry],
+        reader: &mut R,
+        disk_size: u64,
+    ) -> Result<Self> {
+        Self::with_part_size(entries, reader, disk_size, SWM_PART_SIZE)
+    }
+
+    fn with_part_size<R: Read + Seek>(
+        entries: &[IsoEntry],
+        reader: &mut R,
+        disk_size: u64,
+        part_size: u64,
+    ) -> Result<Self> {
+        let mut nodes = Vec::new();
+        let mut split_parts = 0;
+        for entry in entries {
+            if entry.is_dir {
+                nodes.push(Node {
+                    path: entry.path.clone(),
+                    size: 0,
+                    content: Content::Dir,
+                });
+                continue;
+            }
+            if entry.size <= FAT32_MAX_FILE {
+                nodes.push(Node {
+                    path: entry.path.clone(),
+                    size: entry.size,
+                    content: Content::File(entry),
+                });
+                continue;
+            }
+            let Some(stem) = wim_stem(entry.name()) else {
+                bail!(too_large(entry));
+            };
+            let mut read = |offset, len| entry.read(reader, offset, len);
+            let parts = wim::plan_split(&mut read, stem, part_size)
+                .with_context(|| format!("Failed to split {}", entry.path))?;
+            split_parts = parts.len();
+            let parent = parent_of(&entry.path);
+            for part in parts {
+                nodes.push(Node {
+                    path: join(parent, &part.name),
+                    size: part.size,
+                    content: Content::Part(entry, part),
+                });
+            }
+        }
+
+        // One partition from 1 MiB to the last whole MiB before the backup GPT
+        let partition_end =
+            disk_size.saturating_sub(33 * SECTOR_SIZE) / PARTITION_ALIGN * PARTITION_ALIGN;
+        let partition_size = partition_end
+            .saturating_sub(PARTITION_ALIGN)
+            .min(u64::from(u32::MAX) * SECTOR_SIZE);
+        let fat = FatGeometry::new(partition_size)?;
+        let partition_start = PARTITION_ALIGN;
+
+        // Directories, with root first
+        let mut dirs = vec![Dir::default()];
+        let mut dir_index = HashMap::from([(String::new(), 0)]);
+        for (i, node) in nodes.iter().enumerate() {
+            let parent = *dir_index
+                .get(parent_of(&node.path))
+                .with_context(|| format!("No directory for {}", node.path))?;
+            dirs[parent].children.push(i);
+            if matches!(node.content, Content::Dir) {
+                dir_index.insert(node.path.clone(), dirs.len());
+                dirs.push(Dir {
+                    node: Some(i),
+                    parent,
+                    ..Dir::default()
+                });
+            }
+        }
+        let node_dir: HashMap<usize, usize> = dirs
+            .iter()
+            .enumerate()
+            .filter_map(|(d, dir)| dir.node.map(|n| (n, d)))
+            .collect();
+
+        // Short names decide how many entries each directory needs
+        let mut names = vec![([0u8; 11], false); nodes.len()];
+        for dir in &mut dirs {
+            let mut taken = HashSet::new();
+            let mut count = if dir.node.is_some() { 2 } else { 1 };
+            for &child in &dir.children {
+                let name = nodes[child].path.rsplit('/').next().unwrap_or_default();
+                let (short, needs_long) = short_name(name, &mut taken);
+                names[child] = (short, needs_long);
+                count += 1 + if needs_long {
+                    long_entry_count(name)
+                } else {
+                    0
+                };
+            }
+            dir.clusters = (count as u64 * DIR_ENTRY_SIZE as u64)
+                .div_ceil(fat.cluster_size())
+                .max(1);
+        }
+
+        // Clusters: directories, then files in image order so writes run forward
+        let mut next_cluster = 2u64;
+        for dir in &mut dirs {
+            dir.cluster = next_cluster;
+            next_cluster += dir.clusters;
+        }
+        let mut order: Vec<usize> = (0..nodes.len())
+            .filter(|i| !matches!(nodes[*i].content, Content::Dir))
+            .collect();
+        order.sort_by_key(|i| match &nodes[*i].content {
+            Content::File(entry) => entry.extents.first().map_or(0, |e| e.offset),
+            Content::Part(entry, part) => part
+                .copies
+                .first()
+                .and_then(|c| entry.ranges(c.source, 1).first().copied())
+                .map_or(0, |e| e.offset),
+            Content::Dir => 0,
+        });
+        let mut first_cluster = vec![0u64; nodes.len()];
+        for &i in &order {
+            let clusters = nodes[i].size.div_ceil(fat.cluster_size());
+            if clusters > 0 {
+                first_cluster[i] = next_cluster;
+                next_cluster += clusters;
+            }
+        }
+        for (node, dir) in &node_dir {
+            first_cluster[*node] = dirs[*dir].cluster;
+        }
+        let used_clusters = next_cluster - 2;
+        if used_clusters > fat.clusters {
+            bail!(
+                "The Windows files need {} but the device only holds {}",
+                crate::utils::bytes_to_human(used_clusters * fat.cluster_size()),
+                crate::utils::bytes_to_human(fat.clusters * fat.cluster_size())
+            );
+        }
+
+        let fs_offset = partition_start;
+        let cluster_offset = |cluster: u64| {
+            fs_offset + (fat.data_start() + (cluster - 2) * fat.sectors_per_cluster) * SECTOR_SIZE
+        };
+
+        // File allocation table: every file and directory is one contiguous run
+        let mut table = vec![0u8; (fat.fat_sectors * SECTOR_SIZE) as usize];
+        let mut set = |cluster: u64, value: u32| {
+            let at = cluster as usize * 4;
+            table[at..at + 4].copy_from_slice(&value.to_le_bytes());
+        };
+        set(0, 0x0fff_fff8);
+        set(1, FAT_END_OF_CHAIN);
+        let runs = dirs
+            .iter()
+            .map(|d| (d.cluster, d.clusters))
+            .chain(order.iter().map(|i| {
+                (
+                    first_cluster[*i],
+                    nodes[*i].size.div_ceil(fat.cluster_size()),
+                )
+            }));
+        for (start, len) in runs.filter(|(_, len)| *len > 0) {
+            for cluster in start..start + len - 1 {
+                set(cluster, cluster as u32 + 1);
+            }
+            set(start + len - 1, FAT_END_OF_CHAIN);
+        }
+
+        let mut writes = partition::build_gpt(
+            disk_size,
+            &[NewPartition {
+                start: partition_start,
+                size: partition_size,
+                type_guid: GPT_BASIC_DATA,
+                name: "Windows Setup",
+            }],
+        )?;
+        let volume_id = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs() as u32;
+        writes.push((
+            fs_offset,
+            fat.reserved_region(
+                partition_start / SECTOR_SIZE,
+                volume_id,
+                fat.clusters - used_clusters,
+                next_cluster,
+            ),
+        ));
+        for copy in 0..FAT_COPIES {
+            writes.push((
+                fs_offset + (FAT_RESERVED_SECTORS + copy * fat.fat_sectors) * SECTOR_SIZE,
+                table.clone(),
+            ));
+        }
+
+        for dir in &dirs {
+            let mut data = Vec::new();
+            match dir.node {
+                None => data.extend(dir_entry(VOLUME_LABEL, ATTR_VOLUME_ID, 0, 0)),
+                Some(_) => {
+                    let parent_cluster = match dir.parent {
+                        0 => 0,
+                        p => dirs[p].cluster,
+                    };
+                    data.extend(dir_entry(b".          ", ATTR_DIRECTORY, dir.cluster, 0));
+                    data.extend(dir_entry(b"..         ", ATTR_DIRECTORY, parent_cluster, 0));
+                }
+            }
+            for &child in &dir.children {
+                let node = &nodes[child];
+                let (short, needs_long) = &names[child];
+                if *needs_long {
+                    let name = node.path.rsplit('/').next().unwrap_or_default();
+                    for entry in long_name_entries(name, short) {
+                        data.extend(entry);
+                    }
+                }
+                let (attr, size) = match node.content {
+                    Content::Dir => (ATTR_DIRECTORY, 0),
+                    _ => (0, node.size as u32),
+                };
+                data.extend(dir_entry(short, attr, first_cluster[child], size));
+            }
+            data.resize((dir.clusters * fat.cluster_size()) as usize, 0);
+            writes.push((cluster_offset(dir.cluster), data));
+        }
+
+        // Map image extents onto the files' clusters
+        let mut files = Vec::new();
+        let mut copies = Vec::new();
+        for &i in &order {
+            let node = &nodes[i];
+            let file = files.len();
+            files.push((node.path.clone(), node.size));
+            if first_cluster[i] == 0 {
+                // Empty files have no clusters
+                continue;
+            }
+            let base = cluster_offset(first_cluster[i]);
+            match &node.content {
+                Content::File(entry) => {
+                    let mut file_offset = 0;
+                    for extent in &entry.extents {
+                        copies.push(Copy {
+                            image: extent.offset,
+                            len: extent.len,
+                            device: base + file_offset,
+                            file,
+                            file_offset,
+                        });
+                        file_offset += extent.len;
+                    }
+                }
+                Content::Part(entry, part) => {
+                    for (offset, data) in &part.metadata {
+                        writes.push((base + offset, data.clone()));
+                    }
+                    for resource in &part.copies {
+                        let mut file_offset = resource.dest;
+                        for range in entry.ranges(resource.source, resource.len) {
+                            copies.push(Copy {
+                                image: range.offset,
+                                len: range.len,
+                                device: base + file_offset,
+                                file,
+                                file_offset,
+                            });
+                            file_offset += range.len;
+                        }
+                    }
+                }
+                Content::Dir => {}
+            }
+        }
+        copies.sort_by_key(|c| c.image);
+
+        Ok(Self {
+            writes,
+            copies,
+            file_count: files.len(),
+            files,
+            split_parts,
+        })
+    }
+
+    /// The file being written at an image offset, for the progress panel
+    pub fn file_at(&self, offset: u64) -> Option<String> {
+        let index = self.copies.partition_point(|c| c.image <= offset);
+        let copy = self.copies[..index].last()?;
+        if offset >= copy.image + copy.len {
+            return None;
+        }
+        let (path, size) = &self.files[copy.file];
+        let done = copy.file_offset + (offset - copy.image);
+        Some(format!(
+            "{} ({:.0}%)",
+            path,
+            done as f64 / (*size).max(1) as f64 * 100.0
+        ))
+    }
+}
+
+#[derive(Default)]
+struct Dir {
+    /// `None` for the root directory
+    node: Option<usize>,
+    parent: usize,
+    children: Vec<usize>,
+    cluster: u64,
+    clusters: u64,
+}
+
+fn parent_of(path: &str) -> &str {
+    path.rsplit_once('/').map_or("", |(parent, _)| parent)
+}
+
+fn join(parent: &str, name: &str) -> String {
+    if parent.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}/{}", parent, name)
+    }
+}
+
+/// Sizes of a FAT32 filesystem filling a partition, in 512-byte sectors
+struct FatGeometry {
+    total_sectors: u64,
+    sectors_per_cluster: u64,
+    fat_sectors: u64,
+    clusters: u64,
+}
+
+impl FatGeometry {
+    fn new(size: u64) -> Result<Self> {
+        let total_sectors = size / SECTOR_SIZE;
+        // Cluster sizes Windows picks for FAT32 volumes of this size
+        let mut sectors_per_cluster = match size >> 30 {
+            0..8 => 8,
+            8..16 => 16,
+            16..32 => 32,
+            _ => 64,
+        };
+        loop {
+            // The FAT's size depends on the cluster count and the other way round
+            let mut fat_sectors = 1;
+            let clusters = loop {
+                let data =
+                    total_sectors.saturating_sub(FAT_RESERVED_SECTORS + FAT_COPIES * fat_sectors);
+                let clusters = data / sectors_per_cluster;
+                let needed = ((clusters + 2) * 4).div_ceil(SECTOR_SIZE);
+                if needed <= fat_sectors {
+                    break clusters;
+                }
+                fat_sectors = needed;
+            };
+            if clusters >= FAT32_MIN_CLUSTERS {
+                return Ok(Self {
+                    total_sectors,
+                    sectors_per_cluster,
+                    fat_sectors,
+                    clusters,
+                });
+            }
+            if sectors_per_cluster == 1 {
+                bail!("Device is too small for a FAT32 installer");
+            }
+            sectors_per_cluster /= 2;
+        }
+    }
+
+    fn cluster_size(&self) -> u64 {
+        self.sectors_per_cluster * SECTOR_SIZE
+    }
+
+    /// First data sector, relative to the partition
+    fn data_start(&self) -> u64 {
+        FAT_RESERVED_SECTORS + FAT_COPIES * self.fat_sectors
+    }
+
+    /// Boot sector, FSInfo and their backups
+    fn reserved_region(
+        &self,
+        hidden_sectors: u64,
+        volume_id: u32,
+        free_clusters: u64,
+        next_free: u64,
+    ) -> Vec<u8> {
+        let mut boot = vec![0u8; SECTOR_SIZE as usize];
+        boot[..3].copy_from_slice(&[0xeb, 0x58, 0x90]);
+        boot[3..11].copy_from_slice(b"MSWIN4.1");
+        boot[11..13].copy_from_slice(&(SECTOR_SIZE as u16).to_le_bytes());
+        boot[13] = self.sectors_per_cluster as u8;
+        boot[14..16].copy_from_slice(&(FAT_RESERVED_SECTORS as u16).to_le_bytes());
+        boot[16] = FAT_COPIES as u8;
+        boot[21] = 0xf8;
+        boot[24..26].copy_from_slice(&63u16.to_le_bytes());
+        boot[26..28].copy_from_slice(&255u16.to_le_bytes());
+        boot[28..32].copy_from_slice(&(hidden_sectors as u32).to_le_bytes());
+        boot[32..36].copy_from_slice(&(self.total_sectors as u32).to_le_bytes());
+        boot[36..40].copy_from_slice(&(self.fat_sectors as u32).to_le_bytes());
+        boot[44..48].copy_from_slice(&2u32.to_le_bytes());
+        boot[48..50].copy_from_slice(&1u16.to_le_bytes());
+        boot[50..52].copy_from_slice(&6u16.to_le_bytes());
+        boot[64] = 0x80;
+        boot[66] = 0x29;
+        boot[67..71].copy_from_slice(&volume_id.to_le_bytes());
+        boot[71..82].copy_from_slice(VOLUME_LABEL);
+        boot[82..90].copy_from_slice(b"FAT32   ");
+        // Legacy BIOS: hand over to the next boot device (int 18h), the stick is UEFI-only
+        boot[90..94].copy_from_slice(&[0xcd, 0x18, 0xeb, 0xfe]);
+        boot[510..512].copy_from_slice(&[0x55, 0xaa]);
+
+        let mut fs_info = vec![0u8; SECTOR_SIZE as usize];
+        fs_info[..4].copy_from_slice(&0x4161_5252u32.to_le_bytes());
+        fs_info[484..488].copy_from_slice(&0x6141_7272u32.to_le_bytes());
+        fs_info[488..492].copy_from_slice(&(free_clusters as u32).to_le_bytes());
+        fs_info[492..496].copy_from_slice(&(next_free as u32).to_le_bytes());
+        fs_info[508..512].copy_from_slice(&0xaa55_0000u32.to_le_bytes());
+
+        let mut region = vec![0u8; (FAT_RESERVED_SECTORS * SECTOR_SIZE) as usize];
+        for (sector, data) in [(0, &boot), (1, &fs_info), (6, &boot), (7, &fs_info)] {
+            let at = sector * SECTOR_SIZE as usize;
+            region[at..at + data.len()].copy_from_slice(data);
+        }
+        region
+    }
+}
+
+fn dir_entry(short: &[u8; 11], attr: u8, cluster: u64, size: u32) -> [u8; DIR_ENTRY_SIZE] {
+    let mut entry = [0u8; DIR_ENTRY_SIZE];
+    entry[..11].copy_from_slice(short);
+    entry[11] = attr;
+    if attr != ATTR_VOLUME_ID {
+        for at in [16, 18, 24] {
+            entry[at..at + 2].copy_from_slice(&DOS_DATE.to_le_bytes());
+        }
+    }
+    entry[20..22].copy_from_slice(&((cluster >> 16) as u16).to_le_bytes());
+    entry[26..28].copy_from_slice(&(cluster as u16).to_le_bytes());
+    entry[28..32].copy_from_slice(&size.to_le_bytes());
+    entry
+}
+
+/// The 8.3 name for a directory entry, unique among `taken`, and whether
+/// long name entries must carry the real name
+fn short_name(name: &str, taken: &mut HashSet<[u8; 11]>) -> ([u8; 11], bool) {
+    let (base, ext) = match name.rfind('.') {
+        Some(at) if at > 0 => (&name[..at], &name[at + 1..]),
+        _ => (name, ""),
+    };
+    let clean = |part: &str| -> (Vec<u8>, bool) {
+        let mut lossy = false;
+        let bytes = part
+            .chars()
+            .map(|c| match c.to_ascii_uppercase() {
+                c @ ('A'..='Z' | '0'..='9') => c as u8,
+                c if "!#$%&'()-@^_`{}~".contains(c) => c as u8,
+                _ => {
+                    lossy = true;
+                    b'_'
+                }
+            })
+            .collect();
+        (bytes, lossy)
+    };
+    let (base_bytes, base_lossy) = clean(base);
+    let (ext_bytes, ext_lossy) = clean(ext);
+
+    let mut short = [b' '; 11];
+    let fits = !base_bytes.is_empty() && base_bytes.len() <= 8 && ext_bytes.len() <= 3;
+    if fits && !base_lossy && !ext_lossy && !name.chars().any(|c| c.is_ascii_lowercase()) {
+        short[..base_bytes.len()].copy_from_slice(&base_bytes);
+        short[8..8 + ext_bytes.len()].copy_from_slice(&ext_bytes);
+        if taken.insert(short) {
+            return (short, false);
+        }
+    }
+
+    let ext_len = ext_bytes.len().min(3);
+    for n in 1u32.. {
+        let tail = format!("~{}", n);
+        let keep = base_bytes.len().min(8 - tail.len());
+        short = [b' '; 11];
+        short[..keep].copy_from_slice(&base_bytes[..keep]);
+        short[keep..keep + tail.len()].copy_from_slice(tail.as_bytes());
+        short[8..8 + ext_len].copy_from_slice(&ext_bytes[..ext_len]);
+        if taken.insert(short) {
+            break;
+        }
+    }
+    (short, true)
+}
+
+fn long_entry_count(name: &str) -> usize {
+    name.encode_utf16().count().div_ceil(13)
+}
+
+/// VFAT long name entries for `name`, in the order they precede the short entry
+fn long_name_entries(name: &str, short: &[u8; 11]) -> Vec<[u8; DIR_ENTRY_SIZE]> {
+    let checksum = short
+        .iter()
+        .fold(0u8, |sum, c| sum.rotate_right(1).wrapping_add(*c));
+    let mut units: Vec<u16> = name.encode_utf16().collect();
+    let count = units.len().div_ceil(13);
+    // Terminated by a NUL if there's room, then padded with 0xffff
+    if !units.len().is_multiple_of(13) {
+        units.push(0);
+    }
+    units.resize(count * 13, 0xffff);
+
+    let mut entries = Vec::new();
+    for (i, chunk) in units.chunks(13).enumerate().rev() {
+        let mut entry = [0u8; DIR_ENTRY_SIZE];
+        entry[0] = i as u8 + 1 + if i + 1 == count { 0x40 } else { 0 };
+        entry[11] = ATTR_LONG_NAME;
+        entry[13] = checksum;
+        let slots = (1..11)
+            .step_by(2)
+            .chain((14..26).step_by(2))
+            .chain((28..32).step_by(2));
+        for (slot, unit) in slots.zip(chunk) {
+            entry[slot..slot + 2].copy_from_slice(&unit.to_le_bytes());
+        }
+        entries.push(entry);
+    }
+    entries
+}
+
+/// Scatters the streamed ISO into the planned files, then writes the
+/// filesystem and partition table once all data is in place
+struct MediaWriter {
+    device: File,
+    plan: Arc<MediaPlan>,
+    /// Image offset of the next chunk
+    position: u64,
+    /// First copy not yet reached
+    next: usize,
+    /// Copies overlapping the current position
+    active: Vec<usize>,
+}
+
+impl MediaWriter {
+    fn new(device: File, plan: Arc<MediaPlan>) -> Self {
+        Self {
+            device,
+            plan,
+            position: 0,
+            next: 0,
+            active: Vec::new(),
+        }
+    }
+}
+
+impl ImageSink for MediaWriter {
+    fn write(&mut self, chunk: &[u8]) -> Result<()> {
+        let start = self.position;
+        let end = start + chunk.len() as u64;
+        while let Some(copy) = self.plan.copies.get(self.next)
+            && copy.image < end
+        {
+            self.active.push(self.next);
+            self.next += 1;
+        }
+        for &i in &self.active {
+            let copy = &self.plan.copies[i];
+            let from = copy.image.max(start);
+            let to = (copy.image + copy.len).min(end);
+            if from < to {
+                self.device
+                    .write_all_at(
+                        &chunk[(from - start) as usize..(to - start) as usize],
+                        copy.device + (from - copy.image),
+                    )
+                    .context("Failed to write to device")?;
+            }
+        }
+        let plan = &self.plan;
+        self.active.retain(|i| {
+            let copy = &plan.copies[*i];
+            copy.image + copy.len > end
+        });
+        self.position = end;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        for (offset, data) in &self.plan.writes {
+            self.device
+                .write_all_at(data, *offset)
+                .context("Failed to write the filesystem")?;
+        }
+        self.device.sync_all().context("Failed to sync device")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::filesystem::{self, FsKind};
+    use crate::core::isofs::Extent;
+    use crate::core::partition::le32;
+    use std::io::Cursor;
+
+    fn file(path: &str, offset: u64, len: u64) -> IsoEntry {
+        IsoEntry {
+            path: path.to_string(),
+            is_dir: false,
+            size: len,
+            extents: vec![Extent { offset, len }],
+        }
+    }
+
+    #[test]
+    fn test_oversized_esd() {
+        let mut install = file("sources/install.esd", 4096, 2048);
+        install.size = FAT32_MAX_FILE + 1;
+        let media = WindowsMedia {
+            entries: vec![file("sources/boot.wim", 2048, 0), install],
+        };
+        assert!(is_windows_installer(&media.entries));
+        let reason = media.unwritable().unwrap();
+        assert!(
+            reason.starts_with("sources/install.esd is 4.00 GB"),
+            "{}",
+            reason
+        );
+
+        let error = MediaPlan::new(
+            &media.entries,
+            &mut Cursor::new(vec![0u8; 8192]),
+            64 * 1024 * 1024,
+        )
+        .err()
+        .unwrap();
+        assert_eq!(error.to_string(), reason);
+    }
+
+    /// Walk a directory and return (name, first cluster, size) of its entries
+    fn list_dir(disk: &[u8], fs: u64, fat: &FatGeometry, cluster: u64) -> Vec<(String, u64, u32)> {
+        let at = fs + (fat.data_start() + (cluster - 2) * fat.sectors_per_cluster) * SECTOR_SIZE;
+        let data = &disk[at as usize..(at + fat.cluster_size()) as usize];
+        let mut long = Vec::new();
+        let mut found = Vec::new();
+        for entry in data.chunks_exact(DIR_ENTRY_SIZE) {
+            if entry[0] == 0 {
+                break;
+            }
+            if entry[11] == ATTR_LONG_NAME {
+                let slots = (1..11)
+                    .step_by(2)
+                    .chain((14..26).step_by(2))
+                    .chain((28..32).step_by(2));
+                let units: Vec<u16> = slots
+                    .map(|s| u16::from_le_bytes([entry[s], entry[s + 1]]))
+                    .take_while(|u| *u != 0 && *u != 0xffff)
+                    .collect();
+                long.insert(0, String::from_utf16_lossy(&units));
+                continue;
+            }
+            let name = if long.is_empty() {
+                String::from_utf8_lossy(&entry[..11]).trim_end().to_string()
+            } else {
+                long.concat()
+            };
+            long.clear();
+            let cluster = u64::from(u16::from_le_bytes([entry[26], entry[27]]))
+                | u64::from(u16::from_le_bytes([entry[20], entry[21]])) << 16;
+            found.push((name, cluster, le32(&entry[28..])));
+        }
+        found
+    }
+
+    /// Byte offset of a cluster on the test disk
+    fn cluster_at(fs: u64, fat: &FatGeometry, cluster: u64) -> usize {
+        (fs + (fat.data_start() + (cluster - 2) * fat.sectors_per_cluster) * SECTOR_SIZE) as usize
+    }
+
+    #[test]
+    fn test_write_media() {
+        // A WIM with three 2000-byte resources, each a run of one byte value
+        let mut wim = vec![0u8; wim::HEADER_SIZE];
+        wim[..8].copy_from_slice(b"MSWIM\0\0\0");
+        wim[42] = 1;
+        let mut table = Vec::new();
+        for i in 0..3u8 {
+            let offset = wim.len() as u64;
+            wim.extend(vec![b'w' + i; 2000]);
+            table.extend(2000u64.to_le_bytes());
+            table.extend(offset.to_le_bytes());
+            table.extend(2000u64.to_le_bytes());
+            table.extend([1, 0, 1, 0, 0, 0]);
+            table.extend([i; 20]);
+        }
+        wim[48..56].copy_from_slice(&(table.len() as u64).to_le_bytes());
+        let lookup = wim.len() as u64;
+        wim[56..64].copy_from_slice(&lookup.to_le_bytes());
+        wim.extend(table);
+
+        // The "ISO": bootmgr, and the WIM over two extents
+        let mut image = vec![0u8; 64 * 1024];
+        image[2048..2048 + 700].fill(b'b');
+        image[8192..8192 + 4000].copy_from_slice(&wim[..4000]);
+        image[32768..32768 + wim.len() - 4000].copy_from_slice(&wim[4000..]);
+        let mut install = file("sources/install.wim", 8192, 4000);
+        install.extents.push(Extent {
+            offset: 32768,
+            len: wim.len() as u64 - 4000,
+        });
+        // Past the FAT32 limit, so it must be split (into 4500-byte parts here)
+        install.size = FAT32_MAX_FILE + 1;
+        let sources = IsoEntry {
+            path: "sources".to_string(),
+            is_dir: true,
+            size: 0,
+            extents: Vec::new(),
+        };
+        let entries = vec![
+            file("bootmgr", 2048, 700),
+            sources,
+            install,
+            file("sources/boot.wim", 2048, 0),
+        ];
+        assert!(is_windows_installer(&entries));
+
+        let disk_size = 64 * 1024 * 1024;
+        let plan =
+            MediaPlan::with_part_size(&entries, &mut Cursor::new(&image), disk_size, 4500).unwrap();
+        assert_eq!((plan.file_count, plan.split_parts), (4, 2));
+        assert_eq!(plan.file_at(2048 + 350).as_deref(), Some("bootmgr (50%)"));
+
+        let mut device = tempfile_device(disk_size);
+        let mut writer = MediaWriter::new(device.try_clone().unwrap(), Arc::new(plan));
+        for chunk in image.chunks(1000) {
+            writer.write(chunk).unwrap();
+        }
+        writer.finish().unwrap();
+        let mut disk = Vec::new();
+        device.seek(SeekFrom::Start(0)).unwrap();
+        device.read_to_end(&mut disk).unwrap();
+
+        let table = partition::read_partition_table(&mut Cursor::new(&disk), disk_size)
+            .unwrap()
+            .unwrap();
+        assert_eq!(table.partitions[0].type_id, GPT_BASIC_DATA);
+        let fs = table.partitions[0].start;
+        assert_eq!(
+            filesystem::detect(&mut Cursor::new(&disk), fs).unwrap(),
+            Some(FsKind::Fat32)
+        );
+
+        let fat = FatGeometry::new(table.partitions[0].size).unwrap();
+        let root = list_dir(&disk, fs, &fat, 2);
+        let names: Vec<&str> = root.iter().map(|e| e.0.as_str()).collect();
+        assert_eq!(names, vec!["WINSETUP", "bootmgr", "sources"]);
+        let at = cluster_at(fs, &fat, root[1].1);
+        assert_eq!(root[1].2, 700);
+        assert!(disk[at..at + 700].iter().all(|b| *b == b'b'));
+
+        let listing = list_dir(&disk, fs, &fat, root[2].1);
+        let names: Vec<&str> = listing.iter().map(|e| e.0.as_str()).collect();
+        assert_eq!(
+            names,
+            vec![".", "..", "install.swm", "install2.swm", "boot.wim"]
+        );
+        // Resources land in their part after the 208-byte header
+        let at = cluster_at(fs, &fat, listing[2].1);
+        assert_eq!(disk[at + 40..at + 44], [1, 0, 2, 0]);
+        assert!(disk[at + 208..at + 2208].iter().all(|b| *b == b'w'));
+        assert!(disk[at + 2208..at + 4208].iter().all(|b| *b == b'x'));
+        let at = cluster_at(fs, &fat, listing[3].1);
+        assert_eq!(disk[at + 40..at + 44], [2, 0, 2, 0]);
+        assert!(disk[at + 208..at + 2208].iter().all(|b| *b == b'y'));
+    }
+
+    fn tempfile_device(size: u64) -> File {
+        let path = std::env::temp_dir().join(format!("pervie-windows-{}", std::process::id()));
+        let device = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)
+            .unwrap();
+        device.set_len(size).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        device
+    }
+}
@@ -0,0 +1,378 @@
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::time::Instant;
+
+use anyhow::{Context, Result, anyhow};
+use tokio::sync::mpsc::UnboundedSender;
+
+use super::AppState;
+
+const CHUNK_SIZE: usize = 1024 * 1024;
+/// Multi-pass wipes can be set between these
+pub const MIN_PASSES: u32 = 2;
+pub const MAX_PASSES: u32 = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WipeMode {
+    Zero,
+    Random,
+    /// Random passes, then a final zero pass
+    MultiPass,
+    Discard,
+    SecureDiscard,
+}
+
+impl WipeMode {
+    pub const ALL: [WipeMode; 5] = [
+        WipeMode::Zero,
+        WipeMode::Random,
+        WipeMode::MultiPass,
+        WipeMode::Discard,
+        WipeMode::SecureDiscard,
+    ];
+
+    pub fn display_name(self) -> &'static str {
+        match self {
+            WipeMode::Zero => "Zero fill",
+            WipeMode::Random => "Random fill",
+            WipeMode::MultiPass => "Multi-pass",
+            WipeMode::Discard => "Discard (TRIM)",
+            WipeMode::SecureDiscard => "Secure discard",
+        }
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            WipeMode::Zero => "Overwrite every byte with zeros once",
+            WipeMode::Random => "Overwrite every byte with random data once",
+            WipeMode::MultiPass => "Random data on every pass but the last, which writes zeros",
+            WipeMode::Discard => "Mark all blocks unused; fast, but the flash may keep old data",
+            WipeMode::SecureDiscard => "Ask the device to erase all blocks (BLKSECDISCARD)",
+        }
+    }
+
+    pub fn is_discard(self) -> bool {
+        matches!(self, WipeMode::Discard | WipeMode::SecureDiscard)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct WipeJob {
+    pub device_path: String,
+    /// Size reported by the OS, for devices that can't be seeked to their end
+    pub device_size: u64,
+    pub mode: WipeMode,
+    /// Only used by `WipeMode::MultiPass`
+    pub passes: u32,
+    /// Read the device back after the last pass
+    pub verify: bool,
+}
+
+impl WipeJob {
+    fn patterns(&self) -> Result<Vec<Pattern>> {
+        Ok(match self.mode {
+            WipeMode::Zero => vec![Pattern::Zero],
+            WipeMode::Random => vec![Pattern::Random(random_seed()?)],
+            WipeMode::MultiPass => {
+                let mut patterns = Vec::new();
+                for _ in 1..self.passes.clamp(MIN_PASSES, MAX_PASSES) {
+                    patterns.push(Pattern::Random(random_seed()?));
+                }
+                patterns.push(Pattern::Zero);
+                patterns
+            }
+            WipeMode::Discard | WipeMode::SecureDiscard => Vec::new(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct WipeProgress {
+    /// 1-based pass being written
+    pub pass: u32,
+    pub passes: u32,
+    pub verifying: bool,
+    pub bytes_done: u64,
+    pub total_bytes: u64,
+    pub speed_mbps: f64,
+    /// Of the whole wipe, verification included
+    pub percent: f64,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Pattern {
+    Zero,
+    /// Seed for a generator that can replay the same data for verification
+    Random([u64; 4]),
+}
+
+/// Overwrite the whole device with the job's passes, then optionally read
+/// the last one back. Blocking: run it on a blocking thread.
+pub fn fill(job: &WipeJob, progress_tx: &UnboundedSender<AppState>) -> Result<u64> {
+    let patterns = job.patterns()?;
+    let mut device = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(&job.device_path)
+        .with_context(|| format!("Failed to open device {}", job.device_path))?;
+    let total_bytes = device_size(&mut device, job.device_size);
+    let mut reporter = Reporter::new(job, patterns.len() as u32, total_bytes, progress_tx);
+
+    let mut buf = vec![0u8; CHUNK_SIZE];
+    for (pass, pattern) in patterns.iter().enumerate() {
+        reporter.start_pass(pass as u32 + 1, false);
+        device.seek(SeekFrom::Start(0))?;
+        let mut generator = Generator::new(*pattern);
+        let mut written = 0u64;
+        while written < total_bytes {
+            let len = (total_bytes - written).min(CHUNK_SIZE as u64) as usize;
+            generator.fill(&mut buf[..len]);
+            device
+                .write_all(&buf[..len])
+                .with_context(|| format!("Failed to write at byte {}", written))?;
+            written += len as u64;
+            reporter.update(written);
+        }
+        sync(&device)?;
+    }
+
+    if job.verify
+        && let Some(last) = patterns.last()
+    {
+        reporter.start_pass(patterns.len() as u32, true);
+        verify_pattern(&job.device_path, total_bytes, *last, &mut reporter)?;
+    }
+    Ok(total_bytes)
+}
+
+/// Check that a discarded device reads back as zeros. Many USB sticks
+/// don't guarantee that, in which case a fill mode is needed instead.
+pub fn verify_discard(job: &WipeJob, progress_tx: &UnboundedSender<AppState>) -> Result<u64> {
+    let mut device = File::open(&job.device_path)
+        .with_context(|| format!("Failed to open device {}", job.device_path))?;
+    let total_bytes = device_size(&mut device, job.device_size);
+    let mut reporter = Reporter::new(job, 0, total_bytes, progress_tx);
+    reporter.start_pass(0, true);
+    verify_pattern(&job.device_path, total_bytes, Pattern::Zero, &mut reporter)
+        .context("Discarded blocks don't read back as zeros; use a fill mode")?;
+    Ok(total_bytes)
+}
+
+fn verify_pattern(
+    device_path: &str,
+    total_bytes: u64,
+    pattern: Pattern,
+    reporter: &mut Reporter,
+) -> Result<()> {
+    let mut device = File::open(device_path)
+        .with_context(|| format!("Failed to open device {}", device_path))?;
+    // Make sure the data comes from the device, not from the page cache
+    #[cfg(target_os = "linux")]
+    unsafe {
+        use std::os::fd::AsRawFd;
+        libc::posix_fadvise(device.as_raw_fd(), 0, 0, libc::POSIX_FADV_DONTNEED);
+    }
+
+    let mut generator = Generator::new(pattern);
+    let mut expected = vec![0u8; CHUNK_SIZE];
+    let mut actual = vec![0u8; CHUNK_SIZE];
+    let mut verified = 0u64;
+    while verified < total_bytes {
+        let len = (total_bytes - verified).min(CHUNK_SIZE as u64) as usize;
+        generator.fill(&mut expected[..len]);
+        device
+            .read_exact(&mut actual[..len])
+            .with_context(|| format!("Failed to read back at byte {}", verified))?;
+        if let Some(i) = (0..len).find(|i| expected[*i] != actual[*i]) {
+            return Err(anyhow!(
+                "Verification failed: byte {} was not overwritten",
+                verified + i as u64
+            ));
+        }
+        verified += len as u64;
+        reporter.update(verified);
+    }
+    Ok(())
+}
+
+fn device_size(device: &mut File, reported: u64) -> u64 {
+    match device.seek(SeekFrom::End(0)) {
+        Ok(size) if size > 0 => size,
+        _ => reported,
+    }
+}
+
+fn sync(device: &File) -> Result<()> {
+    if let Err(e) = device.sync_all() {
+        // Raw devices on macOS/BSD answer fsync with ENOTTY
+        #[cfg(any(target_os = "macos", target_os = "freebsd"))]
+        if e.raw_os_error() == Some(25) {
+            return Ok(());
+        }
+        return Err(anyhow::Error::new(e).context("Failed to sync device"));
+    }
+    Ok(())
+}
+
+fn random_seed() -> Result<[u64; 4]> {
+    let mut bytes = [0u8; 32];
+    File::open("/dev/urandom")
+        .and_then(|mut f| f.read_exact(&mut bytes))
+        .context("Failed to read /dev/urandom")?;
+    let mut seed = [0u64; 4];
+    for (word, chunk) in seed.iter_mut().zip(bytes.chunks_exact(8)) {
+        *word = u64::from_le_bytes(chunk.try_into().unwrap());
+    }
+    // xoshiro must not start from all zeros
+    seed[0] |= 1;
+    Ok(seed)
+}
+
+/// Produces a pass's data. Random passes use xoshiro256**: fast enough to
+/// keep up with any USB device, and replayable from the seed for verifying.
+struct Generator {
+    pattern: Pattern,
+    state: [u64; 4],
+}
+
+impl Generator {
+    fn new(pattern: Pattern) -> Self {
+        let state = match pattern {
+            Pattern::Zero => [0; 4],
+            Pattern::Random(seed) => seed,
+        };
+        Self { pattern, state }
+    }
+
+    fn next(&mut self) -> u64 {
+        let s = &mut self.state;
+        let result = s[1].wrapping_mul(5).rotate_left(7).wrapping_mul(9);
+        let t = s[1] << 17;
+        s[2] ^= s[0];
+        s[3] ^= s[1];
+        s[1] ^= s[2];
+        s[0] ^= s[3];
+        s[2] ^= t;
+        s[3] = s[3].rotate_left(45);
+        result
+    }
+
+    /// Fill `buf`; callers pass whole chunks so the stream replays identically
+    fn fill(&mut self, buf: &mut [u8]) {
+        match self.pattern {
+            Pattern::Zero => buf.fill(0),
+            Pattern::Random(_) => {
+                for chunk in buf.chunks_mut(8) {
+                    let word = self.next().to_le_bytes();
+                    chunk.copy_from_slice(&word[..chunk.len()]);
+                }
+            }
+        }
+    }
+}
+
+struct Reporter<'a> {
+    progress: WipeProgress,
+    /// Passes plus the verification read, in device lengths
+    rounds: f64,
+    tx: &'a UnboundedSender<AppState>,
+    pass_start: Instant,
+    last_update: Instant,
+}
+
+impl<'a> Reporter<'a> {
+    fn new(
+        job: &WipeJob,
+        passes: u32,
+        total_bytes: u64,
+        tx: &'a UnboundedSender<AppState>,
+    ) -> Self {
+        let rounds = passes + u32::from(job.verify);
+        Self {
+            progress: WipeProgress {
+                pass: 1,
+                passes,
+                verifying: false,
+                bytes_done: 0,
+                total_bytes,
+                speed_mbps: 0.0,
+                percent: 0.0,
+            },
+            rounds: f64::from(rounds.max(1)),
+            tx,
+            pass_start: Instant::now(),
+            last_update: Instant::now(),
+        }
+    }
+
+    fn start_pass(&mut self, pass: u32, verifying: bool) {
+        self.progress.pass = pass;
+        self.progress.verifying = verifying;
+        self.progress.bytes_done = 0;
+        self.pass_start = Instant::now();
+        self.send();
+    }
+
+    fn update(&mut self, bytes_done: u64) {
+        self.progress.bytes_done = bytes_done;
+        if self.last_update.elapsed().as_millis() > 100 {
+            self.send();
+        }
+    }
+
+    fn send(&mut self) {
+        let progress = &mut self.progress;
+        let round = if progress.verifying {
+            progress.passes
+        } else {
+            progress.pass - 1
+        };
+        let fraction = progress.bytes_done as f64 / progress.total_bytes.max(1) as f64;
+        progress.percent = (f64::from(round) + fraction) / self.rounds * 100.0;
+        progress.speed_mbps =
+            (progress.bytes_done as f64 / 1_000_000.0) / self.pass_start.elapsed().as_secs_f64();
+        let _ = self.tx.send(AppState::Wiping(progress.clone()));
+        self.last_update = Instant::now();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fill_and_verify() {
+        let dir = std::env::temp_dir().join(format!("pervie-wipe-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("stick.img");
+        let (tx, _rx) = tokio::sync::mpsc::unbounded_channel();
+
+        for mode in [WipeMode::Zero, WipeMode::Random, WipeMode::MultiPass] {
+            std::fs::write(&path, vec![0x5a; 3 * CHUNK_SIZE + 100]).unwrap();
+            let job = WipeJob {
+                device_path: path.to_string_lossy().into_owned(),
+                device_size: 0,
+                mode,
+                passes: 3,
+                verify: true,
+            };
+            assert_eq!(fill(&job, &tx).unwrap(), 3 * CHUNK_SIZE as u64 + 100);
+            let data = std::fs::read(&path).unwrap();
+            assert!(!data.contains(&0x5a) || mode == WipeMode::Random);
+            assert_eq!(data.iter().all(|b| *b == 0), mode != WipeMode::Random);
+        }
+
+        // A block the device silently kept is caught by the read-back
+        std::fs::write(&path, [vec![0; CHUNK_SIZE], vec![7; 10]].concat()).unwrap();
+        let job = WipeJob {
+            device_path: path.to_string_lossy().into_owned(),
+            device_size: 0,
+            mode: WipeMode::Discard,
+            passes: 1,
+            verify: true,
+        };
+        let error = verify_discard(&job, &tx).unwrap_err();
+        assert!(format!("{:#}", error).contains(&format!("byte {}", CHUNK_SIZE)));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
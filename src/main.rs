@@ -76,49 +76,114 @@ async fn run_app<B: Backend>(terminal: &mut Terminal<B>, app: &mut App) -> anyho
 
         // Check for operation results
         if let Ok(new_state) = app.operation_rx.try_recv() {
-            app.state = new_state.clone();
-            if let AppState::Success(_) = new_state {
-                let _ = app.refresh_devices().await;
+            match new_state {
+                AppState::PreflightDone(job) => app.confirm_flash(*job),
+                AppState::MirrorListingLoaded(listing) => app.on_mirror_listing(*listing),
+                AppState::IsoContentsUpdate(update) => app.on_iso_update(*update),
+                AppState::Identified(identification) => app.on_identified(*identification),
+                AppState::MultibootReady(stick) => app.on_multiboot_ready(*stick),
+                AppState::PiSetup(boot) => app.open_pi_setup(*boot),
+                AppState::Success(_) => {
+                    app.state = new_state;
+                    let _ = app.refresh_devices().await;
+                }
+                _ => app.state = new_state,
             }
         }
 
         terminal.draw(|f| ui::draw(f, app))?;
 
         // Poll for events with timeout for tick
-        if event::poll(Duration::from_millis(100))? {
-            if let Event::Key(key) = event::read()? {
-                if key.kind != KeyEventKind::Press {
-                    continue;
-                }
-
-                match &app.state {
-                    AppState::Idle => {
-                        handle_idle_input(app, key.code).await;
-                    }
-                    AppState::DeviceSelected(_) => {
-                        handle_selected_input(app, key.code);
-                    }
-                    AppState::IsoSelection => {
-                        handle_iso_selection_input(app, key.code);
-                    }
-                    AppState::FormattingMenu => {
-                        handle_format_menu_input(app, key.code);
-                    }
-                    AppState::ConfirmDestructive(_) | AppState::ConfirmFlash(_) => {
-                        handle_confirm_input(app, key.code);
-                    }
-                    AppState::Flashing(_) | AppState::InProgress(_) => {
-                        // Block input during operations
-                    }
-                    AppState::Error(_) | AppState::Success(_) => {
-                        handle_message_input(app, key.code);
-                    }
-                }
-
-                if app.should_quit {
-                    return Ok(());
+        if event::poll(Duration::from_millis(100))?
+            && let Event::Key(key) = event::read()?
+        {
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+
+            match &app.state {
+                AppState::Idle => {
+                    handle_idle_input(app, key.code).await;
+                }
+                AppState::DeviceSelected(_) => {
+                    handle_selected_input(app, key.code);
+                }
+                AppState::IsoSelection => {
+                    handle_iso_selection_input(app, key.code);
+                }
+                AppState::CustomUrlInput => {
+                    handle_custom_url_input(app, key.code);
+                }
+                AppState::MirrorUrlInput => {
+                    handle_mirror_url_input(app, key.code);
+                }
+                AppState::MirrorBrowser => {
+                    handle_mirror_browser_input(app, key.code);
+                }
+                AppState::IsoContentsInput => {
+                    handle_iso_contents_url_input(app, key.code);
+                }
+                AppState::IsoContents => {
+                    handle_iso_contents_input(app, key.code);
+                }
+                AppState::FormattingMenu => {
+                    handle_format_menu_input(app, key.code);
+                }
+                AppState::CloneTargetSelection => {
+                    handle_clone_target_input(app, key.code);
+                }
+                AppState::WipeMenu => {
+                    handle_wipe_menu_input(app, key.code);
+                }
+                AppState::MultibootMenu => {
+                    handle_multiboot_input(app, key.code);
+                }
+                AppState::MultibootFileInput => {
+                    handle_multiboot_file_input(app, key.code);
+                }
+                AppState::PersistenceOffer(_) => {
+                    handle_persistence_input(app, key.code);
+                }
+                AppState::SeedEditor => {
+                    handle_seed_editor_input(app, key.code);
+                }
+                AppState::PiSetup(_) => {
+                    handle_pi_setup_input(app, key.code);
+                }
+                AppState::InjectionEditor => {
+                    handle_injection_editor_input(app, key.code);
+                }
+                AppState::ConfirmDestructive(_)
+                | AppState::ConfirmFlash(_)
+                | AppState::ConfirmClone(_)
+                | AppState::ConfirmWipe(_)
+                | AppState::ConfirmMultiboot(_) => {
+                    handle_confirm_input(app, key.code);
+                }
+                AppState::Flashing(_) => {
+                    handle_flashing_input(app, key.code);
+                }
+                AppState::BackupSetup => {
+                    handle_backup_setup_input(app, key.code);
+                }
+                AppState::BackingUp(_)
+                | AppState::Wiping(_)
+                | AppState::InProgress(_)
+                | AppState::PreflightDone(_)
+                | AppState::MirrorListingLoaded(_)
+                | AppState::IsoContentsUpdate(_)
+                | AppState::Identified(_)
+                | AppState::MultibootReady(_) => {
+                    // Block input during operations
+                }
+                AppState::Error(_) | AppState::Success(_) => {
+                    handle_message_input(app, key.code);
                 }
             }
+
+            if app.should_quit {
+                return Ok(());
+            }
         }
     }
 }
@@ -144,7 +209,113 @@ fn handle_selected_input(app: &mut App, key: KeyCode) {
         KeyCode::Down => app.select_next(),
         KeyCode::Char('u') => app.unmount_selected(),
         KeyCode::Char('f') => app.enter_format_menu(),
+        KeyCode::Char('w') => app.enter_wipe_menu(),
         KeyCode::Char('i') => app.enter_iso_selection(),
+        KeyCode::Char('b') => app.enter_backup_setup(),
+        KeyCode::Char('c') => app.enter_clone_target_selection(),
+        KeyCode::Char('m') => app.open_multiboot(),
+        KeyCode::Char('I') => app.identify_selected(),
+        _ => {}
+    }
+}
+
+fn handle_multiboot_input(app: &mut App, key: KeyCode) {
+    match key {
+        KeyCode::Esc => app.close_multiboot(),
+        KeyCode::Up => app.multiboot_select_previous(),
+        KeyCode::Down => app.multiboot_select_next(),
+        KeyCode::Char('a') => app.enter_iso_selection(),
+        KeyCode::Char('u') => app.enter_custom_url(),
+        KeyCode::Char('l') => app.enter_multiboot_file_input(),
+        KeyCode::Char('d') => app.remove_selected_multiboot_iso(),
+        _ => {}
+    }
+}
+
+fn handle_persistence_input(app: &mut App, key: KeyCode) {
+    match key {
+        KeyCode::Char('y') | KeyCode::Enter => app.accept_persistence(),
+        KeyCode::Char('n') | KeyCode::Esc => app.decline_persistence(),
+        _ => {}
+    }
+}
+
+fn handle_injection_editor_input(app: &mut App, key: KeyCode) {
+    match key {
+        KeyCode::Esc => app.close_injection_editor(),
+        KeyCode::Enter => app.add_injection(),
+        KeyCode::Delete => app.remove_selected_injection(),
+        KeyCode::Up => app.injection_select_previous(),
+        KeyCode::Down => app.injection_select_next(),
+        KeyCode::Backspace => {
+            app.injection_input.pop();
+        }
+        KeyCode::Char(c) => {
+            app.injection_input.push(c);
+        }
+        _ => {}
+    }
+}
+
+fn handle_pi_setup_input(app: &mut App, key: KeyCode) {
+    match key {
+        KeyCode::Esc => app.skip_pi_setup(),
+        KeyCode::Enter => app.apply_pi_setup(),
+        KeyCode::Up | KeyCode::BackTab => app.pi_previous_field(),
+        KeyCode::Down | KeyCode::Tab => app.pi_next_field(),
+        KeyCode::F(2) => app.pi_next_preset(),
+        KeyCode::F(3) => app.save_pi_preset(),
+        KeyCode::Backspace => app.pi_pop(),
+        KeyCode::Char(c) => app.pi_push(c),
+        _ => {}
+    }
+}
+
+fn handle_seed_editor_input(app: &mut App, key: KeyCode) {
+    match key {
+        KeyCode::Esc => app.close_seed_editor(),
+        KeyCode::Tab => app.next_seed_file(),
+        KeyCode::F(2) => app.next_seed_template(),
+        KeyCode::Delete => app.detach_seed(),
+        KeyCode::Enter => {
+            if let Some(text) = app.seed_text_mut() {
+                text.push('\n');
+            }
+        }
+        KeyCode::Backspace => {
+            if let Some(text) = app.seed_text_mut() {
+                text.pop();
+            }
+        }
+        KeyCode::Char(c) => {
+            if let Some(text) = app.seed_text_mut() {
+                text.push(c);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn handle_multiboot_file_input(app: &mut App, key: KeyCode) {
+    match key {
+        KeyCode::Esc => app.cancel(),
+        KeyCode::Enter => app.submit_multiboot_file(),
+        KeyCode::Backspace => {
+            app.input_buffer.pop();
+        }
+        KeyCode::Char(c) => {
+            app.input_buffer.push(c);
+        }
+        _ => {}
+    }
+}
+
+fn handle_clone_target_input(app: &mut App, key: KeyCode) {
+    match key {
+        KeyCode::Esc => app.cancel(),
+        KeyCode::Up => app.select_previous(),
+        KeyCode::Down => app.select_next(),
+        KeyCode::Enter => app.pick_clone_target(),
         _ => {}
     }
 }
@@ -160,13 +331,129 @@ fn handle_format_menu_input(app: &mut App, key: KeyCode) {
     }
 }
 
+fn handle_wipe_menu_input(app: &mut App, key: KeyCode) {
+    match key {
+        KeyCode::Char('q') => app.should_quit = true,
+        KeyCode::Esc => app.cancel(),
+        KeyCode::Up => app.select_wipe_mode(false),
+        KeyCode::Down => app.select_wipe_mode(true),
+        KeyCode::Left => app.adjust_wipe_passes(false),
+        KeyCode::Right => app.adjust_wipe_passes(true),
+        KeyCode::Char('v') => app.toggle_wipe_verify(),
+        KeyCode::Enter => app.enter_wipe_confirm(),
+        _ => {}
+    }
+}
+
 fn handle_iso_selection_input(app: &mut App, key: KeyCode) {
+    if app.iso_search_active {
+        match key {
+            KeyCode::Esc => app.clear_iso_search(),
+            KeyCode::Enter => app.finish_iso_search(),
+            KeyCode::Up => app.select_previous_iso(),
+            KeyCode::Down => app.select_next_iso(),
+            KeyCode::Backspace => app.pop_iso_search(),
+            KeyCode::Char(c) => app.push_iso_search(c),
+            _ => {}
+        }
+        return;
+    }
+
     match key {
         KeyCode::Char('q') => app.should_quit = true,
         KeyCode::Esc => app.cancel(),
         KeyCode::Up => app.select_previous_iso(),
         KeyCode::Down => app.select_next_iso(),
-        KeyCode::Enter => app.flash_selected_iso(),
+        KeyCode::Left => app.collapse_selected_group(),
+        KeyCode::Right => app.expand_selected_group(),
+        KeyCode::Char('/') => app.start_iso_search(),
+        KeyCode::Char('a') => app.cycle_arch_filter(),
+        KeyCode::Char('v') => app.enter_iso_contents_input(),
+        KeyCode::Enter => app.activate_selected_iso_row(),
+        _ => {}
+    }
+}
+
+fn handle_custom_url_input(app: &mut App, key: KeyCode) {
+    match key {
+        KeyCode::Esc => app.enter_iso_selection(),
+        KeyCode::Enter => app.submit_custom_url(),
+        KeyCode::Up => app.history_previous(),
+        KeyCode::Down => app.history_next(),
+        KeyCode::Backspace => {
+            app.input_buffer.pop();
+        }
+        KeyCode::Char(c) => {
+            app.input_buffer.push(c);
+        }
+        _ => {}
+    }
+}
+
+fn handle_mirror_url_input(app: &mut App, key: KeyCode) {
+    match key {
+        KeyCode::Esc => app.enter_iso_selection(),
+        KeyCode::Enter => app.submit_mirror_url(),
+        KeyCode::Backspace => {
+            app.input_buffer.pop();
+        }
+        KeyCode::Char(c) => {
+            app.input_buffer.push(c);
+        }
+        _ => {}
+    }
+}
+
+fn handle_mirror_browser_input(app: &mut App, key: KeyCode) {
+    match key {
+        KeyCode::Char('q') => app.should_quit = true,
+        KeyCode::Esc => app.enter_iso_selection(),
+        KeyCode::Up => app.mirror_select_previous(),
+        KeyCode::Down => app.mirror_select_next(),
+        KeyCode::Left => app.mirror_collapse(),
+        KeyCode::Right => app.mirror_expand(),
+        KeyCode::Enter => app.mirror_activate(),
+        _ => {}
+    }
+}
+
+fn handle_iso_contents_url_input(app: &mut App, key: KeyCode) {
+    match key {
+        KeyCode::Esc => app.enter_iso_selection(),
+        KeyCode::Enter => app.submit_iso_contents(),
+        KeyCode::Backspace => {
+            app.input_buffer.pop();
+        }
+        KeyCode::Char(c) => {
+            app.input_buffer.push(c);
+        }
+        _ => {}
+    }
+}
+
+fn handle_iso_contents_input(app: &mut App, key: KeyCode) {
+    if let Some(input) = app.iso_extract_input_mut() {
+        match key {
+            KeyCode::Backspace => {
+                input.pop();
+            }
+            KeyCode::Char(c) => input.push(c),
+            KeyCode::Enter => app.extract_selected_iso_file(),
+            KeyCode::Esc => app.cancel_iso_extract(),
+            _ => {}
+        }
+        return;
+    }
+
+    match key {
+        KeyCode::Char('q') => app.should_quit = true,
+        KeyCode::Esc => app.close_iso_contents(),
+        KeyCode::Up => app.iso_contents_select_previous(),
+        KeyCode::Down => app.iso_contents_select_next(),
+        KeyCode::Left => app.iso_contents_collapse(),
+        KeyCode::Right => app.iso_contents_expand(),
+        KeyCode::Enter => app.iso_contents_activate(),
+        KeyCode::Char('x') => app.start_iso_extract(),
         _ => {}
     }
 }
@@ -174,11 +461,43 @@ fn handle_iso_selection_input(app: &mut App, key: KeyCode) {
 fn handle_confirm_input(app: &mut App, key: KeyCode) {
     match key {
         KeyCode::Esc => app.cancel(),
-        KeyCode::Enter => match app.state {
-            AppState::ConfirmDestructive(_) => app.format_selected(),
-            AppState::ConfirmFlash(_) => app.start_flashing(),
-            _ => {}
-        },
+        KeyCode::Tab if matches!(app.state, AppState::ConfirmFlash(_)) => app.open_seed_editor(),
+        KeyCode::F(2) if matches!(app.state, AppState::ConfirmFlash(_)) => {
+            app.open_injection_editor()
+        }
+        KeyCode::F(3) if matches!(app.state, AppState::ConfirmFlash(_)) => app.toggle_grow(),
+        KeyCode::F(4) if matches!(app.state, AppState::ConfirmFlash(_)) => app.toggle_delta(),
+        KeyCode::Enter => {
+            // Whatever the drive held is about to change
+            app.identified = None;
+            match app.state {
+                AppState::ConfirmDestructive(_) => app.format_selected(),
+                AppState::ConfirmFlash(_) => app.start_flashing(),
+                AppState::ConfirmClone(_) => app.start_clone(),
+                AppState::ConfirmWipe(_) => app.start_wipe(),
+                AppState::ConfirmMultiboot(_) => app.start_multiboot_prepare(),
+                _ => {}
+            }
+        }
+        KeyCode::Backspace => {
+            app.input_buffer.pop();
+        }
+        KeyCode::Char(c) => {
+            app.input_buffer.push(c);
+        }
+        _ => {}
+    }
+}
+
+fn handle_backup_setup_input(app: &mut App, key: KeyCode) {
+    match key {
+        KeyCode::Esc => app.cancel(),
+        KeyCode::Enter => app.start_backup(),
+        KeyCode::Left => app.cycle_backup_compression(false),
+        KeyCode::Right => app.cycle_backup_compression(true),
+        KeyCode::Up => app.move_backup_option(false),
+        KeyCode::Down => app.move_backup_option(true),
+        KeyCode::Tab => app.toggle_backup_option(),
         KeyCode::Backspace => {
             app.input_buffer.pop();
         }
@@ -189,6 +508,14 @@ fn handle_confirm_input(app: &mut App, key: KeyCode) {
     }
 }
 
+fn handle_flashing_input(app: &mut App, key: KeyCode) {
+    match key {
+        KeyCode::Char('+') | KeyCode::Char('=') => app.step_rate_limit(true),
+        KeyCode::Char('-') => app.step_rate_limit(false),
+        _ => {}
+    }
+}
+
 fn handle_message_input(app: &mut App, key: KeyCode) {
     match key {
         KeyCode::Char('q') => app.should_quit = true,
@@ -1,10 +1,22 @@
 use async_trait::async_trait;
 use serde::Deserialize;
+use std::ffi::{CString, OsString};
+use std::fs::OpenOptions;
+use std::io::{Seek, SeekFrom};
+use std::os::fd::AsRawFd;
+use std::os::unix::ffi::OsStringExt;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 
-use crate::core::disk_ops::DiskManager;
+use crate::core::disk_ops::{DiskManager, PartitionSpec};
+use crate::core::partition;
 use crate::core::{Device, DiskError, FileSystemType};
 
+/// `_IO(0x12, 95)`, `_IO(0x12, 119)` and `_IO(0x12, 125)` from linux/fs.h
+const BLKRRPART: u32 = 0x125f;
+const BLKDISCARD: u32 = 0x1277;
+const BLKSECDISCARD: u32 = 0x127d;
+
 /// Linux-specific disk manager using lsblk and standard Linux tools
 pub struct LinuxDiskManager;
 
@@ -60,7 +72,6 @@ impl LinuxDiskManager {
                             .fstype
                             .clone()
                             .unwrap_or_else(|| "Unknown".to_string()),
-                        label: block.label.clone().unwrap_or_else(|| block.name.clone()),
                         mount_point: block.mountpoint.clone(),
                         is_protected: is_root_device,
                         is_removable: block.rm.unwrap_or(false),
@@ -103,11 +114,47 @@ struct BlockDevice {
     #[serde(rename = "type")]
     device_type: String,
     fstype: Option<String>,
-    label: Option<String>,
     mountpoint: Option<String>,
     path: Option<String>,
     rm: Option<bool>,
-    children: Option<Vec<BlockDevice>>,
+}
+
+/// Where the device at `path` is mounted, if anywhere
+fn mount_target(path: &str) -> Option<String> {
+    let output = Command::new("findmnt")
+        .args(["-n", "-o", "TARGET", "--source", path])
+        .output()
+        .ok()?;
+    let mounted = String::from_utf8_lossy(&output.stdout);
+    mounted
+        .lines()
+        .next()
+        .filter(|t| !t.is_empty())
+        .map(str::to_string)
+}
+
+const MOUNT_PREFIX: &str = "pervie-mnt-";
+
+/// A fresh, uniquely named directory to mount a partition on
+fn make_mount_point(name: &str) -> Result<PathBuf, DiskError> {
+    let template = std::env::temp_dir().join(format!("{}{}-XXXXXX", MOUNT_PREFIX, name));
+    let template = CString::new(template.into_os_string().into_vec())
+        .map_err(|e| DiskError::CommandFailed(e.to_string()))?;
+    let mut template = template.into_bytes_with_nul();
+    // SAFETY: the template is NUL-terminated and mkdtemp only rewrites the Xs
+    if unsafe { libc::mkdtemp(template.as_mut_ptr().cast()) }.is_null() {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    template.pop();
+    Ok(PathBuf::from(OsString::from_vec(template)))
+}
+
+/// Whether `mount` made this directory
+fn is_own_mount_point(target: &Path) -> bool {
+    target.parent() == Some(std::env::temp_dir().as_path())
+        && target
+            .file_name()
+            .is_some_and(|name| name.to_string_lossy().starts_with(MOUNT_PREFIX))
 }
 
 /// Parse size string from lsblk (e.g., "500G", "1T", "256M") to bytes
@@ -142,11 +189,7 @@ fn parse_size(size_str: &str) -> u64 {
 impl DiskManager for LinuxDiskManager {
     async fn list_devices(&self) -> Result<Vec<Device>, DiskError> {
         let output = Command::new("lsblk")
-            .args([
-                "--json",
-                "-o",
-                "NAME,SIZE,TYPE,FSTYPE,LABEL,MOUNTPOINT,PATH,RM",
-            ])
+            .args(["--json", "-o", "NAME,SIZE,TYPE,FSTYPE,MOUNTPOINT,PATH,RM"])
             .output()?;
 
         if !output.status.success() {
@@ -164,6 +207,7 @@ impl DiskManager for LinuxDiskManager {
             return Err(DiskError::InsufficientPrivileges);
         }
 
+        let target = mount_target(path);
         let output = Command::new("umount").arg(path).output()?;
 
         if !output.status.success() {
@@ -178,6 +222,117 @@ impl DiskManager for LinuxDiskManager {
             return Err(DiskError::CommandFailed(stderr.to_string()));
         }
 
+        // Mount points `mount` made are ours to clean up
+        if let Some(target) = target.filter(|t| is_own_mount_point(Path::new(t))) {
+            let _ = std::fs::remove_dir(target);
+        }
+
+        Ok(())
+    }
+
+    async fn partition(
+        &self,
+        path: &str,
+        partitions: &[PartitionSpec],
+    ) -> Result<Vec<String>, DiskError> {
+        if !self.has_privileges() {
+            return Err(DiskError::InsufficientPrivileges);
+        }
+
+        partition::create_gpt(path, partitions)
+            .map_err(|e| DiskError::CommandFailed(format!("{:#}", e)))?;
+        self.reread_partitions(path).await?;
+
+        let mut paths = Vec::new();
+        for (i, spec) in partitions.iter().enumerate() {
+            let partition_path = self.partition_path(path, i as u32 + 1);
+            self.format(&partition_path, spec.fs_type, &spec.label)
+                .await?;
+            paths.push(partition_path);
+        }
+        Ok(paths)
+    }
+
+    async fn reread_partitions(&self, path: &str) -> Result<(), DiskError> {
+        if !self.has_privileges() {
+            return Err(DiskError::InsufficientPrivileges);
+        }
+
+        let device = OpenOptions::new().read(true).open(path)?;
+        // SAFETY: BLKRRPART takes no argument
+        if unsafe { libc::ioctl(device.as_raw_fd(), BLKRRPART as _) } != 0 {
+            let error = std::io::Error::last_os_error();
+            return match error.raw_os_error() {
+                Some(libc::EBUSY) => Err(DiskError::DeviceBusy),
+                _ => Err(error.into()),
+            };
+        }
+        // Wait for udev to create the partition nodes
+        let _ = Command::new("udevadm").arg("settle").output();
+
+        Ok(())
+    }
+
+    async fn mount(&self, path: &str) -> Result<String, DiskError> {
+        if !self.has_privileges() {
+            return Err(DiskError::InsufficientPrivileges);
+        }
+
+        if let Some(target) = mount_target(path) {
+            return Ok(target);
+        }
+
+        let name = path.rsplit('/').next().unwrap_or(path);
+        let target = make_mount_point(name)?;
+        let output = Command::new("mount").arg(path).arg(&target).output()?;
+
+        if !output.status.success() {
+            let _ = std::fs::remove_dir(&target);
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            if stderr.contains("unknown filesystem type") {
+                return Err(DiskError::UnsupportedFilesystem(stderr.trim().to_string()));
+            }
+            return Err(DiskError::CommandFailed(stderr.to_string()));
+        }
+
+        Ok(target.to_string_lossy().into_owned())
+    }
+
+    fn partition_path(&self, path: &str, number: u32) -> String {
+        // nvme0n1 and mmcblk0 end in a digit, so their partitions get a `p`
+        if path.ends_with(|c: char| c.is_ascii_digit()) {
+            format!("{}p{}", path, number)
+        } else {
+            format!("{}{}", path, number)
+        }
+    }
+
+    async fn discard(&self, path: &str, secure: bool) -> Result<(), DiskError> {
+        if !self.has_privileges() {
+            return Err(DiskError::InsufficientPrivileges);
+        }
+
+        let mut device = OpenOptions::new().write(true).open(path)?;
+        let size = device.seek(SeekFrom::End(0))?;
+        let range: [u64; 2] = [0, size];
+        let request = if secure { BLKSECDISCARD } else { BLKDISCARD };
+        // SAFETY: the ioctl reads two u64s from `range`, which outlives the call
+        let result = unsafe { libc::ioctl(device.as_raw_fd(), request as _, range.as_ptr()) };
+        if result != 0 {
+            let error = std::io::Error::last_os_error();
+            return match error.raw_os_error() {
+                Some(libc::EOPNOTSUPP) | Some(libc::ENOTTY) => {
+                    Err(DiskError::CommandFailed(format!(
+                        "{} does not support {}discard",
+                        path,
+                        if secure { "secure " } else { "" }
+                    )))
+                }
+                Some(libc::EBUSY) => Err(DiskError::DeviceBusy),
+                _ => Err(error.into()),
+            };
+        }
+
         Ok(())
     }
 
@@ -209,7 +364,6 @@ impl DiskManager for LinuxDiskManager {
         let (cmd, args) = match fs_type {
             FileSystemType::Fat32 => ("mkfs.vfat", vec!["-F", "32", "-n", label, path]),
             FileSystemType::ExFat => ("mkfs.exfat", vec!["-n", label, path]),
-            FileSystemType::Ntfs => ("mkfs.ntfs", vec!["-f", "-L", label, path]),
             FileSystemType::Ext4 => ("mkfs.ext4", vec!["-L", label, path]),
             FileSystemType::Apfs => {
                 return Err(DiskError::UnsupportedFilesystem(
@@ -235,3 +389,20 @@ impl DiskManager for LinuxDiskManager {
         crate::utils::is_root()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mount_points() {
+        let first = make_mount_point("sdb1").unwrap();
+        let second = make_mount_point("sdb1").unwrap();
+        assert_ne!(first, second);
+        assert!(first.is_dir());
+        assert!(is_own_mount_point(&first));
+        assert!(!is_own_mount_point(Path::new("/mnt/usb")));
+        std::fs::remove_dir(first).unwrap();
+        std::fs::remove_dir(second).unwrap();
+    }
+}
@@ -2,7 +2,8 @@ use async_trait::async_trait;
 use std::process::Command;
 
 use crate::core::{Device, DiskError, FileSystemType};
-use crate::core::disk_ops::DiskManager;
+use crate::core::disk_ops::{DiskManager, PartitionSpec};
+use crate::core::partition;
 
 pub struct MacOSDiskManager;
 
@@ -78,7 +79,6 @@ impl MacOSDiskManager {
                     name: format!("Disk {}", device_identifier),
                     size_bytes,
                     filesystem: content.to_string(),
-                    label: device_identifier.to_string(),
                     mount_point: None,
                     is_protected: is_system,
                     is_removable: !is_system,
@@ -142,17 +142,18 @@ impl DiskManager for MacOSDiskManager {
             .strip_prefix("/dev/")
             .ok_or_else(|| DiskError::DeviceNotFound(path.to_string()))?;
 
-        // Always extract parent disk - eraseDisk requires whole disk identifier
-        // disk4s1 -> disk4, disk4 -> disk4 (unchanged if already whole disk)
+        // eraseDisk requires the whole disk identifier and repartitions it;
+        // a partition we added ourselves (disk4s3) is erased on its own
+        // with eraseVolume instead
         let target_disk = extract_parent_disk(identifier);
+        let verb = if target_disk == identifier {
+            "eraseDisk"
+        } else {
+            "eraseVolume"
+        };
 
         let output = Command::new("diskutil")
-            .args([
-                "eraseDisk",
-                fs_type.as_diskutil_format(),
-                label,
-                &target_disk,
-            ])
+            .args([verb, fs_type.as_diskutil_format(), label, identifier])
             .output()?;
 
         if !output.status.success() {
@@ -166,6 +167,97 @@ impl DiskManager for MacOSDiskManager {
         Ok(())
     }
 
+    async fn partition(
+        &self,
+        path: &str,
+        partitions: &[PartitionSpec],
+    ) -> Result<Vec<String>, DiskError> {
+        if !self.has_privileges() {
+            return Err(DiskError::InsufficientPrivileges);
+        }
+
+        // partitionDisk would slip its own EFI partition in front of ours on
+        // larger disks, so the table is written directly and each
+        // partition formatted with newfs
+        partition::create_gpt(path, partitions)
+            .map_err(|e| DiskError::CommandFailed(format!("{:#}", e)))?;
+        // Disk Arbitration mounts what it finds on the new table
+        let _ = Command::new("diskutil")
+            .args(["unmountDisk", path])
+            .output();
+
+        let mut paths = Vec::new();
+        for (i, spec) in partitions.iter().enumerate() {
+            let partition_path = self.partition_path(path, i as u32 + 1);
+            let raw_path = partition_path.replace("/dev/disk", "/dev/rdisk");
+            let (cmd, args) = match spec.fs_type {
+                FileSystemType::Fat32 => (
+                    "newfs_msdos",
+                    vec!["-F", "32", "-v", spec.label.as_str(), raw_path.as_str()],
+                ),
+                FileSystemType::ExFat => (
+                    "newfs_exfat",
+                    vec!["-v", spec.label.as_str(), raw_path.as_str()],
+                ),
+                other => {
+                    return Err(DiskError::UnsupportedFilesystem(format!(
+                        "{} partitions are not supported",
+                        other.display_name()
+                    )));
+                }
+            };
+
+            let output = Command::new(cmd).args(&args).output()?;
+
+            if !output.status.success() {
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                if stderr.contains("busy") {
+                    return Err(DiskError::DeviceBusy);
+                }
+                return Err(DiskError::CommandFailed(stderr.to_string()));
+            }
+            paths.push(partition_path);
+        }
+        Ok(paths)
+    }
+
+    async fn reread_partitions(&self, _path: &str) -> Result<(), DiskError> {
+        // Disk Arbitration probes a disk again by itself once it's written and closed
+        Ok(())
+    }
+
+    async fn mount(&self, path: &str) -> Result<String, DiskError> {
+        if !self.has_privileges() {
+            return Err(DiskError::InsufficientPrivileges);
+        }
+
+        let output = Command::new("diskutil").args(["mount", path]).output()?;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(DiskError::CommandFailed(stderr.to_string()));
+        }
+
+        let output = Command::new("diskutil").args(["info", path]).output()?;
+        let info = String::from_utf8_lossy(&output.stdout);
+        info.lines()
+            .find_map(|line| line.trim().strip_prefix("Mount Point:"))
+            .map(|mount_point| mount_point.trim().to_string())
+            .filter(|mount_point| !mount_point.is_empty())
+            .ok_or_else(|| DiskError::ParseError(format!("No mount point for {}", path)))
+    }
+
+    fn partition_path(&self, path: &str, number: u32) -> String {
+        format!("{}s{}", path, number)
+    }
+
+    async fn discard(&self, _path: &str, _secure: bool) -> Result<(), DiskError> {
+        // macOS only TRIMs through the filesystem; there is no raw-device ioctl
+        Err(DiskError::CommandFailed(
+            "Discard is not supported on macOS".to_string(),
+        ))
+    }
+
     async fn eject(&self, path: &str) -> Result<(), DiskError> {
         if !self.has_privileges() {
             return Err(DiskError::InsufficientPrivileges);
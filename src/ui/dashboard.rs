@@ -138,7 +138,13 @@ fn draw_device_table(frame: &mut Frame, area: Rect, app: &App) {
             };
 
             let mount = device.mount_point.as_deref().unwrap_or("—");
-            let status_text = if device.is_protected {
+            let is_clone_source = app
+                .clone_source
+                .as_ref()
+                .is_some_and(|source| source.path == device.path);
+            let status_text = if is_clone_source {
+                "Clone source"
+            } else if device.is_protected {
                 "Protected"
             } else if device.mount_point.is_some() {
                 "Mounted"
@@ -202,10 +208,44 @@ fn draw_help_bar(frame: &mut Frame, area: Rect, app: &App) {
         AppState::DeviceSelected(_) => vec![
             ("u", "Unmount"),
             ("f", "Format"),
+            ("w", "Wipe"),
             ("i", "Flash ISO"),
+            ("b", "Back up"),
+            ("c", "Clone"),
+            ("m", "Multi-boot"),
+            ("I", "Identify"),
             ("Esc", "Back"),
             ("q", "Quit"),
         ],
+        AppState::CloneTargetSelection => vec![
+            ("↑↓", "Pick target"),
+            ("Enter", "Clone onto it"),
+            ("Esc", "Cancel"),
+        ],
+        AppState::IsoSelection if app.iso_search_active => {
+            vec![("Type", "Search"), ("Enter", "Done"), ("Esc", "Clear")]
+        }
+        AppState::IsoSelection => vec![
+            ("↑↓", "Navigate"),
+            ("←→", "Fold"),
+            ("/", "Search"),
+            ("a", "Arch"),
+            (
+                "Enter",
+                if app.multiboot.is_some() {
+                    "Add"
+                } else {
+                    "Flash"
+                },
+            ),
+            ("Esc", "Back"),
+        ],
+        AppState::MultibootMenu => vec![
+            ("↑↓", "Select"),
+            ("a", "Add from catalog"),
+            ("d", "Delete"),
+            ("Esc", "Eject"),
+        ],
         _ => vec![("Esc", "Back"), ("q", "Quit")],
     };
 
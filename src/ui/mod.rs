@@ -5,6 +5,7 @@ use ratatui::Frame;
 
 use crate::app::App;
 use crate::core::AppState;
+use prompt::ConfirmKind;
 
 /// Main draw function that dispatches to appropriate view
 pub fn draw(frame: &mut Frame, app: &App) {
@@ -20,18 +21,119 @@ pub fn draw(frame: &mut Frame, app: &App) {
             dashboard::draw_dashboard(frame, app);
             prompt::draw_iso_selection(frame, app);
         }
+        AppState::CustomUrlInput => {
+            dashboard::draw_dashboard(frame, app);
+            prompt::draw_custom_url_input(frame, app);
+        }
         AppState::ConfirmDestructive(path) => {
             dashboard::draw_dashboard(frame, app);
-            prompt::draw_confirm_dialog(frame, path, &app.input_buffer, false);
+            prompt::draw_confirm_dialog(frame, path, &app.input_buffer, ConfirmKind::Format);
         }
         AppState::ConfirmFlash(path) => {
             dashboard::draw_dashboard(frame, app);
-            prompt::draw_confirm_dialog(frame, path, &app.input_buffer, true);
+            if let Some(job) = &app.flash_job {
+                prompt::draw_confirm_dialog(
+                    frame,
+                    path,
+                    &app.input_buffer,
+                    ConfirmKind::Flash(job),
+                );
+            }
+        }
+        AppState::WipeMenu => {
+            dashboard::draw_dashboard(frame, app);
+            prompt::draw_wipe_menu(frame, app);
+        }
+        AppState::ConfirmWipe(path) => {
+            dashboard::draw_dashboard(frame, app);
+            let kind = ConfirmKind::Wipe {
+                mode: app.wipe_mode,
+                passes: app.wipe_passes,
+                verify: app.wipe_verify,
+            };
+            prompt::draw_confirm_dialog(frame, path, &app.input_buffer, kind);
+        }
+        AppState::MultibootMenu => {
+            dashboard::draw_dashboard(frame, app);
+            prompt::draw_multiboot_menu(frame, app);
+        }
+        AppState::MultibootFileInput => {
+            dashboard::draw_dashboard(frame, app);
+            prompt::draw_multiboot_file_input(frame, app);
+        }
+        AppState::ConfirmMultiboot(path) => {
+            dashboard::draw_dashboard(frame, app);
+            prompt::draw_confirm_dialog(frame, path, &app.input_buffer, ConfirmKind::Multiboot);
+        }
+        AppState::PiSetup(boot) => {
+            dashboard::draw_dashboard(frame, app);
+            prompt::draw_pi_setup(frame, app, boot);
+        }
+        AppState::InjectionEditor => {
+            dashboard::draw_dashboard(frame, app);
+            prompt::draw_injection_editor(frame, app);
+        }
+        AppState::SeedEditor => {
+            dashboard::draw_dashboard(frame, app);
+            prompt::draw_seed_editor(frame, app);
+        }
+        AppState::PersistenceOffer(offer) => {
+            dashboard::draw_dashboard(frame, app);
+            prompt::draw_persistence_offer(frame, offer);
+        }
+        AppState::Wiping(progress) => {
+            dashboard::draw_dashboard(frame, app);
+            prompt::draw_wipe_progress(frame, progress);
+        }
+        AppState::CloneTargetSelection => {
+            dashboard::draw_dashboard(frame, app);
+        }
+        AppState::ConfirmClone(path) => {
+            dashboard::draw_dashboard(frame, app);
+            if let Some(source) = &app.clone_source {
+                prompt::draw_confirm_dialog(
+                    frame,
+                    path,
+                    &app.input_buffer,
+                    ConfirmKind::Clone(source),
+                );
+            }
         }
         AppState::Flashing(progress) => {
             dashboard::draw_dashboard(frame, app);
             prompt::draw_flash_progress(frame, progress);
         }
+        AppState::BackupSetup => {
+            dashboard::draw_dashboard(frame, app);
+            prompt::draw_backup_setup(frame, app);
+        }
+        AppState::BackingUp(progress) => {
+            dashboard::draw_dashboard(frame, app);
+            prompt::draw_backup_progress(frame, progress);
+        }
+        AppState::MirrorUrlInput => {
+            dashboard::draw_dashboard(frame, app);
+            prompt::draw_mirror_url_input(frame, app);
+        }
+        AppState::MirrorBrowser => {
+            dashboard::draw_dashboard(frame, app);
+            prompt::draw_mirror_browser(frame, app);
+        }
+        AppState::IsoContentsInput => {
+            dashboard::draw_dashboard(frame, app);
+            prompt::draw_iso_contents_input(frame, app);
+        }
+        AppState::IsoContents => {
+            dashboard::draw_dashboard(frame, app);
+            prompt::draw_iso_contents(frame, app);
+        }
+        AppState::PreflightDone(_)
+        | AppState::MirrorListingLoaded(_)
+        | AppState::IsoContentsUpdate(_)
+        | AppState::Identified(_)
+        | AppState::MultibootReady(_) => {
+            dashboard::draw_dashboard(frame, app);
+        }
         AppState::InProgress(msg) => {
             dashboard::draw_dashboard(frame, app);
             prompt::draw_status_message(frame, app, msg, prompt::MessageType::Info);
@@ -1,13 +1,28 @@
 use ratatui::{
     Frame,
-    layout::{Alignment, Constraint, Flex, Layout, Rect},
+    layout::{Alignment, Constraint, Flex, Layout, Margin, Rect},
     style::{Color, Modifier, Style},
     text::{Line, Span},
-    widgets::{Block, Borders, Clear, List, ListItem, Paragraph, Wrap},
+    widgets::{
+        Block, Borders, Cell, Clear, List, ListItem, Padding, Paragraph, Row, Table, TableState,
+        Wrap,
+    },
 };
 
 use crate::app::App;
-use crate::core::flasher::FlashProgress;
+use crate::core::backup::{BackupOption, BackupProgress, BackupStage, Compression};
+use crate::core::catalog::{self, IsoRow};
+use crate::core::cloudinit::{self, SeedFile};
+use crate::core::flasher::{self, FlashProgress};
+use crate::core::inspect::{ImageFormat, Inspection};
+use crate::core::isofs::IsoKind;
+use crate::core::mirror;
+use crate::core::multiboot::BootMethod;
+use crate::core::partition::TableKind;
+use crate::core::persistence::PersistenceOffer;
+use crate::core::rpi::{PiBoot, PiField};
+use crate::core::wipe::{WipeMode, WipeProgress};
+use crate::core::{Device, FlashJob};
 use crate::utils::bytes_to_human;
 use ratatui::widgets::Gauge;
 
@@ -51,71 +66,1228 @@ pub fn draw_format_menu(frame: &mut Frame, app: &App) {
     frame.render_widget(list, inner);
 }
 
+/// Draw the wipe mode menu with pass count and verification toggles
+pub fn draw_wipe_menu(frame: &mut Frame, app: &App) {
+    let area = centered_rect(60, 50, frame.area());
+
+    frame.render_widget(Clear, area);
+
+    let block = Block::default()
+        .title(" Wipe Mode ")
+        .borders(Borders::ALL)
+        .style(Style::default().fg(Color::Cyan));
+
+    let inner = block.inner(area);
+    frame.render_widget(block, area);
+
+    let chunks = Layout::vertical([
+        Constraint::Min(1),
+        Constraint::Length(2),
+        Constraint::Length(1),
+        Constraint::Length(1),
+    ])
+    .split(inner);
+
+    let items: Vec<ListItem> = WipeMode::ALL
+        .iter()
+        .map(|mode| {
+            let style = if *mode == app.wipe_mode {
+                Style::default()
+                    .fg(Color::Yellow)
+                    .add_modifier(Modifier::BOLD | Modifier::REVERSED)
+            } else {
+                Style::default()
+            };
+            let name = match mode {
+                WipeMode::MultiPass => format!("{} ◀ {} ▶", mode.display_name(), app.wipe_passes),
+                _ => mode.display_name().to_string(),
+            };
+            ListItem::new(name).style(style)
+        })
+        .collect();
+    frame.render_widget(List::new(items), chunks[0]);
+
+    let description = Paragraph::new(app.wipe_mode.description())
+        .style(Style::default().fg(Color::White))
+        .wrap(Wrap { trim: true });
+    frame.render_widget(description, chunks[1]);
+
+    let verify = if app.wipe_verify {
+        "[x] Read the device back to verify"
+    } else {
+        "[ ] Read the device back to verify"
+    };
+    frame.render_widget(
+        Paragraph::new(verify).style(Style::default().fg(Color::White)),
+        chunks[2],
+    );
+
+    let footer = Paragraph::new("↑↓ Mode  │  ←→ Passes  │  v Verify  │  Enter Wipe  │  Esc Back")
+        .alignment(Alignment::Center)
+        .style(Style::default().fg(Color::DarkGray));
+    frame.render_widget(footer, chunks[3]);
+}
+
+pub fn draw_wipe_progress(frame: &mut Frame, progress: &WipeProgress) {
+    let area = centered_rect(60, 25, frame.area());
+    frame.render_widget(Clear, area);
+
+    let title = if progress.verifying {
+        " Verifying wipe... ".to_string()
+    } else {
+        format!(" Wiping, pass {}/{}... ", progress.pass, progress.passes)
+    };
+    let block = Block::default()
+        .title(title)
+        .borders(Borders::ALL)
+        .style(Style::default().fg(Color::Cyan));
+
+    let inner = block.inner(area);
+    frame.render_widget(block, area);
+
+    let chunks = Layout::vertical([Constraint::Length(2), Constraint::Length(3)]).split(inner);
+
+    let info = Paragraph::new(format!(
+        "{}/{} ({:.1} MB/s)",
+        bytes_to_human(progress.bytes_done),
+        bytes_to_human(progress.total_bytes),
+        progress.speed_mbps
+    ))
+    .alignment(Alignment::Center);
+    frame.render_widget(info, chunks[0]);
+
+    let gauge = Gauge::default()
+        .block(Block::default().borders(Borders::NONE))
+        .gauge_style(Style::default().fg(Color::Red))
+        .ratio((progress.percent / 100.0).clamp(0.0, 1.0))
+        .label(format!("{:.1}%", progress.percent));
+    frame.render_widget(gauge, chunks[1]);
+}
+
 /// Draw the ISO selection menu
 pub fn draw_iso_selection(frame: &mut Frame, app: &App) {
-    let area = centered_rect(70, 70, frame.area());
+    let area = centered_rect(80, 70, frame.area());
+
+    frame.render_widget(Clear, area);
+
+    let block = Block::default()
+        .title(" Select ISO Image ")
+        .borders(Borders::ALL)
+        .style(Style::default().fg(Color::Magenta));
+
+    let inner = block.inner(area);
+    frame.render_widget(block, area);
+
+    let chunks = Layout::vertical([Constraint::Length(2), Constraint::Min(1)]).split(inner);
+    draw_iso_filter_bar(frame, chunks[0], app);
+
+    let body = Layout::horizontal([Constraint::Percentage(60), Constraint::Percentage(40)])
+        .split(chunks[1]);
+
+    let rows = app.visible_iso_rows();
+    let table_rows: Vec<Row> = rows.iter().map(|row| iso_table_row(app, row)).collect();
+
+    let header = Row::new(vec!["DISTRO / VERSION", "ARCH", "VARIETY"])
+        .style(
+            Style::default()
+                .fg(Color::White)
+                .add_modifier(Modifier::BOLD),
+        )
+        .bottom_margin(1);
+
+    let table = Table::new(
+        table_rows,
+        [
+            Constraint::Min(16),
+            Constraint::Length(9),
+            Constraint::Min(10),
+        ],
+    )
+    .header(header)
+    .column_spacing(1)
+    .highlight_style(
+        Style::default()
+            .fg(Color::Yellow)
+            .add_modifier(Modifier::BOLD | Modifier::REVERSED),
+    );
+
+    let mut state = TableState::default().with_selected(Some(app.selected_iso_row));
+    frame.render_stateful_widget(table, body[0], &mut state);
+
+    // Only the "Custom URL…" and "Browse mirror…" entries are left
+    if rows.len() == 2 {
+        let empty = Paragraph::new("No images match the current filter")
+            .alignment(Alignment::Center)
+            .style(Style::default().fg(Color::DarkGray));
+        frame.render_widget(empty, body[0].inner(Margin::new(0, 2)));
+    }
+
+    draw_iso_details(frame, body[1], app, &rows);
+}
+
+fn iso_table_row(app: &App, row: &IsoRow) -> Row<'static> {
+    match row {
+        IsoRow::Group {
+            name,
+            count,
+            collapsed,
+        } => {
+            let marker = if *collapsed { "▸" } else { "▾" };
+            Row::new(vec![
+                Cell::from(format!("{} {}", marker, name)),
+                Cell::from(""),
+                Cell::from(format!(
+                    "{} image{}",
+                    count,
+                    if *count == 1 { "" } else { "s" }
+                )),
+            ])
+            .style(
+                Style::default()
+                    .fg(Color::White)
+                    .add_modifier(Modifier::BOLD),
+            )
+        }
+        IsoRow::Item(i) => {
+            let iso = &app.isos[*i];
+            Row::new(vec![
+                Cell::from(format!("    {}", iso.version)),
+                Cell::from(iso.arch.clone()),
+                Cell::from(iso.variety.clone()),
+            ])
+        }
+        IsoRow::CustomUrl => {
+            Row::new(vec![Cell::from("+ Custom URL…")]).style(Style::default().fg(Color::Cyan))
+        }
+        IsoRow::BrowseMirror => {
+            Row::new(vec![Cell::from("+ Browse mirror…")]).style(Style::default().fg(Color::Cyan))
+        }
+    }
+}
+
+fn draw_iso_filter_bar(frame: &mut Frame, area: Rect, app: &App) {
+    let search_style = if app.iso_search_active {
+        Style::default().fg(Color::Yellow)
+    } else {
+        Style::default().fg(Color::White)
+    };
+    let cursor = if app.iso_search_active { "▏" } else { "" };
+
+    let arch_label = match &app.iso_filter.arch {
+        Some(arch) if *arch == catalog::host_arch() => format!("{} (host)", arch),
+        Some(arch) => arch.clone(),
+        None => "all".to_string(),
+    };
+
+    let bar = Paragraph::new(Line::from(vec![
+        Span::styled("/ Search: ", Style::default().fg(Color::DarkGray)),
+        Span::styled(format!("{}{}", app.iso_filter.query, cursor), search_style),
+        Span::raw("    "),
+        Span::styled("a Arch: ", Style::default().fg(Color::DarkGray)),
+        Span::styled(arch_label, Style::default().fg(Color::White)),
+        Span::raw("    "),
+        Span::styled("v Browse contents", Style::default().fg(Color::DarkGray)),
+    ]))
+    .block(Block::default().borders(Borders::BOTTOM));
+
+    frame.render_widget(bar, area);
+}
+
+fn draw_iso_details(frame: &mut Frame, area: Rect, app: &App, rows: &[IsoRow]) {
+    let block = Block::default()
+        .title(" Details ")
+        .borders(Borders::LEFT)
+        .padding(Padding::horizontal(1))
+        .style(Style::default().fg(Color::White));
+
+    let label = Style::default().fg(Color::DarkGray);
+    let lines = match rows.get(app.selected_iso_row) {
+        Some(IsoRow::Item(i)) => {
+            let iso = &app.isos[*i];
+            let preflight = app.preflights.get(&iso.url);
+            let size = iso
+                .size_bytes
+                .or(preflight.map(|p| p.total_bytes))
+                .map(bytes_to_human)
+                .unwrap_or_else(|| "Unknown until pre-flight".to_string());
+            let checksum = match (&iso.sha256, preflight) {
+                (Some(_), _) => Span::styled("SHA-256 pinned", Style::default().fg(Color::Green)),
+                (None, Some(p)) => match (&p.sha256, &p.checksum_source) {
+                    (Some(_), Some(source)) => Span::styled(
+                        format!("From {}", source),
+                        Style::default().fg(Color::Green),
+                    ),
+                    _ if p.verifies_pieces => {
+                        Span::styled("Pieces verified", Style::default().fg(Color::Green))
+                    }
+                    _ => Span::styled("None published", Style::default().fg(Color::Yellow)),
+                },
+                (None, None) => Span::raw("From the published checksum file, if any"),
+            };
+            let released = iso.release_date.as_deref().unwrap_or("Unknown");
+
+            vec![
+                Line::from(Span::styled(
+                    format!("{} {}", iso.name, iso.version),
+                    Style::default().add_modifier(Modifier::BOLD),
+                )),
+                Line::default(),
+                Line::from(vec![
+                    Span::styled("Variety   ", label),
+                    Span::raw(iso.variety.clone()),
+                ]),
+                Line::from(vec![
+                    Span::styled("Arch      ", label),
+                    Span::raw(iso.arch.clone()),
+                ]),
+                Line::from(vec![Span::styled("Size      ", label), Span::raw(size)]),
+                Line::from(vec![Span::styled("Checksum  ", label), checksum]),
+                Line::from(vec![Span::styled("Released  ", label), Span::raw(released)]),
+                Line::default(),
+                Line::from(iso.description.clone()),
+            ]
+        }
+        Some(IsoRow::Group {
+            name,
+            count,
+            collapsed,
+        }) => vec![
+            Line::from(Span::styled(
+                name.clone(),
+                Style::default().add_modifier(Modifier::BOLD),
+            )),
+            Line::default(),
+            Line::from(format!("{} matching image(s)", count)),
+            Line::from(Span::styled(
+                if *collapsed {
+                    "Enter/→ to expand"
+                } else {
+                    "Enter/← to collapse"
+                },
+                label,
+            )),
+        ],
+        Some(IsoRow::CustomUrl) => vec![
+            Line::from(Span::styled(
+                "Custom URL",
+                Style::default().add_modifier(Modifier::BOLD),
+            )),
+            Line::default(),
+            Line::from("Flash any http(s) image that is not in the catalog."),
+            Line::default(),
+            Line::from(Span::styled(
+                format!("{} recently used", app.url_history.entries.len()),
+                label,
+            )),
+        ],
+        Some(IsoRow::BrowseMirror) => vec![
+            Line::from(Span::styled(
+                "Browse mirror",
+                Style::default().add_modifier(Modifier::BOLD),
+            )),
+            Line::default(),
+            Line::from("Walk an Apache/nginx directory listing and pick an image from it."),
+        ],
+        None => Vec::new(),
+    };
+
+    let details = Paragraph::new(lines).wrap(Wrap { trim: true }).block(block);
+    frame.render_widget(details, area);
+}
+
+/// Draw the custom image URL input with validation and history
+pub fn draw_custom_url_input(frame: &mut Frame, app: &App) {
+    let area = centered_rect(70, 50, frame.area());
+
+    frame.render_widget(Clear, area);
+
+    let block = Block::default()
+        .title(" Custom Image URL ")
+        .borders(Borders::ALL)
+        .style(Style::default().fg(Color::Magenta));
+
+    let inner = block.inner(area);
+    frame.render_widget(block, area);
+
+    let chunks = Layout::vertical([
+        Constraint::Length(3),
+        Constraint::Length(2),
+        Constraint::Min(1),
+        Constraint::Length(1),
+    ])
+    .split(inner);
+
+    let input_display = Paragraph::new(format!("{}▏", app.input_buffer)).block(
+        Block::default()
+            .borders(Borders::ALL)
+            .title(" URL ")
+            .style(Style::default().fg(Color::White)),
+    );
+    frame.render_widget(input_display, chunks[0]);
+
+    let validation = match flasher::validate_image_url(&app.input_buffer) {
+        Ok(_) => Span::styled("✓ Looks good", Style::default().fg(Color::Green)),
+        Err(_) if app.input_buffer.is_empty() => Span::styled(
+            "http(s) link to an .iso / .img file",
+            Style::default().fg(Color::DarkGray),
+        ),
+        Err(e) => Span::styled(e, Style::default().fg(Color::Red)),
+    };
+    frame.render_widget(Paragraph::new(Line::from(validation)), chunks[1]);
+
+    let items: Vec<ListItem> = app
+        .url_history
+        .entries
+        .iter()
+        .enumerate()
+        .map(|(i, url)| {
+            let style = if Some(i) == app.history_cursor {
+                Style::default()
+                    .fg(Color::Yellow)
+                    .add_modifier(Modifier::BOLD | Modifier::REVERSED)
+            } else {
+                Style::default().fg(Color::White)
+            };
+            ListItem::new(url.as_str()).style(style)
+        })
+        .collect();
+
+    let history = List::new(items).block(
+        Block::default()
+            .borders(Borders::TOP)
+            .title(" Recent ")
+            .style(Style::default().fg(Color::DarkGray)),
+    );
+    frame.render_widget(history, chunks[2]);
+
+    let footer = Paragraph::new("↑↓ History  │  Enter Check & continue  │  Esc Back")
+        .alignment(Alignment::Center)
+        .style(Style::default().fg(Color::DarkGray));
+    frame.render_widget(footer, chunks[3]);
+}
+
+/// Draw the base URL input for the mirror browser
+pub fn draw_mirror_url_input(frame: &mut Frame, app: &App) {
+    let area = centered_rect(70, 25, frame.area());
+
+    frame.render_widget(Clear, area);
+
+    let block = Block::default()
+        .title(" Browse Mirror ")
+        .borders(Borders::ALL)
+        .style(Style::default().fg(Color::Magenta));
+
+    let inner = block.inner(area);
+    frame.render_widget(block, area);
+
+    let chunks = Layout::vertical([
+        Constraint::Length(3),
+        Constraint::Min(1),
+        Constraint::Length(1),
+    ])
+    .split(inner);
+
+    let input_display = Paragraph::new(format!("{}▏", app.input_buffer)).block(
+        Block::default()
+            .borders(Borders::ALL)
+            .title(" Directory URL ")
+            .style(Style::default().fg(Color::White)),
+    );
+    frame.render_widget(input_display, chunks[0]);
+
+    let hint = Paragraph::new("e.g. https://cdimage.debian.org/debian-cd/current/")
+        .style(Style::default().fg(Color::DarkGray));
+    frame.render_widget(hint, chunks[1]);
+
+    let footer = Paragraph::new("Enter Open  │  Esc Back")
+        .alignment(Alignment::Center)
+        .style(Style::default().fg(Color::DarkGray));
+    frame.render_widget(footer, chunks[2]);
+}
+
+/// Draw the mirror directory tree with images and checksum files highlighted
+pub fn draw_mirror_browser(frame: &mut Frame, app: &App) {
+    let Some(tree) = app.mirror.as_ref() else {
+        return;
+    };
+    let area = centered_rect(80, 70, frame.area());
+
+    frame.render_widget(Clear, area);
+
+    let block = Block::default()
+        .title(format!(" {} ", tree.base_url))
+        .borders(Borders::ALL)
+        .style(Style::default().fg(Color::Magenta));
+
+    let inner = block.inner(area);
+    frame.render_widget(block, area);
+
+    let chunks = Layout::vertical([
+        Constraint::Min(1),
+        Constraint::Length(2),
+        Constraint::Length(1),
+    ])
+    .split(inner);
+
+    let rows: Vec<Row> = tree
+        .nodes
+        .iter()
+        .map(|node| {
+            let indent = "  ".repeat(node.depth);
+            let entry = &node.entry;
+            let (name, style) = if entry.is_dir {
+                let marker = if node.expanded { "▾" } else { "▸" };
+                (
+                    format!("{}{} {}/", indent, marker, entry.name),
+                    Style::default()
+                        .fg(Color::White)
+                        .add_modifier(Modifier::BOLD),
+                )
+            } else if entry.is_image() {
+                (
+                    format!("{}  {}", indent, entry.name),
+                    Style::default()
+                        .fg(Color::Green)
+                        .add_modifier(Modifier::BOLD),
+                )
+            } else if entry.is_checksum() {
+                (
+                    format!("{}  {}", indent, entry.name),
+                    Style::default().fg(Color::Cyan),
+                )
+            } else {
+                (
+                    format!("{}  {}", indent, entry.name),
+                    Style::default().fg(Color::DarkGray),
+                )
+            };
+            Row::new(vec![
+                Cell::from(name),
+                Cell::from(entry.size_bytes.map(bytes_to_human).unwrap_or_default()),
+                Cell::from(entry.modified.clone().unwrap_or_default()),
+            ])
+            .style(style)
+        })
+        .collect();
+
+    let table = Table::new(
+        rows,
+        [
+            Constraint::Min(20),
+            Constraint::Length(11),
+            Constraint::Length(30),
+        ],
+    )
+    .column_spacing(1)
+    .highlight_style(
+        Style::default()
+            .fg(Color::Yellow)
+            .add_modifier(Modifier::BOLD | Modifier::REVERSED),
+    );
+    let mut state = TableState::default().with_selected(Some(tree.selected));
+    frame.render_stateful_widget(table, chunks[0], &mut state);
+
+    let status = if let Some(url) = &tree.loading {
+        let spinner_frames = ["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏"];
+        let spinner = spinner_frames[app.tick as usize % spinner_frames.len()];
+        Line::from(Span::styled(
+            format!("{} Loading {}", spinner, url),
+            Style::default().fg(Color::Cyan),
+        ))
+    } else if let Some(error) = &tree.error {
+        Line::from(Span::styled(error.clone(), Style::default().fg(Color::Red)))
+    } else {
+        match tree.selected_node() {
+            Some(node) if node.entry.is_image() => {
+                match mirror::sibling_checksum(&tree.siblings(tree.selected), &node.entry) {
+                    Some(checksum) => Line::from(Span::styled(
+                        format!("Checksum: {}", checksum.name),
+                        Style::default().fg(Color::Green),
+                    )),
+                    None => Line::from(Span::styled(
+                        "No checksum file next to this image",
+                        Style::default().fg(Color::Yellow),
+                    )),
+                }
+            }
+            _ if tree.nodes.is_empty() => Line::from(Span::styled(
+                "Empty listing",
+                Style::default().fg(Color::DarkGray),
+            )),
+            _ => Line::default(),
+        }
+    };
+    frame.render_widget(Paragraph::new(status).wrap(Wrap { trim: true }), chunks[1]);
+
+    let footer = Paragraph::new("↑↓ Navigate  │  ←→ Fold  │  Enter Open/Flash  │  Esc Back")
+        .alignment(Alignment::Center)
+        .style(Style::default().fg(Color::DarkGray));
+    frame.render_widget(footer, chunks[2]);
+}
+
+/// Draw the input for the image whose contents to browse
+pub fn draw_iso_contents_input(frame: &mut Frame, app: &App) {
+    let area = centered_rect(70, 25, frame.area());
+
+    frame.render_widget(Clear, area);
+
+    let block = Block::default()
+        .title(" Browse Image Contents ")
+        .borders(Borders::ALL)
+        .style(Style::default().fg(Color::Magenta));
+
+    let inner = block.inner(area);
+    frame.render_widget(block, area);
+
+    let chunks = Layout::vertical([
+        Constraint::Length(3),
+        Constraint::Min(1),
+        Constraint::Length(1),
+    ])
+    .split(inner);
+
+    let input_display = Paragraph::new(format!("{}▏", app.input_buffer)).block(
+        Block::default()
+            .borders(Borders::ALL)
+            .title(" Image URL or path ")
+            .style(Style::default().fg(Color::White)),
+    );
+    frame.render_widget(input_display, chunks[0]);
+
+    let hint = Paragraph::new(
+        "An ISO on an http(s), s3 or oci URL, or a local file; only the directories are fetched",
+    )
+    .wrap(Wrap { trim: true })
+    .style(Style::default().fg(Color::DarkGray));
+    frame.render_widget(hint, chunks[1]);
+
+    let footer = Paragraph::new("Enter Open  │  Esc Back")
+        .alignment(Alignment::Center)
+        .style(Style::default().fg(Color::DarkGray));
+    frame.render_widget(footer, chunks[2]);
+}
+
+/// Draw the directory tree inside an image, with extraction
+pub fn draw_iso_contents(frame: &mut Frame, app: &App) {
+    let Some(tree) = app.iso_tree.as_ref() else {
+        return;
+    };
+    let area = centered_rect(80, 70, frame.area());
+
+    frame.render_widget(Clear, area);
+
+    let title = match tree.kind {
+        Some(kind) => format!(" {} ({}) ", tree.image, isofs_name(kind)),
+        None => format!(" {} ", tree.image),
+    };
+    let block = Block::default()
+        .title(title)
+        .borders(Borders::ALL)
+        .style(Style::default().fg(Color::Magenta));
+
+    let inner = block.inner(area);
+    frame.render_widget(block, area);
+
+    let chunks = Layout::vertical([
+        Constraint::Min(1),
+        Constraint::Length(3),
+        Constraint::Length(1),
+    ])
+    .split(inner);
+
+    let rows: Vec<Row> = tree
+        .nodes
+        .iter()
+        .map(|node| {
+            let indent = "  ".repeat(node.depth);
+            let entry = &node.entry;
+            if entry.is_dir {
+                let marker = if node.expanded { "▾" } else { "▸" };
+                Row::new(vec![
+                    Cell::from(format!("{}{} {}/", indent, marker, entry.name())),
+                    Cell::from(""),
+                ])
+                .style(
+                    Style::default()
+                        .fg(Color::White)
+                        .add_modifier(Modifier::BOLD),
+                )
+            } else {
+                Row::new(vec![
+                    Cell::from(format!("{}  {}", indent, entry.name())),
+                    Cell::from(bytes_to_human(entry.size)),
+                ])
+                .style(Style::default().fg(Color::Gray))
+            }
+        })
+        .collect();
+
+    let table = Table::new(rows, [Constraint::Min(20), Constraint::Length(11)])
+        .column_spacing(1)
+        .highlight_style(
+            Style::default()
+                .fg(Color::Yellow)
+                .add_modifier(Modifier::BOLD | Modifier::REVERSED),
+        );
+    let mut state = TableState::default().with_selected(Some(tree.selected));
+    frame.render_stateful_widget(table, chunks[0], &mut state);
+
+    let spinner_frames = ["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏"];
+    let spinner = spinner_frames[app.tick as usize % spinner_frames.len()];
+    if let Some(input) = &tree.extract_input {
+        let input_display = Paragraph::new(format!("{}▏", input)).block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title(" Extract to ")
+                .style(Style::default().fg(Color::Yellow)),
+        );
+        frame.render_widget(input_display, chunks[1]);
+    } else {
+        let status = if tree.loading {
+            Line::from(Span::styled(
+                format!("{} Reading the directory tree...", spinner),
+                Style::default().fg(Color::Cyan),
+            ))
+        } else if let Some(path) = &tree.extracting {
+            Line::from(Span::styled(
+                format!("{} Extracting {}...", spinner, path),
+                Style::default().fg(Color::Cyan),
+            ))
+        } else if let Some(error) = &tree.error {
+            Line::from(Span::styled(error.clone(), Style::default().fg(Color::Red)))
+        } else if let Some(notice) = &tree.notice {
+            Line::from(Span::styled(
+                notice.clone(),
+                Style::default().fg(Color::Green),
+            ))
+        } else if tree.nodes.is_empty() {
+            Line::from(Span::styled(
+                "The image has no files",
+                Style::default().fg(Color::DarkGray),
+            ))
+        } else {
+            Line::from(Span::styled(
+                format!(
+                    "{} files, {}",
+                    tree.entries.iter().filter(|e| !e.is_dir).count(),
+                    bytes_to_human(tree.entries.iter().map(|e| e.size).sum())
+                ),
+                Style::default().fg(Color::DarkGray),
+            ))
+        };
+        frame.render_widget(Paragraph::new(status).wrap(Wrap { trim: true }), chunks[1]);
+    }
+
+    let footer = if tree.extract_input.is_some() {
+        "Enter Extract  │  Esc Cancel"
+    } else {
+        "↑↓ Navigate  │  ←→ Fold  │  Enter Open/Extract  │  x Extract  │  Esc Back"
+    };
+    let footer = Paragraph::new(footer)
+        .alignment(Alignment::Center)
+        .style(Style::default().fg(Color::DarkGray));
+    frame.render_widget(footer, chunks[2]);
+}
+
+fn isofs_name(kind: IsoKind) -> &'static str {
+    match kind {
+        IsoKind::Udf => "UDF",
+        IsoKind::RockRidge => "Rock Ridge",
+        IsoKind::Joliet => "Joliet",
+        IsoKind::Iso9660 => "ISO 9660",
+    }
+}
+
+/// Draw the ISOs on a multi-boot stick with its free space
+pub fn draw_multiboot_menu(frame: &mut Frame, app: &App) {
+    let Some(stick) = app.multiboot.as_ref() else {
+        return;
+    };
+    let area = centered_rect(70, 60, frame.area());
+
+    frame.render_widget(Clear, area);
+
+    let block = Block::default()
+        .title(format!(" Multi-boot Stick {} ", stick.device_path))
+        .borders(Borders::ALL)
+        .style(Style::default().fg(Color::Cyan));
+
+    let inner = block.inner(area);
+    frame.render_widget(block, area);
+
+    let chunks = Layout::vertical([
+        Constraint::Length(1),
+        Constraint::Length(1),
+        Constraint::Min(1),
+        Constraint::Length(1),
+    ])
+    .split(inner);
+
+    let used = stick.total_bytes.saturating_sub(stick.free_bytes);
+    let gauge = Gauge::default()
+        .gauge_style(Style::default().fg(Color::Blue))
+        .ratio((used as f64 / stick.total_bytes.max(1) as f64).clamp(0.0, 1.0))
+        .label(format!(
+            "{} free of {}",
+            bytes_to_human(stick.free_bytes),
+            bytes_to_human(stick.total_bytes)
+        ));
+    frame.render_widget(gauge, chunks[0]);
+
+    if let Some(notice) = &stick.notice {
+        let notice = Paragraph::new(notice.as_str()).style(Style::default().fg(Color::Yellow));
+        frame.render_widget(notice, chunks[1]);
+    }
+
+    if stick.isos.is_empty() {
+        let empty = Paragraph::new("No ISOs yet. Add one from the catalog, a URL or a local file.")
+            .style(Style::default().fg(Color::DarkGray))
+            .wrap(Wrap { trim: true });
+        frame.render_widget(empty, chunks[2]);
+    } else {
+        let rows: Vec<Row> = stick
+            .isos
+            .iter()
+            .map(|iso| {
+                let boot_style = match iso.boot {
+                    BootMethod::Unsupported => Style::default().fg(Color::Red),
+                    _ => Style::default().fg(Color::DarkGray),
+                };
+                Row::new(vec![
+                    Cell::from(iso.name.clone()).style(Style::default().fg(Color::White)),
+                    Cell::from(bytes_to_human(iso.size)).style(Style::default().fg(Color::White)),
+                    Cell::from(iso.boot.display_name()).style(boot_style),
+                ])
+            })
+            .collect();
+        let table = Table::new(
+            rows,
+            [
+                Constraint::Min(20),
+                Constraint::Length(10),
+                Constraint::Length(14),
+            ],
+        )
+        .header(
+            Row::new(vec!["ISO", "Size", "Boots via"]).style(
+                Style::default()
+                    .fg(Color::Cyan)
+                    .add_modifier(Modifier::BOLD),
+            ),
+        )
+        .highlight_style(
+            Style::default()
+                .fg(Color::Yellow)
+                .add_modifier(Modifier::BOLD | Modifier::REVERSED),
+        );
+        let mut state = TableState::default().with_selected(Some(app.multiboot_selected));
+        frame.render_stateful_widget(table, chunks[2], &mut state);
+    }
+
+    let footer = Paragraph::new(
+        "↑↓ Select  │  a Catalog  │  u URL  │  l Local file  │  d Delete  │  Esc Eject",
+    )
+    .alignment(Alignment::Center)
+    .style(Style::default().fg(Color::DarkGray));
+    frame.render_widget(footer, chunks[3]);
+}
+
+/// Ask whether the space after a live image should become persistence
+pub fn draw_persistence_offer(frame: &mut Frame, offer: &PersistenceOffer) {
+    let area = centered_rect(60, 30, frame.area());
+
+    frame.render_widget(Clear, area);
+
+    let block = Block::default()
+        .title(" Flash complete ")
+        .borders(Borders::ALL)
+        .style(Style::default().fg(Color::Green));
+
+    let inner = block.inner(area);
+    frame.render_widget(block, area);
+
+    let chunks = Layout::vertical([Constraint::Min(1), Constraint::Length(1)]).split(inner);
+
+    let text = Paragraph::new(vec![
+        Line::from(Span::styled(
+            format!("This is a {} image.", offer.flavor.display_name()),
+            Style::default().fg(Color::White),
+        )),
+        Line::from(Span::styled(
+            format!(
+                "{} after it is unused. Keep changes there across reboots?",
+                bytes_to_human(offer.size)
+            ),
+            Style::default().fg(Color::White),
+        )),
+        Line::from(""),
+        Line::from(Span::styled(
+            format!(
+                "Adds an ext4 partition labelled {}; boot with `{}` to use it",
+                offer.flavor.label(),
+                offer.flavor.boot_option()
+            ),
+            Style::default().fg(Color::Cyan),
+        )),
+    ])
+    .wrap(Wrap { trim: true });
+    frame.render_widget(text, chunks[0]);
+
+    let footer = Paragraph::new("y Add persistence  │  n Skip and eject")
+        .alignment(Alignment::Center)
+        .style(Style::default().fg(Color::DarkGray));
+    frame.render_widget(footer, chunks[1]);
+}
+
+/// Draw the list of files injected into the image after flashing
+pub fn draw_injection_editor(frame: &mut Frame, app: &App) {
+    let area = centered_rect(80, 60, frame.area());
 
     frame.render_widget(Clear, area);
 
     let block = Block::default()
-        .title(" Select ISO Image ")
+        .title(" Files to Inject ")
         .borders(Borders::ALL)
-        .style(Style::default().fg(Color::Magenta));
+        .style(Style::default().fg(Color::Cyan));
 
     let inner = block.inner(area);
     frame.render_widget(block, area);
 
-    let chunks = Layout::vertical([Constraint::Length(2), Constraint::Min(1)]).split(inner);
+    let chunks = Layout::vertical([
+        Constraint::Min(3),
+        Constraint::Length(3),
+        Constraint::Length(1),
+        Constraint::Length(1),
+    ])
+    .split(inner);
+
+    let injections = app
+        .flash_job
+        .as_ref()
+        .map(|job| job.injections.as_slice())
+        .unwrap_or_default();
+    let lines: Vec<Line> = if injections.is_empty() {
+        vec![Line::from(Span::styled(
+            "No files yet. They are written after the image and read back.",
+            Style::default().fg(Color::DarkGray),
+        ))]
+    } else {
+        injections
+            .iter()
+            .enumerate()
+            .map(|(i, injection)| {
+                let style = if i == app.injection_selected {
+                    Style::default()
+                        .fg(Color::Black)
+                        .bg(Color::Cyan)
+                        .add_modifier(Modifier::BOLD)
+                } else {
+                    Style::default().fg(Color::White)
+                };
+                Line::from(Span::styled(injection.to_string(), style))
+            })
+            .collect()
+    };
+    frame.render_widget(Paragraph::new(lines), chunks[0]);
 
-    let header_text = format!(
-        "   {:<12} | {:<10} | {:<8} | {}",
-        "DISTRO", "VERSION", "ARCH", "VARIETY"
+    let input_display = Paragraph::new(format!("{}▏", app.injection_input)).block(
+        Block::default()
+            .borders(Borders::ALL)
+            .title(" <file> -> <partition number or label>:<path> ")
+            .style(Style::default().fg(Color::White)),
     );
-    let header = Paragraph::new(header_text)
-        .style(
-            Style::default()
-                .fg(Color::White)
-                .add_modifier(Modifier::BOLD),
-        )
-        .block(Block::default().borders(Borders::BOTTOM));
+    frame.render_widget(input_display, chunks[1]);
 
-    frame.render_widget(header, chunks[0]);
+    let status = match &app.injection_error {
+        Some(e) => Span::styled(e.clone(), Style::default().fg(Color::Red)),
+        None => Span::styled(
+            "e.g. ~/ks.cfg -> 1:/ks.cfg or license.txt -> BOOT:/license.txt",
+            Style::default().fg(Color::DarkGray),
+        ),
+    };
+    frame.render_widget(Paragraph::new(Line::from(status)), chunks[2]);
 
-    let items: Vec<ListItem> = app
-        .isos
+    let footer = Paragraph::new("Enter Add  │  ↑↓ Select  │  Del Remove  │  Esc Done")
+        .alignment(Alignment::Center)
+        .style(Style::default().fg(Color::DarkGray));
+    frame.render_widget(footer, chunks[3]);
+}
+
+/// Draw the headless setup form for a flashed Raspberry Pi OS image
+pub fn draw_pi_setup(frame: &mut Frame, app: &App, boot: &PiBoot) {
+    let area = centered_rect(70, 60, frame.area());
+
+    frame.render_widget(Clear, area);
+
+    let block = Block::default()
+        .title(" Raspberry Pi OS Setup ")
+        .borders(Borders::ALL)
+        .style(Style::default().fg(Color::Green));
+
+    let inner = block.inner(area);
+    frame.render_widget(block, area);
+
+    let chunks = Layout::vertical([
+        Constraint::Length(2),
+        Constraint::Min(PiField::ALL.len() as u16),
+        Constraint::Length(1),
+        Constraint::Length(1),
+    ])
+    .split(inner);
+
+    let intro = Paragraph::new(format!(
+        "Flashed. Written to the boot partition ({}) before ejecting:",
+        bytes_to_human(boot.partition.size)
+    ))
+    .style(Style::default().fg(Color::White))
+    .wrap(Wrap { trim: true });
+    frame.render_widget(intro, chunks[0]);
+
+    let settings = &app.pi_settings;
+    let masked = |typed: &str, saved: &str| {
+        if !typed.is_empty() {
+            "*".repeat(typed.chars().count())
+        } else if !saved.is_empty() {
+            "(saved)".to_string()
+        } else {
+            String::new()
+        }
+    };
+    let lines: Vec<Line> = PiField::ALL
         .iter()
         .enumerate()
-        .map(|(i, iso)| {
-            let content = format!(
-                "{:<12} | {:<10} | {:<8} | {}",
-                iso.name, iso.version, iso.arch, iso.variety
-            );
-
-            let style = if i == app.selected_iso_index {
+        .map(|(i, field)| {
+            let value = match field {
+                PiField::Preset => settings.name.clone(),
+                PiField::Hostname => settings.hostname.clone(),
+                PiField::Username => settings.username.clone(),
+                PiField::Password => masked(&settings.password, &settings.password_hash),
+                PiField::Ssh => if settings.ssh { "[x]" } else { "[ ]" }.to_string(),
+                PiField::AuthorizedKey => settings.authorized_key.clone(),
+                PiField::WifiSsid => settings.wifi_ssid.clone(),
+                PiField::WifiPassword => masked(&settings.wifi_password, &settings.wifi_psk),
+                PiField::WifiCountry => settings.wifi_country.clone(),
+            };
+            let selected = i == app.pi_field;
+            let style = if selected {
                 Style::default()
-                    .fg(Color::Yellow)
-                    .add_modifier(Modifier::BOLD | Modifier::REVERSED)
+                    .fg(Color::Black)
+                    .bg(Color::Green)
+                    .add_modifier(Modifier::BOLD)
             } else {
-                Style::default()
+                Style::default().fg(Color::White)
+            };
+            let cursor = if selected && *field != PiField::Ssh {
+                "█"
+            } else {
+                ""
             };
-            ListItem::new(content).style(style)
+            Line::from(vec![
+                Span::styled(
+                    format!("{:>16} ", field.label()),
+                    Style::default().fg(Color::Gray),
+                ),
+                Span::styled(format!("{}{}", value, cursor), style),
+            ])
         })
         .collect();
+    frame.render_widget(Paragraph::new(lines), chunks[1]);
 
-    let list = List::new(items);
-    frame.render_widget(list, chunks[1]);
+    let notice = match (&app.pi_notice, settings.validate()) {
+        (Some(notice), _) => Span::styled(notice.clone(), Style::default().fg(Color::Yellow)),
+        (None, Err(e)) => Span::styled(e, Style::default().fg(Color::Red)),
+        (None, Ok(())) => Span::styled(
+            "Empty fields are left as the image has them",
+            Style::default().fg(Color::DarkGray),
+        ),
+    };
+    frame.render_widget(Paragraph::new(Line::from(notice)), chunks[2]);
+
+    let footer = Paragraph::new(
+        "↑↓ Field  │  Space Toggle SSH  │  F2 Next preset  │  F3 Save preset  │  Enter Write  │  Esc Skip",
+    )
+    .alignment(Alignment::Center)
+    .style(Style::default().fg(Color::DarkGray));
+    frame.render_widget(footer, chunks[3]);
 }
 
-/// Draw confirmation dialog for destructive operations
-pub fn draw_confirm_dialog(frame: &mut Frame, device_path: &str, input: &str, is_flash: bool) {
-    let area = centered_rect(60, 40, frame.area());
+/// Draw the editor for the cloud-init seed attached to a flash
+pub fn draw_seed_editor(frame: &mut Frame, app: &App) {
+    let area = centered_rect(80, 70, frame.area());
+
+    frame.render_widget(Clear, area);
+
+    let template = app
+        .seed_templates
+        .get(app.seed_template_index)
+        .map(|t| t.name.as_str())
+        .unwrap_or("");
+    let block = Block::default()
+        .title(format!(" cloud-init Seed: {} ", template))
+        .borders(Borders::ALL)
+        .style(Style::default().fg(Color::Cyan));
+
+    let inner = block.inner(area);
+    frame.render_widget(block, area);
+
+    let chunks = Layout::vertical([
+        Constraint::Length(1),
+        Constraint::Min(3),
+        Constraint::Length(1),
+        Constraint::Length(1),
+    ])
+    .split(inner);
+
+    let Some(seed) = app.flash_job.as_ref().and_then(|job| job.seed.as_ref()) else {
+        return;
+    };
+
+    let mut tabs = Vec::new();
+    for file in SeedFile::ALL {
+        let style = if file == app.seed_file {
+            Style::default()
+                .fg(Color::Black)
+                .bg(Color::Cyan)
+                .add_modifier(Modifier::BOLD)
+        } else {
+            Style::default().fg(Color::Gray)
+        };
+        tabs.push(Span::styled(format!(" {} ", file.file_name()), style));
+        tabs.push(Span::raw(" "));
+    }
+    frame.render_widget(Paragraph::new(Line::from(tabs)), chunks[0]);
+
+    // Keep the end of the file, where typing happens, in view
+    let text = format!("{}█", seed.get(app.seed_file));
+    let height = chunks[1].height.saturating_sub(2) as usize;
+    let lines: Vec<Line> = text.lines().map(|l| Line::from(l.to_string())).collect();
+    let skip = lines.len().saturating_sub(height);
+    let editor = Paragraph::new(lines.into_iter().skip(skip).collect::<Vec<_>>()).block(
+        Block::default()
+            .borders(Borders::ALL)
+            .style(Style::default().fg(Color::White)),
+    );
+    frame.render_widget(editor, chunks[1]);
+
+    let status = match seed.validate() {
+        Ok(()) if app.seed_file == SeedFile::NetworkConfig && seed.network_config.is_empty() => {
+            Span::styled(
+                "Left out of the seed while empty",
+                Style::default().fg(Color::DarkGray),
+            )
+        }
+        Ok(()) => Span::styled(
+            format!(
+                "Written to a {} partition after the image",
+                cloudinit::LABEL
+            ),
+            Style::default().fg(Color::Green),
+        ),
+        Err(e) => Span::styled(e, Style::default().fg(Color::Red)),
+    };
+    frame.render_widget(Paragraph::new(Line::from(status)), chunks[2]);
+
+    let footer = Paragraph::new("Tab File  │  F2 Next template  │  Del Detach  │  Esc Done")
+        .alignment(Alignment::Center)
+        .style(Style::default().fg(Color::DarkGray));
+    frame.render_widget(footer, chunks[3]);
+}
+
+/// Draw the path input for copying a local ISO onto the stick
+pub fn draw_multiboot_file_input(frame: &mut Frame, app: &App) {
+    let area = centered_rect(70, 25, frame.area());
 
     frame.render_widget(Clear, area);
 
-    let title = if is_flash {
-        " ⚠️  CONFIRM FLASH "
+    let block = Block::default()
+        .title(" Add Local ISO ")
+        .borders(Borders::ALL)
+        .style(Style::default().fg(Color::Magenta));
+
+    let inner = block.inner(area);
+    frame.render_widget(block, area);
+
+    let chunks = Layout::vertical([
+        Constraint::Length(3),
+        Constraint::Min(1),
+        Constraint::Length(1),
+    ])
+    .split(inner);
+
+    let input_display = Paragraph::new(format!("{}▏", app.input_buffer)).block(
+        Block::default()
+            .borders(Borders::ALL)
+            .title(" File ")
+            .style(Style::default().fg(Color::White)),
+    );
+    frame.render_widget(input_display, chunks[0]);
+
+    let path = app.input_buffer.trim();
+    let validation = if path.is_empty() {
+        Span::styled(
+            "Absolute path to an .iso file",
+            Style::default().fg(Color::DarkGray),
+        )
+    } else if !path.starts_with('/') {
+        Span::styled("Path must be absolute", Style::default().fg(Color::Red))
+    } else if !std::path::Path::new(path).is_file() {
+        Span::styled("No such file", Style::default().fg(Color::Red))
+    } else {
+        Span::styled("✓ Looks good", Style::default().fg(Color::Green))
+    };
+    frame.render_widget(Paragraph::new(Line::from(validation)), chunks[1]);
+
+    let footer = Paragraph::new("Enter Copy to stick  │  Esc Back")
+        .alignment(Alignment::Center)
+        .style(Style::default().fg(Color::DarkGray));
+    frame.render_widget(footer, chunks[2]);
+}
+
+/// Draw confirmation dialog for destructive operations.
+/// `job` is set when confirming a flash and describes the pre-flighted image.
+/// What a typed-path confirmation is about to do to the device
+pub enum ConfirmKind<'a> {
+    Format,
+    Flash(&'a FlashJob),
+    /// Clone from this source device
+    Clone(&'a Device),
+    Wipe {
+        mode: WipeMode,
+        passes: u32,
+        verify: bool,
+    },
+    /// Turn the device into a multi-boot stick
+    Multiboot,
+}
+
+pub fn draw_confirm_dialog(frame: &mut Frame, device_path: &str, input: &str, kind: ConfirmKind) {
+    let summary_height = match kind {
+        ConfirmKind::Format => 0,
+        ConfirmKind::Flash(job) => {
+            5 + u16::from(job.windows.is_none())
+                + u16::from(job.already_on_device)
+                + job
+                    .inspection
+                    .as_ref()
+                    .map_or(0, |i| inspection_lines(i).len()) as u16
+        }
+        _ => 3,
+    };
+    let height = if matches!(kind, ConfirmKind::Flash(_)) {
+        60
     } else {
-        " ⚠️  CONFIRM FORMAT "
+        40
+    };
+    let area = centered_rect(60, height, frame.area());
+
+    frame.render_widget(Clear, area);
+
+    let title = match kind {
+        ConfirmKind::Format => " ⚠️  CONFIRM FORMAT ",
+        ConfirmKind::Flash(_) => " ⚠️  CONFIRM FLASH ",
+        ConfirmKind::Clone(_) => " ⚠️  CONFIRM CLONE ",
+        ConfirmKind::Wipe { .. } => " ⚠️  CONFIRM WIPE ",
+        ConfirmKind::Multiboot => " ⚠️  CREATE MULTI-BOOT STICK ",
     };
     let block = Block::default()
         .title(title)
@@ -127,16 +1299,19 @@ pub fn draw_confirm_dialog(frame: &mut Frame, device_path: &str, input: &str, is
 
     let chunks = Layout::vertical([
         Constraint::Length(2),
+        Constraint::Length(summary_height),
         Constraint::Length(2),
         Constraint::Length(3),
         Constraint::Min(1),
     ])
     .split(inner);
 
-    let warning_text = if is_flash {
-        "This will OVERWRITE the device with the ISO image!"
-    } else {
-        "This will PERMANENTLY ERASE all data!"
+    let warning_text = match kind {
+        ConfirmKind::Format => "This will PERMANENTLY ERASE all data!",
+        ConfirmKind::Flash(_) => "This will OVERWRITE the device with the ISO image!",
+        ConfirmKind::Clone(_) => "This will OVERWRITE the device with a copy of another!",
+        ConfirmKind::Wipe { .. } => "This will DESTROY all data beyond recovery!",
+        ConfirmKind::Multiboot => "This will REPARTITION the device and erase all data!",
     };
 
     let warning = Paragraph::new(Line::from(vec![
@@ -148,9 +1323,179 @@ pub fn draw_confirm_dialog(frame: &mut Frame, device_path: &str, input: &str, is
     ]));
     frame.render_widget(warning, chunks[0]);
 
+    if let ConfirmKind::Wipe {
+        mode,
+        passes,
+        verify,
+    } = kind
+    {
+        let name = match mode {
+            WipeMode::MultiPass => format!("{} ({} passes)", mode.display_name(), passes),
+            _ => mode.display_name().to_string(),
+        };
+        let verify = if verify {
+            Span::styled(
+                "The device will be read back and verified",
+                Style::default().fg(Color::Green),
+            )
+        } else {
+            Span::styled(
+                "The result will not be verified",
+                Style::default().fg(Color::Yellow),
+            )
+        };
+        let summary = Paragraph::new(vec![
+            Line::from(Span::styled(name, Style::default().fg(Color::White))),
+            Line::from(verify),
+        ])
+        .wrap(Wrap { trim: true });
+        frame.render_widget(summary, chunks[1]);
+    }
+
+    if let ConfirmKind::Multiboot = kind {
+        let summary = Paragraph::new(vec![
+            Line::from(Span::styled(
+                "No multi-boot stick found on this device",
+                Style::default().fg(Color::White),
+            )),
+            Line::from(Span::styled(
+                "It gets a GRUB EFI partition and an exFAT partition for ISOs",
+                Style::default().fg(Color::Cyan),
+            )),
+        ])
+        .wrap(Wrap { trim: true });
+        frame.render_widget(summary, chunks[1]);
+    }
+
+    if let ConfirmKind::Clone(source) = kind {
+        let summary = Paragraph::new(vec![
+            Line::from(Span::styled(
+                format!(
+                    "From {} ({})",
+                    source.path,
+                    bytes_to_human(source.size_bytes)
+                ),
+                Style::default().fg(Color::White),
+            )),
+            Line::from(Span::styled(
+                "The copy will be read back and verified",
+                Style::default().fg(Color::Green),
+            )),
+        ])
+        .wrap(Wrap { trim: true });
+        frame.render_widget(summary, chunks[1]);
+    }
+
+    if let ConfirmKind::Flash(job) = kind {
+        let checksum = match (&job.preflight.sha256, &job.preflight.checksum_source) {
+            (Some(_), Some(source)) => Span::styled(
+                format!("SHA-256 will be verified ({})", source),
+                Style::default().fg(Color::Green),
+            ),
+            _ if job.preflight.verifies_pieces => Span::styled(
+                "Pieces will be verified as they download",
+                Style::default().fg(Color::Green),
+            ),
+            _ => Span::styled(
+                "No checksum found, integrity will not be verified",
+                Style::default().fg(Color::Yellow),
+            ),
+        };
+        let mut lines = vec![
+            Line::from(Span::styled(
+                format!(
+                    "{} {} ({})",
+                    job.iso.name,
+                    job.iso.version,
+                    bytes_to_human(job.preflight.total_bytes)
+                ),
+                Style::default().fg(Color::White),
+            )),
+            Line::from(checksum),
+        ];
+        if let Some(inspection) = &job.inspection {
+            lines.extend(inspection_lines(inspection));
+        }
+        if job.already_on_device {
+            lines.push(Line::from(Span::styled(
+                "The drive already holds exactly this image; Esc to skip flashing it again",
+                Style::default().fg(Color::Green),
+            )));
+        }
+        if job.windows.is_some() {
+            lines.push(Line::from(Span::styled(
+                "Windows installer: files go onto a new GPT/FAT32 stick (UEFI boot)",
+                Style::default().fg(Color::Cyan),
+            )));
+        } else if let Some(seed) = &job.seed {
+            let files: Vec<&str> = seed.files().iter().map(|f| f.file_name()).collect();
+            lines.push(Line::from(Span::styled(
+                format!(
+                    "cloud-init seed: {} partition ({})  │  Tab edit",
+                    cloudinit::LABEL,
+                    files.join(", ")
+                ),
+                Style::default().fg(Color::Cyan),
+            )));
+        } else {
+            lines.push(Line::from(Span::styled(
+                "Tab attach a cloud-init seed",
+                Style::default().fg(Color::DarkGray),
+            )));
+        }
+        if job.injections.is_empty() {
+            lines.push(Line::from(Span::styled(
+                "F2 add files to the written image",
+                Style::default().fg(Color::DarkGray),
+            )));
+        } else {
+            let count = job.injections.len();
+            lines.push(Line::from(Span::styled(
+                format!(
+                    "{} file{} injected after flashing  │  F2 edit",
+                    count,
+                    if count == 1 { "" } else { "s" }
+                ),
+                Style::default().fg(Color::Cyan),
+            )));
+        }
+        if job.windows.is_none() {
+            let line = if job.grow && job.seed.is_some() {
+                Span::styled(
+                    "[x] Grow the last partition: off while a seed is attached  │  F3",
+                    Style::default().fg(Color::DarkGray),
+                )
+            } else if job.grow {
+                Span::styled(
+                    "[x] Grow the last partition to fill the device  │  F3",
+                    Style::default().fg(Color::Cyan),
+                )
+            } else {
+                Span::styled(
+                    "[ ] Grow the last partition to fill the device  │  F3",
+                    Style::default().fg(Color::DarkGray),
+                )
+            };
+            lines.push(Line::from(line));
+            lines.push(Line::from(if job.delta {
+                Span::styled(
+                    "[x] Only write blocks that differ from the drive  │  F4",
+                    Style::default().fg(Color::Cyan),
+                )
+            } else {
+                Span::styled(
+                    "[ ] Only write blocks that differ from the drive  │  F4",
+                    Style::default().fg(Color::DarkGray),
+                )
+            }));
+        }
+        let summary = Paragraph::new(lines).wrap(Wrap { trim: true });
+        frame.render_widget(summary, chunks[1]);
+    }
+
     let instruction = Paragraph::new(format!("Type '{}' to confirm:", device_path))
         .style(Style::default().fg(Color::Yellow));
-    frame.render_widget(instruction, chunks[1]);
+    frame.render_widget(instruction, chunks[2]);
 
     let input_display = Paragraph::new(input).block(
         Block::default()
@@ -158,15 +1503,98 @@ pub fn draw_confirm_dialog(frame: &mut Frame, device_path: &str, input: &str, is
             .title(" Input ")
             .style(Style::default().fg(Color::White)),
     );
-    frame.render_widget(input_display, chunks[2]);
+    frame.render_widget(input_display, chunks[3]);
+}
+
+/// What the image inspection found: format, partitions, how it boots and
+/// any reason it won't
+fn inspection_lines(inspection: &Inspection) -> Vec<Line<'static>> {
+    let mut format = inspection.format.describe();
+    match inspection.table {
+        Some(TableKind::Mbr) => format.push_str(", MBR"),
+        Some(TableKind::Gpt) => format.push_str(", GPT"),
+        None => {}
+    }
+    if let Some(label) = &inspection.label {
+        format.push_str(&format!("  │  label {}", label));
+    }
+    let mut lines = vec![Line::from(Span::styled(
+        format,
+        Style::default().fg(Color::White),
+    ))];
+
+    if !inspection.partitions.is_empty() {
+        let partitions: Vec<String> = inspection
+            .partitions
+            .iter()
+            .map(|p| {
+                format!(
+                    "{} {}{} {}",
+                    p.partition.number,
+                    p.filesystem_name(),
+                    if p.is_efi_system() { " (ESP)" } else { "" },
+                    bytes_to_human(p.partition.size)
+                )
+            })
+            .collect();
+        lines.push(Line::from(Span::styled(
+            format!("Partitions: {}", partitions.join(", ")),
+            Style::default().fg(Color::Gray),
+        )));
+    }
+
+    let mut boots = Vec::new();
+    if inspection.bios {
+        boots.push("BIOS");
+    }
+    if inspection.uefi {
+        boots.push("UEFI");
+    }
+    // Windows installers are unpacked, so how the ISO boots doesn't matter
+    if inspection.format != ImageFormat::WindowsIso
+        && (!boots.is_empty() || !inspection.el_torito.is_empty())
+    {
+        let mut boot = if boots.is_empty() {
+            "Doesn't boot from USB".to_string()
+        } else {
+            format!("Boots from USB: {}", boots.join(", "))
+        };
+        if !inspection.el_torito.is_empty() {
+            boot.push_str(&format!(
+                "  │  El Torito: {}",
+                inspection.el_torito.join(", ")
+            ));
+        }
+        lines.push(Line::from(Span::styled(
+            boot,
+            Style::default().fg(if boots.is_empty() {
+                Color::Yellow
+            } else {
+                Color::Green
+            }),
+        )));
+    }
+
+    for warning in &inspection.warnings {
+        lines.push(Line::from(Span::styled(
+            format!("⚠ {}", warning),
+            Style::default().fg(Color::Yellow),
+        )));
+    }
+    lines
 }
 
 pub fn draw_flash_progress(frame: &mut Frame, progress: &FlashProgress) {
-    let area = centered_rect(60, 25, frame.area());
+    let area = centered_rect(60, 30, frame.area());
     frame.render_widget(Clear, area);
 
+    let title = if progress.verifying {
+        " Verifying... "
+    } else {
+        " Flashing ISO... "
+    };
     let block = Block::default()
-        .title(" Flashing ISO... ")
+        .title(title)
         .borders(Borders::ALL)
         .style(Style::default().fg(Color::Cyan));
 
@@ -177,16 +1605,23 @@ pub fn draw_flash_progress(frame: &mut Frame, progress: &FlashProgress) {
         Constraint::Length(2),
         Constraint::Length(3),
         Constraint::Length(1),
+        Constraint::Length(1),
     ])
     .split(inner);
 
-    let info = Paragraph::new(format!(
+    let mut info = vec![Line::from(format!(
         "{}/{} ({:.1} MB/s)",
         bytes_to_human(progress.bytes_written),
         bytes_to_human(progress.total_bytes),
         progress.speed_mbps
-    ))
-    .alignment(Alignment::Center);
+    ))];
+    if let Some(file) = &progress.current_file {
+        info.push(Line::from(Span::styled(
+            file.as_str(),
+            Style::default().fg(Color::DarkGray),
+        )));
+    }
+    let info = Paragraph::new(info).alignment(Alignment::Center);
 
     frame.render_widget(info, chunks[0]);
 
@@ -197,6 +1632,168 @@ pub fn draw_flash_progress(frame: &mut Frame, progress: &FlashProgress) {
         .label(format!("{:.1}%", progress.percent));
 
     frame.render_widget(gauge, chunks[1]);
+
+    if let Some(status) = &progress.source_status {
+        let status = Paragraph::new(status.as_str())
+            .style(Style::default().fg(Color::DarkGray))
+            .alignment(Alignment::Center);
+        frame.render_widget(status, chunks[2]);
+    }
+
+    let limit = match progress.rate_limit {
+        _ if progress.verifying => "Reading the device back".to_string(),
+        _ if !progress.remote => "Local copy, no download cap".to_string(),
+        Some(rate) => format!(
+            "Capped at {:.1} MB/s · +/- to change",
+            rate as f64 / 1_000_000.0
+        ),
+        None => "No download cap · - to set one".to_string(),
+    };
+    let limit = Paragraph::new(limit)
+        .style(Style::default().fg(Color::DarkGray))
+        .alignment(Alignment::Center);
+    frame.render_widget(limit, chunks[3]);
+}
+
+/// Draw the backup dialog: output path, compression and unused-space skipping
+pub fn draw_backup_setup(frame: &mut Frame, app: &App) {
+    let area = centered_rect(70, 40, frame.area());
+
+    frame.render_widget(Clear, area);
+
+    let title = match app.selected_device() {
+        Some(device) => format!(" Back up {} ", device.path),
+        None => " Back up ".to_string(),
+    };
+    let block = Block::default()
+        .title(title)
+        .borders(Borders::ALL)
+        .style(Style::default().fg(Color::Cyan));
+
+    let inner = block.inner(area);
+    frame.render_widget(block, area);
+
+    let chunks = Layout::vertical([
+        Constraint::Length(3),
+        Constraint::Length(1),
+        Constraint::Length(3),
+        Constraint::Min(1),
+        Constraint::Length(1),
+    ])
+    .split(inner);
+
+    let input_display = Paragraph::new(format!("{}▏", app.input_buffer)).block(
+        Block::default()
+            .borders(Borders::ALL)
+            .title(" Save image to ")
+            .style(Style::default().fg(Color::White)),
+    );
+    frame.render_widget(input_display, chunks[0]);
+
+    let mut spans = vec![Span::raw("Compression: ")];
+    for (i, compression) in Compression::ALL.iter().enumerate() {
+        if i > 0 {
+            spans.push(Span::raw("  "));
+        }
+        let style = if *compression == app.backup_compression {
+            Style::default()
+                .fg(Color::Yellow)
+                .add_modifier(Modifier::BOLD | Modifier::REVERSED)
+        } else {
+            Style::default().fg(Color::White)
+        };
+        spans.push(Span::styled(
+            format!(" {} ", compression.display_name()),
+            style,
+        ));
+    }
+    frame.render_widget(Paragraph::new(Line::from(spans)), chunks[1]);
+
+    let options: Vec<Line> = BackupOption::ALL
+        .iter()
+        .map(|option| {
+            let (checked, label) = match option {
+                BackupOption::SkipUnused => (
+                    app.backup_skip_unused,
+                    "Skip unused space (read only what partitions and filesystems use)",
+                ),
+                BackupOption::Shrink => (
+                    app.backup_shrink,
+                    "Shrink the last partition to its used size (ext2/3/4, FAT)",
+                ),
+                BackupOption::AutoExpand => (
+                    app.backup_auto_expand,
+                    "Grow it back to fill the card on first boot (ext4 + systemd)",
+                ),
+            };
+            let style = if *option == app.backup_option {
+                Style::default()
+                    .fg(Color::Yellow)
+                    .add_modifier(Modifier::BOLD)
+            } else {
+                Style::default().fg(Color::White)
+            };
+            let mark = if checked { "[x]" } else { "[ ]" };
+            Line::styled(format!("{} {}", mark, label), style)
+        })
+        .collect();
+    frame.render_widget(Paragraph::new(options), chunks[2]);
+
+    let footer =
+        Paragraph::new("←→ Compression  │  ↑↓ Option  │  Tab Toggle  │  Enter Start  │  Esc Back")
+            .alignment(Alignment::Center)
+            .style(Style::default().fg(Color::DarkGray));
+    frame.render_widget(footer, chunks[4]);
+}
+
+pub fn draw_backup_progress(frame: &mut Frame, progress: &BackupProgress) {
+    let area = centered_rect(60, 25, frame.area());
+    frame.render_widget(Clear, area);
+
+    let title = match progress.stage {
+        BackupStage::Reading => " Backing up device... ",
+        BackupStage::Shrinking => " Shrinking image... ",
+        BackupStage::Compressing => " Compressing shrunk image... ",
+    };
+    let block = Block::default()
+        .title(title)
+        .borders(Borders::ALL)
+        .style(Style::default().fg(Color::Cyan));
+
+    let inner = block.inner(area);
+    frame.render_widget(block, area);
+
+    let chunks = Layout::vertical([
+        Constraint::Length(2),
+        Constraint::Length(3),
+        Constraint::Length(1),
+    ])
+    .split(inner);
+
+    let info = Paragraph::new(format!(
+        "{}/{} ({:.1} MB/s)",
+        bytes_to_human(progress.bytes_read),
+        bytes_to_human(progress.total_bytes),
+        progress.speed_mbps
+    ))
+    .alignment(Alignment::Center);
+    frame.render_widget(info, chunks[0]);
+
+    let gauge = Gauge::default()
+        .block(Block::default().borders(Borders::NONE))
+        .gauge_style(Style::default().fg(Color::Green))
+        .ratio((progress.percent / 100.0).clamp(0.0, 1.0))
+        .label(format!("{:.1}%", progress.percent));
+    frame.render_widget(gauge, chunks[1]);
+
+    let detail = Paragraph::new(format!(
+        "{} skipped as unused · {} written",
+        bytes_to_human(progress.skipped_bytes),
+        bytes_to_human(progress.compressed_bytes)
+    ))
+    .style(Style::default().fg(Color::DarkGray))
+    .alignment(Alignment::Center);
+    frame.render_widget(detail, chunks[2]);
 }
 
 /// Draw status/info messages
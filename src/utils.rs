@@ -1,5 +1,7 @@
 use elevate::RunningAs;
+use std::path::PathBuf;
 use std::sync::OnceLock;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 static IS_ROOT: OnceLock<bool> = OnceLock::new();
 
@@ -19,6 +21,45 @@ pub fn escalate_if_needed() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+/// Directory where Pervie keeps its config and history files.
+/// Uses $XDG_CONFIG_HOME/pervie, falling back to ~/.config/pervie.
+pub fn config_dir() -> Option<PathBuf> {
+    if let Some(dir) = std::env::var_os("XDG_CONFIG_HOME").filter(|d| !d.is_empty()) {
+        return Some(PathBuf::from(dir).join("pervie"));
+    }
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config").join("pervie"))
+}
+
+/// Expand a leading `~/` to the home directory
+pub fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), std::env::var_os("HOME")) {
+        (Some(rest), Some(home)) => PathBuf::from(home).join(rest),
+        _ => PathBuf::from(path),
+    }
+}
+
+/// UTC year, month, day, hour, minute and second of `time`
+pub fn utc_datetime(time: SystemTime) -> (i64, i64, i64, i64, i64, i64) {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i64;
+    let (days, rem) = (secs.div_euclid(86_400), secs.rem_euclid(86_400));
+
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    (year, month, day, rem / 3600, rem % 3600 / 60, rem % 60)
+}
+
 /// Convert bytes to human-readable format (KB, MB, GB, TB)
 pub fn bytes_to_human(bytes: u64) -> String {
     const KB: u64 = 1024;